@@ -0,0 +1,144 @@
+use lru::LruCache;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Bounded cache, evicting the least recently used entries, shared between threads
+pub struct Cache<K, V> {
+    entries: Mutex<Option<LruCache<K, V>>>, // None if the capacity is 0 (disabled)
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Usage of a cache
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl<K: Hash + Eq, V: Clone> Cache<K, V> {
+    pub fn new(capacity: usize) -> Cache<K, V> {
+        Cache {
+            // Not allocated for the whole capacity, which can be large
+            entries: Mutex::new((capacity > 0).then(LruCache::unbounded)),
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get a copy of an entry, counting the hits and misses
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self
+            .entries
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|x| x.get(key).cloned());
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        if let Some(entries) = self.entries.lock().unwrap().as_mut() {
+            entries.put(key, value);
+            if entries.len() > self.capacity {
+                entries.pop_lru();
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &K) {
+        if let Some(entries) = self.entries.lock().unwrap().as_mut() {
+            entries.pop(key);
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Some(entries) = self.entries.lock().unwrap().as_mut() {
+            entries.clear();
+        }
+    }
+
+    pub fn get_stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            len: self.entries.lock().unwrap().as_ref().map_or(0, |x| x.len()),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lookups = self.hits + self.misses;
+        let ratio = if lookups > 0 {
+            self.hits as f64 * 100.0 / lookups as f64
+        } else {
+            0.0
+        };
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}% hits), {}/{} entries",
+            self.hits, self.misses, ratio, self.len, self.capacity
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru() {
+        let cache: Cache<u64, u64> = Cache::new(2);
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        assert_eq!(cache.get(&1), Some(10));
+        // 2 is the least recently used
+        cache.insert(3, 30);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!(cache.get(&3), Some(30));
+        cache.remove(&1);
+        assert_eq!(cache.get(&1), None);
+        let stats = cache.get_stats();
+        assert_eq!((stats.hits, stats.misses, stats.len), (3, 2, 1));
+        assert_eq!(
+            stats.to_string(),
+            "3 hits, 2 misses (60.0% hits), 1/2 entries"
+        );
+        cache.clear();
+        assert_eq!(cache.get_stats().len, 0);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let cache: Cache<u64, u64> = Cache::new(0);
+        cache.insert(1, 10);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(
+            cache.get_stats().to_string(),
+            "0 hits, 1 misses (0.0% hits), 0/0 entries"
+        );
+    }
+
+    #[test]
+    fn test_large_capacity() {
+        // The entries are not allocated in advance
+        let cache: Cache<u64, u64> = Cache::new(usize::MAX);
+        cache.insert(1, 10);
+        assert_eq!(cache.get(&1), Some(10));
+    }
+}
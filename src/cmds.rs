@@ -1,36 +1,158 @@
+pub mod blocks;
 pub mod cat;
 pub mod df;
+pub mod dumpblock;
+pub mod dumpe2fs;
+pub mod dumpinode;
+pub mod filefrag;
+pub mod freefrag;
+pub mod fsck;
 pub mod hd;
+pub mod icheck;
 pub mod ls;
+pub mod lsdel;
+pub mod ncheck;
+pub mod overlay;
+pub mod partitions;
+pub mod shell;
 pub mod stat;
+pub mod undelete;
 
+use crate::ext2::Ext2Filesystem;
+use crate::fs::{mount, Filesystem, MountOptions};
+use std::fmt;
 use std::io::Error;
 use std::str::FromStr;
+use std::sync::Arc;
 
-#[derive(Debug)]
 pub struct Options {
     pub filename: String,
+    pub mount_options: MountOptions,
+    pub fs: Option<Arc<Ext2Filesystem>>, // Filesystem mounted once for all the commands (shell)
+    pub cwd: String,                     // Directory the relative paths are resolved from (shell)
 }
 
+impl Options {
+    /// Path of a file relative to the current directory
+    pub fn get_path(&self, path: &str) -> String {
+        if path.starts_with('/') || path.starts_with('<') || self.cwd == "/" {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.cwd.trim_end_matches('/'), path)
+        }
+    }
+
+    /// Mount the filesystem, or return the already mounted one
+    pub fn mount(&self) -> Result<Arc<dyn Filesystem>, Error> {
+        match &self.fs {
+            Some(fs) => Ok(fs.clone()),
+            None => Ok(Arc::from(mount(&self.filename, &self.mount_options)?)),
+        }
+    }
+
+    /// Mount the Ext2 filesystem, or return the already mounted one
+    pub fn mount_ext2(&self) -> Result<Arc<Ext2Filesystem>, Error> {
+        match &self.fs {
+            Some(fs) => Ok(fs.clone()),
+            None => Ok(Arc::new(Ext2Filesystem::mount(
+                &self.filename,
+                &self.mount_options,
+            )?)),
+        }
+    }
+}
+
+/// Error ending a command which already displayed its message
+#[derive(Debug)]
+pub struct ExitStatus(pub i32);
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "exit status {}", self.0)
+    }
+}
+
+impl std::error::Error for ExitStatus {}
+
+/// Returns an error ending the command with the given exit status
+pub fn exit_status(status: i32) -> Error {
+    Error::other(ExitStatus(status))
+}
+
+/// Returns the exit status if the error was returned by exit_status
+pub fn get_exit_status(err: &Error) -> Option<i32> {
+    err.get_ref()?.downcast_ref::<ExitStatus>().map(|x| x.0)
+}
+
+pub const COMMAND_NAMES: [&str; 19] = [
+    "blocks",
+    "cat",
+    "df",
+    "dumpblock",
+    "dumpe2fs",
+    "dumpinode",
+    "filefrag",
+    "freefrag",
+    "fsck",
+    "hd",
+    "icheck",
+    "ls",
+    "lsdel",
+    "ncheck",
+    "overlay",
+    "partitions",
+    "shell",
+    "stat",
+    "undelete",
+];
+
 #[derive(Debug)]
 #[allow(non_camel_case_types)]
 pub enum Command {
+    blocks,
     cat,
     df,
+    dumpblock,
+    dumpe2fs,
+    dumpinode,
+    filefrag,
+    freefrag,
+    fsck,
     hd,
+    icheck,
     ls,
+    lsdel,
+    ncheck,
+    overlay,
+    partitions,
+    shell,
     stat,
+    undelete,
 }
 
 impl FromStr for Command {
     type Err = ();
     fn from_str(src: &str) -> Result<Command, ()> {
         return match src {
+            "blocks" => Ok(Command::blocks),
             "cat" => Ok(Command::cat),
             "df" => Ok(Command::df),
+            "dumpblock" => Ok(Command::dumpblock),
+            "dumpe2fs" => Ok(Command::dumpe2fs),
+            "dumpinode" => Ok(Command::dumpinode),
+            "filefrag" => Ok(Command::filefrag),
+            "freefrag" => Ok(Command::freefrag),
+            "fsck" => Ok(Command::fsck),
             "hd" => Ok(Command::hd),
+            "icheck" => Ok(Command::icheck),
             "ls" => Ok(Command::ls),
+            "lsdel" => Ok(Command::lsdel),
+            "ncheck" => Ok(Command::ncheck),
+            "overlay" => Ok(Command::overlay),
+            "partitions" => Ok(Command::partitions),
+            "shell" => Ok(Command::shell),
             "stat" => Ok(Command::stat),
+            "undelete" => Ok(Command::undelete),
             _ => Err(()),
         };
     }
@@ -39,11 +161,49 @@ impl FromStr for Command {
 impl Command {
     pub fn run_command(&self, options: &Options, args: Vec<String>) -> Result<(), Error> {
         match self {
-            Command::cat => cat::cat(&options, args),
-            Command::df => df::df(&options, args),
-            Command::hd => hd::hd(&options, args),
-            Command::ls => ls::ls(&options, args),
-            Command::stat => stat::stat(&options, args),
+            Command::blocks => blocks::blocks(options, args),
+            Command::cat => cat::cat(options, args),
+            Command::df => df::df(options, args),
+            Command::dumpblock => dumpblock::dumpblock(options, args),
+            Command::dumpe2fs => dumpe2fs::dumpe2fs(options, args),
+            Command::dumpinode => dumpinode::dumpinode(options, args),
+            Command::filefrag => filefrag::filefrag(options, args),
+            Command::freefrag => freefrag::freefrag(options, args),
+            Command::fsck => fsck::fsck(options, args),
+            Command::hd => hd::hd(options, args),
+            Command::icheck => icheck::icheck(options, args),
+            Command::ls => ls::ls(options, args),
+            Command::lsdel => lsdel::lsdel(options, args),
+            Command::ncheck => ncheck::ncheck(options, args),
+            Command::overlay => overlay::overlay(options, args),
+            Command::partitions => partitions::partitions(options, args),
+            Command::shell => shell::shell(options, args),
+            Command::stat => stat::stat(options, args),
+            Command::undelete => undelete::undelete(options, args),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(cwd: &str) -> Options {
+        Options {
+            filename: String::new(),
+            mount_options: MountOptions::default(),
+            fs: None,
+            cwd: cwd.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_get_path() {
+        assert_eq!(options("/").get_path("hello.txt"), "hello.txt");
+        assert_eq!(options("/dir1").get_path("file.txt"), "/dir1/file.txt");
+        assert_eq!(options("/dir1/").get_path("a/b"), "/dir1/a/b");
+        assert_eq!(options("/dir1").get_path("/hello.txt"), "/hello.txt");
+        assert_eq!(options("/dir1").get_path("<16>"), "<16>");
+        assert_eq!(options("<14>").get_path("file.txt"), "<14>/file.txt");
+    }
+}
@@ -0,0 +1,53 @@
+use crate::cmds::{exit_status, Options};
+use crate::ext2::inode::Ext2BlockKind;
+use crate::ext2::Ext2Filesystem;
+use argparse::{ArgumentParser, List};
+use std::io::{self, Error};
+
+fn parse_args(args: Vec<String>, paths: &mut Vec<String>) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("List the blocks used by the FILE(s).");
+    parser.refer(paths).add_argument("file", List, "FILE");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+fn format_kind(kind: Ext2BlockKind) -> &'static str {
+    // Mark the metadata blocks
+    match kind {
+        Ext2BlockKind::Data => "",
+        Ext2BlockKind::Indirect => "(IND)",
+        Ext2BlockKind::DoublyIndirect => "(DIND)",
+        Ext2BlockKind::TriplyIndirect => "(TIND)",
+        Ext2BlockKind::ExtentTree => "(ETB)",
+    }
+}
+
+fn print_blocks(fs: &Ext2Filesystem, path: &str) -> Result<(), Error> {
+    let blocks: Vec<String> = fs
+        .get_file_block_map(path)?
+        .iter()
+        .map(|x| format!("{}{}", format_kind(x.kind), x.block_num))
+        .collect();
+    println!("{}", blocks.join(" "));
+    Ok(())
+}
+
+pub fn blocks(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut paths: Vec<String> = vec![];
+    parse_args(args, &mut paths)?;
+    if paths.is_empty() {
+        eprintln!("blocks: missing operand");
+        return Err(exit_status(1));
+    }
+    let fs = options.mount_ext2()?;
+    for path in paths.iter() {
+        if let Err(err) = print_blocks(&fs, &options.get_path(path)) {
+            eprintln!("blocks: {}: {}", path, err);
+            return Err(exit_status(1));
+        }
+    }
+    Ok(())
+}
@@ -1,36 +1,34 @@
-use crate::cmds::Options;
+use crate::cmds::{exit_status, Options};
 use crate::file::FsFile;
-use crate::fs::{mount, Filesystem};
+use crate::fs::Filesystem;
 use argparse::{ArgumentParser, List};
-use std::io::{self, Error, Read, Write};
+use std::io::{self, BufRead, Error, Write};
 
-fn parse_args(args: Vec<String>, paths: &mut Vec<String>) {
+fn parse_args(args: Vec<String>, paths: &mut Vec<String>) -> Result<(), Error> {
     let mut parser = ArgumentParser::new();
     parser.set_description("Concatenate FILE(s) to standard output.");
     parser.refer(paths).add_argument("file", List, "FILE");
-    if let Err(x) = parser.parse(args, &mut io::stdout(), &mut io::stderr()) {
-        std::process::exit(x);
-    }
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
 }
 
-const BUFFER_SIZE: usize = 1024;
-
 pub fn print_file(f: &mut FsFile) -> Result<(), Error> {
-    // Print file content on the standard output
-    let mut buffer: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+    // Print file content on the standard output, without copying it if the disk is mapped
+    let mut stdout = io::stdout().lock();
     loop {
-        let len = f.read(&mut buffer)?;
-        if len == 0 {
+        let buffer = f.fill_buf()?;
+        if buffer.is_empty() {
             break;
         }
-        io::stdout()
-            .write(&buffer)
-            .expect("Unable to write on stdout");
+        let len = buffer.len();
+        stdout.write_all(buffer).expect("Unable to write on stdout");
+        f.consume(len);
     }
     Ok(())
 }
 
-pub fn cat_file(path: &String, fs: &Box<dyn Filesystem>) -> Result<(), Error> {
+pub fn cat_file(path: &String, fs: &dyn Filesystem) -> Result<(), Error> {
     // Open a file and print the content on the standard output
     match fs.open(path) {
         Ok(mut f) => {
@@ -38,7 +36,7 @@ pub fn cat_file(path: &String, fs: &Box<dyn Filesystem>) -> Result<(), Error> {
         }
         Err(x) => {
             eprintln!("cat: {}: {}", path, x);
-            std::process::exit(1);
+            return Err(exit_status(1));
         }
     }
     Ok(())
@@ -47,10 +45,10 @@ pub fn cat_file(path: &String, fs: &Box<dyn Filesystem>) -> Result<(), Error> {
 pub fn cat(options: &Options, args: Vec<String>) -> Result<(), Error> {
     // Parse command argument
     let mut paths: Vec<String> = vec![];
-    parse_args(args, &mut paths);
-    let fs = mount(&options.filename)?;
+    parse_args(args, &mut paths)?;
+    let fs = options.mount()?;
     for path in paths.iter() {
-        cat_file(path, &fs)?;
+        cat_file(&options.get_path(path), fs.as_ref())?;
     }
     Ok(())
 }
@@ -1,21 +1,38 @@
-use crate::cmds::Options;
-use crate::fs::mount;
-use argparse::ArgumentParser;
+use crate::cmds::{exit_status, Options};
+use argparse::{ArgumentParser, StoreTrue};
 use humansize::{file_size_opts as options, FileSize};
 use std::io::{self, Error};
 
-fn parse_args(args: Vec<String>) {
+fn parse_args(args: Vec<String>, inodes_flg: &mut bool) -> Result<(), Error> {
     // Parse command argument
     let mut parser = ArgumentParser::new();
     parser.set_description("Show information about the file system.");
-    if let Err(x) = parser.parse(args, &mut io::stdout(), &mut io::stderr()) {
-        std::process::exit(x);
-    }
+    parser.refer(inodes_flg).add_option(
+        &["-i", "--inodes"],
+        StoreTrue,
+        "list inode information instead of block usage",
+    );
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
 }
 
 pub fn df(options: &Options, args: Vec<String>) -> Result<(), Error> {
-    let fs = mount(&options.filename)?;
-    parse_args(args);
+    let fs = options.mount()?;
+    let mut inodes_flg = false;
+    parse_args(args, &mut inodes_flg)?;
+    if inodes_flg {
+        let inodes = fs.get_inodes_count();
+        let ifree = fs.get_free_inodes_count()?;
+        let iused = inodes - ifree;
+        let iuse_percent = 100.0 * iused as f32 / inodes as f32;
+        println!("Filesystem                      Inodes        IUsed        IFree    IUse%");
+        println!(
+            "{:30} {:12} {:12} {:12} {:.0}%",
+            options.filename, inodes, iused, ifree, iuse_percent,
+        );
+        return Ok(());
+    }
     let size = fs.get_blocks_count() * fs.get_block_size();
     let avail = fs.get_free_blocks_count() * fs.get_block_size();
     let used = size - avail;
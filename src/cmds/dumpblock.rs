@@ -0,0 +1,31 @@
+use crate::cmds::hd::print_hex;
+use crate::cmds::{exit_status, Options};
+use crate::fs::Filesystem;
+use argparse::{ArgumentParser, Store};
+use std::io::{self, Error};
+
+fn parse_args(args: Vec<String>, block_num: &mut u64, count: &mut u64) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("Display filesystem blocks in hexadecimal.");
+    parser
+        .refer(block_num)
+        .required()
+        .add_argument("block", Store, "BLOCK");
+    parser
+        .refer(count)
+        .add_option(&["-c", "--count"], Store, "number of blocks to display");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+pub fn dumpblock(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut block_num: u64 = 0;
+    let mut count: u64 = 1;
+    parse_args(args, &mut block_num, &mut count)?;
+    let fs = options.mount_ext2()?;
+    let buffer = fs.read_blocks(block_num, count)?;
+    print_hex(&buffer, block_num * fs.get_block_size());
+    Ok(())
+}
@@ -0,0 +1,369 @@
+use crate::cmds::{exit_status, Options};
+use crate::ext2::group::{EXT2_BG_BLOCK_UNINIT, EXT2_BG_INODE_UNINIT, EXT2_BG_INODE_ZEROED};
+use crate::ext2::superblock::Ext2SuperBlock;
+use crate::ext2::Ext2Filesystem;
+use argparse::{ArgumentParser, StoreTrue};
+use chrono::prelude::*;
+use std::io::{self, Error};
+
+const FMT_CTIME: &str = "%a %b %e %H:%M:%S %Y";
+
+fn format_time(time: i64) -> String {
+    // Format timestamp, 0 means never
+    if time == 0 {
+        return String::from("n/a");
+    }
+    let naive = NaiveDateTime::from_timestamp(time, 0);
+    let datetime: DateTime<Utc> = DateTime::from_utc(naive, Utc);
+    datetime.format(FMT_CTIME).to_string()
+}
+
+fn format_uuid(uuid: &[u8]) -> String {
+    if uuid.iter().all(|x| *x == 0) {
+        return String::from("<none>");
+    }
+    let hex: Vec<String> = uuid.iter().map(|x| format!("{:02x}", x)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex[0..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..16].concat()
+    )
+}
+
+fn format_string(buffer: &[u8], default: &str) -> String {
+    // Zero terminated string
+    let len = buffer.iter().position(|x| *x == 0).unwrap_or(buffer.len());
+    if len == 0 {
+        String::from(default)
+    } else {
+        String::from_utf8_lossy(&buffer[..len]).into_owned()
+    }
+}
+
+fn format_names(names: Vec<String>) -> String {
+    if names.is_empty() {
+        String::from("(none)")
+    } else {
+        names.join(" ")
+    }
+}
+
+fn format_ranges(values: impl Iterator<Item = u64>) -> String {
+    // Collapse the consecutive values: 1-3, 5, 7-9
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for value in values {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == value => *last = value,
+            _ => ranges.push((value, value)),
+        }
+    }
+    let ranges: Vec<String> = ranges
+        .iter()
+        .map(|(first, last)| {
+            if first == last {
+                format!("{}", first)
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect();
+    ranges.join(", ")
+}
+
+fn parse_args(args: Vec<String>, header_flg: &mut bool) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("Dump the superblock and the block group descriptors.");
+    parser.refer(header_flg).add_option(
+        &["-H", "--header"],
+        StoreTrue,
+        "only display the superblock information",
+    );
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+fn print_super_block(sb: &Ext2SuperBlock) {
+    let time = |lo: u32, hi: u8| lo as i64 | (hi as i64) << 32;
+    let block_size = sb.get_block_size();
+    let inode_blocks = (sb.s_inodes_per_group as u64 * sb.s_inode_size as u64).div_ceil(block_size);
+    println!(
+        "Filesystem volume name:   {}",
+        format_string(&sb.s_volume_name, "<none>")
+    );
+    println!(
+        "Last mounted on:          {}",
+        format_string(&sb.s_last_mounted, "<not available>")
+    );
+    println!("Filesystem UUID:          {}", format_uuid(&sb.s_uuid));
+    println!("Filesystem magic number:  {:#06X}", sb.s_magic);
+    println!("Filesystem revision #:    {}", sb.get_revision_name());
+    println!(
+        "Filesystem features:      {}",
+        format_names(sb.get_feature_names())
+    );
+    println!(
+        "Filesystem flags:         {}",
+        format_names(sb.get_flag_names())
+    );
+    println!(
+        "Default mount options:    {}",
+        format_names(sb.get_default_mount_opts_names())
+    );
+    println!("Filesystem state:         {}", sb.get_state_name());
+    println!("Errors behavior:          {}", sb.get_errors_name());
+    println!("Filesystem OS type:       {}", sb.get_creator_os_name());
+    println!("Inode count:              {}", sb.s_inodes_count);
+    println!("Block count:              {}", sb.get_blocks_count());
+    println!("Reserved block count:     {}", sb.get_r_blocks_count());
+    if sb.s_overhead_blocks != 0 {
+        println!("Overhead clusters:        {}", sb.s_overhead_blocks);
+    }
+    println!("Free blocks:              {}", sb.get_free_blocks_count());
+    println!("Free inodes:              {}", sb.s_free_inodes_count);
+    println!("First block:              {}", sb.s_first_data_block);
+    println!("Block size:               {}", block_size);
+    println!("Fragment size:            {}", 1024 << sb.s_log_frag_size);
+    if sb.get_desc_size() != 32 {
+        println!("Group descriptor size:    {}", sb.get_desc_size());
+    }
+    if sb.s_reserved_gdt_blocks != 0 {
+        println!("Reserved GDT blocks:      {}", sb.s_reserved_gdt_blocks);
+    }
+    println!("Blocks per group:         {}", sb.s_blocks_per_group);
+    println!("Fragments per group:      {}", sb.s_frags_per_group);
+    println!("Inodes per group:         {}", sb.s_inodes_per_group);
+    println!("Inode blocks per group:   {}", inode_blocks);
+    if sb.s_log_groups_per_flex != 0 {
+        println!(
+            "Flex block group size:    {}",
+            1u64 << sb.s_log_groups_per_flex
+        );
+    }
+    println!(
+        "Filesystem created:       {}",
+        format_time(time(sb.s_mkfs_time, sb.s_mkfs_time_hi))
+    );
+    println!(
+        "Last mount time:          {}",
+        format_time(time(sb.s_mtime, sb.s_mtime_hi))
+    );
+    println!(
+        "Last write time:          {}",
+        format_time(time(sb.s_wtime, sb.s_wtime_hi))
+    );
+    println!("Mount count:              {}", sb.s_mnt_count);
+    println!("Maximum mount count:      {}", sb.s_max_mnt_count as i16);
+    println!(
+        "Last checked:             {}",
+        format_time(time(sb.s_lastcheck, sb.s_lastcheck_hi))
+    );
+    println!("Check interval:           {}", sb.s_checkinterval);
+    if sb.s_kbytes_written != 0 {
+        println!("Lifetime writes:          {} kB", sb.s_kbytes_written);
+    }
+    println!("Reserved blocks uid:      {}", sb.s_def_resuid);
+    println!("Reserved blocks gid:      {}", sb.s_def_regid);
+    if sb.s_rev_level >= 1 {
+        println!("First inode:              {}", sb.s_first_ino);
+        println!("Inode size:               {}", sb.s_inode_size);
+        if sb.s_min_extra_isize != 0 {
+            println!("Required extra isize:     {}", sb.s_min_extra_isize);
+        }
+        if sb.s_want_extra_isize != 0 {
+            println!("Desired extra isize:      {}", sb.s_want_extra_isize);
+        }
+    }
+    if sb.s_journal_uuid.iter().any(|x| *x != 0) {
+        println!(
+            "Journal UUID:             {}",
+            format_uuid(&sb.s_journal_uuid)
+        );
+    }
+    if sb.s_journal_inum != 0 {
+        println!("Journal inode:            {}", sb.s_journal_inum);
+    }
+    if sb.s_journal_dev != 0 {
+        println!("Journal device:           {:#06x}", sb.s_journal_dev);
+    }
+    if sb.s_last_orphan != 0 {
+        println!("First orphan inode:       {}", sb.s_last_orphan);
+    }
+    if sb.s_hash_seed.iter().any(|x| *x != 0) {
+        let seed: Vec<u8> = sb
+            .s_hash_seed
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        println!("Default directory hash:   {}", sb.get_hash_version_name());
+        println!("Directory Hash Seed:      {}", format_uuid(&seed));
+    }
+    if sb.s_jnl_backup_type == 1 {
+        println!("Journal backup:           inode blocks");
+    }
+    if sb.has_metadata_csum() {
+        println!(
+            "Checksum type:            {}",
+            if sb.s_checksum_type == 1 {
+                "crc32c"
+            } else {
+                "unknown"
+            }
+        );
+        println!("Checksum:                 {:#010x}", sb.s_checksum);
+        println!("Checksum seed:            {:#010x}", sb.get_checksum_seed());
+    }
+}
+
+fn print_groups(fs: &Ext2Filesystem) -> Result<(), Error> {
+    let sb = fs.get_super_block();
+    let block_groups = fs.get_block_groups();
+    let csum = sb.has_metadata_csum() || sb.has_gdt_csum();
+    let gdt_blocks = sb.get_gdt_blocks_count();
+    let inode_blocks =
+        (sb.s_inodes_per_group as u64 * sb.s_inode_size as u64).div_ceil(sb.get_block_size());
+    // Location of a metadata block, relative to the group (or to the group containing it)
+    let location = |group_num: usize, block_num: u64| {
+        let first = sb.get_group_first_block(group_num);
+        if block_num >= first && block_num < first + sb.get_group_blocks_count(group_num) {
+            format!("+{}", block_num - first)
+        } else {
+            let other = ((block_num - sb.s_first_data_block as u64) / sb.s_blocks_per_group as u64)
+                as usize;
+            format!(
+                "bg #{} + {}",
+                other,
+                block_num - sb.get_group_first_block(other)
+            )
+        }
+    };
+    for group in block_groups.iter() {
+        let group_num = group.group_num;
+        let desc = &group.ext2_group_desc;
+        let first = sb.get_group_first_block(group_num);
+        let last = first + sb.get_group_blocks_count(group_num) - 1;
+        let mut header = format!("Group {}: (Blocks {}-{})", group_num, first, last);
+        if csum {
+            header.push_str(&format!(" csum {:#06x}", desc.bg_checksum));
+            let mut flags: Vec<&str> = Vec::new();
+            if desc.bg_flags & EXT2_BG_INODE_UNINIT != 0 {
+                flags.push("INODE_UNINIT");
+            }
+            if desc.bg_flags & EXT2_BG_BLOCK_UNINIT != 0 {
+                flags.push("BLOCK_UNINIT");
+            }
+            if desc.bg_flags & EXT2_BG_INODE_ZEROED != 0 {
+                flags.push("ITABLE_ZEROED");
+            }
+            if !flags.is_empty() {
+                header.push_str(&format!(" [{}]", flags.join(", ")));
+            }
+        }
+        println!("{}", header);
+        if sb.group_has_super(group_num) {
+            println!(
+                "  {} superblock at {}, Group descriptors at {}-{}",
+                if group_num == 0 { "Primary" } else { "Backup" },
+                first,
+                first + 1,
+                first + gdt_blocks
+            );
+            if sb.s_reserved_gdt_blocks != 0 {
+                println!(
+                    "  Reserved GDT blocks at {}-{}",
+                    first + gdt_blocks + 1,
+                    first + gdt_blocks + sb.s_reserved_gdt_blocks as u64
+                );
+            }
+        }
+        let bitmap_csum = |lo: u16, hi: u16| {
+            if !sb.has_metadata_csum() {
+                String::new()
+            } else if sb.get_desc_size() >= 64 {
+                format!(", csum {:#010x}", lo as u32 | (hi as u32) << 16)
+            } else {
+                format!(", csum {:#06x}", lo)
+            }
+        };
+        println!(
+            "  Block bitmap at {} ({}){}",
+            desc.get_block_bitmap(),
+            location(group_num, desc.get_block_bitmap()),
+            bitmap_csum(desc.bg_block_bitmap_csum_lo, desc.bg_block_bitmap_csum_hi)
+        );
+        println!(
+            "  Inode bitmap at {} ({}){}",
+            desc.get_inode_bitmap(),
+            location(group_num, desc.get_inode_bitmap()),
+            bitmap_csum(desc.bg_inode_bitmap_csum_lo, desc.bg_inode_bitmap_csum_hi)
+        );
+        println!(
+            "  Inode table at {}-{} ({})",
+            desc.get_inode_table(),
+            desc.get_inode_table() + inode_blocks - 1,
+            location(group_num, desc.get_inode_table())
+        );
+        let mut counts = format!(
+            "  {} free blocks, {} free inodes, {} directories",
+            desc.get_free_blocks_count(),
+            desc.get_free_inodes_count(),
+            desc.get_used_dirs_count()
+        );
+        if csum {
+            counts.push_str(&format!(", {} unused inodes", desc.get_itable_unused()));
+        }
+        println!("{}", counts);
+        let block_bitmap = fs.get_block_bitmap(group_num)?;
+        println!("  Free blocks: {}", format_ranges(block_bitmap.iter_free()));
+        let inode_bitmap = fs.get_inode_bitmap(group_num)?;
+        println!("  Free inodes: {}", format_ranges(inode_bitmap.iter_free()));
+    }
+    Ok(())
+}
+
+pub fn dumpe2fs(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut header_flg = false;
+    parse_args(args, &mut header_flg)?;
+    let fs = options.mount_ext2()?;
+    print_super_block(fs.get_super_block());
+    if !header_flg {
+        println!();
+        println!();
+        print_groups(&fs)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_time(0), "n/a");
+        assert_eq!(format_time(1700000000), "Tue Nov 14 22:13:20 2023");
+        let uuid = [
+            0x11, 0x11, 0x11, 0x11, 0x22, 0x22, 0x33, 0x33, 0x44, 0x44, 0x55, 0x55, 0x55, 0x55,
+            0x55, 0x55,
+        ];
+        assert_eq!(format_uuid(&uuid), "11111111-2222-3333-4444-555555555555");
+        assert_eq!(format_uuid(&[0; 16]), "<none>");
+        assert_eq!(format_string(b"ext2test\0\0", "<none>"), "ext2test");
+        assert_eq!(format_string(b"no zero", "<none>"), "no zero");
+        assert_eq!(format_string(&[0; 16], "<none>"), "<none>");
+        assert_eq!(format_names(vec![]), "(none)");
+        assert_eq!(format_names(vec!["a".into(), "b".into()]), "a b");
+    }
+
+    #[test]
+    fn test_format_ranges() {
+        let values = [1, 2, 3, 5, 7, 8, 9];
+        assert_eq!(format_ranges(values.into_iter()), "1-3, 5, 7-9");
+        assert_eq!(format_ranges([42].into_iter()), "42");
+        assert_eq!(format_ranges(std::iter::empty()), "");
+    }
+}
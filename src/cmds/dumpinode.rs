@@ -0,0 +1,90 @@
+use crate::cmds::{exit_status, Options};
+use crate::ext2::inode::Ext2InodeStruct;
+use argparse::{ArgumentParser, Store};
+use chrono::prelude::*;
+use std::io::{self, Error};
+
+const FMT_LONG: &str = "%Y-%m-%d %H:%M:%S";
+
+fn format_time(time: u32) -> String {
+    // Format timestamp, 0 means never
+    if time == 0 {
+        return String::from("0");
+    }
+    let naive = NaiveDateTime::from_timestamp(time as i64, 0);
+    let datetime: DateTime<Utc> = DateTime::from_utc(naive, Utc);
+    format!("{} ({})", time, datetime.format(FMT_LONG))
+}
+
+fn parse_args(args: Vec<String>, inode_num: &mut u64) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("Display all the fields of an inode.");
+    parser
+        .refer(inode_num)
+        .required()
+        .add_argument("inode", Store, "INODE");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+fn print_inode(inode: &Ext2InodeStruct, inode_size: u64) {
+    let flags = inode.get_flag_names();
+    println!(
+        "i_mode:          {:06o} ({})",
+        inode.i_mode,
+        unix_mode::to_string(inode.i_mode as u32)
+    );
+    println!("i_uid:           {}", inode.i_uid);
+    println!("i_size:          {}", inode.i_size);
+    println!("i_atime:         {}", format_time(inode.i_atime));
+    println!("i_ctime:         {}", format_time(inode.i_ctime));
+    println!("i_mtime:         {}", format_time(inode.i_mtime));
+    println!("i_dtime:         {}", format_time(inode.i_dtime));
+    println!("i_gid:           {}", inode.i_gid);
+    println!("i_links_count:   {}", inode.i_links_count);
+    println!("i_blocks:        {}", inode.i_blocks);
+    println!(
+        "i_flags:         {:#010x} ({})",
+        inode.i_flags,
+        flags.join(" ")
+    );
+    println!("l_i_reserved1:   {}", inode.l_i_reserved1);
+    for (i, block) in inode.i_block.iter().enumerate() {
+        println!("i_block[{:2}]:     {}", i, block);
+    }
+    println!("i_generation:    {}", inode.i_generation);
+    println!("i_file_acl:      {}", inode.i_file_acl);
+    println!("i_size_high:     {}", inode.i_size_high);
+    println!("i_faddr:         {}", inode.i_faddr);
+    println!("l_i_frag:        {}", inode.l_i_frag);
+    println!("l_i_fsize:       {}", inode.l_i_fsize);
+    println!("i_pad1:          {}", inode.i_pad1);
+    println!("l_i_uid_high:    {}", inode.l_i_uid_high);
+    println!("l_i_gid_high:    {}", inode.l_i_gid_high);
+    println!("l_i_checksum_lo: {:#06x}", inode.l_i_checksum_lo);
+    println!("l_i_reserved:    {}", inode.l_i_reserved);
+    if inode_size > 128 {
+        // Large inode fields
+        println!("i_extra_isize:   {}", inode.i_extra_isize);
+        println!("i_checksum_hi:   {:#06x}", inode.i_checksum_hi);
+        println!("i_ctime_extra:   {}", inode.i_ctime_extra);
+        println!("i_mtime_extra:   {}", inode.i_mtime_extra);
+        println!("i_atime_extra:   {}", inode.i_atime_extra);
+        println!("i_crtime:        {}", format_time(inode.i_crtime));
+        println!("i_crtime_extra:  {}", inode.i_crtime_extra);
+        println!("i_version_hi:    {}", inode.i_version_hi);
+        println!("i_projid:        {}", inode.i_projid);
+    }
+}
+
+pub fn dumpinode(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut inode_num: u64 = 0;
+    parse_args(args, &mut inode_num)?;
+    let fs = options.mount_ext2()?;
+    let inode = fs.read_inode_struct(inode_num)?;
+    println!("Inode: {}", inode_num);
+    print_inode(&inode, fs.get_super_block().s_inode_size as u64);
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use crate::cmds::{exit_status, Options};
+use crate::ext2::Ext2Filesystem;
+use crate::fs::Filesystem;
+use argparse::{ArgumentParser, List, StoreTrue};
+use std::io::{self, Error};
+
+fn parse_args(
+    args: Vec<String>,
+    paths: &mut Vec<String>,
+    verbose_flg: &mut bool,
+) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("Report the fragmentation of the FILE(s).");
+    parser.refer(paths).add_argument("file", List, "FILE");
+    parser.refer(verbose_flg).add_option(
+        &["-v", "--verbose"],
+        StoreTrue,
+        "print the extents of each file",
+    );
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+fn print_filefrag(fs: &Ext2Filesystem, path: &str, verbose_flg: bool) -> Result<(), Error> {
+    let metadata = fs.metadata(path)?;
+    let block_size = fs.get_block_size();
+    let file_blocks = metadata.size.div_ceil(block_size);
+    let extents = fs.get_file_extents(path)?;
+    if verbose_flg {
+        println!(
+            "File size of {} is {} ({} of {} bytes)",
+            path,
+            metadata.size,
+            plural(file_blocks as usize, "block"),
+            block_size
+        );
+        println!(" ext:     logical_offset:        physical_offset: length:   expected:");
+    }
+    let mut fragments = 0; // Extents not following the previous one on the disk
+    let mut holes = 0; // Unmapped ranges of the file
+    let mut logical_end = 0;
+    let mut expected: Option<u64> = None;
+    for (i, extent) in extents.iter().enumerate() {
+        let fragment = expected.filter(|x| *x != extent.physical);
+        if fragment.is_some() {
+            fragments += 1;
+        }
+        if extent.logical > logical_end {
+            holes += 1;
+        }
+        if verbose_flg {
+            let line = format!(
+                "{:4}: {:>8}..{:>8}: {:>10}..{:>10}: {:>6}: {:>10}",
+                i,
+                extent.logical,
+                extent.get_logical_end() - 1,
+                extent.physical,
+                extent.get_physical_end() - 1,
+                extent.len,
+                fragment.map_or(String::new(), |x| x.to_string())
+            );
+            println!("{}", line.trim_end());
+        }
+        logical_end = extent.get_logical_end();
+        expected = Some(extent.get_physical_end());
+    }
+    if file_blocks > logical_end {
+        holes += 1;
+    }
+    println!(
+        "{}: {} found, {}, {}",
+        path,
+        plural(extents.len(), "extent"),
+        plural(fragments, "fragment"),
+        plural(holes, "hole")
+    );
+    Ok(())
+}
+
+pub fn filefrag(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut paths: Vec<String> = vec![];
+    let mut verbose_flg = false;
+    parse_args(args, &mut paths, &mut verbose_flg)?;
+    if paths.is_empty() {
+        eprintln!("filefrag: missing operand");
+        return Err(exit_status(1));
+    }
+    let fs = options.mount_ext2()?;
+    for path in paths.iter() {
+        if let Err(err) = print_filefrag(&fs, &options.get_path(path), verbose_flg) {
+            eprintln!("filefrag: {}: {}", path, err);
+            return Err(exit_status(1));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plural() {
+        assert_eq!(plural(0, "extent"), "0 extents");
+        assert_eq!(plural(1, "extent"), "1 extent");
+        assert_eq!(plural(3, "extent"), "3 extents");
+    }
+}
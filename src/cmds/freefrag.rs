@@ -0,0 +1,79 @@
+use crate::cmds::{exit_status, Options};
+use crate::fs::Filesystem;
+use argparse::ArgumentParser;
+use humansize::{file_size_opts as options, FileSize};
+use std::io::{self, Error};
+
+fn parse_args(args: Vec<String>) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("Report the free space fragmentation.");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+fn format_size(size: u64) -> String {
+    size.file_size(options::BINARY).unwrap()
+}
+
+pub fn freefrag(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    parse_args(args)?;
+    let fs = options.mount_ext2()?;
+    let block_size = fs.get_block_size();
+    let blocks_count = fs.get_blocks_count();
+    let extents = fs.get_free_extents()?;
+    let free_blocks: u64 = extents.iter().map(|(_, len)| len).sum();
+    println!("Device: {}", options.filename);
+    println!("Blocksize: {} bytes", block_size);
+    println!("Total blocks: {}", blocks_count);
+    println!(
+        "Free blocks: {} ({:.1}%)",
+        free_blocks,
+        100.0 * free_blocks as f64 / blocks_count as f64
+    );
+    if extents.is_empty() {
+        return Ok(());
+    }
+    let min = extents.iter().map(|(_, len)| *len).min().unwrap_or(0);
+    let max = extents.iter().map(|(_, len)| *len).max().unwrap_or(0);
+    println!();
+    println!("Min. free extent: {}", format_size(min * block_size));
+    println!("Max. free extent: {}", format_size(max * block_size));
+    println!(
+        "Avg. free extent: {}",
+        format_size(free_blocks * block_size / extents.len() as u64)
+    );
+    println!("Num. free extent: {}", extents.len());
+    // Histogram of the extent sizes, by powers of 2
+    let mut histogram: Vec<(u64, u64)> = vec![(0, 0); 64];
+    for (_, len) in extents.iter() {
+        let i = (63 - len.leading_zeros()) as usize;
+        histogram[i].0 += 1;
+        histogram[i].1 += len;
+    }
+    println!();
+    println!("HISTOGRAM OF FREE EXTENT SIZES:");
+    println!(
+        "{:>21} : {:>12} {:>12} {:>8}",
+        "Extent Size Range", "Free extents", "Free Blocks", "Percent"
+    );
+    for (i, (count, blocks)) in histogram.iter().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+        let range = format!(
+            "{}...{}-",
+            format_size((1 << i) * block_size),
+            format_size((1 << (i + 1)) * block_size)
+        );
+        println!(
+            "{:>21} : {:>12} {:>12} {:>7.2}%",
+            range,
+            count,
+            blocks,
+            100.0 * *blocks as f64 / free_blocks as f64
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use crate::cmds::{exit_status, Options};
+use crate::ext2::checksum::ChecksumMode;
+use crate::ext2::fsck::{Ext2Checker, Severity};
+use crate::ext2::Ext2Filesystem;
+use argparse::{ArgumentParser, StoreTrue};
+use std::io::{self, Error};
+
+// Same exit codes as e2fsck
+const FSCK_ERRORS_CORRECTED: i32 = 1;
+const FSCK_ERRORS_UNCORRECTED: i32 = 4;
+
+struct FsckFlags {
+    verbose: bool,
+    repair: bool,
+}
+
+fn parse_args(args: Vec<String>, flags: &mut FsckFlags) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("Check the file system consistency.");
+    parser
+        .refer(&mut flags.verbose)
+        .add_option(&["-v", "--verbose"], StoreTrue, "Show the passes");
+    parser.refer(&mut flags.repair).add_option(
+        &["--repair"],
+        StoreTrue,
+        "Repair the file system (the device is opened for writing)",
+    );
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+pub fn fsck(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut flags = FsckFlags {
+        verbose: false,
+        repair: false,
+    };
+    parse_args(args, &mut flags)?;
+    // Checksum mismatches are reported by the checker
+    let mut mount_options = options.mount_options.clone();
+    mount_options.checksum_mode = ChecksumMode::Ignore;
+    mount_options.read_write = flags.repair;
+    let fs = Ext2Filesystem::mount(&options.filename, &mount_options)?;
+    let mut checker = Ext2Checker::new(&fs);
+    checker.repair = flags.repair;
+    let passes = [
+        "Checking superblock and group descriptors",
+        "Pass 1: Checking inodes, blocks, and sizes",
+        "Pass 2: Checking directory structure",
+        "Pass 3: Checking directory connectivity",
+        "Pass 4: Checking reference counts",
+        "Pass 5: Checking group summary information",
+    ];
+    for (pass, title) in passes.iter().enumerate() {
+        if flags.verbose {
+            println!("{}", title);
+        }
+        let first = checker.diagnostics.len();
+        let first_change = checker.changes.len();
+        checker.check_pass(pass as u8)?;
+        for diagnostic in &checker.diagnostics[first..] {
+            println!("{}", diagnostic);
+        }
+        for change in &checker.changes[first_change..] {
+            println!("{}", change);
+        }
+    }
+    let used_inodes = checker.get_used_inodes_count();
+    let used_blocks = checker.get_used_blocks_count();
+    println!(
+        "{}: {}/{} files, {}/{} blocks",
+        options.filename,
+        used_inodes,
+        checker.get_inodes_count(),
+        used_blocks,
+        checker.get_blocks_count(),
+    );
+    let fixed = checker.diagnostics.iter().filter(|d| d.fixed).count();
+    let uncorrected = checker
+        .diagnostics
+        .iter()
+        .filter(|d| d.severity() == Severity::Error && !d.fixed)
+        .count();
+    if uncorrected > 0 {
+        eprintln!("{}: {} errors found", options.filename, uncorrected);
+        return Err(exit_status(FSCK_ERRORS_UNCORRECTED));
+    } else if fixed > 0 {
+        eprintln!("{}: {} problems fixed", options.filename, fixed);
+        return Err(exit_status(FSCK_ERRORS_CORRECTED));
+    }
+    Ok(())
+}
@@ -1,16 +1,16 @@
-use crate::cmds::Options;
+use crate::cmds::{exit_status, Options};
 use crate::file::FsFile;
-use crate::fs::{mount, Filesystem};
+use crate::fs::Filesystem;
 use argparse::{ArgumentParser, List};
 use std::io::{self, Error, Read};
 
-fn parse_args(args: Vec<String>, paths: &mut Vec<String>) {
+fn parse_args(args: Vec<String>, paths: &mut Vec<String>) -> Result<(), Error> {
     let mut parser = ArgumentParser::new();
     parser.set_description("Concatenate FILE(s) to standard output.");
     parser.refer(paths).add_argument("file", List, "FILE");
-    if let Err(x) = parser.parse(args, &mut io::stdout(), &mut io::stderr()) {
-        std::process::exit(x);
-    }
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
 }
 
 const BUFFER_SIZE: usize = 1024;
@@ -23,32 +23,38 @@ pub fn get_char(ch: u8) -> char {
     }
 }
 
+pub fn print_hex(buffer: &[u8], address: u64) {
+    // Print a buffer in hexadecimal, the first byte being at the given address
+    for (b, t) in buffer.chunks(16).enumerate() {
+        print!("{:08x} ", address + b as u64 * 16);
+        for ch in t {
+            print!(" {:02x}", ch);
+        }
+        print!("{}", "   ".repeat(16 - t.len()));
+        print!("  |");
+        for ch in t {
+            print!("{}", get_char(*ch));
+        }
+        println!("|");
+    }
+}
+
 pub fn print_file(f: &mut FsFile) -> Result<(), Error> {
     // Print file content on the standard output
     let mut buffer: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+    let mut address: u64 = 0;
     loop {
         let len = f.read(&mut buffer)?;
         if len == 0 {
             break;
         }
-        for b in 0..(len + 15) / 16 {
-            let addr = b * 16;
-            let t = &buffer[addr..addr + 16];
-            print!("{:08x} ", addr);
-            for ch in t {
-                print!(" {:02x}", ch);
-            }
-            print!("  |");
-            for ch in t {
-                print!("{}", get_char(*ch));
-            }
-            println!("|");
-        }
+        print_hex(&buffer[..len], address);
+        address += len as u64;
     }
     Ok(())
 }
 
-pub fn show_file(path: &String, fs: &Box<dyn Filesystem>) -> Result<(), Error> {
+pub fn show_file(path: &String, fs: &dyn Filesystem) -> Result<(), Error> {
     // Open a file and print the content on the standard output
     match fs.open(path) {
         Ok(mut f) => {
@@ -56,7 +62,7 @@ pub fn show_file(path: &String, fs: &Box<dyn Filesystem>) -> Result<(), Error> {
         }
         Err(x) => {
             eprintln!("hd: {}: {}", path, x);
-            std::process::exit(1);
+            return Err(exit_status(1));
         }
     }
     Ok(())
@@ -65,10 +71,10 @@ pub fn show_file(path: &String, fs: &Box<dyn Filesystem>) -> Result<(), Error> {
 pub fn hd(options: &Options, args: Vec<String>) -> Result<(), Error> {
     // Parse command argument
     let mut paths: Vec<String> = vec![];
-    parse_args(args, &mut paths);
-    let fs = mount(&options.filename)?;
+    parse_args(args, &mut paths)?;
+    let fs = options.mount()?;
     for path in paths.iter() {
-        show_file(path, &fs)?;
+        show_file(&options.get_path(path), fs.as_ref())?;
     }
     Ok(())
 }
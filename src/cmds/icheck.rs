@@ -0,0 +1,32 @@
+use crate::cmds::{exit_status, Options};
+use argparse::{ArgumentParser, List};
+use std::io::{self, Error};
+
+fn parse_args(args: Vec<String>, blocks: &mut Vec<u64>) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("Find the inodes owning the BLOCK(s).");
+    parser.refer(blocks).add_argument("block", List, "BLOCK");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+pub fn icheck(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut blocks: Vec<u64> = vec![];
+    parse_args(args, &mut blocks)?;
+    if blocks.is_empty() {
+        eprintln!("icheck: missing operand");
+        return Err(exit_status(1));
+    }
+    let fs = options.mount_ext2()?;
+    let owners = fs.find_block_owners(&blocks)?;
+    println!("Block\tInode number");
+    for (block_num, owner) in blocks.iter().zip(owners) {
+        match owner {
+            Some(inode_num) => println!("{}\t{}", block_num, inode_num),
+            None => println!("{}\t<block not found>", block_num),
+        }
+    }
+    Ok(())
+}
@@ -1,6 +1,6 @@
-use crate::cmds::Options;
+use crate::cmds::{exit_status, Options};
 use crate::dir::{DefaultDirEntry, DirEntry};
-use crate::fs::{mount, Filesystem};
+use crate::fs::Filesystem;
 use argparse::{ArgumentParser, List, StoreTrue};
 use chrono::prelude::*;
 use chrono::Duration;
@@ -25,6 +25,7 @@ struct LsFlags {
     long_flg: bool,
     inode_flg: bool,
     size_flg: bool,
+    deleted_flg: bool,
 }
 
 fn parse_args(
@@ -33,7 +34,8 @@ fn parse_args(
     long_flg: &mut bool,
     inode_flg: &mut bool,
     size_flg: &mut bool,
-) {
+    deleted_flg: &mut bool,
+) -> Result<(), Error> {
     // Parse command argument
     let mut parser = ArgumentParser::new();
     parser.set_description("List information about the FILEs.");
@@ -51,13 +53,18 @@ fn parse_args(
         StoreTrue,
         "print the allocated size of each file, in blocks",
     );
-    if let Err(x) = parser.parse(args, &mut io::stdout(), &mut io::stderr()) {
-        std::process::exit(x);
-    }
+    parser.refer(deleted_flg).add_option(
+        &["-d", "--deleted"],
+        StoreTrue,
+        "list the deleted entries found in the directories",
+    );
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
 }
 
 fn print_direntry(
-    fs: &mut Box<dyn Filesystem>,
+    fs: &dyn Filesystem,
     entry: &Box<dyn DirEntry>,
     flags: &LsFlags,
 ) -> Result<(), Error> {
@@ -92,15 +99,29 @@ fn print_direntry(
     Ok(())
 }
 
-fn print_dir(fs: &mut Box<dyn Filesystem>, path: &str, flags: &LsFlags) -> Result<(), Error> {
+fn print_dir(fs: &dyn Filesystem, path: &str, flags: &LsFlags) -> Result<(), Error> {
     let entries = fs.read_dir(path)?;
     for entry in entries.values() {
-        print_direntry(fs, &entry, flags)?
+        // An entry pointing to an invalid inode does not hide the other entries
+        if let Err(err) = print_direntry(fs, entry, flags) {
+            eprintln!("ls: {}: {}", entry.path(), err);
+        }
     }
     Ok(())
 }
 
-fn print_path(fs: &mut Box<dyn Filesystem>, path: &str, flags: &LsFlags) -> Result<(), Error> {
+fn print_deleted(fs: &dyn Filesystem, path: &str) -> Result<(), Error> {
+    // The inode of a deleted entry may have been reused by another file
+    for entry in fs.read_deleted_dir(path)? {
+        println!("{:7 } {}", entry.inode_num(), entry.file_name());
+    }
+    Ok(())
+}
+
+fn print_path(fs: &dyn Filesystem, path: &str, flags: &LsFlags) -> Result<(), Error> {
+    if flags.deleted_flg {
+        return print_deleted(fs, path);
+    }
     let metadata = fs.symlink_metadata(path)?;
     if metadata.is_dir() {
         print_dir(fs, path, flags)
@@ -115,36 +136,39 @@ fn print_path(fs: &mut Box<dyn Filesystem>, path: &str, flags: &LsFlags) -> Resu
 }
 
 pub fn ls(options: &Options, args: Vec<String>) -> Result<(), Error> {
-    let mut fs = mount(&options.filename)?;
+    let fs = options.mount()?;
     let mut paths: Vec<String> = vec![];
     let mut long_flg = false;
     let mut inode_flg = false;
     let mut size_flg = false;
+    let mut deleted_flg = false;
     parse_args(
         args,
         &mut paths,
         &mut long_flg,
         &mut inode_flg,
         &mut size_flg,
-    );
+        &mut deleted_flg,
+    )?;
     if paths.is_empty() {
-        paths = vec![String::from("/")];
+        paths = vec![String::from(".")];
     }
     let flags = LsFlags {
         long_flg,
         inode_flg,
         size_flg,
+        deleted_flg,
     };
     for path in paths.iter() {
-        match print_path(&mut fs, &path, &flags) {
+        match print_path(fs.as_ref(), &options.get_path(path), &flags) {
             Ok(_) => {}
             Err(err) => {
                 eprintln!("ls: {}: {}", path, err);
-                std::process::exit(1);
+                return Err(exit_status(1));
             }
         }
     }
-    if !long_flg {
+    if !long_flg && !deleted_flg {
         println!();
     }
     Ok(())
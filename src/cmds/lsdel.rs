@@ -0,0 +1,43 @@
+use crate::cmds::{exit_status, Options};
+use argparse::ArgumentParser;
+use chrono::prelude::*;
+use std::io::{self, Error};
+
+const FMT_LONG: &str = "%Y-%m-%d %H:%M:%S";
+
+fn format_time(time: i64) -> String {
+    // Format timestamp
+    let naive = NaiveDateTime::from_timestamp(time, 0);
+    let datetime: DateTime<Utc> = DateTime::from_utc(naive, Utc);
+    datetime.format(FMT_LONG).to_string()
+}
+
+fn parse_args(args: Vec<String>) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("List the deleted inodes.");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+pub fn lsdel(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    parse_args(args)?;
+    let fs = options.mount_ext2()?;
+    let deleted = fs.get_deleted_inodes()?;
+    println!(" Inode  Owner  Mode        Size      Blocks   Time deleted");
+    for inode in deleted.iter() {
+        println!(
+            "{:>6} {:>6} {:>6o} {:>11} {:>6}/{:>6} {}",
+            inode.inode_num,
+            inode.uid,
+            inode.mode,
+            inode.size,
+            inode.free_blocks_count,
+            inode.blocks_count,
+            format_time(inode.dtime),
+        );
+    }
+    println!("{} deleted inodes found.", deleted.len());
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use crate::cmds::{exit_status, Options};
+use argparse::{ArgumentParser, List};
+use std::io::{self, Error};
+
+fn parse_args(args: Vec<String>, inodes: &mut Vec<u64>) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("Find the pathnames of the INODE(s).");
+    parser.refer(inodes).add_argument("inode", List, "INODE");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+pub fn ncheck(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut inodes: Vec<u64> = vec![];
+    parse_args(args, &mut inodes)?;
+    if inodes.is_empty() {
+        eprintln!("ncheck: missing operand");
+        return Err(exit_status(1));
+    }
+    let fs = options.mount_ext2()?;
+    println!("Inode\tPathname");
+    for (inode_num, path) in fs.find_inode_paths(&inodes)? {
+        println!("{}\t{}", inode_num, path);
+    }
+    Ok(())
+}
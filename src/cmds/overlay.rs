@@ -0,0 +1,165 @@
+use crate::cmds::{exit_status, Options};
+use crate::disk::overlay::{Overlay, OVERLAY_CHUNK_SIZE};
+use crate::disk::Offset;
+use crate::fs::{open_image, split_partition};
+use argparse::{ArgumentParser, Store};
+use humansize::{file_size_opts as options, FileSize};
+use std::io::{self, Error, ErrorKind};
+
+fn parse_args(args: Vec<String>, action: &mut String) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description(
+        "Show the blocks changed in the overlay (list), forget them (discard) or write them to the image (commit).",
+    );
+    parser
+        .refer(action)
+        .add_argument("action", Store, "list (default), discard or commit");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+/// Print the ranges of changed chunks
+fn list(overlay: &Overlay) {
+    let chunks = overlay.get_chunks();
+    println!(
+        "Overlay {}: {} changed blocks of {} bytes",
+        overlay.get_name(),
+        chunks.len(),
+        OVERLAY_CHUNK_SIZE
+    );
+    if chunks.is_empty() {
+        return;
+    }
+    println!();
+    println!("{:>12} {:>14} {:>10}", "Block", "Offset", "Size");
+    let mut ranges: Vec<(u64, u64)> = Vec::new(); // First chunk and count
+    for chunk_num in chunks {
+        match ranges.last_mut() {
+            Some((first, count)) if *first + *count == chunk_num => *count += 1,
+            _ => ranges.push((chunk_num, 1)),
+        }
+    }
+    for (first, count) in ranges {
+        let block = if count == 1 {
+            format!("{}", first)
+        } else {
+            format!("{}-{}", first, first + count - 1)
+        };
+        println!(
+            "{:>12} {:>14} {:>10}",
+            block,
+            first * OVERLAY_CHUNK_SIZE,
+            (count * OVERLAY_CHUNK_SIZE)
+                .file_size(options::BINARY)
+                .unwrap()
+        );
+    }
+}
+
+/// Write the changed chunks to the image, then empty the overlay
+fn commit(overlay: &Overlay, filename: &str) -> Result<(), Error> {
+    let (device, _) = split_partition(filename);
+    let disk = open_image(device, true)?;
+    let size = disk.get_size()?;
+    if let Some(base_size) = overlay.get_base_size() {
+        if base_size != size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The changes were made on a disk of {} bytes, {} has {} bytes",
+                    base_size, device, size
+                ),
+            ));
+        }
+    }
+    let chunks = overlay.get_chunks();
+    for chunk_num in &chunks {
+        let data = overlay.read_chunk(*chunk_num)?.unwrap();
+        // The last chunk can go beyond the end of the disk
+        let start = chunk_num * OVERLAY_CHUNK_SIZE;
+        let len = OVERLAY_CHUNK_SIZE.min(size - start) as usize;
+        let offset = Offset::Block {
+            block_size: 1,
+            block_num: start,
+        };
+        disk.write(&data[..len], offset)?;
+    }
+    overlay.discard()?;
+    println!("{}: {} blocks written", device, chunks.len());
+    Ok(())
+}
+
+pub fn overlay(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut action = String::from("list");
+    parse_args(args, &mut action)?;
+    let Some(overlay) = &options.mount_options.overlay else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "No overlay (use --overlay FILE or --overlay-memory)",
+        ));
+    };
+    match action.as_str() {
+        "list" => list(overlay),
+        "discard" => {
+            let count = overlay.get_chunks().len();
+            overlay.discard()?;
+            println!("Overlay {}: {} blocks discarded", overlay.get_name(), count);
+        }
+        "commit" => commit(overlay, &options.filename)?,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid action: {} (list, discard or commit)", action),
+            ))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::overlay::OverlayDisk;
+    use crate::disk::{read_at, Disk};
+    use crate::test_util::{temp_path, MemoryDisk};
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_commit() {
+        // Write to the overlay, reopen it and commit the changes to the image
+        let image = temp_path("commit.img");
+        let sidecar = temp_path("commit.cow");
+        let _ = fs::remove_file(&sidecar);
+        fs::write(&image, vec![1; 10000]).unwrap();
+        let overlay = Arc::new(Overlay::open(&sidecar).unwrap());
+        let disk = OverlayDisk::new(open_image(&image, false).unwrap(), overlay).unwrap();
+        let offset = |x| Offset::Block {
+            block_size: 1,
+            block_num: x,
+        };
+        disk.write(&[2; 4], offset(4094)).unwrap();
+        disk.write(&[3; 2], offset(9998)).unwrap();
+        assert_eq!(read_at(&disk, 6, 4093).unwrap(), [1, 2, 2, 2, 2, 1]);
+        drop(disk);
+        assert_eq!(fs::read(&image).unwrap(), vec![1; 10000]);
+        let overlay = Overlay::open(&sidecar).unwrap();
+        commit(&overlay, &image).unwrap();
+        assert!(overlay.get_chunks().is_empty());
+        let data = fs::read(&image).unwrap();
+        fs::remove_file(&sidecar).unwrap();
+        // The image has another size
+        fs::write(&image, vec![1; 100]).unwrap();
+        let overlay = Arc::new(Overlay::new_memory());
+        let disk = OverlayDisk::new(Box::new(MemoryDisk::new(vec![1; 200])), overlay.clone());
+        disk.unwrap().write(&[2], offset(0)).unwrap();
+        let result = commit(&overlay, &image);
+        fs::remove_file(&image).unwrap();
+        assert_eq!(data.len(), 10000);
+        assert_eq!(data[4093..4099], [1, 2, 2, 2, 2, 1]);
+        assert_eq!(data[9997..], [1, 3, 3]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
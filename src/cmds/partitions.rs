@@ -0,0 +1,47 @@
+use crate::cmds::{exit_status, Options};
+use crate::disk::partition::PartitionTable;
+use crate::fs::{open_image, split_partition};
+use argparse::ArgumentParser;
+use humansize::{file_size_opts as options, FileSize};
+use std::io::{self, Error};
+
+fn parse_args(args: Vec<String>) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("List the partitions of the disk.");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+pub fn partitions(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    parse_args(args)?;
+    let (device, _) = split_partition(&options.filename);
+    let disk = open_image(device, false)?;
+    let table = PartitionTable::read(disk.as_ref())?;
+    let sector_size = table.sector_size;
+    println!("Disk: {}", device);
+    println!("Size: {} bytes", disk.get_size()?);
+    println!("Partition table: {}", table.kind);
+    println!("Sector size: {} bytes", sector_size);
+    println!();
+    println!(
+        "{:>6} {:>12} {:>12} {:>10}  {:24} Name",
+        "Number", "Start", "End", "Size", "Type"
+    );
+    for partition in table.partitions.iter() {
+        let start = partition.start / sector_size;
+        let end = (partition.start + partition.size) / sector_size;
+        let line = format!(
+            "{:>6} {:>12} {:>12} {:>10}  {:24} {}",
+            partition.number,
+            start,
+            end.saturating_sub(1),
+            partition.size.file_size(options::BINARY).unwrap(),
+            partition.type_name,
+            partition.name
+        );
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
@@ -0,0 +1,532 @@
+use crate::cmds::{exit_status, get_exit_status, Command, Options, COMMAND_NAMES};
+use crate::ext2::Ext2Filesystem;
+use crate::fs::Filesystem;
+use argparse::{ArgumentParser, Store, StoreTrue};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, IsTerminal};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const HISTORY_FILE: &str = ".ext2_history";
+
+const BUILTIN_NAMES: [&str; 6] = ["cache", "cd", "exit", "help", "pwd", "quit"];
+
+/// Commands run against a single mount of the filesystem
+pub struct Session {
+    options: Options, // Options passed to the commands (with the mounted filesystem and the current directory)
+    fs: Arc<Ext2Filesystem>, // Mounted filesystem
+}
+
+impl Session {
+    /// Mount the filesystem
+    pub fn new(options: &Options) -> Result<Session, Error> {
+        let fs = options.mount_ext2()?;
+        Ok(Session {
+            options: Options {
+                filename: options.filename.clone(),
+                mount_options: options.mount_options.clone(),
+                fs: Some(fs.clone()),
+                cwd: String::from("/"),
+            },
+            fs,
+        })
+    }
+
+    /// Current directory
+    pub fn get_cwd(&self) -> &str {
+        &self.options.cwd
+    }
+
+    /// Change the current directory
+    fn cd(&mut self, path: &str) -> Result<(), Error> {
+        let path = join_path(&self.options.cwd, path);
+        if !self.fs.metadata(&path)?.is_dir() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Not a directory"));
+        }
+        self.options.cwd = path;
+        Ok(())
+    }
+
+    /// Mount the filesystem again, to read the superblock, the group descriptors
+    /// and the metadata written by another mount
+    fn remount(&mut self) -> Result<(), Error> {
+        let fs = Ext2Filesystem::mount(&self.options.filename, &self.options.mount_options)?;
+        self.fs = Arc::new(fs);
+        self.options.fs = Some(self.fs.clone());
+        Ok(())
+    }
+
+    /// Run a command line (the words are split as in a shell, # starts a comment).
+    /// Returns false if the session must end.
+    pub fn run_line(&mut self, line: &str) -> Result<bool, Error> {
+        let args = match shlex::split(line) {
+            Some(args) => args,
+            None => return Err(Error::new(ErrorKind::InvalidInput, "Unbalanced quotes")),
+        };
+        let name = match args.first() {
+            Some(name) => name.clone(),
+            None => return Ok(true),
+        };
+        match name.as_str() {
+            "exit" | "quit" => return Ok(false),
+            "help" => print_help(),
+            "pwd" => println!("{}", self.options.cwd),
+            "cache" => {
+                for (name, stats) in self.fs.get_cache_stats() {
+                    println!("{:<9} {}", format!("{}:", name), stats);
+                }
+            }
+            "cd" => {
+                let path = args.get(1).map_or("/", |x| x.as_str());
+                if let Err(err) = self.cd(path) {
+                    eprintln!("cd: {}: {}", path, err);
+                    return Err(exit_status(1));
+                }
+            }
+            _ => {
+                let command = match Command::from_str(&name) {
+                    Ok(Command::shell) | Err(_) => {
+                        eprintln!("{}: command not found", name);
+                        return Err(exit_status(1));
+                    }
+                    Ok(command) => command,
+                };
+                let result = command.run_command(&self.options, args);
+                // These commands write the disk through their own mount
+                if matches!(command, Command::fsck | Command::overlay) {
+                    if let Err(err) = self.remount() {
+                        eprintln!("{}: remount: {}", name, err);
+                        return Err(exit_status(1));
+                    }
+                }
+                if let Err(err) = result {
+                    if get_exit_status(&err).is_some() {
+                        return Err(err);
+                    }
+                    eprintln!("{}: {}", name, err);
+                    return Err(exit_status(1));
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Join a path to the current directory, removing the . and .. components
+fn join_path(cwd: &str, path: &str) -> String {
+    let full_path = if path.starts_with('/') || path.starts_with('<') {
+        path.to_string()
+    } else {
+        format!("{}/{}", cwd, path)
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in full_path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => match parts.last() {
+                Some(last) if !last.starts_with('<') && *last != ".." => {
+                    parts.pop();
+                }
+                // The parent of an inode number (<12>) is unknown
+                Some(_) => parts.push(part),
+                // The parent of the root is the root
+                None => {}
+            },
+            _ => parts.push(part),
+        }
+    }
+    if full_path.starts_with('<') {
+        parts.join("/")
+    } else {
+        format!("/{}", parts.join("/"))
+    }
+}
+
+fn print_help() {
+    println!("Builtin commands:");
+    println!("  cd [DIR]         Change the current directory.");
+    println!("  pwd              Print the current directory.");
+    println!("  cache            Show the statistics of the block, inode and dentry caches.");
+    println!("  exit, quit       Leave the shell.");
+    println!();
+    println!("Commands: {}", COMMAND_NAMES.join(" "));
+    println!("Run COMMAND --help for the usage of a command.");
+}
+
+/// Completion of the command names and of the paths in the filesystem
+struct ShellHelper {
+    fs: Arc<Ext2Filesystem>,
+    cwd: String, // Current directory of the session
+}
+
+impl ShellHelper {
+    /// Entries of the directory part of a path, starting with the file name part
+    fn complete_path(&self, word: &str) -> Vec<Pair> {
+        let (dir, prefix) = match word.rfind('/') {
+            Some(i) => (&word[..i + 1], &word[i + 1..]),
+            None => ("", word),
+        };
+        let entries = match self.fs.read_dir(&join_path(&self.cwd, dir)) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .values()
+            .filter(|x| x.file_name() != "." && x.file_name() != "..")
+            .filter(|x| x.file_name().starts_with(prefix))
+            .map(|x| {
+                let is_dir = self.fs.metadata(&x.path()).is_ok_and(|m| m.is_dir());
+                let suffix = if is_dir { "/" } else { "" };
+                Pair {
+                    display: format!("{}{}", x.file_name(), suffix),
+                    replacement: format!("{}{}{}", dir, x.file_name(), suffix),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(' ').map_or(0, |x| x + 1);
+        let word = &line[start..pos];
+        if line[..start].trim().is_empty() {
+            // First word: command name
+            let candidates = BUILTIN_NAMES
+                .iter()
+                .chain(COMMAND_NAMES.iter())
+                .filter(|x| x.starts_with(word) && **x != "shell")
+                .map(|x| Pair {
+                    display: x.to_string(),
+                    replacement: format!("{} ", x),
+                })
+                .collect();
+            Ok((start, candidates))
+        } else {
+            Ok((start, self.complete_path(word)))
+        }
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+fn get_history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+fn parse_args(args: Vec<String>, script: &mut String, keep_going: &mut bool) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description(
+        "Run the commands interactively on a single mount, or in batch from a script \
+         (one command per line, # starts a comment).",
+    );
+    parser.refer(script).add_option(
+        &["-f", "--file"],
+        Store,
+        "Run the commands of the SCRIPT (- for the standard input)",
+    );
+    parser.refer(keep_going).add_option(
+        &["-k", "--keep-going"],
+        StoreTrue,
+        "In batch, continue after a failed command",
+    );
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+/// Run the commands of a script, stopping at the first failed command unless keep_going
+fn run_script(
+    session: &mut Session,
+    reader: &mut dyn BufRead,
+    script: &str,
+    keep_going: bool,
+) -> Result<(), Error> {
+    let mut status = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        match session.run_line(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => {
+                let line_status = match get_exit_status(&err) {
+                    Some(line_status) => line_status,
+                    None => {
+                        eprintln!("{}:{}: {}", script, i + 1, err);
+                        1
+                    }
+                };
+                if !keep_going {
+                    eprintln!("{}:{}: aborting", script, i + 1);
+                    return Err(exit_status(line_status));
+                }
+                status = line_status;
+            }
+        }
+    }
+    match status {
+        0 => Ok(()),
+        _ => Err(exit_status(status)),
+    }
+}
+
+/// Read the commands with line editing, completion and history
+fn run_interactive(session: &mut Session) -> Result<(), Error> {
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new().map_err(Error::other)?;
+    editor.set_helper(Some(ShellHelper {
+        fs: session.fs.clone(),
+        cwd: session.get_cwd().to_string(),
+    }));
+    let history_path = get_history_path();
+    if let Some(path) = &history_path {
+        let _ = editor.load_history(path);
+    }
+    loop {
+        let prompt = format!("ext2:{}> ", session.get_cwd());
+        match editor.readline(&prompt) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    let _ = editor.add_history_entry(line.as_str());
+                }
+                let result = session.run_line(&line);
+                if let Some(helper) = editor.helper_mut() {
+                    helper.fs = session.fs.clone();
+                    helper.cwd = session.get_cwd().to_string();
+                }
+                match result {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(err) => {
+                        // The errors of the commands are already displayed
+                        if get_exit_status(&err).is_none() {
+                            eprintln!("{}", err);
+                        }
+                    }
+                }
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(Error::other(err)),
+        }
+    }
+    if let Some(path) = &history_path {
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
+
+pub fn shell(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut script = String::new();
+    let mut keep_going = false;
+    parse_args(args, &mut script, &mut keep_going)?;
+    let mut reader: Box<dyn BufRead> = match script.as_str() {
+        "" if io::stdin().is_terminal() => {
+            let mut session = Session::new(options)?;
+            return run_interactive(&mut session);
+        }
+        "" | "-" => {
+            script = String::from("<stdin>");
+            Box::new(io::stdin().lock())
+        }
+        _ => match File::open(&script) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(err) => {
+                eprintln!("{}: {}", script, err);
+                return Err(exit_status(1));
+            }
+        },
+    };
+    let mut session = Session::new(options)?;
+    run_script(&mut session, reader.as_mut(), &script, keep_going)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MountOptions;
+    use crate::test_util::{patch_image, unpack_image};
+    use std::io::Cursor;
+
+    fn session() -> Session {
+        let options = Options {
+            filename: unpack_image("ext2.img.gz"),
+            mount_options: MountOptions::default(),
+            fs: None,
+            cwd: String::from("/"),
+        };
+        Session::new(&options).unwrap()
+    }
+
+    #[test]
+    fn test_join_path() {
+        assert_eq!(join_path("/", "dir1"), "/dir1");
+        assert_eq!(join_path("/dir1", "a/./b"), "/dir1/a/b");
+        assert_eq!(join_path("/dir1", ".."), "/");
+        assert_eq!(join_path("/", "../.."), "/");
+        assert_eq!(join_path("/dir1", "/hello.txt"), "/hello.txt");
+        assert_eq!(join_path("/dir1", "<14>/x"), "<14>/x");
+        assert_eq!(join_path("<14>", "a/.."), "<14>");
+        // The parent of an inode number is kept
+        assert_eq!(join_path("<14>", ".."), "<14>/..");
+        assert_eq!(join_path("<14>/..", ".."), "<14>/../..");
+    }
+
+    #[test]
+    fn test_run_line() {
+        let mut session = session();
+        assert!(session.run_line("").unwrap());
+        assert!(session.run_line("   ").unwrap());
+        assert!(session.run_line("cd dir1").unwrap());
+        assert_eq!(session.get_cwd(), "/dir1");
+        assert!(session.run_line("cat file.txt").unwrap());
+        // The commands resolve the relative paths from the directory of the session
+        assert_eq!(session.options.get_path("file.txt"), "/dir1/file.txt");
+        assert!(session.run_line("cd ..").unwrap());
+        assert_eq!(session.get_cwd(), "/");
+        // Failed commands return an exit status, the directory is unchanged
+        for line in [
+            "cd missing",
+            "cd hello.txt",
+            "cat missing",
+            "unknown",
+            "shell",
+        ] {
+            let err = session.run_line(line).unwrap_err();
+            assert_eq!(get_exit_status(&err), Some(1), "{}", line);
+        }
+        assert_eq!(session.get_cwd(), "/");
+        let err = session.run_line("cat 'hello.txt").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(session.run_line("cd").unwrap());
+        assert!(!session.run_line("exit").unwrap());
+        assert!(!session.run_line("quit").unwrap());
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let helper = ShellHelper {
+            fs: session().fs.clone(),
+            cwd: String::from("/"),
+        };
+        let replacements = |word: &str| -> Vec<String> {
+            let mut result: Vec<String> = helper
+                .complete_path(word)
+                .into_iter()
+                .map(|x| x.replacement)
+                .collect();
+            result.sort();
+            result
+        };
+        assert_eq!(replacements("/dir"), ["/dir1/"]);
+        assert_eq!(replacements("/dir1/"), ["/dir1/file.txt"]);
+        assert_eq!(replacements("h"), ["hello.txt"]);
+        assert!(replacements("/missing/").is_empty());
+        assert_eq!(replacements("").len(), 6);
+        let helper = ShellHelper {
+            cwd: String::from("/dir1"),
+            ..helper
+        };
+        let replacements: Vec<String> = helper
+            .complete_path("f")
+            .into_iter()
+            .map(|x| x.replacement)
+            .collect();
+        assert_eq!(replacements, ["file.txt"]);
+    }
+
+    #[test]
+    fn test_independent_sessions() {
+        // Two sessions on the same filesystem have their own current directory
+        let mut first = session();
+        let mut second = Session::new(&first.options).unwrap();
+        assert!(Arc::ptr_eq(&first.fs, &second.fs));
+        first.run_line("cd dir1").unwrap();
+        assert_eq!(second.get_cwd(), "/");
+        assert!(second.run_line("cat hello.txt").unwrap());
+        assert!(first.run_line("cat file.txt").unwrap());
+        assert!(second.run_line("cat file.txt").is_err());
+    }
+
+    #[test]
+    fn test_remount_after_repair() {
+        // Superblock free blocks count (s_free_blocks_count) set to 16
+        let image = patch_image("ext2.img.gz", &[(1024 + 12, &16u32.to_le_bytes())]);
+        let options = Options {
+            filename: image.path.clone(),
+            mount_options: MountOptions {
+                read_write: true,
+                ..Default::default()
+            },
+            fs: None,
+            cwd: String::from("/"),
+        };
+        let mut session = Session::new(&options).unwrap();
+        assert_eq!(session.fs.get_super_block().get_free_blocks_count(), 16);
+        assert!(session.run_line("cd dir1").unwrap());
+        assert!(session.run_line("fsck --repair").is_err()); // Errors corrected
+        let fresh = Ext2Filesystem::mount(&options.filename, &options.mount_options).unwrap();
+        let free_blocks = fresh.get_super_block().get_free_blocks_count();
+        assert_ne!(free_blocks, 16);
+        assert_eq!(
+            session.fs.get_super_block().get_free_blocks_count(),
+            free_blocks
+        );
+        assert!(Arc::ptr_eq(
+            &session.fs,
+            session.options.fs.as_ref().unwrap()
+        ));
+        assert_eq!(session.get_cwd(), "/dir1");
+    }
+
+    #[test]
+    fn test_run_script() {
+        let mut session = session();
+        let script = "# comment\n\ncd dir1\ncat file.txt\n";
+        run_script(&mut session, &mut Cursor::new(script), "test", false).unwrap();
+        assert_eq!(session.get_cwd(), "/dir1");
+        // exit stops the script
+        let script = "cd /\nexit\ncd dir1\n";
+        run_script(&mut session, &mut Cursor::new(script), "test", false).unwrap();
+        assert_eq!(session.get_cwd(), "/");
+    }
+
+    #[test]
+    fn test_run_script_errors() {
+        // Stop at the first failed command
+        let mut session = session();
+        let script = "cd missing\ncd dir1\n";
+        let err = run_script(&mut session, &mut Cursor::new(script), "test", false).unwrap_err();
+        assert_eq!(get_exit_status(&err), Some(1));
+        assert_eq!(session.get_cwd(), "/");
+        // Run the following commands, the exit status is the last failure
+        let script = "cd missing\ncd dir1\ncat 'unbalanced\npwd\n";
+        let err = run_script(&mut session, &mut Cursor::new(script), "test", true).unwrap_err();
+        assert_eq!(get_exit_status(&err), Some(1));
+        assert_eq!(session.get_cwd(), "/dir1");
+    }
+}
@@ -1,5 +1,5 @@
-use crate::cmds::Options;
-use crate::fs::{mount, Filesystem};
+use crate::cmds::{exit_status, Options};
+use crate::fs::Filesystem;
 use argparse::{ArgumentParser, List};
 use chrono::prelude::*;
 use std::io::{self, Error};
@@ -16,17 +16,17 @@ fn format_time(time: i64) -> String {
 
 struct StatFlags {}
 
-fn parse_args(args: Vec<String>, paths: &mut Vec<String>) {
+fn parse_args(args: Vec<String>, paths: &mut Vec<String>) -> Result<(), Error> {
     // Parse command argument
     let mut parser = ArgumentParser::new();
     parser.set_description("Display file status.");
     parser.refer(paths).add_argument("file", List, "FILE");
-    if let Err(x) = parser.parse(args, &mut io::stdout(), &mut io::stderr()) {
-        std::process::exit(x);
-    }
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
 }
 
-fn print_stat(fs: &mut Box<dyn Filesystem>, path: &str, _flags: &StatFlags) -> Result<(), Error> {
+fn print_stat(fs: &dyn Filesystem, path: &str, _flags: &StatFlags) -> Result<(), Error> {
     let metadata = fs.symlink_metadata(path)?;
     println!("  File: {}", path);
     println!(
@@ -55,20 +55,20 @@ fn print_stat(fs: &mut Box<dyn Filesystem>, path: &str, _flags: &StatFlags) -> R
 }
 
 pub fn stat(options: &Options, args: Vec<String>) -> Result<(), Error> {
-    let mut fs = mount(&options.filename)?;
+    let fs = options.mount()?;
     let mut paths: Vec<String> = vec![];
-    parse_args(args, &mut paths);
+    parse_args(args, &mut paths)?;
     if paths.is_empty() {
         eprintln!("stat: missing operand");
-        std::process::exit(1);
+        return Err(exit_status(1));
     }
     let flags = StatFlags {};
     for path in paths.iter() {
-        match print_stat(&mut fs, &path, &flags) {
+        match print_stat(fs.as_ref(), &options.get_path(path), &flags) {
             Ok(_) => {}
             Err(err) => {
                 eprintln!("stat: {}: {}", path, err);
-                std::process::exit(1);
+                return Err(exit_status(1));
             }
         }
     }
@@ -0,0 +1,38 @@
+use crate::cmds::{exit_status, Options};
+use argparse::{ArgumentParser, Store};
+use std::fs::File;
+use std::io::{self, BufWriter, Error, Write};
+
+fn parse_args(args: Vec<String>, inode_num: &mut u64, dest: &mut String) -> Result<(), Error> {
+    // Parse command argument
+    let mut parser = ArgumentParser::new();
+    parser.set_description("Recover the content of a deleted inode into DEST.");
+    parser
+        .refer(inode_num)
+        .required()
+        .add_argument("inode", Store, "INODE");
+    parser
+        .refer(dest)
+        .required()
+        .add_argument("dest", Store, "DEST");
+    parser
+        .parse(args, &mut io::stdout(), &mut io::stderr())
+        .map_err(exit_status)
+}
+
+pub fn undelete(options: &Options, args: Vec<String>) -> Result<(), Error> {
+    let mut inode_num: u64 = 0;
+    let mut dest = String::new();
+    parse_args(args, &mut inode_num, &mut dest)?;
+    let fs = options.mount_ext2()?;
+    let mut writer = BufWriter::new(File::create(&dest)?);
+    let reallocated = fs.undelete(inode_num, &mut writer)?;
+    writer.flush()?;
+    if reallocated > 0 {
+        eprintln!(
+            "undelete: {}: {} blocks were reallocated, the content may be corrupted",
+            dest, reallocated
+        );
+    }
+    Ok(())
+}
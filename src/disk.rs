@@ -1,12 +1,20 @@
-use std::cell::UnsafeCell;
-use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::prelude::*;
+pub mod cache;
+pub mod compressed;
+pub mod mmap;
+pub mod overlay;
+pub mod partition;
+pub mod qcow2;
+pub mod split;
+pub mod vhd;
+pub mod vhdx;
+pub mod vmdk;
+
+use flate2::Crc;
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
 use std::io::Error;
 use std::io::ErrorKind;
-use std::io::Read;
-use std::io::SeekFrom;
+use std::os::unix::fs::FileExt;
 
 #[derive(Debug)]
 pub enum Offset {
@@ -38,72 +46,116 @@ impl Offset {
 }
 
 pub struct FileDisk {
-    file: UnsafeCell<File>,
+    file: File,
 }
 
-pub trait Disk {
+/// Disk image, shared between threads: the reads must not depend on a file cursor
+pub trait Disk: Send + Sync {
     fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error>;
 
+    fn write(&self, _buffer: &[u8], _offset: Offset) -> Result<(), Error> {
+        Err(Error::new(ErrorKind::PermissionDenied, "Read-only disk"))
+    }
+
+    /// Read bytes without copying them when the disk is in memory (memory-mapped)
+    fn read_slice(&self, size: u64, offset: Offset) -> Result<Cow<'_, [u8]>, Error> {
+        Ok(Cow::Owned(self.read(size, offset)?))
+    }
+
+    /// Size of the disk in bytes
+    fn get_size(&self) -> Result<u64, Error>;
+
     fn calc_offset(&self, block_size: u64, base_block_num: u64, delta: u64) -> u64 {
         base_block_num as u64 * block_size + delta
     }
 }
 
+/// Read bytes at a byte offset
+pub fn read_at(disk: &dyn Disk, size: u64, offset: u64) -> Result<Vec<u8>, Error> {
+    let offset = Offset::Block {
+        block_size: 1,
+        block_num: offset,
+    };
+    disk.read(size, offset)
+}
+
+/// crc32 (IEEE) of a buffer
+pub fn crc32(buffer: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(buffer);
+    crc.sum()
+}
+
+/// Read a range of a disk image split in allocation units (clusters, grains, blocks):
+/// read_unit(offset, buffer) fills the part of the range in each unit
+pub fn read_units(
+    size: u64,
+    offset: u64,
+    unit_size: u64,
+    disk_size: u64,
+    mut read_unit: impl FnMut(u64, &mut [u8]) -> Result<(), Error>,
+) -> Result<Vec<u8>, Error> {
+    if offset + size > disk_size {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("Read beyond the end of the disk ({})", offset + size),
+        ));
+    }
+    let mut buffer = vec![0; size as usize];
+    let mut done: u64 = 0;
+    while done < size {
+        let position = offset + done;
+        let len = (unit_size - position % unit_size).min(size - done);
+        read_unit(position, &mut buffer[done as usize..(done + len) as usize])?;
+        done += len;
+    }
+    Ok(buffer)
+}
+
 impl FileDisk {
     pub fn open(filename: &str) -> Result<Self, Error> {
         let file = File::open(filename)?;
-        Ok(Self { file: file.into() })
+        Ok(Self { file })
+    }
+
+    pub fn open_rw(filename: &str) -> Result<Self, Error> {
+        let file = OpenOptions::new().read(true).write(true).open(filename)?;
+        Ok(Self { file })
     }
 }
 
 impl Disk for FileDisk {
     fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
         let offset: u64 = offset.calc_offset();
-        let mut buffer: Vec<u8> = Vec::new();
-        buffer.resize(size as usize, 0);
-        unsafe {
-            let mut file = &*self.file.get();
-            file.seek(SeekFrom::Start(offset))?;
-            let nbytes: usize = file.read(&mut buffer)?;
-            if nbytes != size as usize {
-                Err(Error::new(
-                    ErrorKind::UnexpectedEof,
-                    format!("Not enough bytes read {nbytes} < {size}"),
-                ))
-            } else {
-                Ok(buffer)
+        let mut buffer: Vec<u8> = vec![0; size as usize];
+        // Positional reads: no shared file cursor, the disk can be read from several threads
+        let mut nbytes: usize = 0;
+        while nbytes < buffer.len() {
+            match self
+                .file
+                .read_at(&mut buffer[nbytes..], offset + nbytes as u64)
+            {
+                Ok(0) => break,
+                Ok(n) => nbytes += n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
             }
         }
+        if nbytes != size as usize {
+            Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Not enough bytes read {nbytes} < {size}"),
+            ))
+        } else {
+            Ok(buffer)
+        }
     }
-}
 
-pub struct BlockCache<'a> {
-    disk: &'a Box<dyn Disk>,
-    block_size: u64,
-    cache: HashMap<u64, Vec<u8>>,
-}
-
-impl BlockCache<'_> {
-    pub fn new(disk: &Box<dyn Disk>, block_size: u64) -> BlockCache {
-        let cache: HashMap<u64, Vec<u8>> = HashMap::new();
-        BlockCache {
-            disk: disk,
-            block_size: block_size,
-            cache: cache,
-        }
+    fn write(&self, buffer: &[u8], offset: Offset) -> Result<(), Error> {
+        self.file.write_all_at(buffer, offset.calc_offset())
     }
 
-    pub fn get_block(&mut self, block_num: u64) -> Result<&Vec<u8>, Error> {
-        match self.cache.entry(block_num) {
-            Vacant(entry) => {
-                let offset = Offset::Block {
-                    block_size: self.block_size,
-                    block_num: block_num,
-                };
-                let data = self.disk.read(self.block_size, offset)?;
-                Ok(entry.insert(data))
-            }
-            Occupied(entry) => Ok(entry.into_mut()),
-        }
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.file.metadata()?.len())
     }
 }
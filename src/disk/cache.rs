@@ -0,0 +1,180 @@
+use crate::cache::Cache;
+use crate::disk::{read_at, read_units, Disk, Offset};
+use std::borrow::Cow;
+use std::io::Error;
+use std::sync::{Arc, Mutex};
+
+/// Blocks cached by a CachedDisk
+pub type CachedBlocks = Cache<u64, Arc<Vec<u8>>>;
+
+/// Disk caching the blocks read, shared by all the readers of the filesystem.
+/// The writes go through to the disk. The file contents (read_slice) bypass the cache:
+/// they are read once, or borrowed from the mapping of a memory-mapped image.
+pub struct CachedDisk {
+    disk: Box<dyn Disk>,
+    block_size: u64,
+    size: u64, // Size of the disk
+    cache: Arc<CachedBlocks>,
+    generation: Mutex<u64>, // Number of writes, a block read before a write is not cached
+}
+
+impl CachedDisk {
+    pub fn new(
+        disk: Box<dyn Disk>,
+        block_size: u64,
+        cache: Arc<CachedBlocks>,
+    ) -> Result<CachedDisk, Error> {
+        let size = disk.get_size()?;
+        Ok(CachedDisk {
+            disk,
+            block_size,
+            size,
+            cache,
+            generation: Mutex::new(0),
+        })
+    }
+
+    /// Read a part of a block, through the cache
+    fn read_block(&self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        let block_num = offset / self.block_size;
+        let data = match self.cache.get(&block_num) {
+            Some(data) => data,
+            None => {
+                // The last block can be incomplete
+                let start = block_num * self.block_size;
+                let size = self.block_size.min(self.size - start);
+                let generation = *self.generation.lock().unwrap();
+                let data = Arc::new(read_at(self.disk.as_ref(), size, start)?);
+                // The data may be outdated if a write happened during the read
+                let current = self.generation.lock().unwrap();
+                if *current == generation {
+                    self.cache.insert(block_num, data.clone());
+                }
+                data
+            }
+        };
+        let start = (offset % self.block_size) as usize;
+        buffer.copy_from_slice(&data[start..start + buffer.len()]);
+        Ok(())
+    }
+}
+
+impl Disk for CachedDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        let offset = offset.calc_offset();
+        read_units(
+            size,
+            offset,
+            self.block_size,
+            self.size,
+            |offset, buffer| self.read_block(offset, buffer),
+        )
+    }
+
+    fn read_slice(&self, size: u64, offset: Offset) -> Result<Cow<'_, [u8]>, Error> {
+        self.disk.read_slice(size, offset)
+    }
+
+    fn write(&self, buffer: &[u8], offset: Offset) -> Result<(), Error> {
+        let offset = offset.calc_offset();
+        self.disk.write(
+            buffer,
+            Offset::Block {
+                block_size: 1,
+                block_num: offset,
+            },
+        )?;
+        let end = offset + buffer.len() as u64;
+        let mut generation = self.generation.lock().unwrap();
+        *generation += 1;
+        for block_num in offset / self.block_size..end.div_ceil(self.block_size) {
+            self.cache.remove(&block_num);
+        }
+        Ok(())
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryDisk;
+    use std::sync::Weak;
+
+    const BLOCK_SIZE: u64 = 512;
+
+    fn offset(offset: u64) -> Offset {
+        Offset::Block {
+            block_size: 1,
+            block_num: offset,
+        }
+    }
+
+    /// Disk whose next read is followed by a write of the same block through the
+    /// CachedDisk, before the CachedDisk gets the (now outdated) data
+    struct RacingDisk {
+        disk: MemoryDisk,
+        cached: Arc<Mutex<Option<Weak<CachedDisk>>>>,
+    }
+
+    impl Disk for RacingDisk {
+        fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+            let start = offset.calc_offset();
+            let data = self.disk.read(size, offset)?;
+            let cached = self.cached.lock().unwrap().take();
+            if let Some(cached) = cached.and_then(|x| x.upgrade()) {
+                cached.write(&[2; 4], self::offset(start))?;
+            }
+            Ok(data)
+        }
+
+        fn write(&self, buffer: &[u8], offset: Offset) -> Result<(), Error> {
+            self.disk.write(buffer, offset)
+        }
+
+        fn get_size(&self) -> Result<u64, Error> {
+            self.disk.get_size()
+        }
+    }
+
+    #[test]
+    fn test_cached_reads() {
+        let cache = Arc::new(CachedBlocks::new(4));
+        let disk = CachedDisk::new(
+            Box::new(MemoryDisk::new(vec![1; 4 * BLOCK_SIZE as usize + 100])),
+            BLOCK_SIZE,
+            cache.clone(),
+        )
+        .unwrap();
+        assert_eq!(read_at(&disk, 4, 510).unwrap(), [1; 4]);
+        assert_eq!(cache.get_stats().len, 2);
+        // The incomplete last block
+        assert_eq!(read_at(&disk, 100, 4 * BLOCK_SIZE).unwrap(), [1; 100]);
+        assert!(read_at(&disk, 101, 4 * BLOCK_SIZE).is_err());
+        // The writes go through, and invalidate the blocks
+        disk.write(&[3; 4], offset(510)).unwrap();
+        assert_eq!(cache.get_stats().len, 1);
+        assert_eq!(read_at(&disk, 6, 509).unwrap(), [1, 3, 3, 3, 3, 1]);
+        assert_eq!(read_at(disk.disk.as_ref(), 4, 510).unwrap(), [3; 4]);
+    }
+
+    #[test]
+    fn test_write_during_read() {
+        let hook = Arc::new(Mutex::new(None));
+        let racing = RacingDisk {
+            disk: MemoryDisk::new(vec![1; BLOCK_SIZE as usize]),
+            cached: hook.clone(),
+        };
+        let cache = Arc::new(CachedBlocks::new(4));
+        let disk = Arc::new(CachedDisk::new(Box::new(racing), BLOCK_SIZE, cache.clone()).unwrap());
+        *hook.lock().unwrap() = Some(Arc::downgrade(&disk));
+        // The outdated data is returned, but not cached
+        assert_eq!(read_at(disk.as_ref(), 4, 0).unwrap(), [1; 4]);
+        assert_eq!(cache.get_stats().len, 0);
+        assert_eq!(read_at(disk.as_ref(), 4, 0).unwrap(), [2; 4]);
+        assert_eq!(cache.get_stats().len, 1);
+    }
+}
@@ -0,0 +1,891 @@
+use crate::disk::{crc32, read_at, read_units, Disk, Offset};
+use flate2::Crc;
+use miniz_oxide::inflate::core::inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
+use miniz_oxide::inflate::core::{decompress, DecompressorOxide};
+use miniz_oxide::inflate::TINFLStatus;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufReader, Cursor, Error, ErrorKind, Read};
+use std::sync::{Arc, Mutex};
+use xz2::read::XzDecoder;
+
+pub const GZIP_MAGIC: &[u8; 2] = b"\x1f\x8b";
+pub const XZ_MAGIC: &[u8; 6] = b"\xfd7zXZ\x00";
+pub const ZSTD_MAGIC: &[u8; 4] = b"\x28\xb5\x2f\xfd";
+
+const CHECKPOINT_INTERVAL: u64 = 8 << 20; // Uncompressed bytes between two gzip checkpoints
+const PAGE_SIZE: u64 = 64 * 1024; // Unit of the cache of uncompressed data
+const PAGE_CACHE_SIZE: usize = 256; // Number of pages in the cache
+const INPUT_SIZE: u64 = 64 * 1024; // Compressed bytes read at once
+
+const DEFLATE_WINDOW_SIZE: usize = 32 * 1024;
+const GZIP_FLAG_HCRC: u8 = 1 << 1;
+const GZIP_FLAG_EXTRA: u8 = 1 << 2;
+const GZIP_FLAG_NAME: u8 = 1 << 3;
+const GZIP_FLAG_COMMENT: u8 = 1 << 4;
+const GZIP_TRAILER_SIZE: u64 = 8;
+
+const XZ_HEADER_SIZE: u64 = 12;
+const XZ_FOOTER_MAGIC: &[u8; 2] = b"YZ";
+
+const ZSTD_SKIPPABLE_MAGIC: u32 = 0x184d2a50; // The low 4 bits are free
+const ZSTD_SEEKABLE_MAGIC: u32 = 0x8f92eab1;
+const ZSTD_SEEKABLE_FOOTER_SIZE: u64 = 9;
+const ZSTD_BLOCK_RLE: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionFormat {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl CompressionFormat {
+    /// Detect the compression format from the first bytes of a file
+    pub fn detect(magic: &[u8]) -> Option<CompressionFormat> {
+        if magic.starts_with(GZIP_MAGIC) {
+            Some(CompressionFormat::Gzip)
+        } else if magic.starts_with(XZ_MAGIC) {
+            Some(CompressionFormat::Xz)
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Some(CompressionFormat::Zstd)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            CompressionFormat::Gzip => "gzip",
+            CompressionFormat::Xz => "xz",
+            CompressionFormat::Zstd => "zstd",
+        }
+    }
+}
+
+/// Sequential reader of a range of a disk
+struct DiskReader {
+    disk: Arc<dyn Disk>,
+    offset: u64,
+    end: u64,
+}
+
+impl Read for DiskReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = (buf.len() as u64).min(self.end - self.offset);
+        if size == 0 {
+            return Ok(0);
+        }
+        let data = read_at(self.disk.as_ref(), size, self.offset)?;
+        buf[..size as usize].copy_from_slice(&data);
+        self.offset += size;
+        Ok(size as usize)
+    }
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+fn invalid(format: &str, message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{}: {}", format, message))
+}
+
+/// State of the gzip decompression, allowing to restart it in the middle of a member
+#[derive(Clone)]
+struct GzipCheckpoint {
+    compressed: u64,                       // Offset of the next compressed byte
+    position: u64,                         // Offset of the next uncompressed byte
+    state: Option<Box<DecompressorOxide>>, // None between two members
+    window: Box<[u8]>,                     // Last 32 KiB of uncompressed data
+    window_pos: usize,
+}
+
+/// Decompression of (multi-member) gzip files, using a wrapping 32 KiB output window
+/// so that the state can be saved in a checkpoint
+struct GzipReader {
+    file: Arc<dyn Disk>,
+    file_size: u64,
+    input: Vec<u8>,
+    input_start: u64, // Offset of input[0] in the file
+    input_pos: usize,
+    checkpoint: GzipCheckpoint,
+    pending: (usize, usize), // Range of the window not returned yet
+    crc: Option<Crc>,        // Checksum of the member (verified during the first pass only)
+}
+
+impl GzipReader {
+    fn new(file: Arc<dyn Disk>, checkpoint: GzipCheckpoint, verify: bool) -> Result<Self, Error> {
+        let file_size = file.get_size()?;
+        Ok(GzipReader {
+            file,
+            file_size,
+            input: Vec::new(),
+            input_start: checkpoint.compressed,
+            input_pos: 0,
+            checkpoint,
+            pending: (0, 0),
+            crc: if verify { Some(Crc::new()) } else { None },
+        })
+    }
+
+    fn get_compressed(&self) -> u64 {
+        self.input_start + self.input_pos as u64
+    }
+
+    /// Save the state, when all the uncompressed data was returned
+    fn get_checkpoint(&self) -> Option<GzipCheckpoint> {
+        if self.pending.0 != self.pending.1 {
+            return None;
+        }
+        let mut checkpoint = self.checkpoint.clone();
+        checkpoint.compressed = self.get_compressed();
+        Some(checkpoint)
+    }
+
+    /// Read compressed data at the given offset
+    fn fill_input(&mut self, offset: u64) -> Result<(), Error> {
+        let size = INPUT_SIZE.min(self.file_size - offset);
+        self.input = read_at(self.file.as_ref(), size, offset)?;
+        self.input_start = offset;
+        self.input_pos = 0;
+        Ok(())
+    }
+
+    /// Parse the header of a member, returns false at the end of the file
+    fn read_header(&mut self) -> Result<bool, Error> {
+        let offset = self.get_compressed();
+        let mut reader = BufReader::new(DiskReader {
+            disk: self.file.clone(),
+            offset,
+            end: self.file_size,
+        });
+        let mut header = [0; 10];
+        match reader.read_exact(&mut header) {
+            Ok(()) if header.starts_with(GZIP_MAGIC) => {}
+            // Trailing data (padding) is ignored, as gzip does
+            _ if offset > 0 => return Ok(false),
+            _ => return Err(invalid("gzip", "bad magic")),
+        }
+        if header[2] != 8 {
+            return Err(invalid("gzip", "unknown compression method"));
+        }
+        let flags = header[3];
+        let mut size = header.len() as u64;
+        if flags & GZIP_FLAG_EXTRA != 0 {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            let len = u16::from_le_bytes(len) as u64;
+            io::copy(&mut (&mut reader).take(len), &mut io::sink())?;
+            size += 2 + len;
+        }
+        for flag in [GZIP_FLAG_NAME, GZIP_FLAG_COMMENT] {
+            if flags & flag != 0 {
+                let mut name = Vec::new();
+                io::BufRead::read_until(&mut reader, 0, &mut name)?;
+                size += name.len() as u64;
+            }
+        }
+        if flags & GZIP_FLAG_HCRC != 0 {
+            size += 2;
+        }
+        self.fill_input(offset + size)?;
+        self.checkpoint.state = Some(Box::new(DecompressorOxide::new()));
+        if let Some(crc) = &mut self.crc {
+            crc.reset();
+        }
+        Ok(true)
+    }
+
+    /// Check the trailer of a member
+    fn read_trailer(&mut self) -> Result<(), Error> {
+        let offset = self.get_compressed();
+        if offset + GZIP_TRAILER_SIZE > self.file_size {
+            return Err(invalid("gzip", "truncated file"));
+        }
+        let trailer = read_at(self.file.as_ref(), GZIP_TRAILER_SIZE, offset)?;
+        if let Some(crc) = &self.crc {
+            if crc.sum() != read_u32(&trailer, 0) || crc.amount() != read_u32(&trailer, 4) {
+                return Err(invalid("gzip", "checksum mismatch"));
+            }
+        }
+        self.fill_input(offset + GZIP_TRAILER_SIZE)?;
+        self.checkpoint.state = None;
+        Ok(())
+    }
+
+    /// Decompress the next bytes into the window, returns false at the end of the data
+    fn decompress(&mut self) -> Result<bool, Error> {
+        loop {
+            if self.checkpoint.state.is_none() && !self.read_header()? {
+                return Ok(false);
+            }
+            if self.input_pos == self.input.len() {
+                self.fill_input(self.get_compressed())?;
+            }
+            let more_input = self.input_start + (self.input.len() as u64) < self.file_size;
+            let flags = if more_input {
+                TINFL_FLAG_HAS_MORE_INPUT
+            } else {
+                0
+            };
+            let checkpoint = &mut self.checkpoint;
+            let (status, consumed, written) = decompress(
+                checkpoint.state.as_mut().unwrap(),
+                &self.input[self.input_pos..],
+                &mut checkpoint.window,
+                checkpoint.window_pos,
+                flags,
+            );
+            self.input_pos += consumed;
+            self.pending = (checkpoint.window_pos, checkpoint.window_pos + written);
+            if let Some(crc) = &mut self.crc {
+                crc.update(&checkpoint.window[self.pending.0..self.pending.1]);
+            }
+            checkpoint.window_pos = (checkpoint.window_pos + written) % DEFLATE_WINDOW_SIZE;
+            checkpoint.position += written as u64;
+            match status {
+                TINFLStatus::Done => self.read_trailer()?,
+                TINFLStatus::NeedsMoreInput | TINFLStatus::FailedCannotMakeProgress
+                    if !more_input =>
+                {
+                    return Err(invalid("gzip", "truncated file"));
+                }
+                TINFLStatus::NeedsMoreInput | TINFLStatus::HasMoreOutput => {}
+                status => return Err(invalid("gzip", &format!("{:?}", status))),
+            }
+            if written > 0 {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl Read for GzipReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.0 == self.pending.1 && !self.decompress()? {
+            return Ok(0);
+        }
+        let len = buf.len().min(self.pending.1 - self.pending.0);
+        buf[..len].copy_from_slice(&self.checkpoint.window[self.pending.0..self.pending.0 + len]);
+        self.pending.0 += len;
+        Ok(len)
+    }
+}
+
+/// Point where the decompression can start
+enum RestartKind {
+    Gzip(Box<GzipCheckpoint>),
+    XzBlock {
+        stream_flags: [u8; 2], // Flags of the stream containing the block
+        compressed: u64,       // Offset of the block
+        unpadded_size: u64,    // Size of the block without the padding
+        size: u64,             // Uncompressed size
+    },
+    ZstdFrame {
+        compressed: u64, // Offset of the frame
+        compressed_size: u64,
+    },
+}
+
+struct RestartPoint {
+    offset: u64, // Uncompressed offset
+    kind: RestartKind,
+}
+
+/// Decompression in progress, reused by the following reads
+struct DecompressionCursor {
+    index: usize,  // Restart point the decompression started from
+    position: u64, // Uncompressed offset of the next byte
+    reader: Box<dyn Read + Send>,
+}
+
+/// Read-only raw image compressed with gzip, xz or zstd, with random access.
+/// The decompression restarts from the closest independent unit (xz block, zstd frame)
+/// or gzip checkpoint (saved every 8 MiB during a first pass over the file).
+pub struct CompressedDisk {
+    file: Arc<dyn Disk>,
+    size: u64,
+    restarts: Vec<RestartPoint>, // Sorted by uncompressed offset
+    cursor: Mutex<Option<DecompressionCursor>>,
+    cache: Mutex<PageCache>,
+}
+
+/// Uncompressed pages, the oldest one is evicted first
+#[derive(Default)]
+struct PageCache {
+    pages: HashMap<u64, Arc<Vec<u8>>>,
+    order: VecDeque<u64>,
+}
+
+/// Read a variable-length integer of the xz format
+fn read_xz_varint(buffer: &[u8], pos: &mut usize) -> Result<u64, Error> {
+    let mut value: u64 = 0;
+    for i in 0..9 {
+        let byte = *buffer
+            .get(*pos)
+            .ok_or_else(|| invalid("xz", "truncated index"))?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("xz", "invalid index"))
+}
+
+fn write_xz_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// List the blocks of the xz streams of a file, using the index at the end of each stream
+fn read_xz_blocks(file: &dyn Disk) -> Result<Vec<RestartPoint>, Error> {
+    let mut streams: Vec<Vec<RestartPoint>> = Vec::new();
+    let mut end = file.get_size()?;
+    while end > 0 {
+        if end < XZ_HEADER_SIZE * 2 {
+            return Err(invalid("xz", "truncated file"));
+        }
+        let footer = read_at(file, XZ_HEADER_SIZE, end - XZ_HEADER_SIZE)?;
+        // Stream padding (multiples of 4 null bytes, after any stream)
+        if footer[8..12].iter().all(|x| *x == 0) {
+            end -= 4;
+            continue;
+        }
+        if &footer[10..12] != XZ_FOOTER_MAGIC {
+            return Err(invalid("xz", "bad stream footer"));
+        }
+        let index_size = (read_u32(&footer, 4) as u64 + 1) * 4;
+        let index_offset = (end - XZ_HEADER_SIZE)
+            .checked_sub(index_size)
+            .ok_or_else(|| invalid("xz", "invalid index size"))?;
+        let index = read_at(file, index_size, index_offset)?;
+        let mut pos = 1;
+        let count = read_xz_varint(&index, &mut pos)?;
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let unpadded_size = read_xz_varint(&index, &mut pos)?;
+            let size = read_xz_varint(&index, &mut pos)?;
+            records.push((unpadded_size, size));
+        }
+        let blocks_size: u64 = records.iter().map(|x| x.0.next_multiple_of(4)).sum();
+        let start = index_offset
+            .checked_sub(blocks_size + XZ_HEADER_SIZE)
+            .ok_or_else(|| invalid("xz", "invalid index"))?;
+        let header = read_at(file, XZ_HEADER_SIZE, start)?;
+        if !header.starts_with(XZ_MAGIC) || header[6..8] != footer[8..10] {
+            return Err(invalid("xz", "bad stream header"));
+        }
+        let mut compressed = start + XZ_HEADER_SIZE;
+        let mut blocks = Vec::new();
+        for (unpadded_size, size) in records {
+            blocks.push(RestartPoint {
+                offset: 0,
+                kind: RestartKind::XzBlock {
+                    stream_flags: [header[6], header[7]],
+                    compressed,
+                    unpadded_size,
+                    size,
+                },
+            });
+            compressed += unpadded_size.next_multiple_of(4);
+        }
+        streams.push(blocks);
+        end = start;
+    }
+    Ok(streams.into_iter().rev().flatten().collect())
+}
+
+/// Compressed size of a zstd frame (parsing the block headers) and its uncompressed size,
+/// if stored in the frame header
+fn read_zstd_frame(file: &dyn Disk, offset: u64) -> Result<(u64, Option<u64>), Error> {
+    let header = read_at(file, 14.min(file.get_size()? - offset), offset)?;
+    if header.len() < 6 {
+        return Err(invalid("zstd", "truncated frame"));
+    }
+    let descriptor = header[4];
+    let single_segment = descriptor & 0x20 != 0;
+    let has_checksum = descriptor & 0x04 != 0;
+    let dict_id_size = [0, 1, 2, 4][(descriptor & 3) as usize];
+    let size_field_size = match descriptor >> 6 {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let size_offset = 5 + (!single_segment as usize) + dict_id_size;
+    let header_size = (size_offset + size_field_size) as u64;
+    if header.len() < header_size as usize {
+        return Err(invalid("zstd", "truncated frame"));
+    }
+    let mut field = [0; 8];
+    field[..size_field_size].copy_from_slice(&header[size_offset..size_offset + size_field_size]);
+    let size = match size_field_size {
+        0 => None,
+        2 => Some(u64::from_le_bytes(field) + 256),
+        _ => Some(u64::from_le_bytes(field)),
+    };
+    let mut pos = offset + header_size;
+    loop {
+        let block = read_at(file, 3, pos)?;
+        let block = u32::from_le_bytes([block[0], block[1], block[2], 0]);
+        let block_size = if (block >> 1) & 3 == ZSTD_BLOCK_RLE {
+            1
+        } else {
+            (block >> 3) as u64
+        };
+        pos += 3 + block_size;
+        if block & 1 != 0 {
+            break;
+        }
+    }
+    if has_checksum {
+        pos += 4;
+    }
+    Ok((pos - offset, size))
+}
+
+/// List the frames of a zstd file, from the seek table of the seekable format if present
+fn read_zstd_frames(file: &dyn Disk) -> Result<Vec<RestartPoint>, Error> {
+    let file_size = file.get_size()?;
+    let mut frames = Vec::new();
+    if file_size >= ZSTD_SEEKABLE_FOOTER_SIZE {
+        let footer = read_at(
+            file,
+            ZSTD_SEEKABLE_FOOTER_SIZE,
+            file_size - ZSTD_SEEKABLE_FOOTER_SIZE,
+        )?;
+        if read_u32(&footer, 5) == ZSTD_SEEKABLE_MAGIC {
+            let count = read_u32(&footer, 0) as u64;
+            let entry_size = if footer[4] & 0x80 != 0 { 12 } else { 8 };
+            let table_size = count * entry_size;
+            let table_offset = (file_size - ZSTD_SEEKABLE_FOOTER_SIZE)
+                .checked_sub(table_size)
+                .ok_or_else(|| invalid("zstd", "invalid seek table"))?;
+            let table = read_at(file, table_size, table_offset)?;
+            let mut compressed = 0;
+            for entry in table.chunks_exact(entry_size as usize) {
+                let compressed_size = read_u32(entry, 0) as u64;
+                frames.push(RestartPoint {
+                    offset: read_u32(entry, 4) as u64, // Uncompressed size, until sorted out
+                    kind: RestartKind::ZstdFrame {
+                        compressed,
+                        compressed_size,
+                    },
+                });
+                compressed += compressed_size;
+            }
+            return Ok(frames);
+        }
+    }
+    let mut offset = 0;
+    while offset + 8 <= file_size {
+        let header = read_at(file, 8, offset)?;
+        let magic = read_u32(&header, 0);
+        if magic & 0xffff_fff0 == ZSTD_SKIPPABLE_MAGIC {
+            offset += 8 + read_u32(&header, 4) as u64;
+            continue;
+        }
+        if header[0..4] != *ZSTD_MAGIC {
+            return Err(invalid("zstd", "bad frame magic"));
+        }
+        let (compressed_size, size) = read_zstd_frame(file, offset)?;
+        frames.push(RestartPoint {
+            offset: size.unwrap_or(u64::MAX), // Uncompressed size, until sorted out
+            kind: RestartKind::ZstdFrame {
+                compressed: offset,
+                compressed_size,
+            },
+        });
+        offset += compressed_size;
+    }
+    Ok(frames)
+}
+
+impl CompressedDisk {
+    pub fn open(file: Box<dyn Disk>, format: CompressionFormat) -> Result<CompressedDisk, Error> {
+        let file: Arc<dyn Disk> = Arc::from(file);
+        let mut disk = CompressedDisk {
+            file: file.clone(),
+            size: 0,
+            restarts: Vec::new(),
+            cursor: Mutex::new(None),
+            cache: Mutex::new(PageCache::default()),
+        };
+        match format {
+            CompressionFormat::Gzip => disk.index_gzip()?,
+            CompressionFormat::Xz => {
+                // The offsets are set from the uncompressed sizes of the blocks
+                disk.restarts = read_xz_blocks(file.as_ref())?;
+                for restart in disk.restarts.iter_mut() {
+                    restart.offset = disk.size;
+                    if let RestartKind::XzBlock { size, .. } = restart.kind {
+                        disk.size += size;
+                    }
+                }
+            }
+            CompressionFormat::Zstd => {
+                // The offset holds the uncompressed size of the frame (or u64::MAX if unknown)
+                disk.restarts = read_zstd_frames(file.as_ref())?;
+                for i in 0..disk.restarts.len() {
+                    let mut size = disk.restarts[i].offset;
+                    if size == u64::MAX {
+                        size = io::copy(&mut disk.open_reader(i)?, &mut io::sink())?;
+                    }
+                    disk.restarts[i].offset = disk.size;
+                    disk.size += size;
+                }
+            }
+        }
+        Ok(disk)
+    }
+
+    /// Decompress the whole file once, saving checkpoints
+    fn index_gzip(&mut self) -> Result<(), Error> {
+        let start = GzipCheckpoint {
+            compressed: 0,
+            position: 0,
+            state: None,
+            window: vec![0; DEFLATE_WINDOW_SIZE].into_boxed_slice(),
+            window_pos: 0,
+        };
+        let mut reader = GzipReader::new(self.file.clone(), start.clone(), true)?;
+        self.restarts.push(RestartPoint {
+            offset: 0,
+            kind: RestartKind::Gzip(Box::new(start)),
+        });
+        let mut buffer = vec![0; 2 * DEFLATE_WINDOW_SIZE];
+        let mut next_checkpoint = CHECKPOINT_INTERVAL;
+        loop {
+            let len = reader.read(&mut buffer)?;
+            if len == 0 {
+                break;
+            }
+            self.size += len as u64;
+            if self.size >= next_checkpoint {
+                if let Some(checkpoint) = reader.get_checkpoint() {
+                    self.restarts.push(RestartPoint {
+                        offset: self.size,
+                        kind: RestartKind::Gzip(Box::new(checkpoint)),
+                    });
+                    next_checkpoint = self.size + CHECKPOINT_INTERVAL;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the decompression at a restart point
+    fn open_reader(&self, index: usize) -> Result<Box<dyn Read + Send>, Error> {
+        match &self.restarts[index].kind {
+            RestartKind::Gzip(checkpoint) => Ok(Box::new(GzipReader::new(
+                self.file.clone(),
+                checkpoint.as_ref().clone(),
+                false,
+            )?)),
+            RestartKind::XzBlock {
+                stream_flags,
+                compressed,
+                unpadded_size,
+                size,
+            } => {
+                // Single block stream: header, block, index and footer
+                let mut header = XZ_MAGIC.to_vec();
+                header.extend_from_slice(stream_flags);
+                header.extend_from_slice(&crc32(stream_flags).to_le_bytes());
+                let mut index = vec![0, 1];
+                write_xz_varint(&mut index, *unpadded_size);
+                write_xz_varint(&mut index, *size);
+                index.resize(index.len().next_multiple_of(4), 0);
+                index.extend_from_slice(&crc32(&index).to_le_bytes());
+                let mut footer = ((index.len() / 4 - 1) as u32).to_le_bytes().to_vec();
+                footer.extend_from_slice(stream_flags);
+                let mut trailer = index;
+                trailer.extend_from_slice(&crc32(&footer).to_le_bytes());
+                trailer.extend_from_slice(&footer);
+                trailer.extend_from_slice(XZ_FOOTER_MAGIC);
+                let block = DiskReader {
+                    disk: self.file.clone(),
+                    offset: *compressed,
+                    end: compressed + unpadded_size.next_multiple_of(4),
+                };
+                let stream = Cursor::new(header).chain(block).chain(Cursor::new(trailer));
+                Ok(Box::new(XzDecoder::new(stream)))
+            }
+            RestartKind::ZstdFrame {
+                compressed,
+                compressed_size,
+            } => {
+                let frame = DiskReader {
+                    disk: self.file.clone(),
+                    offset: *compressed,
+                    end: compressed + compressed_size,
+                };
+                Ok(Box::new(
+                    zstd::stream::read::Decoder::new(frame)?.single_frame(),
+                ))
+            }
+        }
+    }
+
+    /// Read from the cursor, continuing with the next xz block or zstd frame at its end
+    fn read_cursor(&self, cursor: &mut DecompressionCursor, buf: &mut [u8]) -> Result<(), Error> {
+        let mut done = 0;
+        while done < buf.len() {
+            let len = cursor.reader.read(&mut buf[done..])?;
+            if len == 0 {
+                let next = cursor.index + 1;
+                if next >= self.restarts.len() || self.restarts[next].offset != cursor.position {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Truncated compressed data",
+                    ));
+                }
+                cursor.reader = self.open_reader(next)?;
+                cursor.index = next;
+            }
+            done += len;
+            cursor.position += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Decompress a page, from the cursor if it is before the page
+    fn decompress_page(&self, page: u64) -> Result<Vec<u8>, Error> {
+        let start = page * PAGE_SIZE;
+        let index = self.restarts.partition_point(|x| x.offset <= start) - 1;
+        let mut cursor = self.cursor.lock().unwrap();
+        let reusable = matches!(&*cursor, Some(x)
+            if x.position <= start && x.position >= self.restarts[index].offset);
+        if !reusable {
+            *cursor = Some(DecompressionCursor {
+                index,
+                position: self.restarts[index].offset,
+                reader: self.open_reader(index)?,
+            });
+        }
+        let cursor = cursor.as_mut().unwrap();
+        let mut skipped = vec![0; PAGE_SIZE as usize];
+        while cursor.position < start {
+            let len = (start - cursor.position).min(PAGE_SIZE) as usize;
+            self.read_cursor(cursor, &mut skipped[..len])?;
+        }
+        let mut data = vec![0; PAGE_SIZE.min(self.size - start) as usize];
+        self.read_cursor(cursor, &mut data)?;
+        Ok(data)
+    }
+
+    /// Read a part of a page, through the cache. The cache is not locked during the
+    /// decompression, so that the other readers can use it meanwhile.
+    fn read_page(&self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        let page = offset / PAGE_SIZE;
+        let start = (offset % PAGE_SIZE) as usize;
+        let cached = self.cache.lock().unwrap().pages.get(&page).cloned();
+        let data = match cached {
+            Some(data) => data,
+            None => {
+                let data = Arc::new(self.decompress_page(page)?);
+                let mut cache = self.cache.lock().unwrap();
+                // Another reader may have decompressed the page meanwhile
+                if let Some(data) = cache.pages.get(&page) {
+                    data.clone()
+                } else {
+                    if cache.order.len() >= PAGE_CACHE_SIZE {
+                        let oldest = cache.order.pop_front().unwrap();
+                        cache.pages.remove(&oldest);
+                    }
+                    cache.pages.insert(page, data.clone());
+                    cache.order.push_back(page);
+                    data
+                }
+            }
+        };
+        buffer.copy_from_slice(&data[start..start + buffer.len()]);
+        Ok(())
+    }
+}
+
+impl Disk for CompressedDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        let offset = offset.calc_offset();
+        read_units(size, offset, PAGE_SIZE, self.size, |offset, buffer| {
+            self.read_page(offset, buffer)
+        })
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryDisk;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use xz2::write::XzEncoder;
+
+    /// Data that compresses, but not to almost nothing
+    fn test_data(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 7 + i / 4093) as u8).collect()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn xz(data: &[u8]) -> Vec<u8> {
+        let mut encoder = XzEncoder::new(Vec::new(), 1);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn open_data(compressed: Vec<u8>) -> Result<CompressedDisk, Error> {
+        let format = CompressionFormat::detect(&compressed).unwrap();
+        CompressedDisk::open(Box::new(MemoryDisk::new(compressed)), format)
+    }
+
+    /// Compare the whole disk, then reads across pages in reverse order
+    fn check_disk(disk: &CompressedDisk, data: &[u8]) {
+        assert_eq!(disk.get_size().unwrap(), data.len() as u64);
+        assert_eq!(read_at(disk, data.len() as u64, 0).unwrap(), data);
+        for offset in (0..data.len() - 100).step_by(50000).rev() {
+            let expected = &data[offset..offset + 100];
+            assert_eq!(read_at(disk, 100, offset as u64).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            CompressionFormat::detect(&gzip(b"x")),
+            Some(CompressionFormat::Gzip)
+        );
+        assert_eq!(
+            CompressionFormat::detect(&xz(b"x")),
+            Some(CompressionFormat::Xz)
+        );
+        assert_eq!(
+            CompressionFormat::detect(&zstd::encode_all(&b"x"[..], 1).unwrap()),
+            Some(CompressionFormat::Zstd)
+        );
+        assert_eq!(CompressionFormat::detect(b"\x1f"), None);
+    }
+
+    #[test]
+    fn test_gzip() {
+        // Two members, with trailing padding
+        let data = test_data(300000);
+        let mut compressed = gzip(&data[..100000]);
+        compressed.extend_from_slice(&gzip(&data[100000..]));
+        compressed.extend_from_slice(&[0; 512]);
+        check_disk(&open_data(compressed).unwrap(), &data);
+    }
+
+    #[test]
+    fn test_gzip_checkpoints() {
+        let data = test_data((CHECKPOINT_INTERVAL + 3 * PAGE_SIZE) as usize);
+        let disk = open_data(gzip(&data)).unwrap();
+        assert_eq!(disk.restarts.len(), 2);
+        let offset = CHECKPOINT_INTERVAL + PAGE_SIZE - 10;
+        let expected = &data[offset as usize..offset as usize + 20];
+        assert_eq!(read_at(&disk, 20, offset).unwrap(), expected);
+        assert_eq!(read_at(&disk, 20, 10).unwrap(), data[10..30]);
+    }
+
+    #[test]
+    fn test_gzip_errors() {
+        let compressed = gzip(&test_data(1000));
+        let mut corrupted = compressed.clone();
+        let len = corrupted.len();
+        corrupted[len - 8] ^= 1;
+        let err = open_data(corrupted).err().unwrap();
+        assert_eq!(err.to_string(), "gzip: checksum mismatch");
+        let err = open_data(compressed[..len - 4].to_vec()).err().unwrap();
+        assert_eq!(err.to_string(), "gzip: truncated file");
+    }
+
+    #[test]
+    fn test_xz() {
+        // Two streams (one block each), with stream padding
+        let data = test_data(300000);
+        let mut compressed = xz(&data[..200000]);
+        compressed.extend_from_slice(&[0; 4]);
+        compressed.extend_from_slice(&xz(&data[200000..]));
+        let disk = open_data(compressed.clone()).unwrap();
+        assert_eq!(disk.restarts.len(), 2);
+        assert_eq!(disk.restarts[1].offset, 200000);
+        check_disk(&disk, &data);
+        let len = compressed.len();
+        compressed[len - 1] = b'X';
+        assert!(open_data(compressed).is_err());
+    }
+
+    #[test]
+    fn test_zstd() {
+        // A frame with its size, a skippable frame and a frame without its size
+        let data = test_data(300000);
+        let mut compressed = zstd::encode_all(&data[..100000], 1).unwrap();
+        compressed.extend_from_slice(&(ZSTD_SKIPPABLE_MAGIC + 3).to_le_bytes());
+        compressed.extend_from_slice(&4u32.to_le_bytes());
+        compressed.extend_from_slice(b"skip");
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 1).unwrap();
+        encoder.include_contentsize(false).unwrap();
+        encoder.write_all(&data[100000..]).unwrap();
+        compressed.extend_from_slice(&encoder.finish().unwrap());
+        let disk = open_data(compressed).unwrap();
+        assert_eq!(disk.restarts.len(), 2);
+        assert_eq!(disk.restarts[1].offset, 100000);
+        check_disk(&disk, &data);
+    }
+
+    #[test]
+    fn test_zstd_seekable() {
+        // Seek table in a skippable frame: compressed and uncompressed sizes of the frames
+        let data = test_data(200000);
+        let mut compressed = Vec::new();
+        let mut table = Vec::new();
+        for part in data.chunks(150000) {
+            let frame = zstd::encode_all(part, 1).unwrap();
+            table.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            table.extend_from_slice(&(part.len() as u32).to_le_bytes());
+            compressed.extend_from_slice(&frame);
+        }
+        compressed.extend_from_slice(&(ZSTD_SKIPPABLE_MAGIC + 14).to_le_bytes());
+        compressed.extend_from_slice(&(table.len() as u32 + 9).to_le_bytes());
+        compressed.extend_from_slice(&table);
+        compressed.extend_from_slice(&2u32.to_le_bytes());
+        compressed.push(0);
+        compressed.extend_from_slice(&ZSTD_SEEKABLE_MAGIC.to_le_bytes());
+        let disk = open_data(compressed).unwrap();
+        assert_eq!(disk.restarts.len(), 2);
+        check_disk(&disk, &data);
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        // The pages are decompressed by several threads, each one checks its reads
+        let data = test_data(20 * PAGE_SIZE as usize);
+        let disk = open_data(xz(&data)).unwrap();
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let (disk, data) = (&disk, &data);
+                scope.spawn(move || {
+                    for page in (0..20).rev().skip(i) {
+                        let offset = page * PAGE_SIZE as usize + 100;
+                        let read = read_at(disk, 1000, offset as u64).unwrap();
+                        assert_eq!(read, data[offset..offset + 1000]);
+                    }
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,69 @@
+use crate::disk::{Disk, Offset};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+
+/// Read-only memory-mapped image: read_slice returns slices of the mapping
+pub struct MmapDisk {
+    map: Mmap,
+}
+
+impl MmapDisk {
+    /// Map an image file. The file must not be truncated while it is mapped.
+    pub fn open(filename: &str) -> Result<Self, Error> {
+        let file = File::open(filename)?;
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self { map })
+    }
+
+    fn get_range(&self, size: u64, offset: Offset) -> Result<&[u8], Error> {
+        let offset = offset.calc_offset();
+        if offset + size > self.map.len() as u64 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Read beyond the end of the disk ({})", offset + size),
+            ));
+        }
+        Ok(&self.map[offset as usize..(offset + size) as usize])
+    }
+}
+
+impl Disk for MmapDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        Ok(self.get_range(size, offset)?.to_vec())
+    }
+
+    fn read_slice(&self, size: u64, offset: Offset) -> Result<Cow<'_, [u8]>, Error> {
+        Ok(Cow::Borrowed(self.get_range(size, offset)?))
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.map.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::read_at;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn test_mmap() {
+        let path = temp_path("mmap.img");
+        std::fs::write(&path, (0..=255).collect::<Vec<u8>>()).unwrap();
+        let disk = MmapDisk::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(disk.get_size().unwrap(), 256);
+        assert_eq!(read_at(&disk, 3, 10).unwrap(), [10, 11, 12]);
+        let offset = Offset::Block {
+            block_size: 16,
+            block_num: 15,
+        };
+        let slice = disk.read_slice(16, offset).unwrap();
+        assert!(matches!(slice, Cow::Borrowed(_)));
+        assert_eq!(slice[15], 255);
+        assert!(read_at(&disk, 2, 255).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use crate::disk::{read_at, read_units, Disk, Offset};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileExt;
+use std::sync::{Arc, Mutex};
+
+pub const OVERLAY_CHUNK_SIZE: u64 = 4096;
+
+const OVERLAY_MAGIC: &[u8; 8] = b"EXT2COW\x00";
+const OVERLAY_HEADER_SIZE: u64 = 32; // Magic, chunk size, base size and reserved (u64)
+const OVERLAY_RECORD_SIZE: u64 = 8 + OVERLAY_CHUNK_SIZE; // Chunk number and data
+
+/// Changes written to an overlay: the modified chunks of the base disk, kept in memory
+/// or in a sidecar file. A sidecar file is a header followed by records (chunk number
+/// and data), a chunk rewritten is updated in place.
+pub struct Overlay {
+    path: Option<String>, // Sidecar file (None = in memory)
+    state: Mutex<OverlayState>,
+}
+
+struct OverlayState {
+    file: Option<File>,
+    memory: Vec<u8>,            // Records, when in memory
+    base_size: Option<u64>,     // Size of the base disk (None = no change recorded yet)
+    chunks: BTreeMap<u64, u64>, // Offset of the data of each modified chunk
+    records: u64,               // Number of records
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("overlay: {}", message))
+}
+
+impl fmt::Debug for Overlay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Overlay({})", self.get_name())
+    }
+}
+
+impl Overlay {
+    /// Overlay kept in memory, lost at exit
+    pub fn new_memory() -> Overlay {
+        Overlay {
+            path: None,
+            state: Mutex::new(OverlayState {
+                file: None,
+                memory: Vec::new(),
+                base_size: None,
+                chunks: BTreeMap::new(),
+                records: 0,
+            }),
+        }
+    }
+
+    /// Open (or create) a sidecar file, loading the changes it contains
+    pub fn open(path: &str) -> Result<Overlay, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut state = OverlayState {
+            file: None,
+            memory: Vec::new(),
+            base_size: None,
+            chunks: BTreeMap::new(),
+            records: 0,
+        };
+        if file_size > 0 {
+            let mut header = vec![0; OVERLAY_HEADER_SIZE as usize];
+            file.read_exact_at(&mut header, 0)
+                .map_err(|_| invalid("truncated header"))?;
+            if &header[0..8] != OVERLAY_MAGIC {
+                return Err(invalid("bad magic"));
+            }
+            if read_u64(&header, 8) != OVERLAY_CHUNK_SIZE {
+                return Err(invalid("unsupported chunk size"));
+            }
+            let base_size = read_u64(&header, 16);
+            state.base_size = Some(base_size);
+            // An incomplete last record (interrupted write) is ignored
+            state.records = (file_size - OVERLAY_HEADER_SIZE) / OVERLAY_RECORD_SIZE;
+            let mut chunk_num = [0; 8];
+            for i in 0..state.records {
+                let offset = OVERLAY_HEADER_SIZE + i * OVERLAY_RECORD_SIZE;
+                file.read_exact_at(&mut chunk_num, offset)?;
+                let chunk_num = u64::from_le_bytes(chunk_num);
+                if chunk_num >= base_size.div_ceil(OVERLAY_CHUNK_SIZE) {
+                    return Err(invalid(&format!(
+                        "chunk {} beyond the end of the disk",
+                        chunk_num
+                    )));
+                }
+                state.chunks.insert(chunk_num, offset + 8);
+            }
+        }
+        state.file = Some(file);
+        Ok(Overlay {
+            path: Some(path.to_string()),
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Sidecar file name, or "memory"
+    pub fn get_name(&self) -> &str {
+        self.path.as_deref().unwrap_or("memory")
+    }
+
+    /// Size of the base disk the changes were made on
+    pub fn get_base_size(&self) -> Option<u64> {
+        self.state.lock().unwrap().base_size
+    }
+
+    /// Numbers of the modified chunks, in order
+    pub fn get_chunks(&self) -> Vec<u64> {
+        self.state.lock().unwrap().chunks.keys().copied().collect()
+    }
+
+    /// Content of a modified chunk
+    pub fn read_chunk(&self, chunk_num: u64) -> Result<Option<Vec<u8>>, Error> {
+        let state = self.state.lock().unwrap();
+        match state.chunks.get(&chunk_num) {
+            Some(offset) => {
+                let mut data = vec![0; OVERLAY_CHUNK_SIZE as usize];
+                state.read(*offset, &mut data)?;
+                Ok(Some(data))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Forget all the changes
+    pub fn discard(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(file) = &state.file {
+            file.set_len(0)?;
+        }
+        state.memory.clear();
+        state.base_size = None;
+        state.chunks.clear();
+        state.records = 0;
+        Ok(())
+    }
+}
+
+impl OverlayState {
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        match &self.file {
+            Some(file) => file.read_exact_at(buffer, offset),
+            None => {
+                buffer.copy_from_slice(&self.memory[offset as usize..][..buffer.len()]);
+                Ok(())
+            }
+        }
+    }
+
+    fn write(&mut self, offset: u64, buffer: &[u8]) -> Result<(), Error> {
+        match &self.file {
+            Some(file) => file.write_all_at(buffer, offset),
+            None => {
+                let end = offset as usize + buffer.len();
+                if self.memory.len() < end {
+                    self.memory.resize(end, 0);
+                }
+                self.memory[offset as usize..end].copy_from_slice(buffer);
+                Ok(())
+            }
+        }
+    }
+
+    /// Check that the changes were made on a base disk of this size
+    fn check(&self, base_size: u64) -> Result<(), Error> {
+        match self.base_size {
+            Some(size) if size != base_size => Err(invalid(&format!(
+                "the changes were made on a disk of {} bytes, not {}",
+                size, base_size
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the header before the first change
+    fn attach(&mut self, base_size: u64) -> Result<(), Error> {
+        self.check(base_size)?;
+        if self.base_size.is_none() {
+            let mut header = OVERLAY_MAGIC.to_vec();
+            header.extend_from_slice(&OVERLAY_CHUNK_SIZE.to_le_bytes());
+            header.extend_from_slice(&base_size.to_le_bytes());
+            header.resize(OVERLAY_HEADER_SIZE as usize, 0);
+            self.write(0, &header)?;
+            self.base_size = Some(base_size);
+        }
+        Ok(())
+    }
+
+    /// Offset of the data of a chunk, adding a record with the given content if needed
+    fn get_or_add(&mut self, chunk_num: u64, data: &[u8]) -> Result<u64, Error> {
+        if let Some(offset) = self.chunks.get(&chunk_num) {
+            return Ok(*offset);
+        }
+        let offset = OVERLAY_HEADER_SIZE + self.records * OVERLAY_RECORD_SIZE;
+        let mut record = chunk_num.to_le_bytes().to_vec();
+        record.extend_from_slice(data);
+        record.resize(OVERLAY_RECORD_SIZE as usize, 0);
+        self.write(offset, &record)?;
+        self.records += 1;
+        self.chunks.insert(chunk_num, offset + 8);
+        Ok(offset + 8)
+    }
+}
+
+/// Copy-on-write disk: the base disk is never written, the writes go to an overlay
+pub struct OverlayDisk {
+    base: Box<dyn Disk>,
+    size: u64, // Size of the base disk
+    overlay: Arc<Overlay>,
+}
+
+impl OverlayDisk {
+    pub fn new(base: Box<dyn Disk>, overlay: Arc<Overlay>) -> Result<OverlayDisk, Error> {
+        let size = base.get_size()?;
+        overlay.state.lock().unwrap().check(size)?;
+        Ok(OverlayDisk {
+            base,
+            size,
+            overlay,
+        })
+    }
+
+    /// Read a part of a chunk, from the overlay if it was modified
+    fn read_chunk(&self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        let state = self.overlay.state.lock().unwrap();
+        match state.chunks.get(&(offset / OVERLAY_CHUNK_SIZE)) {
+            Some(data_offset) => state.read(data_offset + offset % OVERLAY_CHUNK_SIZE, buffer),
+            None => {
+                buffer.copy_from_slice(&read_at(self.base.as_ref(), buffer.len() as u64, offset)?);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Disk for OverlayDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        let offset = offset.calc_offset();
+        read_units(
+            size,
+            offset,
+            OVERLAY_CHUNK_SIZE,
+            self.size,
+            |offset, buffer| self.read_chunk(offset, buffer),
+        )
+    }
+
+    fn read_slice(&self, size: u64, offset: Offset) -> Result<Cow<'_, [u8]>, Error> {
+        // Borrowed from the base disk when the range was not modified
+        let start = offset.calc_offset();
+        let end = (start + size).div_ceil(OVERLAY_CHUNK_SIZE);
+        let modified = self
+            .overlay
+            .state
+            .lock()
+            .unwrap()
+            .chunks
+            .range(start / OVERLAY_CHUNK_SIZE..end)
+            .next()
+            .is_some();
+        if modified {
+            Ok(Cow::Owned(self.read(size, offset)?))
+        } else {
+            self.base.read_slice(size, offset)
+        }
+    }
+
+    fn write(&self, buffer: &[u8], offset: Offset) -> Result<(), Error> {
+        let offset = offset.calc_offset();
+        let size = buffer.len() as u64;
+        if offset + size > self.size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Write beyond the end of the disk ({})", offset + size),
+            ));
+        }
+        let mut state = self.overlay.state.lock().unwrap();
+        state.attach(self.size)?;
+        let mut done = 0;
+        while done < size {
+            let position = offset + done;
+            let chunk_num = position / OVERLAY_CHUNK_SIZE;
+            let in_chunk = position % OVERLAY_CHUNK_SIZE;
+            let len = (OVERLAY_CHUNK_SIZE - in_chunk).min(size - done);
+            // Copy on write: a new chunk starts with the content of the base disk
+            let initial = if state.chunks.contains_key(&chunk_num) {
+                Vec::new()
+            } else {
+                let start = chunk_num * OVERLAY_CHUNK_SIZE;
+                let chunk_size = OVERLAY_CHUNK_SIZE.min(self.size - start);
+                read_at(self.base.as_ref(), chunk_size, start)?
+            };
+            let data_offset = state.get_or_add(chunk_num, &initial)?;
+            state.write(
+                data_offset + in_chunk,
+                &buffer[done as usize..(done + len) as usize],
+            )?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{temp_path, MemoryDisk};
+
+    const BASE_SIZE: u64 = 10000; // 3 chunks, the last one incomplete
+
+    fn offset(offset: u64) -> Offset {
+        Offset::Block {
+            block_size: 1,
+            block_num: offset,
+        }
+    }
+
+    fn base_disk(size: u64) -> Box<dyn Disk> {
+        Box::new(MemoryDisk::new(vec![1; size as usize]))
+    }
+
+    #[test]
+    fn test_memory_overlay() {
+        let overlay = Arc::new(Overlay::new_memory());
+        let disk = OverlayDisk::new(base_disk(BASE_SIZE), overlay.clone()).unwrap();
+        assert_eq!(overlay.get_base_size(), None);
+        // Across two chunks, and at the end of the incomplete last chunk
+        disk.write(&[2; 4], offset(4094)).unwrap();
+        disk.write(&[3; 2], offset(BASE_SIZE - 2)).unwrap();
+        assert!(disk.write(&[3; 2], offset(BASE_SIZE - 1)).is_err());
+        assert_eq!(overlay.get_base_size(), Some(BASE_SIZE));
+        assert_eq!(overlay.get_chunks(), [0, 1, 2]);
+        assert_eq!(read_at(&disk, 6, 4093).unwrap(), [1, 2, 2, 2, 2, 1]);
+        assert_eq!(read_at(&disk, 3, BASE_SIZE - 3).unwrap(), [1, 3, 3]);
+        assert_eq!(&*disk.read_slice(2, offset(4095)).unwrap(), [2, 2]);
+        // The base disk is not modified
+        assert_eq!(read_at(disk.base.as_ref(), 4, 4094).unwrap(), [1; 4]);
+        let chunk = overlay.read_chunk(1).unwrap().unwrap();
+        assert_eq!(chunk[..3], [2, 2, 1]);
+        assert_eq!(overlay.read_chunk(3).unwrap(), None);
+        // The changes were made on a disk of another size
+        assert!(OverlayDisk::new(base_disk(BASE_SIZE + 1), overlay.clone()).is_err());
+        overlay.discard().unwrap();
+        assert!(overlay.get_chunks().is_empty());
+        assert_eq!(read_at(&disk, 4, 4094).unwrap(), [1; 4]);
+    }
+
+    #[test]
+    fn test_sidecar_file() {
+        let path = temp_path("overlay.cow");
+        let _ = std::fs::remove_file(&path);
+        let overlay = Arc::new(Overlay::open(&path).unwrap());
+        let disk = OverlayDisk::new(base_disk(BASE_SIZE), overlay).unwrap();
+        disk.write(&[2; 4], offset(4094)).unwrap();
+        disk.write(&[3; 4], offset(4098)).unwrap();
+        drop(disk);
+        // An interrupted write leaves an incomplete record
+        let mut data = std::fs::read(&path).unwrap();
+        assert_eq!(
+            data.len() as u64,
+            OVERLAY_HEADER_SIZE + 2 * OVERLAY_RECORD_SIZE
+        );
+        data.extend_from_slice(&[0; 100]);
+        std::fs::write(&path, &data).unwrap();
+        let overlay = Arc::new(Overlay::open(&path).unwrap());
+        assert_eq!(overlay.get_chunks(), [0, 1]);
+        let disk = OverlayDisk::new(base_disk(BASE_SIZE), overlay.clone()).unwrap();
+        assert_eq!(
+            read_at(&disk, 10, 4093).unwrap(),
+            [1, 2, 2, 2, 2, 3, 3, 3, 3, 1]
+        );
+        overlay.discard().unwrap();
+        drop(disk);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_sidecar_file() {
+        let path = temp_path("invalid.cow");
+        let mut header = OVERLAY_MAGIC.to_vec();
+        header.extend_from_slice(&OVERLAY_CHUNK_SIZE.to_le_bytes());
+        header.extend_from_slice(&BASE_SIZE.to_le_bytes());
+        header.resize(OVERLAY_HEADER_SIZE as usize, 0);
+        let mut record = 3u64.to_le_bytes().to_vec();
+        record.resize(OVERLAY_RECORD_SIZE as usize, 0);
+        let mut bad_magic = header.clone();
+        bad_magic[0] = b'X';
+        let mut bad_chunk_size = header.clone();
+        bad_chunk_size[9] = 0x20;
+        let results: Vec<Error> = [
+            bad_magic,
+            bad_chunk_size,
+            header[..10].to_vec(),
+            [header, record].concat(),
+        ]
+        .iter()
+        .map(|data| {
+            std::fs::write(&path, data).unwrap();
+            Overlay::open(&path).err().unwrap()
+        })
+        .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(results[0].to_string(), "overlay: bad magic");
+        assert_eq!(results[1].to_string(), "overlay: unsupported chunk size");
+        assert_eq!(results[2].to_string(), "overlay: truncated header");
+        assert_eq!(
+            results[3].to_string(),
+            "overlay: chunk 3 beyond the end of the disk"
+        );
+    }
+}
@@ -0,0 +1,671 @@
+use crate::disk::{crc32, Disk, Offset};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+const MBR_SIGNATURE: u16 = 0xaa55;
+const MBR_ENTRIES_OFFSET: usize = 446; // Partition entries in the MBR (and in the EBRs)
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_TYPE_GPT: u8 = 0xee; // Protective MBR of a GPT disk
+const MBR_EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+const MBR_FIRST_LOGICAL: u32 = 5; // Number of the first logical partition
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_SECTOR_SIZES: [u64; 2] = [512, 4096];
+const GPT_MAX_ENTRIES: u32 = 1024;
+const GPT_HEADER_MIN_SIZE: usize = 92;
+
+const MBR_TYPE_NAMES: [(u8, &str); 18] = [
+    (0x01, "FAT12"),
+    (0x04, "FAT16 <32M"),
+    (0x05, "Extended"),
+    (0x06, "FAT16"),
+    (0x07, "HPFS/NTFS/exFAT"),
+    (0x0b, "W95 FAT32"),
+    (0x0c, "W95 FAT32 (LBA)"),
+    (0x0e, "W95 FAT16 (LBA)"),
+    (0x0f, "W95 Ext'd (LBA)"),
+    (0x82, "Linux swap"),
+    (0x83, "Linux"),
+    (0x85, "Linux extended"),
+    (0x8e, "Linux LVM"),
+    (0xa5, "FreeBSD"),
+    (0xa6, "OpenBSD"),
+    (0xee, "GPT"),
+    (0xef, "EFI (FAT-12/16/32)"),
+    (0xfd, "Linux raid autodetect"),
+];
+
+const GPT_TYPE_NAMES: [(&str, &str); 12] = [
+    ("C12A7328-F81F-11D2-BA4B-00A0C93EC93B", "EFI System"),
+    ("21686148-6449-6E6F-744E-656564454649", "BIOS boot"),
+    ("E3C9E316-0B5C-4DB8-817D-F92DF00215AE", "Microsoft reserved"),
+    (
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7",
+        "Microsoft basic data",
+    ),
+    ("0FC63DAF-8483-4772-8E79-3D69D8477DE4", "Linux filesystem"),
+    ("0657FD6D-A4AB-43C4-84E5-0933C84B4F4F", "Linux swap"),
+    ("E6D6D379-F507-44C2-A23C-238F2A3DF928", "Linux LVM"),
+    ("A19D880F-05FC-4D3B-A006-743F0F84911E", "Linux RAID"),
+    (
+        "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709",
+        "Linux root (x86-64)",
+    ),
+    ("44479540-F297-41B2-9AF7-D131D5F0458A", "Linux root (x86)"),
+    (
+        "B921B045-1DF0-41C3-AF44-4C6F280D3FAE",
+        "Linux root (ARM-64)",
+    ),
+    ("933AC7E1-2EB4-4F13-B844-0E14E2AEF915", "Linux home"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionTableKind {
+    Mbr,
+    Gpt,
+}
+
+impl fmt::Display for PartitionTableKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartitionTableKind::Mbr => write!(f, "dos"),
+            PartitionTableKind::Gpt => write!(f, "gpt"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub number: u32,       // Partition number (MBR logical partitions start at 5)
+    pub start: u64,        // Offset of the first byte
+    pub size: u64,         // Size in bytes
+    pub type_name: String, // Partition type
+    pub name: String,      // Partition name (GPT only)
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionTable {
+    pub kind: PartitionTableKind,
+    pub sector_size: u64,
+    pub partitions: Vec<Partition>,
+}
+
+impl PartitionTable {
+    /// Read the partition table (MBR, with extended partitions, or GPT) of a disk
+    pub fn read(disk: &dyn Disk) -> Result<PartitionTable, Error> {
+        let mbr = read_sectors(disk, 0, 1, 512)?;
+        if u16::from_le_bytes([mbr[510], mbr[511]]) != MBR_SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "No partition table"));
+        }
+        let entries = read_mbr_entries(&mbr);
+        if entries.iter().any(|x| x.type_id == MBR_TYPE_GPT) {
+            for sector_size in GPT_SECTOR_SIZES {
+                if let Some(table) = read_gpt(disk, sector_size)? {
+                    return Ok(table);
+                }
+            }
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid GPT header"));
+        }
+        read_mbr(disk, &entries)
+    }
+
+    /// Find a partition by number
+    pub fn get_partition(&self, number: u32) -> Result<&Partition, Error> {
+        self.partitions
+            .iter()
+            .find(|x| x.number == number)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("Partition {} not found", number),
+                )
+            })
+    }
+}
+
+/// Part of a disk (a partition, or the data following an offset)
+pub struct PartitionDisk {
+    disk: Box<dyn Disk>,
+    start: u64,        // Offset of the first byte in the underlying disk
+    size: Option<u64>, // Size in bytes (None = up to the end of the underlying disk)
+}
+
+impl PartitionDisk {
+    pub fn new(disk: Box<dyn Disk>, start: u64, size: Option<u64>) -> PartitionDisk {
+        PartitionDisk { disk, start, size }
+    }
+
+    /// Offset in the underlying disk, checking that the range is inside the partition
+    fn get_offset(&self, size: u64, offset: Offset) -> Result<Offset, Error> {
+        let offset = offset.calc_offset();
+        if let Some(partition_size) = self.size {
+            match offset.checked_add(size) {
+                Some(end) if end <= partition_size => {}
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!(
+                            "Read beyond the end of the partition ({} bytes at {})",
+                            size, offset
+                        ),
+                    ))
+                }
+            }
+        }
+        Ok(Offset::BlockDelta {
+            block_size: 1,
+            base_block_num: self.start,
+            delta: offset,
+        })
+    }
+}
+
+impl Disk for PartitionDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        self.disk.read(size, self.get_offset(size, offset)?)
+    }
+
+    fn read_slice(&self, size: u64, offset: Offset) -> Result<Cow<'_, [u8]>, Error> {
+        self.disk.read_slice(size, self.get_offset(size, offset)?)
+    }
+
+    fn write(&self, buffer: &[u8], offset: Offset) -> Result<(), Error> {
+        let offset = self.get_offset(buffer.len() as u64, offset)?;
+        self.disk.write(buffer, offset)
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        match self.size {
+            Some(size) => Ok(size),
+            None => Ok(self.disk.get_size()?.saturating_sub(self.start)),
+        }
+    }
+}
+
+/// Primary partition entry of a MBR or EBR
+struct MbrEntry {
+    type_id: u8,
+    start_lba: u64,
+    sectors: u64,
+}
+
+fn read_sectors(disk: &dyn Disk, lba: u64, count: u64, sector_size: u64) -> Result<Vec<u8>, Error> {
+    let offset = Offset::Block {
+        block_size: sector_size,
+        block_num: lba,
+    };
+    disk.read(count * sector_size, offset)
+}
+
+fn read_mbr_entries(sector: &[u8]) -> Vec<MbrEntry> {
+    (0..4)
+        .map(|i| {
+            let entry = &sector[MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+            MbrEntry {
+                type_id: entry[4],
+                start_lba: u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64,
+                sectors: u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64,
+            }
+        })
+        .collect()
+}
+
+fn get_mbr_type_name(type_id: u8) -> String {
+    match MBR_TYPE_NAMES.iter().find(|x| x.0 == type_id) {
+        Some((_, name)) => name.to_string(),
+        None => format!("Unknown ({:#04x})", type_id),
+    }
+}
+
+fn new_mbr_partition(number: u32, type_id: u8, start_lba: u64, sectors: u64) -> Partition {
+    Partition {
+        number,
+        start: start_lba * 512,
+        size: sectors * 512,
+        type_name: get_mbr_type_name(type_id),
+        name: String::new(),
+    }
+}
+
+/// Read the primary partitions and the logical partitions of the extended partition
+fn read_mbr(disk: &dyn Disk, entries: &[MbrEntry]) -> Result<PartitionTable, Error> {
+    let mut partitions = Vec::new();
+    let mut extended: Option<u64> = None;
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.type_id == 0 || entry.sectors == 0 {
+            continue;
+        }
+        partitions.push(new_mbr_partition(
+            i as u32 + 1,
+            entry.type_id,
+            entry.start_lba,
+            entry.sectors,
+        ));
+        if MBR_EXTENDED_TYPES.contains(&entry.type_id) && extended.is_none() {
+            extended = Some(entry.start_lba);
+        }
+    }
+    if let Some(extended_lba) = extended {
+        // Chain of EBRs: the first entry is the logical partition (relative to the EBR),
+        // the second one links to the next EBR (relative to the extended partition)
+        let mut ebr_lba = extended_lba;
+        let mut visited = HashSet::new();
+        let mut number = MBR_FIRST_LOGICAL;
+        while visited.insert(ebr_lba) && visited.len() <= MAX_LOGICAL_PARTITIONS {
+            let ebr = read_sectors(disk, ebr_lba, 1, 512)?;
+            if u16::from_le_bytes([ebr[510], ebr[511]]) != MBR_SIGNATURE {
+                break;
+            }
+            let ebr_entries = read_mbr_entries(&ebr);
+            let logical = &ebr_entries[0];
+            if logical.type_id != 0 && logical.sectors != 0 {
+                partitions.push(new_mbr_partition(
+                    number,
+                    logical.type_id,
+                    ebr_lba + logical.start_lba,
+                    logical.sectors,
+                ));
+                number += 1;
+            }
+            let next = &ebr_entries[1];
+            if next.type_id == 0 || next.start_lba == 0 {
+                break;
+            }
+            ebr_lba = extended_lba + next.start_lba;
+        }
+    }
+    Ok(PartitionTable {
+        kind: PartitionTableKind::Mbr,
+        sector_size: 512,
+        partitions,
+    })
+}
+
+/// Format a GUID stored in mixed endianness
+pub fn format_guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        bytes[8],
+        bytes[9],
+        bytes[10..16]
+            .iter()
+            .map(|x| format!("{:02X}", x))
+            .collect::<String>()
+    )
+}
+
+fn get_gpt_type_name(guid: &str) -> String {
+    match GPT_TYPE_NAMES.iter().find(|x| x.0 == guid) {
+        Some((_, name)) => name.to_string(),
+        None => guid.to_string(),
+    }
+}
+
+/// GPT header fields used to read the partition entries
+struct GptHeader {
+    entries_lba: u64,   // First sector of the partition entries
+    entries_count: u32, // Number of partition entries
+    entry_size: usize,  // Size of a partition entry
+    entries_crc: u32,   // crc32 of the partition entries
+}
+
+fn gpt_invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// Read and verify the GPT header stored at the given sector, returns None if there is
+/// no GPT signature
+fn read_gpt_header(
+    disk: &dyn Disk,
+    lba: u64,
+    sector_size: u64,
+) -> Result<Option<GptHeader>, Error> {
+    let header = match read_sectors(disk, lba, 1, sector_size) {
+        Ok(header) => header,
+        Err(_) => return Ok(None),
+    };
+    if &header[0..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+    let header_size = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+    if header_size < GPT_HEADER_MIN_SIZE || header_size > header.len() {
+        return Err(gpt_invalid("Invalid GPT header size"));
+    }
+    // The checksum is computed with the checksum field set to 0
+    let mut copy = header[..header_size].to_vec();
+    copy[16..20].fill(0);
+    if crc32(&copy) != u32::from_le_bytes(header[16..20].try_into().unwrap()) {
+        return Err(gpt_invalid("GPT header checksum does not match"));
+    }
+    if u64::from_le_bytes(header[24..32].try_into().unwrap()) != lba {
+        return Err(gpt_invalid("Invalid GPT header location"));
+    }
+    let header = GptHeader {
+        entries_lba: u64::from_le_bytes(header[72..80].try_into().unwrap()),
+        entries_count: u32::from_le_bytes(header[80..84].try_into().unwrap()),
+        entry_size: u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize,
+        entries_crc: u32::from_le_bytes(header[88..92].try_into().unwrap()),
+    };
+    if header.entries_count > GPT_MAX_ENTRIES || header.entry_size < 128 {
+        return Err(gpt_invalid("Invalid GPT header"));
+    }
+    Ok(Some(header))
+}
+
+/// Read and verify the partition entries of a GPT header
+fn read_gpt_entries(
+    disk: &dyn Disk,
+    header: &GptHeader,
+    sector_size: u64,
+) -> Result<Vec<u8>, Error> {
+    let offset = Offset::Block {
+        block_size: sector_size,
+        block_num: header.entries_lba,
+    };
+    let buffer = disk.read(
+        header.entries_count as u64 * header.entry_size as u64,
+        offset,
+    )?;
+    if crc32(&buffer) != header.entries_crc {
+        return Err(gpt_invalid("GPT partition entries checksum does not match"));
+    }
+    Ok(buffer)
+}
+
+/// Read the GPT header at the given sector and its entries
+fn read_gpt_table(
+    disk: &dyn Disk,
+    lba: u64,
+    sector_size: u64,
+) -> Result<Option<PartitionTable>, Error> {
+    let header = match read_gpt_header(disk, lba, sector_size)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let buffer = read_gpt_entries(disk, &header, sector_size)?;
+    let mut partitions = Vec::new();
+    for (i, entry) in buffer.chunks_exact(header.entry_size).enumerate() {
+        if entry[0..16].iter().all(|x| *x == 0) {
+            continue; // Unused entry
+        }
+        let first_lba = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+        let last_lba = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+        let name: Vec<u16> = entry[56..128]
+            .chunks_exact(2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]))
+            .take_while(|x| *x != 0)
+            .collect();
+        partitions.push(Partition {
+            number: i as u32 + 1,
+            start: first_lba * sector_size,
+            size: (last_lba + 1).saturating_sub(first_lba) * sector_size,
+            type_name: get_gpt_type_name(&format_guid(&entry[0..16])),
+            name: String::from_utf16_lossy(&name),
+        });
+    }
+    Ok(Some(PartitionTable {
+        kind: PartitionTableKind::Gpt,
+        sector_size,
+        partitions,
+    }))
+}
+
+/// Read the GPT, using the backup header in the last sector when the primary one
+/// is missing or damaged. Returns None if there is no GPT for this sector size.
+fn read_gpt(disk: &dyn Disk, sector_size: u64) -> Result<Option<PartitionTable>, Error> {
+    let primary = read_gpt_table(disk, 1, sector_size);
+    if let Ok(Some(table)) = primary {
+        return Ok(Some(table));
+    }
+    let sectors = disk.get_size()? / sector_size;
+    if sectors > 2 {
+        if let Ok(Some(table)) = read_gpt_table(disk, sectors - 1, sector_size) {
+            match &primary {
+                Err(err) => eprintln!(
+                    "warning: primary GPT is damaged ({}), using the backup",
+                    err
+                ),
+                _ => eprintln!("warning: primary GPT header not found, using the backup"),
+            }
+            return Ok(Some(table));
+        }
+    }
+    primary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryDisk;
+
+    const SECTORS: u64 = 128; // Size of the test disks in sectors of 512 bytes
+    const LINUX_GUID: [u8; 16] = [
+        0xaf, 0x3d, 0xc6, 0x0f, 0x83, 0x84, 0x72, 0x47, 0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d,
+        0xe4,
+    ];
+
+    fn write_mbr_entry(sector: &mut [u8], i: usize, type_id: u8, start_lba: u32, sectors: u32) {
+        let entry = &mut sector[MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        entry[4] = type_id;
+        entry[8..12].copy_from_slice(&start_lba.to_le_bytes());
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    }
+
+    fn set_mbr_signature(sector: &mut [u8]) {
+        sector[510..512].copy_from_slice(&MBR_SIGNATURE.to_le_bytes());
+    }
+
+    /// GPT header at header_lba, with 4 entries at entries_lba
+    fn write_gpt_header(data: &mut [u8], header_lba: u64, alternate_lba: u64, entries_lba: u64) {
+        let entries_start = entries_lba as usize * 512;
+        let entries_crc = crc32(&data[entries_start..entries_start + 4 * 128]);
+        let header = &mut data[header_lba as usize * 512..][..512];
+        header[0..8].copy_from_slice(GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&(GPT_HEADER_MIN_SIZE as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&header_lba.to_le_bytes());
+        header[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+        header[40..48].copy_from_slice(&34u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(SECTORS - 34).to_le_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let crc = crc32(&header[..GPT_HEADER_MIN_SIZE]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Disk with a protective MBR and a GPT (primary and backup) with a partition
+    fn gpt_disk() -> Vec<u8> {
+        let mut data = vec![0; SECTORS as usize * 512];
+        write_mbr_entry(&mut data, 0, MBR_TYPE_GPT, 1, SECTORS as u32 - 1);
+        set_mbr_signature(&mut data);
+        for entries_lba in [2, SECTORS - 2] {
+            let entry = &mut data[entries_lba as usize * 512..][..128];
+            entry[0..16].copy_from_slice(&LINUX_GUID);
+            entry[32..40].copy_from_slice(&34u64.to_le_bytes());
+            entry[40..48].copy_from_slice(&100u64.to_le_bytes());
+            for (i, c) in "data".encode_utf16().enumerate() {
+                entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        write_gpt_header(&mut data, 1, SECTORS - 1, 2);
+        write_gpt_header(&mut data, SECTORS - 1, 1, SECTORS - 2);
+        data
+    }
+
+    fn read_table(data: Vec<u8>) -> Result<PartitionTable, Error> {
+        PartitionTable::read(&MemoryDisk::new(data))
+    }
+
+    fn assert_gpt(table: &PartitionTable) {
+        assert_eq!(table.kind, PartitionTableKind::Gpt);
+        assert_eq!(table.sector_size, 512);
+        assert_eq!(table.partitions.len(), 1);
+        let partition = &table.partitions[0];
+        assert_eq!(partition.number, 1);
+        assert_eq!(partition.start, 34 * 512);
+        assert_eq!(partition.size, 67 * 512);
+        assert_eq!(partition.type_name, "Linux filesystem");
+        assert_eq!(partition.name, "data");
+    }
+
+    #[test]
+    fn test_format_guid() {
+        assert_eq!(
+            format_guid(&LINUX_GUID),
+            "0FC63DAF-8483-4772-8E79-3D69D8477DE4"
+        );
+        assert_eq!(
+            get_gpt_type_name("0FC63DAF-8483-4772-8E79-3D69D8477DE4"),
+            "Linux filesystem"
+        );
+        assert_eq!(get_mbr_type_name(0x83), "Linux");
+        assert_eq!(get_mbr_type_name(0x42), "Unknown (0x42)");
+    }
+
+    #[test]
+    fn test_gpt() {
+        assert_gpt(&read_table(gpt_disk()).unwrap());
+    }
+
+    #[test]
+    fn test_gpt_backup() {
+        let damages: [(usize, u8); 3] = [
+            (512, 0),             // Primary header signature
+            (512 + 60, 0xff),     // Primary header content (checksum mismatch)
+            (2 * 512 + 60, 0xff), // Primary partition entries (checksum mismatch)
+        ];
+        for (offset, value) in damages {
+            let mut data = gpt_disk();
+            data[offset] = value;
+            assert_gpt(&read_table(data).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_gpt_damaged() {
+        // Both headers damaged
+        let mut data = gpt_disk();
+        data[512 + 60] = 0xff;
+        data[(SECTORS as usize - 1) * 512 + 60] = 0xff;
+        let err = read_table(data).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // Both entries damaged
+        let mut data = gpt_disk();
+        data[2 * 512 + 60] = 0xff;
+        data[(SECTORS as usize - 2) * 512 + 60] = 0xff;
+        let err = read_table(data).unwrap_err();
+        assert!(err.to_string().contains("entries checksum"), "{}", err);
+        // No GPT at all
+        let mut data = gpt_disk();
+        data[512] = 0;
+        data[(SECTORS as usize - 1) * 512] = 0;
+        let err = read_table(data).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_mbr() {
+        let mut data = vec![0; SECTORS as usize * 512];
+        write_mbr_entry(&mut data, 0, 0x83, 1, 10);
+        write_mbr_entry(&mut data, 1, 0x05, 20, 40);
+        set_mbr_signature(&mut data);
+        // Logical partitions: the first EBR links to the second one
+        let ebr = &mut data[20 * 512..21 * 512];
+        write_mbr_entry(ebr, 0, 0x83, 1, 5);
+        write_mbr_entry(ebr, 1, 0x05, 10, 10);
+        set_mbr_signature(ebr);
+        let ebr = &mut data[30 * 512..31 * 512];
+        write_mbr_entry(ebr, 0, 0x82, 2, 4);
+        set_mbr_signature(ebr);
+        let table = read_table(data.clone()).unwrap();
+        assert_eq!(table.kind, PartitionTableKind::Mbr);
+        let partitions: Vec<(u32, u64, u64, &str)> = table
+            .partitions
+            .iter()
+            .map(|x| (x.number, x.start / 512, x.size / 512, x.type_name.as_str()))
+            .collect();
+        let expected = [
+            (1, 1, 10, "Linux"),
+            (2, 20, 40, "Extended"),
+            (5, 21, 5, "Linux"),
+            (6, 32, 4, "Linux swap"),
+        ];
+        assert_eq!(partitions, expected);
+        assert_eq!(table.get_partition(6).unwrap().start, 32 * 512);
+        let err = table.get_partition(3).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        // A loop in the EBR chain stops the scan
+        write_mbr_entry(&mut data[30 * 512..31 * 512], 1, 0x05, 10, 10);
+        assert_eq!(read_table(data).unwrap().partitions.len(), 4);
+        // No signature
+        let err = read_table(vec![0; 1024]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_partition_disk() {
+        let data: Vec<u8> = (0..=255).collect();
+        let disk = PartitionDisk::new(Box::new(MemoryDisk::new(data.clone())), 16, Some(32));
+        assert_eq!(disk.get_size().unwrap(), 32);
+        assert_eq!(
+            disk.read(
+                4,
+                Offset::Block {
+                    block_size: 4,
+                    block_num: 7
+                }
+            )
+            .unwrap(),
+            [44, 45, 46, 47]
+        );
+        for (size, offset) in [(4, 29), (1, 32), (2, u64::MAX)] {
+            let offset = Offset::Block {
+                block_size: 1,
+                block_num: offset,
+            };
+            let err = disk.read(size, offset).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        }
+        disk.write(
+            &[1, 2],
+            Offset::Block {
+                block_size: 1,
+                block_num: 30,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            disk.read(
+                2,
+                Offset::Block {
+                    block_size: 1,
+                    block_num: 30
+                }
+            )
+            .unwrap(),
+            [1, 2]
+        );
+        // Up to the end of the underlying disk
+        let disk = PartitionDisk::new(Box::new(MemoryDisk::new(data)), 16, None);
+        assert_eq!(disk.get_size().unwrap(), 240);
+        assert_eq!(
+            disk.read(
+                1,
+                Offset::Block {
+                    block_size: 1,
+                    block_num: 239
+                }
+            )
+            .unwrap(),
+            [255]
+        );
+    }
+}
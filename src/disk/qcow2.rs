@@ -0,0 +1,397 @@
+use crate::disk::{read_at, read_units, Disk, Offset};
+use flate2::read::DeflateDecoder;
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
+use std::sync::Mutex;
+
+pub const QCOW2_MAGIC: &[u8; 4] = b"QFI\xfb";
+
+const QCOW2_HEADER_SIZE: u64 = 104; // Size of the version 3 header
+const QCOW2_MIN_CLUSTER_BITS: u32 = 9;
+const QCOW2_MAX_CLUSTER_BITS: u32 = 21;
+const QCOW2_MAX_BACKING_FILE_SIZE: u32 = 1023;
+
+const QCOW2_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00; // Host cluster offset in L1/L2 entries
+const QCOW2_COMPRESSED: u64 = 1 << 62; // L2 entry flag: compressed cluster
+const QCOW2_ZERO: u64 = 1; // L2 entry flag: cluster reads as zeros (version 3)
+
+const QCOW2_INCOMPAT_DIRTY: u64 = 1 << 0;
+const QCOW2_INCOMPAT_CORRUPT: u64 = 1 << 1;
+const QCOW2_INCOMPAT_COMPRESSION: u64 = 1 << 3;
+const QCOW2_INCOMPAT_KNOWN: u64 = QCOW2_INCOMPAT_DIRTY | QCOW2_INCOMPAT_CORRUPT;
+
+/// Function opening a backing file
+pub type OpenBacking = dyn Fn(&str) -> Result<Box<dyn Disk>, Error>;
+
+/// Location of the data of a guest cluster
+enum Qcow2Cluster {
+    Unallocated,                           // Read from the backing file (or zeros)
+    Zero,                                  // Reads as zeros
+    Data(u64),                             // Host offset of the cluster
+    Compressed { offset: u64, size: u64 }, // Host offset and size of the deflate stream
+}
+
+/// Read-only qcow2 (version 2 and 3) image
+pub struct Qcow2Disk {
+    file: Box<dyn Disk>,
+    backing: Option<Box<dyn Disk>>,
+    cluster_bits: u32,
+    size: u64,                                       // Virtual disk size
+    l1_table: Vec<u64>,                              // Offsets of the L2 tables
+    l2_cache: Mutex<Option<(u64, Vec<u8>)>>,         // Last L2 table read (offset, table)
+    compressed_cache: Mutex<Option<(u64, Vec<u8>)>>, // Last compressed cluster read
+}
+
+fn read_u32_be(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64_be(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("qcow2: {}", message))
+}
+
+impl Qcow2Disk {
+    /// Open a qcow2 image. The backing file is opened with open_backing,
+    /// relative to the directory of the image.
+    pub fn open(
+        file: Box<dyn Disk>,
+        filename: &str,
+        open_backing: &OpenBacking,
+    ) -> Result<Qcow2Disk, Error> {
+        let header = read_at(file.as_ref(), 72, 0)?;
+        if &header[0..4] != QCOW2_MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        let version = read_u32_be(&header, 4);
+        if version != 2 && version != 3 {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let cluster_bits = read_u32_be(&header, 20);
+        if !(QCOW2_MIN_CLUSTER_BITS..=QCOW2_MAX_CLUSTER_BITS).contains(&cluster_bits) {
+            return Err(invalid(&format!("invalid cluster bits {}", cluster_bits)));
+        }
+        if read_u32_be(&header, 32) != 0 {
+            return Err(invalid("encrypted images are not supported"));
+        }
+        if version == 3 {
+            let header = read_at(file.as_ref(), QCOW2_HEADER_SIZE, 0)?;
+            let incompatible = read_u64_be(&header, 72);
+            if incompatible & QCOW2_INCOMPAT_COMPRESSION != 0 {
+                let header_length = read_u32_be(&header, 100) as u64;
+                let compression = if header_length > QCOW2_HEADER_SIZE {
+                    read_at(file.as_ref(), 1, QCOW2_HEADER_SIZE)?[0]
+                } else {
+                    0
+                };
+                if compression != 0 {
+                    return Err(invalid("only zlib compression is supported"));
+                }
+            }
+            let unknown = incompatible & !(QCOW2_INCOMPAT_KNOWN | QCOW2_INCOMPAT_COMPRESSION);
+            if unknown != 0 {
+                return Err(invalid(&format!(
+                    "unsupported incompatible features {:#x}",
+                    unknown
+                )));
+            }
+            if incompatible & QCOW2_INCOMPAT_CORRUPT != 0 {
+                eprintln!(
+                    "warning: {}: the qcow2 image is marked as corrupt",
+                    filename
+                );
+            }
+        }
+        let size = read_u64_be(&header, 24);
+        let l1_size = read_u32_be(&header, 36) as u64;
+        let l1_offset = read_u64_be(&header, 40);
+        let l2_bits = cluster_bits - 3;
+        let l1_needed = size.div_ceil(1 << (cluster_bits + l2_bits));
+        if l1_size < l1_needed {
+            return Err(invalid("L1 table too small"));
+        }
+        let l1_table = read_at(file.as_ref(), l1_size * 8, l1_offset)?
+            .chunks_exact(8)
+            .map(|x| u64::from_be_bytes(x.try_into().unwrap()) & QCOW2_OFFSET_MASK)
+            .collect();
+        // Backing file, relative to the directory of the image
+        let backing_offset = read_u64_be(&header, 8);
+        let backing_size = read_u32_be(&header, 16);
+        let backing = if backing_offset != 0 && backing_size != 0 {
+            if backing_size > QCOW2_MAX_BACKING_FILE_SIZE {
+                return Err(invalid("backing file name too long"));
+            }
+            let name = read_at(file.as_ref(), backing_size as u64, backing_offset)?;
+            let name = String::from_utf8_lossy(&name).to_string();
+            let path = match Path::new(filename).parent() {
+                Some(dir) => dir.join(&name),
+                None => Path::new(&name).to_path_buf(),
+            };
+            let path = path.to_string_lossy();
+            match open_backing(&path) {
+                Ok(backing) => Some(backing),
+                // Already names the image of the chain that failed
+                Err(err) if err.to_string().starts_with("qcow2: backing file") => return Err(err),
+                Err(err) => {
+                    return Err(Error::new(
+                        err.kind(),
+                        format!("qcow2: backing file {}: {}", path, err),
+                    ))
+                }
+            }
+        } else {
+            None
+        };
+        Ok(Qcow2Disk {
+            file,
+            backing,
+            cluster_bits,
+            size,
+            l1_table,
+            l2_cache: Mutex::new(None),
+            compressed_cache: Mutex::new(None),
+        })
+    }
+
+    fn get_cluster_size(&self) -> u64 {
+        1 << self.cluster_bits
+    }
+
+    /// Find where the guest cluster containing the given offset is stored
+    fn get_cluster(&self, offset: u64) -> Result<Qcow2Cluster, Error> {
+        let l2_bits = self.cluster_bits - 3;
+        let l1_index = (offset >> (self.cluster_bits + l2_bits)) as usize;
+        let l2_index = ((offset >> self.cluster_bits) & ((1 << l2_bits) - 1)) as usize;
+        let l2_offset = self.l1_table[l1_index];
+        if l2_offset == 0 {
+            return Ok(Qcow2Cluster::Unallocated);
+        }
+        let mut l2_cache = self.l2_cache.lock().unwrap();
+        if !matches!(&*l2_cache, Some((cached, _)) if *cached == l2_offset) {
+            let table = read_at(self.file.as_ref(), self.get_cluster_size(), l2_offset)?;
+            *l2_cache = Some((l2_offset, table));
+        }
+        let table = &l2_cache.as_ref().unwrap().1;
+        let entry = read_u64_be(table, l2_index * 8);
+        if entry & QCOW2_COMPRESSED != 0 {
+            // Compressed cluster descriptor: host offset, then number of additional sectors
+            let offset_bits = 62 - (self.cluster_bits - 8);
+            let host_offset = entry & ((1 << offset_bits) - 1);
+            let sectors = ((entry >> offset_bits) & ((1 << (self.cluster_bits - 8)) - 1)) + 1;
+            let size = sectors * 512 - (host_offset & 511);
+            Ok(Qcow2Cluster::Compressed {
+                offset: host_offset,
+                size,
+            })
+        } else if entry & QCOW2_ZERO != 0 {
+            Ok(Qcow2Cluster::Zero)
+        } else if entry & QCOW2_OFFSET_MASK != 0 {
+            Ok(Qcow2Cluster::Data(entry & QCOW2_OFFSET_MASK))
+        } else {
+            Ok(Qcow2Cluster::Unallocated)
+        }
+    }
+
+    /// Read and inflate a compressed cluster
+    fn read_compressed(&self, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        let mut cache = self.compressed_cache.lock().unwrap();
+        if let Some((cached, data)) = &*cache {
+            if *cached == offset {
+                return Ok(data.clone());
+            }
+        }
+        // The last compressed cluster can end before the last sector
+        let size = size.min(self.file.get_size()?.saturating_sub(offset));
+        let compressed = read_at(self.file.as_ref(), size, offset)?;
+        let mut data = Vec::with_capacity(self.get_cluster_size() as usize);
+        DeflateDecoder::new(compressed.as_slice())
+            .take(self.get_cluster_size())
+            .read_to_end(&mut data)?;
+        if data.len() as u64 != self.get_cluster_size() {
+            return Err(invalid("truncated compressed cluster"));
+        }
+        *cache = Some((offset, data.clone()));
+        Ok(data)
+    }
+
+    /// Read a part of a guest cluster
+    fn read_cluster(&self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        let in_cluster = offset & (self.get_cluster_size() - 1);
+        match self.get_cluster(offset)? {
+            Qcow2Cluster::Data(host_offset) => {
+                let data = read_at(
+                    self.file.as_ref(),
+                    buffer.len() as u64,
+                    host_offset + in_cluster,
+                )?;
+                buffer.copy_from_slice(&data);
+            }
+            Qcow2Cluster::Compressed { offset, size } => {
+                let data = self.read_compressed(offset, size)?;
+                let start = in_cluster as usize;
+                buffer.copy_from_slice(&data[start..start + buffer.len()]);
+            }
+            Qcow2Cluster::Zero => buffer.fill(0),
+            Qcow2Cluster::Unallocated => match &self.backing {
+                Some(backing) => {
+                    // The backing file can be smaller than the image
+                    let backing_size = backing.get_size()?;
+                    let len = (buffer.len() as u64).min(backing_size.saturating_sub(offset));
+                    if len > 0 {
+                        let data = read_at(backing.as_ref(), len, offset)?;
+                        buffer[..len as usize].copy_from_slice(&data);
+                    }
+                    buffer[len as usize..].fill(0);
+                }
+                None => buffer.fill(0),
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Disk for Qcow2Disk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        let offset = offset.calc_offset();
+        read_units(
+            size,
+            offset,
+            self.get_cluster_size(),
+            self.size,
+            |offset, buffer| self.read_cluster(offset, buffer),
+        )
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::open_image;
+    use crate::test_util::{temp_path, MemoryDisk};
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const CLUSTER_BITS: u32 = 9;
+    const SIZE: u64 = 4096; // Virtual size: 8 clusters of 512 bytes
+    const L1_OFFSET: usize = 512;
+    const L2_OFFSET: usize = 1024;
+    const DATA_OFFSET: usize = 1536;
+    const COMPRESSED_OFFSET: usize = 2048;
+
+    /// Version 3 image: cluster 0 is data (0xaa), 1 reads as zeros, 2 is compressed
+    /// (0x55), the others are unallocated
+    fn qcow2_image(backing: Option<&str>) -> Vec<u8> {
+        let mut data = vec![0; COMPRESSED_OFFSET];
+        data[0..4].copy_from_slice(QCOW2_MAGIC);
+        data[4..8].copy_from_slice(&3u32.to_be_bytes());
+        if let Some(name) = backing {
+            data[8..16].copy_from_slice(&(QCOW2_HEADER_SIZE).to_be_bytes());
+            data[16..20].copy_from_slice(&(name.len() as u32).to_be_bytes());
+            data[QCOW2_HEADER_SIZE as usize..][..name.len()].copy_from_slice(name.as_bytes());
+        }
+        data[20..24].copy_from_slice(&CLUSTER_BITS.to_be_bytes());
+        data[24..32].copy_from_slice(&SIZE.to_be_bytes());
+        data[36..40].copy_from_slice(&1u32.to_be_bytes());
+        data[40..48].copy_from_slice(&(L1_OFFSET as u64).to_be_bytes());
+        data[100..104].copy_from_slice(&(QCOW2_HEADER_SIZE as u32).to_be_bytes());
+        data[L1_OFFSET..L1_OFFSET + 8].copy_from_slice(&(L2_OFFSET as u64).to_be_bytes());
+        let l2_entries = [
+            DATA_OFFSET as u64,
+            QCOW2_ZERO,
+            QCOW2_COMPRESSED | COMPRESSED_OFFSET as u64,
+        ];
+        for (i, entry) in l2_entries.iter().enumerate() {
+            data[L2_OFFSET + i * 8..][..8].copy_from_slice(&entry.to_be_bytes());
+        }
+        data[DATA_OFFSET..COMPRESSED_OFFSET].fill(0xaa);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0x55; 512]).unwrap();
+        data.extend_from_slice(&encoder.finish().unwrap());
+        data
+    }
+
+    fn no_backing(path: &str) -> Result<Box<dyn Disk>, Error> {
+        panic!("unexpected backing file {}", path)
+    }
+
+    fn open_data(data: Vec<u8>) -> Result<Qcow2Disk, Error> {
+        Qcow2Disk::open(Box::new(MemoryDisk::new(data)), "test.qcow2", &no_backing)
+    }
+
+    fn read_all(disk: &dyn Disk) -> Vec<u8> {
+        read_at(disk, disk.get_size().unwrap(), 0).unwrap()
+    }
+
+    #[test]
+    fn test_read_clusters() {
+        let disk = open_data(qcow2_image(None)).unwrap();
+        assert_eq!(disk.get_size().unwrap(), SIZE);
+        let data = read_all(&disk);
+        assert_eq!(data[..512], [0xaa; 512]);
+        assert_eq!(data[512..1024], [0; 512]);
+        assert_eq!(data[1024..1536], [0x55; 512]);
+        assert!(data[1536..].iter().all(|x| *x == 0));
+        // Across the data and zero clusters
+        assert_eq!(read_at(&disk, 4, 510).unwrap(), [0xaa, 0xaa, 0, 0]);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut data = qcow2_image(None);
+        data[0] = b'X';
+        assert!(open_data(data).is_err());
+        let mut data = qcow2_image(None);
+        data[4..8].copy_from_slice(&4u32.to_be_bytes());
+        let err = open_data(data).err().unwrap();
+        assert_eq!(err.to_string(), "qcow2: unsupported version 4");
+        let mut data = qcow2_image(None);
+        data[20..24].copy_from_slice(&30u32.to_be_bytes());
+        assert!(open_data(data).is_err());
+        let mut data = qcow2_image(None);
+        data[32..36].copy_from_slice(&1u32.to_be_bytes());
+        assert!(open_data(data).is_err());
+        let mut data = qcow2_image(None);
+        data[72..80].copy_from_slice(&(1u64 << 10).to_be_bytes());
+        assert!(open_data(data).is_err());
+    }
+
+    #[test]
+    fn test_backing_file() {
+        // The backing file is smaller than the image: zeros after its end
+        let open_backing = |path: &str| -> Result<Box<dyn Disk>, Error> {
+            assert_eq!(path, "/images/base.raw");
+            Ok(Box::new(MemoryDisk::new(vec![0x11; 2048])))
+        };
+        let disk = Qcow2Disk::open(
+            Box::new(MemoryDisk::new(qcow2_image(Some("base.raw")))),
+            "/images/top.qcow2",
+            &open_backing,
+        )
+        .unwrap();
+        let data = read_all(&disk);
+        assert_eq!(data[..512], [0xaa; 512]);
+        assert_eq!(data[512..1024], [0; 512]);
+        assert_eq!(data[1536..2048], [0x11; 512]);
+        assert!(data[2048..].iter().all(|x| *x == 0));
+    }
+
+    #[test]
+    fn test_backing_loop() {
+        let path = temp_path("loop.qcow2");
+        let name = Path::new(&path).file_name().unwrap().to_str().unwrap();
+        std::fs::write(&path, qcow2_image(Some(name))).unwrap();
+        let result = open_image(&path, false);
+        std::fs::remove_file(&path).unwrap();
+        let err = result.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Backing chain longer than"));
+    }
+}
@@ -0,0 +1,290 @@
+use crate::disk::mmap::MmapDisk;
+use crate::disk::{read_at, Disk, FileDisk, Offset};
+use std::borrow::Cow;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+const EWF_SIGNATURES: [&[u8; 8]; 2] = [b"EVF\x09\x0d\x0a\xff\x00", b"EVF2\x0d\x0a\x81\x00"];
+const EWF_MAX_SEGMENT: usize = 99 + 26 * 26 * 22; // E01 to ZZZ
+
+/// Image split in several raw files (segments), read as one disk
+pub struct SplitDisk {
+    segments: Vec<Box<dyn Disk>>,
+    starts: Vec<u64>, // Offset of each segment in the disk
+    size: u64,
+}
+
+/// Extension of the segment i (starting at 1) with the EWF naming: E01 to E99,
+/// then EAA to EZZ, FAA to ZZZ
+fn ewf_extension(i: usize, uppercase: bool) -> String {
+    let extension = if i <= 99 {
+        format!("E{:02}", i)
+    } else {
+        let n = i - 100;
+        [n / 676, n / 26 % 26, n % 26]
+            .iter()
+            .zip([b'E', b'A', b'A'])
+            .map(|(x, base)| (base + *x as u8) as char)
+            .collect()
+    };
+    if uppercase {
+        extension
+    } else {
+        extension.to_lowercase()
+    }
+}
+
+/// Names of the segments of a split image given its first segment: image.001 (or .000)
+/// followed by image.002..., or image.E01 followed by image.E02...
+/// Returns only the file name if it is not the first segment of a split image.
+pub fn get_segment_names(filename: &str) -> Vec<String> {
+    let mut names = vec![filename.to_string()];
+    let Some((base, extension)) = filename.rsplit_once('.') else {
+        return names;
+    };
+    let next: Box<dyn Fn(usize) -> Option<String>> = match extension {
+        "000" | "001" => {
+            let first: usize = extension.parse().unwrap();
+            Box::new(move |i| Some(format!("{}.{:03}", base, first + i)))
+        }
+        "E01" | "e01" => {
+            let uppercase = extension == "E01";
+            Box::new(move |i| {
+                (i < EWF_MAX_SEGMENT)
+                    .then(|| format!("{}.{}", base, ewf_extension(i + 1, uppercase)))
+            })
+        }
+        _ => return names,
+    };
+    while let Some(name) = next(names.len()) {
+        if !Path::new(&name).exists() {
+            break;
+        }
+        names.push(name);
+    }
+    names
+}
+
+/// Open an image file, or all the segments of a split image
+pub fn open_segments(filename: &str, read_write: bool) -> Result<Box<dyn Disk>, Error> {
+    // Read-only files are memory-mapped, except the ones that cannot be (block devices)
+    let open = |name: &str| -> Result<Box<dyn Disk>, Error> {
+        if read_write {
+            return Ok(Box::new(FileDisk::open_rw(name)?));
+        }
+        match MmapDisk::open(name) {
+            Ok(disk) if disk.get_size()? > 0 => Ok(Box::new(disk)),
+            _ => Ok(Box::new(FileDisk::open(name)?)),
+        }
+    };
+    let names = get_segment_names(filename);
+    let first = open(&names[0])?;
+    let signature = if first.get_size()? >= 8 {
+        read_at(first.as_ref(), 8, 0)?
+    } else {
+        Vec::new()
+    };
+    if EWF_SIGNATURES.iter().any(|x| x[..] == signature[..]) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{}: EWF containers are not supported, only raw segments",
+                filename
+            ),
+        ));
+    }
+    if names.len() == 1 {
+        return Ok(first);
+    }
+    let mut segments = vec![first];
+    for name in &names[1..] {
+        segments.push(open(name)?);
+    }
+    Ok(Box::new(SplitDisk::new(segments)?))
+}
+
+impl SplitDisk {
+    pub fn new(segments: Vec<Box<dyn Disk>>) -> Result<SplitDisk, Error> {
+        let mut starts = Vec::new();
+        let mut size = 0;
+        for segment in &segments {
+            starts.push(size);
+            size += segment.get_size()?;
+        }
+        Ok(SplitDisk {
+            segments,
+            starts,
+            size,
+        })
+    }
+
+    /// Call f(segment, offset in the segment, range of the request) for each segment
+    /// covered by the request
+    fn for_each_segment(
+        &self,
+        size: u64,
+        offset: u64,
+        mut f: impl FnMut(&dyn Disk, u64, std::ops::Range<usize>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if offset + size > self.size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Read beyond the end of the disk ({})", offset + size),
+            ));
+        }
+        let mut done = 0;
+        let mut index = self.starts.partition_point(|x| *x <= offset).max(1) - 1;
+        while done < size {
+            let position = offset + done;
+            let segment_end = self.starts.get(index + 1).copied().unwrap_or(self.size);
+            let len = (segment_end - position).min(size - done);
+            if len > 0 {
+                let range = done as usize..(done + len) as usize;
+                f(
+                    self.segments[index].as_ref(),
+                    position - self.starts[index],
+                    range,
+                )?;
+            }
+            done += len;
+            index += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Disk for SplitDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        let mut buffer = vec![0; size as usize];
+        self.for_each_segment(size, offset.calc_offset(), |segment, offset, range| {
+            let len = range.len() as u64;
+            buffer[range].copy_from_slice(&read_at(segment, len, offset)?);
+            Ok(())
+        })?;
+        Ok(buffer)
+    }
+
+    fn read_slice(&self, size: u64, offset: Offset) -> Result<Cow<'_, [u8]>, Error> {
+        // Borrowed only when the range is inside a single segment
+        let offset = offset.calc_offset();
+        let index = self.starts.partition_point(|x| *x <= offset).max(1) - 1;
+        let segment_end = self.starts.get(index + 1).copied().unwrap_or(self.size);
+        if offset + size <= segment_end {
+            let offset = Offset::Block {
+                block_size: 1,
+                block_num: offset - self.starts[index],
+            };
+            return self.segments[index].read_slice(size, offset);
+        }
+        let offset = Offset::Block {
+            block_size: 1,
+            block_num: offset,
+        };
+        Ok(Cow::Owned(self.read(size, offset)?))
+    }
+
+    fn write(&self, buffer: &[u8], offset: Offset) -> Result<(), Error> {
+        let size = buffer.len() as u64;
+        self.for_each_segment(size, offset.calc_offset(), |segment, offset, range| {
+            let offset = Offset::Block {
+                block_size: 1,
+                block_num: offset,
+            };
+            segment.write(&buffer[range], offset)
+        })
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{temp_path, MemoryDisk};
+    use std::fs;
+
+    /// Segments of 1000, 0 and 500 bytes
+    fn split_disk() -> SplitDisk {
+        let segments: Vec<Box<dyn Disk>> = vec![
+            Box::new(MemoryDisk::new(vec![1; 1000])),
+            Box::new(MemoryDisk::new(Vec::new())),
+            Box::new(MemoryDisk::new(vec![2; 500])),
+        ];
+        SplitDisk::new(segments).unwrap()
+    }
+
+    #[test]
+    fn test_ewf_extension() {
+        assert_eq!(ewf_extension(1, true), "E01");
+        assert_eq!(ewf_extension(99, true), "E99");
+        assert_eq!(ewf_extension(100, true), "EAA");
+        assert_eq!(ewf_extension(126, false), "eba");
+        assert_eq!(ewf_extension(EWF_MAX_SEGMENT, true), "ZZZ");
+    }
+
+    #[test]
+    fn test_segment_names() {
+        let dir = temp_path("split");
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "image.001",
+            "image.002",
+            "image.003",
+            "disk.E01",
+            "disk.E02",
+        ] {
+            fs::write(format!("{}/{}", dir, name), b"data").unwrap();
+        }
+        let names = get_segment_names(&format!("{}/image.001", dir));
+        let ewf_names = get_segment_names(&format!("{}/disk.E01", dir));
+        let single = get_segment_names(&format!("{}/image.002", dir));
+        let disk = open_segments(&format!("{}/image.001", dir), false).unwrap();
+        let size = disk.get_size().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<&str> = names.iter().map(|x| &x[dir.len() + 1..]).collect();
+        assert_eq!(names, ["image.001", "image.002", "image.003"]);
+        assert_eq!(ewf_names.len(), 2);
+        assert!(ewf_names[1].ends_with("disk.E02"));
+        assert_eq!(single.len(), 1);
+        assert_eq!(size, 12);
+    }
+
+    #[test]
+    fn test_ewf_container() {
+        let path = temp_path("container.E01");
+        fs::write(&path, EWF_SIGNATURES[0]).unwrap();
+        let result = open_segments(&path, false);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_read_across_segments() {
+        let disk = split_disk();
+        assert_eq!(disk.get_size().unwrap(), 1500);
+        assert_eq!(read_at(&disk, 4, 998).unwrap(), [1, 1, 2, 2]);
+        assert_eq!(read_at(&disk, 10, 1000).unwrap(), [2; 10]);
+        assert!(read_at(&disk, 2, 1499).is_err());
+        // Borrowed inside a segment, copied across segments
+        let offset = |x| Offset::Block {
+            block_size: 1,
+            block_num: x,
+        };
+        assert_eq!(&*disk.read_slice(2, offset(1000)).unwrap(), [2, 2]);
+        assert_eq!(&*disk.read_slice(2, offset(999)).unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn test_write_across_segments() {
+        let disk = split_disk();
+        let offset = Offset::Block {
+            block_size: 1,
+            block_num: 999,
+        };
+        disk.write(&[5, 6, 7], offset).unwrap();
+        assert_eq!(read_at(&disk, 5, 998).unwrap(), [1, 5, 6, 7, 2]);
+        assert_eq!(read_at(disk.segments[2].as_ref(), 3, 0).unwrap(), [6, 7, 2]);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::disk::partition::PartitionDisk;
+use crate::disk::{read_at, read_units, Disk, Offset};
+use std::io::{Error, ErrorKind};
+
+pub const VHD_COOKIE: &[u8; 8] = b"conectix";
+
+const VHD_FOOTER_SIZE: u64 = 512;
+const VHD_SECTOR_SIZE: u64 = 512;
+const VHD_DYNAMIC_COOKIE: &[u8; 8] = b"cxsparse";
+const VHD_DYNAMIC_HEADER_SIZE: u64 = 1024;
+const VHD_TYPE_FIXED: u32 = 2;
+const VHD_TYPE_DYNAMIC: u32 = 3;
+const VHD_TYPE_DIFFERENCING: u32 = 4;
+const VHD_UNALLOCATED: u32 = 0xffff_ffff; // Block allocation table entry of a missing block
+const VHD_MAX_BLOCK_SIZE: u64 = 1 << 28;
+
+/// Read-only dynamic VHD image (the fixed images are opened as a PartitionDisk)
+pub struct VhdDisk {
+    file: Box<dyn Disk>,
+    size: u64,             // Virtual disk size
+    block_size: u64,       // Size of the data of a block
+    bitmap_size: u64,      // Size of the sector bitmap preceding the data of a block
+    block_table: Vec<u32>, // Sectors of the blocks
+}
+
+fn read_u32_be(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64_be(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("vhd: {}", message))
+}
+
+/// Read the footer of a VHD image: the copy at the beginning of a dynamic image,
+/// or the footer at the end. Returns None if the file is not a VHD image.
+pub fn read_vhd_footer(file: &dyn Disk) -> Result<Option<Vec<u8>>, Error> {
+    let file_size = file.get_size()?;
+    if file_size < VHD_FOOTER_SIZE {
+        return Ok(None);
+    }
+    for offset in [0, file_size - VHD_FOOTER_SIZE] {
+        let footer = read_at(file, VHD_FOOTER_SIZE, offset)?;
+        if &footer[0..8] == VHD_COOKIE {
+            return Ok(Some(footer));
+        }
+    }
+    Ok(None)
+}
+
+/// Open a VHD image, given its footer
+pub fn open_vhd(file: Box<dyn Disk>, footer: &[u8]) -> Result<Box<dyn Disk>, Error> {
+    let size = read_u64_be(footer, 48);
+    match read_u32_be(footer, 60) {
+        VHD_TYPE_FIXED => {
+            if file.get_size()? < size + VHD_FOOTER_SIZE {
+                return Err(invalid("truncated image"));
+            }
+            Ok(Box::new(PartitionDisk::new(file, 0, Some(size))))
+        }
+        VHD_TYPE_DYNAMIC => Ok(Box::new(VhdDisk::open(file, footer)?)),
+        VHD_TYPE_DIFFERENCING => Err(invalid("differencing images are not supported")),
+        disk_type => Err(invalid(&format!("unknown disk type {}", disk_type))),
+    }
+}
+
+impl VhdDisk {
+    fn open(file: Box<dyn Disk>, footer: &[u8]) -> Result<VhdDisk, Error> {
+        let size = read_u64_be(footer, 48);
+        let header_offset = read_u64_be(footer, 16);
+        let header = read_at(file.as_ref(), VHD_DYNAMIC_HEADER_SIZE, header_offset)?;
+        if &header[0..8] != VHD_DYNAMIC_COOKIE {
+            return Err(invalid("bad dynamic disk header cookie"));
+        }
+        let table_offset = read_u64_be(&header, 16);
+        let table_entries = read_u32_be(&header, 28) as u64;
+        let block_size = read_u32_be(&header, 32) as u64;
+        if block_size == 0
+            || !block_size.is_multiple_of(VHD_SECTOR_SIZE)
+            || block_size > VHD_MAX_BLOCK_SIZE
+        {
+            return Err(invalid("invalid block size"));
+        }
+        if table_entries * block_size < size {
+            return Err(invalid("block allocation table too small"));
+        }
+        let bitmap_size = (block_size / VHD_SECTOR_SIZE)
+            .div_ceil(8)
+            .next_multiple_of(VHD_SECTOR_SIZE);
+        let block_table = read_at(file.as_ref(), table_entries * 4, table_offset)?
+            .chunks_exact(4)
+            .map(|x| u32::from_be_bytes(x.try_into().unwrap()))
+            .collect();
+        Ok(VhdDisk {
+            file,
+            size,
+            block_size,
+            bitmap_size,
+            block_table,
+        })
+    }
+
+    /// Read a part of a block
+    fn read_block(&self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        match self.block_table[(offset / self.block_size) as usize] {
+            VHD_UNALLOCATED => buffer.fill(0),
+            sector => {
+                let offset =
+                    sector as u64 * VHD_SECTOR_SIZE + self.bitmap_size + offset % self.block_size;
+                buffer.copy_from_slice(&read_at(self.file.as_ref(), buffer.len() as u64, offset)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Disk for VhdDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        let offset = offset.calc_offset();
+        read_units(
+            size,
+            offset,
+            self.block_size,
+            self.size,
+            |offset, buffer| self.read_block(offset, buffer),
+        )
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryDisk;
+
+    const SIZE: u64 = 16384; // Virtual size: 4 blocks of 4 KiB
+    const BLOCK_SIZE: u32 = 4096;
+    const TABLE_OFFSET: usize = 1536;
+    const BLOCK_SECTOR: u32 = 4;
+
+    fn footer(disk_type: u32) -> Vec<u8> {
+        let mut footer = vec![0; VHD_FOOTER_SIZE as usize];
+        footer[0..8].copy_from_slice(VHD_COOKIE);
+        footer[16..24].copy_from_slice(&512u64.to_be_bytes());
+        footer[48..56].copy_from_slice(&SIZE.to_be_bytes());
+        footer[60..64].copy_from_slice(&disk_type.to_be_bytes());
+        footer
+    }
+
+    /// Dynamic image: block 0 present (after its sector bitmap), the others missing
+    fn dynamic_image() -> Vec<u8> {
+        let mut data = footer(VHD_TYPE_DYNAMIC);
+        let mut header = vec![0; VHD_DYNAMIC_HEADER_SIZE as usize];
+        header[0..8].copy_from_slice(VHD_DYNAMIC_COOKIE);
+        header[16..24].copy_from_slice(&(TABLE_OFFSET as u64).to_be_bytes());
+        header[28..32].copy_from_slice(&4u32.to_be_bytes());
+        header[32..36].copy_from_slice(&BLOCK_SIZE.to_be_bytes());
+        data.extend_from_slice(&header);
+        data.extend_from_slice(&BLOCK_SECTOR.to_be_bytes());
+        for _ in 1..4 {
+            data.extend_from_slice(&VHD_UNALLOCATED.to_be_bytes());
+        }
+        data.resize(BLOCK_SECTOR as usize * 512, 0);
+        data.extend_from_slice(&[0xff; 512]); // Sector bitmap
+        data.extend_from_slice(&[0xaa; BLOCK_SIZE as usize]);
+        data.extend_from_slice(&footer(VHD_TYPE_DYNAMIC));
+        data
+    }
+
+    fn open_data(data: Vec<u8>) -> Result<Box<dyn Disk>, Error> {
+        let disk = MemoryDisk::new(data);
+        let footer = read_vhd_footer(&disk)?.unwrap();
+        open_vhd(Box::new(disk), &footer)
+    }
+
+    #[test]
+    fn test_fixed() {
+        let mut data = vec![0x11; SIZE as usize];
+        data.extend_from_slice(&footer(VHD_TYPE_FIXED));
+        let disk = open_data(data.clone()).unwrap();
+        assert_eq!(disk.get_size().unwrap(), SIZE);
+        assert_eq!(
+            read_at(disk.as_ref(), SIZE, 0).unwrap(),
+            data[..SIZE as usize]
+        );
+        assert!(read_at(disk.as_ref(), 1, SIZE).is_err());
+        // Truncated image
+        let data = data[512..].to_vec();
+        assert!(open_data(data).is_err());
+    }
+
+    #[test]
+    fn test_dynamic() {
+        let disk = open_data(dynamic_image()).unwrap();
+        assert_eq!(disk.get_size().unwrap(), SIZE);
+        let data = read_at(disk.as_ref(), SIZE, 0).unwrap();
+        assert_eq!(data[..4096], [0xaa; 4096]);
+        assert!(data[4096..].iter().all(|x| *x == 0));
+        assert_eq!(read_at(disk.as_ref(), 4, 4094).unwrap(), [0xaa, 0xaa, 0, 0]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(read_vhd_footer(&MemoryDisk::new(vec![0; 4096]))
+            .unwrap()
+            .is_none());
+        assert!(read_vhd_footer(&MemoryDisk::new(vec![0; 100]))
+            .unwrap()
+            .is_none());
+        let mut data = dynamic_image();
+        data[512] = b'X';
+        assert!(open_data(data).is_err());
+        let mut data = dynamic_image();
+        data[512 + 32..512 + 36].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(open_data(data).is_err());
+        let mut data = dynamic_image();
+        data[512 + 28..512 + 32].copy_from_slice(&2u32.to_be_bytes());
+        assert!(open_data(data).is_err());
+        let mut data = dynamic_image();
+        data[60..64].copy_from_slice(&VHD_TYPE_DIFFERENCING.to_be_bytes());
+        let err = open_data(data).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "vhd: differencing images are not supported"
+        );
+    }
+}
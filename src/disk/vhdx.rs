@@ -0,0 +1,349 @@
+use crate::disk::partition::format_guid;
+use crate::disk::{read_at, read_units, Disk, Offset};
+use crate::ext2::checksum::crc32c_le;
+use std::io::{Error, ErrorKind};
+
+pub const VHDX_SIGNATURE: &[u8; 8] = b"vhdxfile";
+
+const VHDX_HEADER_OFFSETS: [u64; 2] = [64 * 1024, 128 * 1024];
+const VHDX_HEADER_SIZE: u64 = 4096;
+const VHDX_HEADER_SIGNATURE: &[u8; 4] = b"head";
+const VHDX_REGION_TABLE_OFFSET: u64 = 192 * 1024;
+const VHDX_REGION_TABLE_SIZE: u64 = 64 * 1024;
+const VHDX_REGION_SIGNATURE: &[u8; 4] = b"regi";
+const VHDX_METADATA_SIGNATURE: &[u8; 8] = b"metadata";
+const VHDX_METADATA_TABLE_SIZE: u64 = 64 * 1024;
+const VHDX_MAX_ENTRIES: usize = 2047;
+
+const VHDX_BAT_GUID: &str = "2DC27766-F623-4200-9D64-115E9BFD4A08";
+const VHDX_METADATA_GUID: &str = "8B7CA206-4790-4B9A-B8FE-575F050F886E";
+const VHDX_FILE_PARAMETERS_GUID: &str = "CAA16737-FA36-4D43-B3B6-33F0AA44E76B";
+const VHDX_VIRTUAL_DISK_SIZE_GUID: &str = "2FA54224-CD1B-4876-B211-5DBED83BF4B8";
+const VHDX_LOGICAL_SECTOR_SIZE_GUID: &str = "8141BF1D-A96F-4709-BA47-F233A8FAAB5F";
+
+const VHDX_HAS_PARENT: u32 = 1 << 1; // File parameters flag of a differencing image
+const VHDX_CHUNK_SECTORS: u64 = 1 << 23; // Sectors described by a sector bitmap block
+const VHDX_BLOCK_STATE_MASK: u64 = 7;
+const VHDX_BLOCK_FULLY_PRESENT: u64 = 6;
+const VHDX_BLOCK_PARTIALLY_PRESENT: u64 = 7;
+const VHDX_MB: u64 = 1024 * 1024;
+
+/// Read-only VHDX image (fixed or dynamic)
+pub struct VhdxDisk {
+    file: Box<dyn Disk>,
+    size: u64,             // Virtual disk size
+    block_size: u64,       // Payload block size
+    chunk_ratio: u64,      // Number of payload blocks per sector bitmap block
+    block_table: Vec<u64>, // Block allocation table (payload and sector bitmap entries)
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("vhdx: {}", message))
+}
+
+/// Verify the crc32c of a structure, stored at offset 4
+fn verify_checksum(buffer: &[u8]) -> bool {
+    let mut copy = buffer.to_vec();
+    copy[4..8].fill(0);
+    !crc32c_le(!0, &copy) == read_u32(buffer, 4)
+}
+
+impl VhdxDisk {
+    pub fn open(file: Box<dyn Disk>, filename: &str) -> Result<VhdxDisk, Error> {
+        // Current header: the valid one with the greatest sequence number
+        let mut header: Option<Vec<u8>> = None;
+        for offset in VHDX_HEADER_OFFSETS {
+            let buffer = read_at(file.as_ref(), VHDX_HEADER_SIZE, offset)?;
+            if &buffer[0..4] != VHDX_HEADER_SIGNATURE || !verify_checksum(&buffer) {
+                continue;
+            }
+            if header
+                .as_ref()
+                .is_none_or(|x| read_u64(x, 8) < read_u64(&buffer, 8))
+            {
+                header = Some(buffer);
+            }
+        }
+        let header = header.ok_or_else(|| invalid("no valid header"))?;
+        if read_u16(&header, 66) != 1 {
+            return Err(invalid("unsupported version"));
+        }
+        if header[48..64].iter().any(|x| *x != 0) {
+            eprintln!(
+                "warning: {}: the vhdx log is not replayed, recent writes can be missing",
+                filename
+            );
+        }
+        // Region table: location of the block allocation table and of the metadata
+        let regions = read_at(
+            file.as_ref(),
+            VHDX_REGION_TABLE_SIZE,
+            VHDX_REGION_TABLE_OFFSET,
+        )?;
+        if &regions[0..4] != VHDX_REGION_SIGNATURE || !verify_checksum(&regions) {
+            return Err(invalid("invalid region table"));
+        }
+        let mut bat_region: Option<(u64, u64)> = None;
+        let mut metadata_region: Option<(u64, u64)> = None;
+        let count = (read_u32(&regions, 8) as usize).min(VHDX_MAX_ENTRIES);
+        for entry in regions[16..].chunks_exact(32).take(count) {
+            let region = Some((read_u64(entry, 16), read_u32(entry, 24) as u64));
+            match format_guid(&entry[0..16]).as_str() {
+                VHDX_BAT_GUID => bat_region = region,
+                VHDX_METADATA_GUID => metadata_region = region,
+                _ if read_u32(entry, 28) & 1 != 0 => {
+                    return Err(invalid("unknown required region"));
+                }
+                _ => {}
+            }
+        }
+        let (bat_offset, bat_length) = bat_region.ok_or_else(|| invalid("missing BAT"))?;
+        let (metadata_offset, _) = metadata_region.ok_or_else(|| invalid("missing metadata"))?;
+        // Metadata items
+        let metadata = read_at(file.as_ref(), VHDX_METADATA_TABLE_SIZE, metadata_offset)?;
+        if &metadata[0..8] != VHDX_METADATA_SIGNATURE {
+            return Err(invalid("invalid metadata table"));
+        }
+        let mut block_size: Option<u64> = None;
+        let mut size: Option<u64> = None;
+        let mut sector_size: Option<u64> = None;
+        let count = (read_u16(&metadata, 10) as usize).min(VHDX_MAX_ENTRIES);
+        for entry in metadata[32..].chunks_exact(32).take(count) {
+            let item_offset = metadata_offset + read_u32(entry, 16) as u64;
+            match format_guid(&entry[0..16]).as_str() {
+                VHDX_FILE_PARAMETERS_GUID => {
+                    let item = read_at(file.as_ref(), 8, item_offset)?;
+                    if read_u32(&item, 4) & VHDX_HAS_PARENT != 0 {
+                        return Err(invalid("differencing images are not supported"));
+                    }
+                    block_size = Some(read_u32(&item, 0) as u64);
+                }
+                VHDX_VIRTUAL_DISK_SIZE_GUID => {
+                    size = Some(read_u64(&read_at(file.as_ref(), 8, item_offset)?, 0));
+                }
+                VHDX_LOGICAL_SECTOR_SIZE_GUID => {
+                    let item = read_at(file.as_ref(), 4, item_offset)?;
+                    sector_size = Some(read_u32(&item, 0) as u64);
+                }
+                _ => {}
+            }
+        }
+        let block_size = block_size.ok_or_else(|| invalid("missing file parameters"))?;
+        let size = size.ok_or_else(|| invalid("missing virtual disk size"))?;
+        let sector_size = sector_size.ok_or_else(|| invalid("missing logical sector size"))?;
+        if !block_size.is_power_of_two()
+            || !(VHDX_MB..=256 * VHDX_MB).contains(&block_size)
+            || (sector_size != 512 && sector_size != 4096)
+        {
+            return Err(invalid("invalid block size"));
+        }
+        // The block allocation table interleaves a sector bitmap entry after chunk_ratio
+        // payload entries
+        let chunk_ratio = VHDX_CHUNK_SECTORS * sector_size / block_size;
+        let blocks = size.div_ceil(block_size);
+        let entries = blocks + blocks.saturating_sub(1) / chunk_ratio;
+        if entries * 8 > bat_length {
+            return Err(invalid("block allocation table too small"));
+        }
+        let block_table = read_at(file.as_ref(), entries * 8, bat_offset)?
+            .chunks_exact(8)
+            .map(|x| u64::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+        Ok(VhdxDisk {
+            file,
+            size,
+            block_size,
+            chunk_ratio,
+            block_table,
+        })
+    }
+
+    /// Read a part of a payload block
+    fn read_block(&self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        let block = offset / self.block_size;
+        let entry = self.block_table[(block + block / self.chunk_ratio) as usize];
+        match entry & VHDX_BLOCK_STATE_MASK {
+            VHDX_BLOCK_FULLY_PRESENT => {
+                let offset = (entry >> 20) * VHDX_MB + offset % self.block_size;
+                buffer.copy_from_slice(&read_at(self.file.as_ref(), buffer.len() as u64, offset)?);
+            }
+            VHDX_BLOCK_PARTIALLY_PRESENT => {
+                return Err(invalid(
+                    "partially present block in a non-differencing image",
+                ));
+            }
+            // Not present, undefined, zero or unmapped
+            _ => buffer.fill(0),
+        }
+        Ok(())
+    }
+}
+
+impl Disk for VhdxDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        let offset = offset.calc_offset();
+        read_units(
+            size,
+            offset,
+            self.block_size,
+            self.size,
+            |offset, buffer| self.read_block(offset, buffer),
+        )
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryDisk;
+
+    const SIZE: u64 = 2 * VHDX_MB; // Virtual size: 2 blocks of 1 MiB
+    const METADATA_OFFSET: usize = VHDX_MB as usize;
+    const BAT_OFFSET: usize = 2 * VHDX_MB as usize;
+    const BLOCK_OFFSET: usize = 3 * VHDX_MB as usize;
+
+    /// GUID in mixed endianness (the reverse of format_guid)
+    fn guid_bytes(guid: &str) -> Vec<u8> {
+        let hex = guid.replace('-', "");
+        let mut bytes: Vec<u8> = (0..16)
+            .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap())
+            .collect();
+        bytes[0..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+        bytes
+    }
+
+    fn set_checksum(buffer: &mut [u8]) {
+        buffer[4..8].fill(0);
+        let crc = !crc32c_le(!0, buffer);
+        buffer[4..8].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn write_header(data: &mut [u8], index: usize, sequence: u64) {
+        let header = &mut data[VHDX_HEADER_OFFSETS[index] as usize..][..VHDX_HEADER_SIZE as usize];
+        header[0..4].copy_from_slice(VHDX_HEADER_SIGNATURE);
+        header[8..16].copy_from_slice(&sequence.to_le_bytes());
+        header[66..68].copy_from_slice(&1u16.to_le_bytes());
+        set_checksum(header);
+    }
+
+    /// Dynamic image: block 0 fully present, block 1 not present
+    fn vhdx_image() -> Vec<u8> {
+        let mut data = vec![0; BLOCK_OFFSET + VHDX_MB as usize];
+        data[0..8].copy_from_slice(VHDX_SIGNATURE);
+        write_header(&mut data, 0, 1);
+        write_header(&mut data, 1, 2);
+        let regions =
+            &mut data[VHDX_REGION_TABLE_OFFSET as usize..][..VHDX_REGION_TABLE_SIZE as usize];
+        regions[0..4].copy_from_slice(VHDX_REGION_SIGNATURE);
+        regions[8..12].copy_from_slice(&2u32.to_le_bytes());
+        for (i, (guid, offset)) in [
+            (VHDX_BAT_GUID, BAT_OFFSET),
+            (VHDX_METADATA_GUID, METADATA_OFFSET),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let entry = &mut regions[16 + i * 32..][..32];
+            entry[0..16].copy_from_slice(&guid_bytes(guid));
+            entry[16..24].copy_from_slice(&(*offset as u64).to_le_bytes());
+            entry[24..28].copy_from_slice(&(VHDX_MB as u32).to_le_bytes());
+            entry[28..32].copy_from_slice(&1u32.to_le_bytes());
+        }
+        set_checksum(regions);
+        // Metadata items, after the table
+        let metadata = &mut data[METADATA_OFFSET..][..VHDX_MB as usize];
+        metadata[0..8].copy_from_slice(VHDX_METADATA_SIGNATURE);
+        metadata[10..12].copy_from_slice(&3u16.to_le_bytes());
+        let items: [(&str, Vec<u8>); 3] = [
+            (
+                VHDX_FILE_PARAMETERS_GUID,
+                (VHDX_MB as u32).to_le_bytes().to_vec(),
+            ),
+            (VHDX_VIRTUAL_DISK_SIZE_GUID, SIZE.to_le_bytes().to_vec()),
+            (VHDX_LOGICAL_SECTOR_SIZE_GUID, 512u32.to_le_bytes().to_vec()),
+        ];
+        for (i, (guid, value)) in items.iter().enumerate() {
+            let item_offset = VHDX_METADATA_TABLE_SIZE as usize + i * 8;
+            let entry = &mut metadata[32 + i * 32..][..32];
+            entry[0..16].copy_from_slice(&guid_bytes(guid));
+            entry[16..20].copy_from_slice(&(item_offset as u32).to_le_bytes());
+            entry[20..24].copy_from_slice(&8u32.to_le_bytes());
+            metadata[item_offset..][..value.len()].copy_from_slice(value);
+        }
+        let entry = (BLOCK_OFFSET as u64 / VHDX_MB) << 20 | VHDX_BLOCK_FULLY_PRESENT;
+        data[BAT_OFFSET..BAT_OFFSET + 8].copy_from_slice(&entry.to_le_bytes());
+        data[BLOCK_OFFSET..].fill(0xaa);
+        data
+    }
+
+    fn open_data(data: Vec<u8>) -> Result<VhdxDisk, Error> {
+        VhdxDisk::open(Box::new(MemoryDisk::new(data)), "test.vhdx")
+    }
+
+    #[test]
+    fn test_guid_bytes() {
+        assert_eq!(format_guid(&guid_bytes(VHDX_BAT_GUID)), VHDX_BAT_GUID);
+    }
+
+    #[test]
+    fn test_read() {
+        let disk = open_data(vhdx_image()).unwrap();
+        assert_eq!(disk.get_size().unwrap(), SIZE);
+        let data = read_at(&disk, 4, VHDX_MB - 2).unwrap();
+        assert_eq!(data, [0xaa, 0xaa, 0, 0]);
+    }
+
+    #[test]
+    fn test_headers() {
+        // The header with the greatest sequence number is used, a damaged one is skipped
+        let mut data = vhdx_image();
+        let header = &mut data[VHDX_HEADER_OFFSETS[1] as usize..][..VHDX_HEADER_SIZE as usize];
+        header[66] = 2;
+        set_checksum(header);
+        let err = open_data(data.clone()).err().unwrap();
+        assert_eq!(err.to_string(), "vhdx: unsupported version");
+        data[VHDX_HEADER_OFFSETS[1] as usize + 100] = 1;
+        assert!(open_data(data.clone()).is_ok());
+        data[VHDX_HEADER_OFFSETS[0] as usize + 100] = 1;
+        assert_eq!(
+            open_data(data).err().unwrap().to_string(),
+            "vhdx: no valid header"
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut data = vhdx_image();
+        data[VHDX_REGION_TABLE_OFFSET as usize + 100] = 1;
+        assert!(open_data(data).is_err());
+        let mut data = vhdx_image();
+        let parameters = METADATA_OFFSET + VHDX_METADATA_TABLE_SIZE as usize;
+        data[parameters + 4] = VHDX_HAS_PARENT as u8;
+        assert!(open_data(data).is_err());
+        let mut data = vhdx_image();
+        data[parameters..parameters + 4].copy_from_slice(&4096u32.to_le_bytes());
+        assert!(open_data(data).is_err());
+        let mut data = vhdx_image();
+        data[BAT_OFFSET] = VHDX_BLOCK_PARTIALLY_PRESENT as u8;
+        let disk = open_data(data).unwrap();
+        assert!(read_at(&disk, 512, 0).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::disk::{read_at, read_units, Disk, Offset};
+use flate2::read::ZlibDecoder;
+use std::io::{Error, ErrorKind, Read};
+use std::sync::Mutex;
+
+pub const VMDK_MAGIC: &[u8; 4] = b"KDMV";
+
+const VMDK_SECTOR_SIZE: u64 = 512;
+const VMDK_HEADER_SIZE: u64 = 512;
+const VMDK_FLAG_COMPRESSED: u32 = 1 << 16; // Grains are compressed (stream-optimized)
+const VMDK_GD_AT_END: u64 = u64::MAX; // The grain directory offset is in the footer
+const VMDK_COMPRESSION_DEFLATE: u16 = 1;
+const VMDK_GTE_ZERO: u32 = 1; // Grain table entry of a grain reading as zeros
+const VMDK_GRAIN_MARKER_SIZE: u64 = 12; // LBA (u64) and size (u32) preceding a compressed grain
+const VMDK_MAX_GRAIN_SIZE: u64 = 1 << 20;
+
+/// Read-only VMware sparse extent (monolithic sparse or stream-optimized)
+pub struct VmdkDisk {
+    file: Box<dyn Disk>,
+    capacity: u64,             // Virtual disk size in bytes
+    grain_size: u64,           // Grain size in bytes
+    grain_table_entries: u64,  // Number of entries in a grain table
+    grain_directory: Vec<u32>, // Sectors of the grain tables
+    compressed: bool,
+    grain_table_cache: Mutex<Option<(u32, Vec<u8>)>>, // Last grain table read
+    grain_cache: Mutex<Option<(u64, Vec<u8>)>>,       // Last compressed grain read
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("vmdk: {}", message))
+}
+
+impl VmdkDisk {
+    pub fn open(file: Box<dyn Disk>) -> Result<VmdkDisk, Error> {
+        let mut header = read_at(file.as_ref(), VMDK_HEADER_SIZE, 0)?;
+        if &header[0..4] != VMDK_MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        let version = read_u32(&header, 4);
+        if version > 3 {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        // Stream-optimized images write the grain directory last, its offset is in the footer
+        if read_u64(&header, 56) == VMDK_GD_AT_END {
+            let file_size = file.get_size()?;
+            if file_size < 2 * VMDK_HEADER_SIZE {
+                return Err(invalid("missing footer"));
+            }
+            header = read_at(
+                file.as_ref(),
+                VMDK_HEADER_SIZE,
+                file_size - 2 * VMDK_HEADER_SIZE,
+            )?;
+            if &header[0..4] != VMDK_MAGIC || read_u64(&header, 56) == VMDK_GD_AT_END {
+                return Err(invalid("invalid footer"));
+            }
+        }
+        let flags = read_u32(&header, 8);
+        let capacity = read_u64(&header, 12) * VMDK_SECTOR_SIZE;
+        let grain_size = read_u64(&header, 20) * VMDK_SECTOR_SIZE;
+        let descriptor_offset = read_u64(&header, 28);
+        let descriptor_size = read_u64(&header, 36);
+        let grain_table_entries = read_u32(&header, 44) as u64;
+        let gd_offset = read_u64(&header, 56);
+        let compression = u16::from_le_bytes([header[77], header[78]]);
+        if grain_size == 0 || grain_size > VMDK_MAX_GRAIN_SIZE || grain_table_entries == 0 {
+            return Err(invalid("invalid grain size"));
+        }
+        let compressed = flags & VMDK_FLAG_COMPRESSED != 0;
+        if compressed && compression != VMDK_COMPRESSION_DEFLATE {
+            return Err(invalid("only deflate compression is supported"));
+        }
+        if descriptor_offset != 0 && descriptor_size != 0 {
+            let descriptor = read_at(
+                file.as_ref(),
+                descriptor_size * VMDK_SECTOR_SIZE,
+                descriptor_offset * VMDK_SECTOR_SIZE,
+            )?;
+            if String::from_utf8_lossy(&descriptor).contains("parentFileNameHint") {
+                return Err(invalid("child (delta) images are not supported"));
+            }
+        }
+        let grain_tables = capacity.div_ceil(grain_size).div_ceil(grain_table_entries);
+        let grain_directory = read_at(
+            file.as_ref(),
+            grain_tables * 4,
+            gd_offset * VMDK_SECTOR_SIZE,
+        )?
+        .chunks_exact(4)
+        .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+        .collect();
+        Ok(VmdkDisk {
+            file,
+            capacity,
+            grain_size,
+            grain_table_entries,
+            grain_directory,
+            compressed,
+            grain_table_cache: Mutex::new(None),
+            grain_cache: Mutex::new(None),
+        })
+    }
+
+    /// Grain table entry of the grain containing the given offset
+    fn get_grain_entry(&self, offset: u64) -> Result<u32, Error> {
+        let grain = offset / self.grain_size;
+        let table_sector = self.grain_directory[(grain / self.grain_table_entries) as usize];
+        if table_sector == 0 {
+            return Ok(0);
+        }
+        let mut cache = self.grain_table_cache.lock().unwrap();
+        if !matches!(&*cache, Some((cached, _)) if *cached == table_sector) {
+            let table = read_at(
+                self.file.as_ref(),
+                self.grain_table_entries * 4,
+                table_sector as u64 * VMDK_SECTOR_SIZE,
+            )?;
+            *cache = Some((table_sector, table));
+        }
+        let table = &cache.as_ref().unwrap().1;
+        Ok(read_u32(
+            table,
+            ((grain % self.grain_table_entries) * 4) as usize,
+        ))
+    }
+
+    /// Read and inflate a compressed grain
+    fn read_compressed(&self, sector: u64) -> Result<Vec<u8>, Error> {
+        let mut cache = self.grain_cache.lock().unwrap();
+        if let Some((cached, data)) = &*cache {
+            if *cached == sector {
+                return Ok(data.clone());
+            }
+        }
+        let offset = sector * VMDK_SECTOR_SIZE;
+        let marker = read_at(self.file.as_ref(), VMDK_GRAIN_MARKER_SIZE, offset)?;
+        let size = read_u32(&marker, 8) as u64;
+        let compressed = read_at(self.file.as_ref(), size, offset + VMDK_GRAIN_MARKER_SIZE)?;
+        let mut data = Vec::with_capacity(self.grain_size as usize);
+        ZlibDecoder::new(compressed.as_slice())
+            .take(self.grain_size)
+            .read_to_end(&mut data)?;
+        // The last grain can be shorter than the grain size
+        data.resize(self.grain_size as usize, 0);
+        *cache = Some((sector, data.clone()));
+        Ok(data)
+    }
+
+    /// Read a part of a grain
+    fn read_grain(&self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        let in_grain = offset % self.grain_size;
+        match self.get_grain_entry(offset)? {
+            0 | VMDK_GTE_ZERO => buffer.fill(0),
+            sector if self.compressed => {
+                let data = self.read_compressed(sector as u64)?;
+                let start = in_grain as usize;
+                buffer.copy_from_slice(&data[start..start + buffer.len()]);
+            }
+            sector => {
+                let offset = sector as u64 * VMDK_SECTOR_SIZE + in_grain;
+                buffer.copy_from_slice(&read_at(self.file.as_ref(), buffer.len() as u64, offset)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Disk for VmdkDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        let offset = offset.calc_offset();
+        read_units(
+            size,
+            offset,
+            self.grain_size,
+            self.capacity,
+            |offset, buffer| self.read_grain(offset, buffer),
+        )
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MemoryDisk;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const GRAIN_SECTORS: u64 = 8; // Grains of 4 KiB
+    const CAPACITY: u64 = 16 * GRAIN_SECTORS; // 16 grains
+    const GT_SECTOR: u32 = 2;
+    const GRAIN_SECTOR: u32 = 8;
+
+    fn write_header(data: &mut [u8], flags: u32, gd_sector: u64) {
+        data[0..4].copy_from_slice(VMDK_MAGIC);
+        data[4..8].copy_from_slice(&1u32.to_le_bytes());
+        data[8..12].copy_from_slice(&flags.to_le_bytes());
+        data[12..20].copy_from_slice(&CAPACITY.to_le_bytes());
+        data[20..28].copy_from_slice(&GRAIN_SECTORS.to_le_bytes());
+        data[44..48].copy_from_slice(&512u32.to_le_bytes());
+        data[56..64].copy_from_slice(&gd_sector.to_le_bytes());
+        if flags & VMDK_FLAG_COMPRESSED != 0 {
+            data[77..79].copy_from_slice(&VMDK_COMPRESSION_DEFLATE.to_le_bytes());
+        }
+    }
+
+    /// Grain directory at sector 1 and grain table: grain 0 at GRAIN_SECTOR,
+    /// grain 1 reads as zeros, the others are missing
+    fn write_tables(data: &mut [u8]) {
+        data[512..516].copy_from_slice(&GT_SECTOR.to_le_bytes());
+        let table = GT_SECTOR as usize * 512;
+        data[table..table + 4].copy_from_slice(&GRAIN_SECTOR.to_le_bytes());
+        data[table + 4..table + 8].copy_from_slice(&VMDK_GTE_ZERO.to_le_bytes());
+    }
+
+    fn monolithic_sparse() -> Vec<u8> {
+        let mut data = vec![0; (GRAIN_SECTOR as u64 + GRAIN_SECTORS) as usize * 512];
+        write_header(&mut data, 0, 1);
+        write_tables(&mut data);
+        data[GRAIN_SECTOR as usize * 512..].fill(0xaa);
+        data
+    }
+
+    /// Compressed grain 0 (its content is shorter than a grain), the grain directory
+    /// offset is in the footer
+    fn stream_optimized() -> Vec<u8> {
+        let mut data = vec![0; GRAIN_SECTOR as usize * 512];
+        write_header(&mut data, VMDK_FLAG_COMPRESSED, VMDK_GD_AT_END);
+        write_tables(&mut data);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0x55; 1000]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(&compressed);
+        data.resize(data.len().next_multiple_of(512), 0);
+        // Footer, followed by the end-of-stream marker
+        let mut footer = vec![0; 512];
+        write_header(&mut footer, VMDK_FLAG_COMPRESSED, 1);
+        data.extend_from_slice(&footer);
+        data.extend_from_slice(&[0; 512]);
+        data
+    }
+
+    fn open_data(data: Vec<u8>) -> Result<VmdkDisk, Error> {
+        VmdkDisk::open(Box::new(MemoryDisk::new(data)))
+    }
+
+    #[test]
+    fn test_monolithic_sparse() {
+        let disk = open_data(monolithic_sparse()).unwrap();
+        assert_eq!(disk.get_size().unwrap(), CAPACITY * 512);
+        let data = read_at(&disk, CAPACITY * 512, 0).unwrap();
+        assert_eq!(data[..4096], [0xaa; 4096]);
+        assert!(data[4096..].iter().all(|x| *x == 0));
+    }
+
+    #[test]
+    fn test_stream_optimized() {
+        let disk = open_data(stream_optimized()).unwrap();
+        let data = read_at(&disk, 8192, 0).unwrap();
+        assert_eq!(data[..1000], [0x55; 1000]);
+        assert!(data[1000..].iter().all(|x| *x == 0));
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut data = monolithic_sparse();
+        data[0] = b'X';
+        assert!(open_data(data).is_err());
+        let mut data = monolithic_sparse();
+        data[4..8].copy_from_slice(&4u32.to_le_bytes());
+        assert!(open_data(data).is_err());
+        let mut data = monolithic_sparse();
+        data[20..28].copy_from_slice(&0u64.to_le_bytes());
+        assert!(open_data(data).is_err());
+        // Stream-optimized image without a footer
+        let mut data = stream_optimized();
+        data.truncate(data.len() - 1024);
+        assert!(open_data(data).is_err());
+    }
+
+    #[test]
+    fn test_delta_image() {
+        let mut data = monolithic_sparse();
+        let descriptor = b"parentFileNameHint=\"base.vmdk\"\n";
+        data[28..36].copy_from_slice(&6u64.to_le_bytes());
+        data[36..44].copy_from_slice(&1u64.to_le_bytes());
+        data[6 * 512..][..descriptor.len()].copy_from_slice(descriptor);
+        let err = open_data(data).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "vmdk: child (delta) images are not supported"
+        );
+    }
+}
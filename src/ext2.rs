@@ -1,57 +1,197 @@
+pub mod bitmap;
+pub mod checksum;
 pub mod dir;
+pub mod dump;
+pub mod extent;
+pub mod filefrag;
+pub mod fsck;
 pub mod group;
+pub mod icheck;
 pub mod inode;
 pub mod superblock;
+pub mod undelete;
+pub mod xattr;
 
+use crate::cache::{Cache, CacheStats};
 use crate::dir::DirEntry;
-use crate::disk::{Disk, FileDisk};
+use crate::disk::cache::{CachedBlocks, CachedDisk};
+use crate::disk::Disk;
+use crate::ext2::checksum::Ext2Checksum;
 use crate::ext2::group::Ext2BlockGroups;
 use crate::ext2::inode::Ext2Inode;
 use crate::ext2::superblock::Ext2SuperBlock;
 use crate::file::FsFile;
-use crate::fs::Filesystem;
+use crate::fs::{open_disk, Filesystem, MountOptions};
 use crate::inode::Inode;
 use crate::metadata::Metadata;
 use std::collections::BTreeMap;
 use std::io::Error;
 use std::io::ErrorKind;
 use std::str;
+use std::sync::Arc;
 
 const EXT2_ROOT_INO: u64 = 2; /* Root inode */
+const DEFAULT_CACHE_SIZE: u64 = 64; // Size of the block cache in MiB
+const DEFAULT_READ_AHEAD: u64 = 128; // Maximum size of a file read in KiB
+const INODE_CACHE_SIZE: usize = 4096;
+const DENTRY_CACHE_SIZE: usize = 4096;
+
+/// Read a little-endian u16 from a buffer
+pub fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+/// Read a little-endian u32 from a buffer
+pub fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+/// Write a little-endian u16 into a buffer
+pub fn write_u16(buffer: &mut [u8], offset: usize, value: u16) {
+    buffer[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Write a little-endian u32 into a buffer
+pub fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
 
 pub struct Ext2Filesystem {
     disk: Box<dyn Disk>,
     super_block: Ext2SuperBlock,
+    super_block_group: usize, // Group of the superblock in use (0 = primary)
     block_groups: Ext2BlockGroups,
+    checksum: Ext2Checksum,
+    block_cache: Arc<CachedBlocks>,
+    inode_cache: Cache<u64, Ext2Inode>,
+    dentry_cache: Cache<(u64, String), u64>, // Inode of a name in a directory
+    read_ahead: u64,                         // Maximum size of a file read, in bytes
 }
 
 impl Ext2Filesystem {
-    pub fn mount(filename: &str) -> Result<Ext2Filesystem, Error> {
-        let disk = FileDisk::open(filename)?;
-        let super_block = Ext2SuperBlock::new(&disk)?;
-        let block_groups = Ext2BlockGroups::new(&disk, &super_block)?;
+    pub fn mount(filename: &str, options: &MountOptions) -> Result<Ext2Filesystem, Error> {
+        let disk = open_disk(filename, options)?;
+        let (super_block, super_block_group) = if options.superblock != 0 {
+            Ext2SuperBlock::read_backup(disk.as_ref(), options.superblock)?
+        } else {
+            // The backups are only used when the primary superblock is unreadable,
+            // a checksum mismatch is reported below
+            match Ext2SuperBlock::new(disk.as_ref()) {
+                Ok(super_block) => (super_block, 0),
+                Err(err) => match Ext2SuperBlock::find_backup(disk.as_ref()) {
+                    Ok((super_block, group_num)) => {
+                        eprintln!(
+                            "warning: primary superblock is damaged ({}), using the backup in group {}",
+                            err, group_num
+                        );
+                        (super_block, group_num)
+                    }
+                    Err(_) => return Err(err),
+                },
+            }
+        };
+        let checksum = Ext2Checksum::new(&super_block, options.checksum_mode);
+        checksum.report(checksum.verify_super_block(super_block.as_bytes()))?;
+        let block_size = super_block.get_block_size();
+        let cache_size = options
+            .cache_size
+            .unwrap_or(DEFAULT_CACHE_SIZE)
+            .checked_mul(1 << 20)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Cache size too large"))?;
+        let read_ahead = options
+            .read_ahead
+            .unwrap_or(DEFAULT_READ_AHEAD)
+            .checked_mul(1 << 10)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Read-ahead size too large"))?;
+        let block_cache = Arc::new(CachedBlocks::new((cache_size / block_size) as usize));
+        let disk: Box<dyn Disk> = Box::new(CachedDisk::new(disk, block_size, block_cache.clone())?);
+        let block_groups =
+            Ext2BlockGroups::new(disk.as_ref(), &super_block, &checksum, super_block_group)?;
         Ok(Ext2Filesystem {
-            disk: Box::new(disk),
-            super_block: super_block,
-            block_groups: block_groups,
+            disk,
+            super_block,
+            super_block_group,
+            block_groups,
+            checksum,
+            block_cache,
+            inode_cache: Cache::new(INODE_CACHE_SIZE),
+            dentry_cache: Cache::new(DENTRY_CACHE_SIZE),
+            read_ahead,
         })
     }
 
+    /// Returns the group of the superblock in use (0 = primary)
+    pub fn get_super_block_group(&self) -> usize {
+        self.super_block_group
+    }
+
+    /// Get the superblock
+    pub fn get_super_block(&self) -> &Ext2SuperBlock {
+        &self.super_block
+    }
+
+    /// Get the block group descriptors
+    pub fn get_block_groups(&self) -> &Ext2BlockGroups {
+        &self.block_groups
+    }
+
+    /// Statistics of the block, inode and dentry caches
+    pub fn get_cache_stats(&self) -> [(&'static str, CacheStats); 3] {
+        [
+            ("blocks", self.block_cache.get_stats()),
+            ("inodes", self.inode_cache.get_stats()),
+            ("dentries", self.dentry_cache.get_stats()),
+        ]
+    }
+
+    /// Forget the cached inodes and directory entries, after they were modified
+    /// (the block cache is updated by the writes)
+    pub fn clear_inode_caches(&self) {
+        self.inode_cache.clear();
+        self.dentry_cache.clear();
+    }
+
     /// Get inode by number
     fn read_inode(&self, inode_num: u64) -> Result<Ext2Inode, Error> {
-        Ext2Inode::new(
+        if inode_num == 0 || inode_num > self.super_block.s_inodes_count as u64 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid inode number"));
+        }
+        if let Some(inode) = self.inode_cache.get(&inode_num) {
+            return Ok(inode);
+        }
+        let inode = Ext2Inode::new(
             &self.disk,
             self.super_block.s_inode_size as u64,
             self.super_block.get_block_size(),
             &self.block_groups,
+            &self.checksum,
             inode_num,
-        )
+        )?;
+        self.inode_cache.insert(inode_num, inode.clone());
+        Ok(inode)
     }
 
-    /// Get inode by path
+    /// Get the inode of an entry of a directory
+    fn get_child(&self, dir: &Ext2Inode, name: &str) -> Option<Ext2Inode> {
+        let key = (dir.get_inode_num(), name.to_string());
+        if let Some(inode_num) = self.dentry_cache.get(&key) {
+            return self.read_inode(inode_num).ok();
+        }
+        // The inode number of the entry is checked against the inode count
+        let entries = dir.read_dir(&self.disk, "").ok()?;
+        let child = self.read_inode(entries.get(name)?.inode_num()).ok()?;
+        self.dentry_cache.insert(key, child.get_inode_num());
+        Some(child)
+    }
+
+    /// Get inode by path (the relative paths are resolved from the root)
     fn resolve(&self, path: &str) -> Result<Ext2Inode, Error> {
-        let root_inode = self.read_inode(EXT2_ROOT_INO)?;
-        self.resolve_relative(path, root_inode, false)
+        self.resolve_relative(path, self.read_inode(EXT2_ROOT_INO)?, false)
+    }
+
+    /// Get inode by path, without following the symbolic link of the last component
+    fn resolve_link(&self, path: &str) -> Result<Ext2Inode, Error> {
+        self.resolve_relative(path, self.read_inode(EXT2_ROOT_INO)?, true)
     }
 
     /// Get inode by relative path
@@ -68,8 +208,15 @@ impl Ext2Filesystem {
         let path_parts: Vec<_> = path.split("/").collect();
         let last = path_parts.len() - 1;
         for (i, part) in path_parts.iter().enumerate() {
+            if i == 0 {
+                // A path can begin with an inode number (e.g. <12>/file)
+                if let Some(inode_num) = parse_inode_num(part) {
+                    inode = self.read_inode(inode_num)?;
+                    continue;
+                }
+            }
             if !part.is_empty() {
-                match inode.get_child(&self.disk, &self.block_groups, part) {
+                match self.get_child(&inode, part) {
                     Some(child) => {
                         let resolve_symlink = child.metadata().is_symlink() && (!link || i != last);
                         if resolve_symlink {
@@ -87,18 +234,39 @@ impl Ext2Filesystem {
         }
         Ok(inode)
     }
-}
 
-impl Filesystem for Ext2Filesystem {
-    fn open(&self, path: &str) -> Result<FsFile, Error> {
-        let inode = self.resolve(path)?;
+    /// Open the content of an inode
+    fn open_file(&self, inode: Ext2Inode) -> Result<FsFile<'_>, Error> {
         if inode.metadata().is_dir() {
             Err(Error::new(ErrorKind::InvalidInput, "Is a directory"))
         } else {
             let blocks = inode.get_blocks(&self.disk)?;
-            Ok(FsFile::new(&self.disk, Box::new(inode), blocks))
+            Ok(FsFile::new(
+                self.disk.as_ref(),
+                Box::new(inode),
+                blocks,
+                self.read_ahead,
+            ))
         }
     }
+}
+
+/// Parse an inode number in the <ino> syntax
+fn parse_inode_num(part: &str) -> Option<u64> {
+    part.strip_prefix('<')?.strip_suffix('>')?.parse().ok()
+}
+
+impl Filesystem for Ext2Filesystem {
+    fn open(&self, path: &str) -> Result<FsFile<'_>, Error> {
+        let inode = self.resolve(path)?;
+        self.open_file(inode)
+    }
+
+    /// Open a file by inode number
+    fn open_inode(&self, inode_num: u64) -> Result<FsFile<'_>, Error> {
+        let inode = self.read_inode(inode_num)?;
+        self.open_file(inode)
+    }
 
     /// Get block size
     fn get_block_size(&self) -> u64 {
@@ -107,12 +275,22 @@ impl Filesystem for Ext2Filesystem {
 
     /// Get the number of blocks in file system
     fn get_blocks_count(&self) -> u64 {
-        self.super_block.s_blocks_count as u64
+        self.super_block.get_blocks_count()
     }
 
     /// Get the number of unallocated blocks
     fn get_free_blocks_count(&self) -> u64 {
-        self.super_block.s_free_blocks_count as u64
+        self.super_block.get_free_blocks_count()
+    }
+
+    /// Get the number of inodes in file system
+    fn get_inodes_count(&self) -> u64 {
+        self.super_block.s_inodes_count as u64
+    }
+
+    /// Get the number of unallocated inodes (counted in the inode bitmaps)
+    fn get_free_inodes_count(&self) -> Result<u64, Error> {
+        self.count_free_inodes()
     }
 
     /// Read the contents of a given directory
@@ -121,26 +299,207 @@ impl Filesystem for Ext2Filesystem {
         inode.read_dir(&self.disk, path)
     }
 
+    /// Read the contents of a directory by inode number
+    fn read_dir_by_inode(
+        &self,
+        inode_num: u64,
+    ) -> Result<BTreeMap<String, Box<dyn DirEntry>>, Error> {
+        let inode = self.read_inode(inode_num)?;
+        inode.read_dir(&self.disk, &format!("<{}>", inode_num))
+    }
+
+    /// Read the deleted entries still present in a given directory
+    fn read_deleted_dir(&self, path: &str) -> Result<Vec<Box<dyn DirEntry>>, Error> {
+        let inode = self.resolve(path)?;
+        let inodes_count = self.super_block.s_inodes_count as u64;
+        let entries = inode.read_deleted_dir(self.disk.as_ref(), path, inodes_count)?;
+        Ok(entries
+            .into_iter()
+            .map(|x| Box::new(x) as Box<dyn DirEntry>)
+            .collect())
+    }
+
     /// Given a path, query the file system to get information about a file, directory, etc.
     fn metadata(&self, path: &str) -> Result<Metadata, Error> {
-        let root_inode = self.read_inode(EXT2_ROOT_INO)?;
-        let inode = self.resolve_relative(path, root_inode, true)?;
+        let inode = self.resolve_link(path)?;
         Ok(inode.metadata())
     }
 
+    /// Query the metadata of an inode (symbolic links are not followed)
+    fn metadata_by_inode(&self, inode_num: u64) -> Result<Metadata, Error> {
+        Ok(self.read_inode(inode_num)?.metadata())
+    }
+
     /// Like stat, except that if path is a symbolic link, then the link itself is stat-ed,
     /// not the file that it refers to.
     fn symlink_metadata(&self, path: &str) -> Result<Metadata, Error> {
-        let root_inode = self.read_inode(EXT2_ROOT_INO)?;
-        let inode = self.resolve_relative(path, root_inode, true)?;
+        let inode = self.resolve_link(path)?;
         Ok(inode.metadata())
     }
 
     /// Reads a symbolic link, returning the file that the link points to
     fn read_link(&self, path: &str) -> Result<String, Error> {
         // Read value of a symbolic link
-        let root_inode = self.read_inode(EXT2_ROOT_INO)?;
-        let inode = self.resolve_relative(path, root_inode, true)?;
+        let inode = self.resolve_link(path)?;
         inode.read_link(&self.disk)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::read_at;
+    use crate::test_util::{block_pattern, mount_image, patch_image, read_file, unpack_image};
+    use std::io::Read;
+    use std::thread;
+
+    #[test]
+    fn test_parse_inode_num() {
+        assert_eq!(parse_inode_num("<12>"), Some(12));
+        assert_eq!(parse_inode_num("<0>"), Some(0));
+        assert_eq!(parse_inode_num("12"), None);
+        assert_eq!(parse_inode_num("<12"), None);
+        assert_eq!(parse_inode_num("<x>"), None);
+        assert_eq!(parse_inode_num("<-1>"), None);
+    }
+
+    #[test]
+    fn test_inode_paths() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        assert_eq!(read_file(&fs, "<16>").unwrap(), b"Hello, world!\n");
+        assert_eq!(read_file(&fs, "<14>/file.txt").unwrap(), b"File in dir1\n");
+        assert_eq!(
+            read_file(&fs, "<2>/dir1/../hello.txt").unwrap(),
+            b"Hello, world!\n"
+        );
+        // Only the first component can be an inode number
+        let err = read_file(&fs, "/dir1/<16>").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        for path in ["<0>", "<100000>"] {
+            let err = read_file(&fs, path).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", path);
+        }
+        assert!(fs.metadata("<14>").unwrap().is_dir());
+        assert!(fs.metadata("<17>").unwrap().is_symlink());
+        assert_eq!(fs.read_link("<17>").unwrap(), "hello.txt");
+    }
+
+    #[test]
+    fn test_by_inode() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let mut content = String::new();
+        fs.open_inode(16)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "Hello, world!\n");
+        assert!(fs.open_inode(14).is_err()); // Directory
+        let entries = fs.read_dir_by_inode(14).unwrap();
+        assert_eq!(entries["file.txt"].path(), "<14>/file.txt");
+        assert_eq!(entries["file.txt"].inode_num(), 15);
+        let metadata = fs.metadata_by_inode(16).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), 14);
+        assert!(fs.metadata_by_inode(17).unwrap().is_symlink());
+    }
+
+    #[test]
+    fn test_symlinks() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        assert_eq!(read_file(&fs, "/link").unwrap(), b"Hello, world!\n");
+        assert!(fs.metadata("/link").unwrap().is_symlink());
+        assert_eq!(fs.read_link("/link").unwrap(), "hello.txt");
+        let err = read_file(&fs, "/missing").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_invalid_child_inode() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        // Inode number of the hello.txt entry in the root directory
+        let root = fs.read_inode(EXT2_ROOT_INO).unwrap();
+        let block_num = root.get_blocks(&fs.disk).unwrap()[0];
+        let block = read_at(fs.disk.as_ref(), 1024, block_num * 1024).unwrap();
+        let pos = block.windows(9).position(|x| x == b"hello.txt").unwrap();
+        let offset = block_num * 1024 + pos as u64 - 8;
+        let image = patch_image("ext2.img.gz", &[(offset, &0xffffff00u32.to_le_bytes())]);
+        let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+        let err = read_file(&fs, "/hello.txt").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(fs.metadata("/hello.txt").is_err());
+        assert!(fs.read_dir("/").unwrap().contains_key("hello.txt"));
+        assert_eq!(read_file(&fs, "/dir1/file.txt").unwrap(), b"File in dir1\n");
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        assert_send_sync(&fs);
+        let big = block_pattern(300);
+        // Relative paths are resolved from the root in every thread
+        thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|i| {
+                    let fs = &fs;
+                    let path = if i % 2 == 0 { "/big.txt" } else { "sparse" };
+                    scope.spawn(move || read_file(fs, path).unwrap())
+                })
+                .collect();
+            for (i, reader) in readers.into_iter().enumerate() {
+                let data = reader.join().unwrap();
+                if i % 2 == 0 {
+                    assert_eq!(data, big.as_bytes());
+                } else {
+                    assert_eq!(data.len(), 300 * 1024 + 4);
+                    assert_eq!(&data[300 * 1024..], b"end\n");
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_cache_size() {
+        let options = MountOptions {
+            cache_size: Some(u64::MAX >> 10),
+            ..Default::default()
+        };
+        let Err(err) = Ext2Filesystem::mount(&unpack_image("ext2.img.gz"), &options) else {
+            panic!("cache size overflow accepted");
+        };
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        // Disabled cache
+        let options = MountOptions {
+            cache_size: Some(0),
+            ..Default::default()
+        };
+        let fs = mount_image("ext2.img.gz", &options);
+        assert_eq!(read_file(&fs, "/hello.txt").unwrap(), b"Hello, world!\n");
+        let [(_, blocks), (_, inodes), _] = fs.get_cache_stats();
+        assert_eq!((blocks.len, blocks.capacity), (0, 0));
+        assert!(inodes.len > 0);
+        // Default cache: the metadata blocks read again are hits
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        read_file(&fs, "/dir1/file.txt").unwrap();
+        fs.clear_inode_caches();
+        read_file(&fs, "/dir1/file.txt").unwrap();
+        let [(_, blocks), _, _] = fs.get_cache_stats();
+        assert_eq!(blocks.capacity, 64 * 1024);
+        assert!(blocks.hits > 0);
+    }
+
+    #[test]
+    fn test_cached_indirect_blocks() {
+        // The block map of big.txt reads its 3 indirect blocks through the block cache
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let inode = fs.read_inode(12).unwrap();
+        let [(_, before), _, _] = fs.get_cache_stats();
+        let blocks = inode.get_blocks(&fs.disk).unwrap();
+        assert_eq!(blocks.len(), 300);
+        assert_eq!(&blocks[11..13], [353, 355]);
+        let [(_, after), _, _] = fs.get_cache_stats();
+        assert_eq!(after.misses - before.misses, 3);
+        // One read per pointer: 256 singly indirect, 32 doubly indirect (2 levels)
+        assert_eq!(after.hits - before.hits, 256 + 32 * 2 - 3);
+    }
+}
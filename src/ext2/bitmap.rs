@@ -0,0 +1,187 @@
+use crate::ext2::Ext2Filesystem;
+use std::io::Error;
+use std::ops::Range;
+
+/// Allocation bitmap of a block group.
+/// The bit i is set if the block (or inode) first + i is in use.
+#[derive(Debug, Clone)]
+pub struct Ext2Bitmap {
+    bitmap: Vec<u8>, // Bitmap (one bit per block or inode)
+    first: u64,      // First block or inode number of the group
+    len: u64,        // Number of blocks or inodes in the group
+}
+
+impl Ext2Bitmap {
+    pub fn new(bitmap: Vec<u8>, first: u64, len: u64) -> Ext2Bitmap {
+        Ext2Bitmap { bitmap, first, len }
+    }
+
+    /// Block or inode numbers covered by the bitmap
+    pub fn get_range(&self) -> Range<u64> {
+        self.first..self.first + self.len
+    }
+
+    /// Returns true if the block or inode is in use (false if out of the range)
+    pub fn is_used(&self, num: u64) -> bool {
+        if !self.get_range().contains(&num) {
+            return false;
+        }
+        let i = num - self.first;
+        self.bitmap[(i / 8) as usize] & (1 << (i % 8)) != 0
+    }
+
+    /// Returns true if the block or inode is free (false if out of the range)
+    pub fn is_free(&self, num: u64) -> bool {
+        self.get_range().contains(&num) && !self.is_used(num)
+    }
+
+    /// Mark a block or inode as in use
+    pub fn set_used(&mut self, num: u64) {
+        if self.get_range().contains(&num) {
+            let i = num - self.first;
+            self.bitmap[(i / 8) as usize] |= 1 << (i % 8);
+        }
+    }
+
+    /// Mark a block or inode as free
+    pub fn set_free(&mut self, num: u64) {
+        if self.get_range().contains(&num) {
+            let i = num - self.first;
+            self.bitmap[(i / 8) as usize] &= !(1 << (i % 8));
+        }
+    }
+
+    /// Iterate over the blocks or inodes in use
+    pub fn iter_used(&self) -> impl Iterator<Item = u64> + '_ {
+        self.get_range().filter(move |x| self.is_used(*x))
+    }
+
+    /// Iterate over the free blocks or inodes
+    pub fn iter_free(&self) -> impl Iterator<Item = u64> + '_ {
+        self.get_range().filter(move |x| !self.is_used(*x))
+    }
+
+    /// Number of blocks or inodes in use
+    pub fn get_used_count(&self) -> u64 {
+        self.iter_used().count() as u64
+    }
+
+    /// Number of free blocks or inodes
+    pub fn get_free_count(&self) -> u64 {
+        self.len - self.get_used_count()
+    }
+
+    /// Raw bitmap
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bitmap
+    }
+}
+
+impl Ext2Filesystem {
+    /// Get the block bitmap of a group
+    pub fn get_block_bitmap(&self, group_num: usize) -> Result<Ext2Bitmap, Error> {
+        self.block_groups
+            .get_block_bitmap(self.disk.as_ref(), group_num)
+    }
+
+    /// Get the inode bitmap of a group
+    pub fn get_inode_bitmap(&self, group_num: usize) -> Result<Ext2Bitmap, Error> {
+        self.block_groups
+            .get_inode_bitmap(self.disk.as_ref(), group_num)
+    }
+
+    /// Count the free inodes in the inode bitmaps
+    pub fn count_free_inodes(&self) -> Result<u64, Error> {
+        let mut count = 0;
+        for group_num in 0..self.block_groups.len() {
+            let bitmap = self.get_inode_bitmap(group_num)?;
+            count += bitmap.get_free_count();
+        }
+        Ok(count)
+    }
+
+    /// Free extents (first block, number of blocks) found in the block bitmaps
+    pub fn get_free_extents(&self) -> Result<Vec<(u64, u64)>, Error> {
+        let mut extents: Vec<(u64, u64)> = Vec::new();
+        let mut current: Option<(u64, u64)> = None;
+        for group_num in 0..self.block_groups.len() {
+            let bitmap = self.get_block_bitmap(group_num)?;
+            for block_num in bitmap.get_range() {
+                if bitmap.is_used(block_num) {
+                    if let Some(extent) = current.take() {
+                        extents.push(extent);
+                    }
+                } else {
+                    // Free extents can span multiple groups
+                    match current.as_mut() {
+                        Some((_, len)) => *len += 1,
+                        None => current = Some((block_num, 1)),
+                    }
+                }
+            }
+        }
+        if let Some(extent) = current {
+            extents.push(extent);
+        }
+        Ok(extents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MountOptions;
+    use crate::test_util::mount_image;
+
+    #[test]
+    fn test_bitmap() {
+        // Blocks 1 to 10, 1, 2 and 9 in use
+        let mut bitmap = Ext2Bitmap::new(vec![0b0000_0011, 0b0000_0001], 1, 10);
+        assert_eq!(bitmap.get_range(), 1..11);
+        assert!(bitmap.is_used(1) && bitmap.is_used(2) && bitmap.is_used(9));
+        assert!(bitmap.is_free(3) && bitmap.is_free(10));
+        // Out of the range: neither used nor free
+        assert!(!bitmap.is_used(0) && !bitmap.is_free(0));
+        assert!(!bitmap.is_used(11) && !bitmap.is_free(11));
+        assert_eq!(bitmap.iter_used().collect::<Vec<u64>>(), [1, 2, 9]);
+        assert_eq!(bitmap.get_used_count(), 3);
+        assert_eq!(bitmap.get_free_count(), 7);
+        bitmap.set_used(5);
+        bitmap.set_used(42); // Ignored
+        assert_eq!(bitmap.as_bytes(), [0b0001_0011, 0b0000_0001]);
+        assert_eq!(
+            bitmap.iter_free().collect::<Vec<u64>>(),
+            [3, 4, 6, 7, 8, 10]
+        );
+    }
+
+    #[test]
+    fn test_count_free_inodes() {
+        for name in ["ext2.img.gz", "ext4.img.gz"] {
+            let fs = mount_image(name, &MountOptions::default());
+            let free_inodes = fs.super_block.s_free_inodes_count as u64;
+            assert_eq!(fs.count_free_inodes().unwrap(), free_inodes, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_free_extents() {
+        for name in ["ext2.img.gz", "ext4.img.gz"] {
+            let fs = mount_image(name, &MountOptions::default());
+            let extents = fs.get_free_extents().unwrap();
+            let free_blocks: u64 = extents.iter().map(|(_, len)| len).sum();
+            assert_eq!(free_blocks, fs.super_block.get_free_blocks_count());
+            // Sorted, separated by blocks in use
+            for pair in extents.windows(2) {
+                assert!(pair[0].0 + pair[0].1 < pair[1].0, "{}: {:?}", name, pair);
+            }
+            let bitmap = fs
+                .block_groups
+                .get_block_bitmap(fs.disk.as_ref(), 0)
+                .unwrap();
+            let (first, len) = extents[0];
+            assert!((first..first + len).all(|x| bitmap.is_free(x)));
+            assert!(bitmap.is_used(first - 1));
+        }
+    }
+}
@@ -0,0 +1,629 @@
+use crate::ext2::superblock::Ext2SuperBlock;
+use crate::ext2::{read_u16, read_u32, write_u16, write_u32};
+use std::error;
+use std::fmt;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::str::FromStr;
+
+const CRC32C_POLY: u32 = 0x82f63b78; // Castagnoli polynomial (reversed)
+const CRC16_POLY: u16 = 0xa001; // ANSI CRC16 polynomial (reversed)
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+const CRC16_TABLE: [u16; 256] = crc16_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u16;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC16_POLY
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Update a crc32c (Castagnoli) checksum, without pre and post inversion (as e2fsprogs does)
+pub fn crc32c_le(crc: u32, buffer: &[u8]) -> u32 {
+    buffer.iter().fold(crc, |crc, b| {
+        CRC32C_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Update a crc16 (ANSI) checksum, used by the uninit_bg group descriptors checksum
+pub fn crc16(crc: u16, buffer: &[u8]) -> u16 {
+    buffer.iter().fold(crc, |crc, b| {
+        CRC16_TABLE[((crc ^ *b as u16) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// What to do when a metadata checksum does not match
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// Print a warning and go on
+    #[default]
+    Lenient,
+    /// Refuse the corrupted structure
+    Strict,
+    /// Don't report checksum mismatches
+    Ignore,
+}
+
+impl FromStr for ChecksumMode {
+    type Err = ();
+    fn from_str(src: &str) -> Result<ChecksumMode, ()> {
+        match src {
+            "lenient" => Ok(ChecksumMode::Lenient),
+            "strict" => Ok(ChecksumMode::Strict),
+            "none" => Ok(ChecksumMode::Ignore),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Metadata structure protected by a checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumKind {
+    SuperBlock,
+    GroupDesc { group_num: usize },
+    BlockBitmap { group_num: usize },
+    InodeBitmap { group_num: usize },
+    Inode { inode_num: u64 },
+    ExtentBlock { inode_num: u64, block_num: u64 },
+    DirBlock { inode_num: u64, block_num: u64 },
+    XattrBlock { block_num: u64 },
+}
+
+impl fmt::Display for ChecksumKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChecksumKind::SuperBlock => write!(f, "superblock"),
+            ChecksumKind::GroupDesc { group_num } => write!(f, "group {} descriptor", group_num),
+            ChecksumKind::BlockBitmap { group_num } => {
+                write!(f, "group {} block bitmap", group_num)
+            }
+            ChecksumKind::InodeBitmap { group_num } => {
+                write!(f, "group {} inode bitmap", group_num)
+            }
+            ChecksumKind::Inode { inode_num } => write!(f, "inode {}", inode_num),
+            ChecksumKind::ExtentBlock {
+                inode_num,
+                block_num,
+            } => write!(f, "inode {} extent block {}", inode_num, block_num),
+            ChecksumKind::DirBlock {
+                inode_num,
+                block_num,
+            } => write!(f, "inode {} directory block {}", inode_num, block_num),
+            ChecksumKind::XattrBlock { block_num } => {
+                write!(f, "extended attribute block {}", block_num)
+            }
+        }
+    }
+}
+
+/// Checksum mismatch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumError {
+    pub kind: ChecksumKind, // Corrupted structure
+    pub expected: u32,      // Checksum stored on disk
+    pub found: u32,         // Calculated checksum
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} checksum does not match (expected {:#x}, found {:#x})",
+            self.kind, self.expected, self.found
+        )
+    }
+}
+
+impl error::Error for ChecksumError {}
+
+impl From<ChecksumError> for Error {
+    fn from(err: ChecksumError) -> Error {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
+
+fn check(kind: ChecksumKind, expected: u32, found: u32) -> Result<(), ChecksumError> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(ChecksumError {
+            kind,
+            expected,
+            found,
+        })
+    }
+}
+
+// Offsets of the checksum fields in the on-disk structures
+const SUPER_BLOCK_CSUM_OFFSET: usize = 0x3fc;
+const GROUP_DESC_CSUM_OFFSET: usize = 0x1e;
+const GROUP_DESC_CSUM_END: usize = GROUP_DESC_CSUM_OFFSET + 2;
+const GROUP_DESC_BLOCK_BITMAP_CSUM_HI_END: usize = 0x3a;
+const GROUP_DESC_INODE_BITMAP_CSUM_HI_END: usize = 0x3c;
+const INODE_CSUM_LO_OFFSET: usize = 0x7c;
+const INODE_CSUM_HI_OFFSET: usize = 0x82;
+const INODE_EXTRA_ISIZE_OFFSET: usize = 0x80;
+const INODE_CSUM_HI_EXTRA_END: u16 = 4; // end of i_checksum_hi relative to the good old inode size
+const EXT2_GOOD_OLD_INODE_SIZE: usize = 128;
+pub const DIR_TAIL_SIZE: usize = 12;
+const DIR_TAIL_FT: u8 = 0xde;
+const XATTR_CSUM_OFFSET: usize = 0x10;
+
+/// Checksum calculation and verification of the metadata structures
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Ext2Checksum {
+    pub mode: ChecksumMode, // What to do on checksum mismatch
+    metadata_csum: bool,    // RO_COMPAT_METADATA_CSUM feature
+    gdt_csum: bool,         // RO_COMPAT_GDT_CSUM feature
+    seed: u32,              // Checksum seed (crc32c of the uuid)
+    uuid: [u8; 16],         // Filesystem uuid
+    desc_size: usize,       // Group descriptor size
+}
+
+impl Ext2Checksum {
+    pub fn new(super_block: &Ext2SuperBlock, mode: ChecksumMode) -> Ext2Checksum {
+        Ext2Checksum {
+            mode,
+            metadata_csum: super_block.has_metadata_csum(),
+            gdt_csum: super_block.has_gdt_csum(),
+            seed: super_block.get_checksum_seed(),
+            uuid: super_block.s_uuid,
+            desc_size: super_block.get_desc_size(),
+        }
+    }
+
+    /// Returns true if the metadata checksums are enabled
+    pub fn is_enabled(&self) -> bool {
+        self.metadata_csum
+    }
+
+    /// Apply the checksum mode to a verification result:
+    /// in strict mode return the error, in lenient mode print a warning
+    pub fn report(&self, result: Result<(), ChecksumError>) -> Result<(), Error> {
+        match (result, self.mode) {
+            (Ok(_), _) | (Err(_), ChecksumMode::Ignore) => Ok(()),
+            (Err(err), ChecksumMode::Strict) => Err(err.into()),
+            (Err(err), ChecksumMode::Lenient) => {
+                eprintln!("warning: {}", err);
+                Ok(())
+            }
+        }
+    }
+
+    /// Checksum seed of the inode metadata (extent blocks, directory blocks)
+    pub fn inode_seed(&self, inode_num: u64, generation: u32) -> u32 {
+        let crc = crc32c_le(self.seed, &(inode_num as u32).to_le_bytes());
+        crc32c_le(crc, &generation.to_le_bytes())
+    }
+
+    /// Verify the superblock checksum
+    pub fn verify_super_block(&self, buffer: &[u8]) -> Result<(), ChecksumError> {
+        if !self.metadata_csum {
+            return Ok(());
+        }
+        let expected = read_u32(buffer, SUPER_BLOCK_CSUM_OFFSET);
+        let found = crc32c_le(!0, &buffer[..SUPER_BLOCK_CSUM_OFFSET]);
+        check(ChecksumKind::SuperBlock, expected, found)
+    }
+
+    /// Update the superblock checksum
+    pub fn set_super_block(&self, buffer: &mut [u8]) {
+        if self.metadata_csum {
+            let crc = crc32c_le(!0, &buffer[..SUPER_BLOCK_CSUM_OFFSET]);
+            write_u32(buffer, SUPER_BLOCK_CSUM_OFFSET, crc);
+        }
+    }
+
+    /// Calculate the group descriptor checksum
+    pub fn group_desc_checksum(&self, group_num: usize, buffer: &[u8]) -> u16 {
+        let group = (group_num as u32).to_le_bytes();
+        let desc = &buffer[..self.desc_size];
+        if self.metadata_csum {
+            let crc = crc32c_le(self.seed, &group);
+            let crc = crc32c_le(crc, &desc[..GROUP_DESC_CSUM_OFFSET]);
+            let crc = crc32c_le(crc, &[0, 0]);
+            let crc = crc32c_le(crc, &desc[GROUP_DESC_CSUM_END..]);
+            (crc & 0xffff) as u16
+        } else {
+            let crc = crc16(!0, &self.uuid);
+            let crc = crc16(crc, &group);
+            let crc = crc16(crc, &desc[..GROUP_DESC_CSUM_OFFSET]);
+            crc16(crc, &desc[GROUP_DESC_CSUM_END..])
+        }
+    }
+
+    /// Verify the group descriptor checksum
+    pub fn verify_group_desc(&self, group_num: usize, buffer: &[u8]) -> Result<(), ChecksumError> {
+        if !self.metadata_csum && !self.gdt_csum {
+            return Ok(());
+        }
+        let expected = read_u16(buffer, GROUP_DESC_CSUM_OFFSET);
+        let found = self.group_desc_checksum(group_num, buffer);
+        check(
+            ChecksumKind::GroupDesc { group_num },
+            expected as u32,
+            found as u32,
+        )
+    }
+
+    /// Update the group descriptor checksum
+    pub fn set_group_desc(&self, group_num: usize, buffer: &mut [u8]) {
+        if self.metadata_csum || self.gdt_csum {
+            let crc = self.group_desc_checksum(group_num, buffer);
+            write_u16(buffer, GROUP_DESC_CSUM_OFFSET, crc);
+        }
+    }
+
+    /// Calculate a bitmap checksum, returns the lower and the upper 16 bits
+    pub fn bitmap_checksum(&self, bitmap: &[u8]) -> (u16, u16) {
+        if !self.metadata_csum {
+            return (0, 0);
+        }
+        let crc = crc32c_le(self.seed, bitmap);
+        (crc as u16, (crc >> 16) as u16)
+    }
+
+    /// Verify a bitmap checksum, the lower 16 bits are stored in csum_lo,
+    /// the upper 16 bits in csum_hi (only with 64 bytes group descriptors)
+    fn verify_bitmap(
+        &self,
+        kind: ChecksumKind,
+        bitmap: &[u8],
+        csum_lo: u16,
+        csum_hi: u16,
+        hi_end: usize,
+    ) -> Result<(), ChecksumError> {
+        if !self.metadata_csum {
+            return Ok(());
+        }
+        let found = crc32c_le(self.seed, bitmap);
+        if self.desc_size >= hi_end {
+            check(kind, csum_lo as u32 | (csum_hi as u32) << 16, found)
+        } else {
+            check(kind, csum_lo as u32, found & 0xffff)
+        }
+    }
+
+    /// Verify the block bitmap checksum (bitmap is clusters_per_group / 8 bytes long)
+    pub fn verify_block_bitmap(
+        &self,
+        group_num: usize,
+        bitmap: &[u8],
+        csum_lo: u16,
+        csum_hi: u16,
+    ) -> Result<(), ChecksumError> {
+        self.verify_bitmap(
+            ChecksumKind::BlockBitmap { group_num },
+            bitmap,
+            csum_lo,
+            csum_hi,
+            GROUP_DESC_BLOCK_BITMAP_CSUM_HI_END,
+        )
+    }
+
+    /// Verify the inode bitmap checksum (bitmap is inodes_per_group / 8 bytes long)
+    pub fn verify_inode_bitmap(
+        &self,
+        group_num: usize,
+        bitmap: &[u8],
+        csum_lo: u16,
+        csum_hi: u16,
+    ) -> Result<(), ChecksumError> {
+        self.verify_bitmap(
+            ChecksumKind::InodeBitmap { group_num },
+            bitmap,
+            csum_lo,
+            csum_hi,
+            GROUP_DESC_INODE_BITMAP_CSUM_HI_END,
+        )
+    }
+
+    /// Calculate the inode checksum, returns the checksum and true if
+    /// the upper 16 bits are stored in the inode
+    fn inode_checksum(&self, inode_num: u64, buffer: &[u8]) -> (u32, bool) {
+        let has_hi = buffer.len() > EXT2_GOOD_OLD_INODE_SIZE
+            && read_u16(buffer, INODE_EXTRA_ISIZE_OFFSET) >= INODE_CSUM_HI_EXTRA_END;
+        let generation = read_u32(buffer, 0x64);
+        let crc = self.inode_seed(inode_num, generation);
+        // The checksum fields are considered as zero
+        let crc = crc32c_le(crc, &buffer[..INODE_CSUM_LO_OFFSET]);
+        let crc = crc32c_le(crc, &[0, 0]);
+        let crc = crc32c_le(
+            crc,
+            &buffer[INODE_CSUM_LO_OFFSET + 2..EXT2_GOOD_OLD_INODE_SIZE],
+        );
+        if has_hi {
+            let crc = crc32c_le(crc, &buffer[EXT2_GOOD_OLD_INODE_SIZE..INODE_CSUM_HI_OFFSET]);
+            let crc = crc32c_le(crc, &[0, 0]);
+            (crc32c_le(crc, &buffer[INODE_CSUM_HI_OFFSET + 2..]), true)
+        } else {
+            let crc = crc32c_le(crc, &buffer[EXT2_GOOD_OLD_INODE_SIZE..]);
+            (crc & 0xffff, false)
+        }
+    }
+
+    /// Verify the inode checksum
+    pub fn verify_inode(&self, inode_num: u64, buffer: &[u8]) -> Result<(), ChecksumError> {
+        if !self.metadata_csum {
+            return Ok(());
+        }
+        let (found, has_hi) = self.inode_checksum(inode_num, buffer);
+        let mut expected = read_u16(buffer, INODE_CSUM_LO_OFFSET) as u32;
+        if has_hi {
+            expected |= (read_u16(buffer, INODE_CSUM_HI_OFFSET) as u32) << 16;
+        }
+        check(ChecksumKind::Inode { inode_num }, expected, found)
+    }
+
+    /// Update the inode checksum
+    pub fn set_inode(&self, inode_num: u64, buffer: &mut [u8]) {
+        if !self.metadata_csum {
+            return;
+        }
+        let (crc, has_hi) = self.inode_checksum(inode_num, buffer);
+        write_u16(buffer, INODE_CSUM_LO_OFFSET, crc as u16);
+        if has_hi {
+            write_u16(buffer, INODE_CSUM_HI_OFFSET, (crc >> 16) as u16);
+        }
+    }
+
+    /// Verify the checksum of an extent tree block (the tail follows the eh_max entries)
+    pub fn verify_extent_block(
+        &self,
+        inode_num: u64,
+        inode_seed: u32,
+        block_num: u64,
+        buffer: &[u8],
+        tail_offset: usize,
+    ) -> Result<(), ChecksumError> {
+        if !self.metadata_csum || tail_offset + 4 > buffer.len() {
+            return Ok(());
+        }
+        let expected = read_u32(buffer, tail_offset);
+        let found = crc32c_le(inode_seed, &buffer[..tail_offset]);
+        check(
+            ChecksumKind::ExtentBlock {
+                inode_num,
+                block_num,
+            },
+            expected,
+            found,
+        )
+    }
+
+    /// Update the checksum of an extent tree block
+    pub fn set_extent_block(&self, inode_seed: u32, buffer: &mut [u8], tail_offset: usize) {
+        if self.metadata_csum && tail_offset + 4 <= buffer.len() {
+            let crc = crc32c_le(inode_seed, &buffer[..tail_offset]);
+            write_u32(buffer, tail_offset, crc);
+        }
+    }
+
+    /// Returns true if the directory block ends with a checksum tail
+    pub fn has_dir_tail(&self, buffer: &[u8]) -> bool {
+        let tail = &buffer[buffer.len() - DIR_TAIL_SIZE..];
+        self.metadata_csum
+            && read_u32(tail, 0) == 0
+            && read_u16(tail, 4) as usize == DIR_TAIL_SIZE
+            && tail[6] == 0
+            && tail[7] == DIR_TAIL_FT
+    }
+
+    /// Verify the checksum of a directory leaf block.
+    /// Blocks without tail (htree internal nodes) are not verified.
+    pub fn verify_dir_block(
+        &self,
+        inode_num: u64,
+        inode_seed: u32,
+        block_num: u64,
+        buffer: &[u8],
+    ) -> Result<(), ChecksumError> {
+        if !self.has_dir_tail(buffer) {
+            return Ok(());
+        }
+        let tail_offset = buffer.len() - DIR_TAIL_SIZE;
+        let expected = read_u32(buffer, tail_offset + 8);
+        let found = crc32c_le(inode_seed, &buffer[..tail_offset]);
+        check(
+            ChecksumKind::DirBlock {
+                inode_num,
+                block_num,
+            },
+            expected,
+            found,
+        )
+    }
+
+    /// Update the checksum of a directory leaf block (if the block has a tail)
+    pub fn set_dir_block(&self, inode_seed: u32, buffer: &mut [u8]) {
+        if self.has_dir_tail(buffer) {
+            let tail_offset = buffer.len() - DIR_TAIL_SIZE;
+            let crc = crc32c_le(inode_seed, &buffer[..tail_offset]);
+            write_u32(buffer, tail_offset + 8, crc);
+        }
+    }
+
+    /// Verify the checksum of an extended attribute block
+    pub fn verify_xattr_block(&self, block_num: u64, buffer: &[u8]) -> Result<(), ChecksumError> {
+        if !self.metadata_csum {
+            return Ok(());
+        }
+        let expected = read_u32(buffer, XATTR_CSUM_OFFSET);
+        let crc = crc32c_le(self.seed, &block_num.to_le_bytes());
+        let crc = crc32c_le(crc, &buffer[..XATTR_CSUM_OFFSET]);
+        let crc = crc32c_le(crc, &[0, 0, 0, 0]);
+        let found = crc32c_le(crc, &buffer[XATTR_CSUM_OFFSET + 4..]);
+        check(ChecksumKind::XattrBlock { block_num }, expected, found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::{Disk, FileDisk, Offset};
+    use crate::ext2::Ext2Filesystem;
+    use crate::fs::MountOptions;
+    use crate::test_util::{
+        block_pattern, inode_offset, mount_image, patch_image, read_file, unpack_image,
+    };
+
+    const EXTENTS_INODE: u64 = 53; // /extents.txt in ext4.img.gz
+
+    fn strict() -> MountOptions {
+        MountOptions {
+            checksum_mode: ChecksumMode::Strict,
+            ..Default::default()
+        }
+    }
+
+    /// Read bytes of a test image
+    fn read_at(name: &str, size: u64, offset: u64) -> Vec<u8> {
+        let disk = FileDisk::open(&unpack_image(name)).unwrap();
+        let offset = Offset::Block {
+            block_size: 1,
+            block_num: offset,
+        };
+        disk.read(size, offset).unwrap()
+    }
+
+    fn read_super_block(name: &str) -> Ext2SuperBlock {
+        let disk = FileDisk::open(&unpack_image(name)).unwrap();
+        Ext2SuperBlock::new(&disk).unwrap()
+    }
+
+    #[test]
+    fn test_crc32c() {
+        // Check value of CRC-32C, with the pre and post inversion
+        assert_eq!(!crc32c_le(!0, b"123456789"), 0xe3069283);
+        assert_eq!(crc32c_le(0x1234, b""), 0x1234);
+        // The checksum can be computed in several parts
+        assert_eq!(
+            crc32c_le(crc32c_le(!0, b"1234"), b"56789"),
+            crc32c_le(!0, b"123456789")
+        );
+    }
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(0, b"123456789"), 0xbb3d); // CRC-16/ARC
+        assert_eq!(crc16(!0, b"123456789"), 0x4b37); // CRC-16/MODBUS
+    }
+
+    #[test]
+    fn test_checksum_mode() {
+        assert_eq!("strict".parse(), Ok(ChecksumMode::Strict));
+        assert_eq!("lenient".parse(), Ok(ChecksumMode::Lenient));
+        assert!("bogus".parse::<ChecksumMode>().is_err());
+    }
+
+    #[test]
+    fn test_super_block_checksum() {
+        let super_block = read_super_block("ext4.img.gz");
+        let checksum = Ext2Checksum::new(&super_block, ChecksumMode::Strict);
+        assert!(checksum.is_enabled());
+        let mut buffer = super_block.as_bytes().to_vec();
+        assert_eq!(checksum.verify_super_block(&buffer), Ok(()));
+        buffer[0x78] ^= 1; // Volume name
+        let err = checksum.verify_super_block(&buffer).unwrap_err();
+        assert_eq!(err.kind, ChecksumKind::SuperBlock);
+        checksum.set_super_block(&mut buffer);
+        assert_eq!(checksum.verify_super_block(&buffer), Ok(()));
+        buffer[0x78] ^= 1;
+        // Without metadata_csum, nothing to verify
+        let checksum = Ext2Checksum::new(&read_super_block("ext2.img.gz"), ChecksumMode::Strict);
+        assert!(!checksum.is_enabled());
+        assert_eq!(checksum.verify_super_block(&buffer), Ok(()));
+    }
+
+    #[test]
+    fn test_group_desc_checksum() {
+        let super_block = read_super_block("ext4.img.gz");
+        let checksum = Ext2Checksum::new(&super_block, ChecksumMode::Strict);
+        let block_size = super_block.get_block_size();
+        let offset = if block_size == 1024 { 2048 } else { block_size };
+        let size = super_block.get_desc_size() as u64;
+        let mut buffer = read_at("ext4.img.gz", size, offset);
+        assert_eq!(checksum.verify_group_desc(0, &buffer), Ok(()));
+        // The group number is part of the checksum
+        assert!(checksum.verify_group_desc(1, &buffer).is_err());
+        buffer[0x0c] ^= 1; // Free blocks count
+        assert!(checksum.verify_group_desc(0, &buffer).is_err());
+        checksum.set_group_desc(0, &mut buffer);
+        assert_eq!(checksum.verify_group_desc(0, &buffer), Ok(()));
+    }
+
+    #[test]
+    fn test_inode_checksum() {
+        let super_block = read_super_block("ext4.img.gz");
+        let checksum = Ext2Checksum::new(&super_block, ChecksumMode::Strict);
+        let inode_size = super_block.s_inode_size as u64;
+        let offset = inode_offset("ext4.img.gz", EXTENTS_INODE);
+        let mut buffer = read_at("ext4.img.gz", inode_size, offset);
+        assert_eq!(checksum.verify_inode(EXTENTS_INODE, &buffer), Ok(()));
+        assert!(checksum.verify_inode(EXTENTS_INODE + 1, &buffer).is_err());
+        buffer[0x10] ^= 1; // i_mtime
+        assert!(checksum.verify_inode(EXTENTS_INODE, &buffer).is_err());
+        checksum.set_inode(EXTENTS_INODE, &mut buffer);
+        assert_eq!(checksum.verify_inode(EXTENTS_INODE, &buffer), Ok(()));
+    }
+
+    #[test]
+    fn test_corrupted_inode() {
+        let offset = inode_offset("ext4.img.gz", EXTENTS_INODE) + 0x10;
+        let image = patch_image("ext4.img.gz", &[(offset, &[0xff])]);
+        // Refused in strict mode
+        let fs = Ext2Filesystem::mount(&image.path, &strict()).unwrap();
+        let err = fs.read_inode(EXTENTS_INODE).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(read_file(&fs, "/extents.txt").is_err());
+        // Read anyway in lenient mode
+        let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+        let data = read_file(&fs, "/extents.txt").unwrap();
+        assert_eq!(data, block_pattern(40).as_bytes());
+        // The image itself is valid
+        let fs = mount_image("ext4.img.gz", &strict());
+        assert!(read_file(&fs, "/extents.txt").is_ok());
+    }
+
+    #[test]
+    fn test_corrupted_super_block() {
+        let image = patch_image("ext4.img.gz", &[(1024 + 0x78, b"X")]);
+        let err = Ext2Filesystem::mount(&image.path, &strict()).err();
+        assert_eq!(err.unwrap().kind(), ErrorKind::InvalidData);
+        assert!(Ext2Filesystem::mount(&image.path, &MountOptions::default()).is_ok());
+    }
+}
@@ -1,17 +1,102 @@
 use crate::dir::DirEntry;
-use std::io::Read;
-use std::mem;
-use std::slice;
-use std::str;
-
-#[repr(C)]
-#[derive(Debug, Default)]
-struct Ext2DirEntryStruct {
-    inode_num: u32, // Inode number
-    rec_len: u16,   // Directory entry length
-    name_len: u8,   // Name length
-    file_type: u8,  // Type indicator
-                    // (only if the feature bit for "directory entries have file type byte" is set)
+use crate::ext2::{read_u16, read_u32};
+
+pub const EXT2_DIR_ENTRY_HEADER_SIZE: usize = 8;
+const EXT2_FT_MAX: u8 = 8; // Number of directory entry file types
+
+/// Length of a directory record with the given name length
+pub fn dir_rec_len(name_len: usize) -> usize {
+    (EXT2_DIR_ENTRY_HEADER_SIZE + name_len + 3) & !3
+}
+
+/// Directory entry as stored on disk, validated
+#[derive(Debug, Clone)]
+pub struct Ext2DirRecord {
+    pub offset: usize,   // Offset of the entry in the block
+    pub inode_num: u64,  // Inode number (0 = unused entry)
+    pub rec_len: usize,  // Directory entry length
+    pub name_len: usize, // Name length
+    pub file_type: u8,   // Type indicator
+    pub name: Vec<u8>,   // File name
+}
+
+impl Ext2DirRecord {
+    /// Parse the entry at the given offset, checking rec_len and name_len
+    pub fn parse(buffer: &[u8], offset: usize) -> Result<Ext2DirRecord, &'static str> {
+        if offset + EXT2_DIR_ENTRY_HEADER_SIZE > buffer.len() {
+            return Err("directory entry goes past the end of the block");
+        }
+        let inode_num = read_u32(buffer, offset) as u64;
+        let rec_len = read_u16(buffer, offset + 4) as usize;
+        let name_len = buffer[offset + 6] as usize;
+        let file_type = buffer[offset + 7];
+        if rec_len < EXT2_DIR_ENTRY_HEADER_SIZE {
+            Err("rec_len is too small")
+        } else if !rec_len.is_multiple_of(4) {
+            Err("rec_len is not a multiple of 4")
+        } else if offset + rec_len > buffer.len() {
+            Err("rec_len goes past the end of the block")
+        } else if EXT2_DIR_ENTRY_HEADER_SIZE + name_len > rec_len {
+            Err("name_len is too large for rec_len")
+        } else {
+            let name_start = offset + EXT2_DIR_ENTRY_HEADER_SIZE;
+            let name = buffer[name_start..name_start + name_len].to_vec();
+            if inode_num != 0 && !is_valid_name(&name) {
+                Err("invalid file name")
+            } else {
+                Ok(Ext2DirRecord {
+                    offset,
+                    inode_num,
+                    rec_len,
+                    name_len,
+                    file_type,
+                    name,
+                })
+            }
+        }
+    }
+
+    /// Forensic mode: look for deleted entries.
+    /// When an entry is removed, the previous record's rec_len is extended over it,
+    /// so the old entry often survives in the slack space after the name.
+    /// The first entry of a block is removed clearing the inode number, its name is kept.
+    pub fn parse_deleted(buffer: &[u8], inodes_count: u64) -> Vec<Ext2DirRecord> {
+        let mut result: Vec<Ext2DirRecord> = Vec::new();
+        let mut offset: usize = 0;
+        while let Ok(record) = Ext2DirRecord::parse(buffer, offset) {
+            if record.inode_num == 0 && record.name_len > 0 && is_valid_name(&record.name) {
+                result.push(record.clone());
+            }
+            let end = offset + record.rec_len;
+            let mut slack_offset = offset + dir_rec_len(record.name_len);
+            while slack_offset + EXT2_DIR_ENTRY_HEADER_SIZE < end {
+                match Ext2DirRecord::parse(&buffer[..end], slack_offset) {
+                    Ok(deleted)
+                        if deleted.inode_num <= inodes_count
+                            && deleted.inode_num != 0
+                            && deleted.file_type < EXT2_FT_MAX =>
+                    {
+                        // Deleted entries can be nested in the slack space of this one
+                        slack_offset += dir_rec_len(deleted.name_len);
+                        result.push(deleted);
+                    }
+                    _ => slack_offset += 4,
+                }
+            }
+            offset = end;
+        }
+        result
+    }
+
+    /// File name (invalid UTF-8 sequences are replaced)
+    pub fn get_name(&self) -> String {
+        String::from_utf8_lossy(&self.name).into_owned()
+    }
+}
+
+/// Returns true if the name can be used for a directory entry
+fn is_valid_name(name: &[u8]) -> bool {
+    !name.is_empty() && !name.contains(&b'/') && !name.contains(&0)
 }
 
 // Directory entry
@@ -22,30 +107,16 @@ pub struct Ext2DirEntry {
     inode_num: u64,    // inode number
 }
 impl Ext2DirEntry {
-    pub fn new(buffer: &Vec<u8>, offset: usize, parent: &str) -> (Ext2DirEntry, usize) {
-        let mut ext2_dir_entry = Ext2DirEntryStruct::default();
-        let size = mem::size_of::<Ext2DirEntryStruct>();
-        let mut buf = &buffer[offset..offset + size];
-        let p = &mut ext2_dir_entry as *mut _ as *mut u8;
-        unsafe {
-            let dir_slice = slice::from_raw_parts_mut(p, size);
-            buf.read_exact(dir_slice).unwrap();
-        }
-        let name_slice = &buffer[offset + size..offset + size + ext2_dir_entry.name_len as usize];
-        let name = match str::from_utf8(name_slice) {
-            Ok(v) => v,
-            Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
-        };
-        let dir_entry = Ext2DirEntry {
+    pub fn with_name(name: &str, inode_num: u64, parent: &str) -> Ext2DirEntry {
+        Ext2DirEntry {
             path: if parent != "/" {
                 format!("{}/{}", parent, name)
             } else {
                 format!("/{}", name)
             },
             file_name: String::from(name),
-            inode_num: ext2_dir_entry.inode_num as u64,
-        };
-        (dir_entry, ext2_dir_entry.rec_len as usize)
+            inode_num,
+        }
     }
 }
 
@@ -65,3 +136,80 @@ impl DirEntry for Ext2DirEntry {
         return self.inode_num;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext2::{write_u16, write_u32};
+    use crate::fs::{Filesystem, MountOptions};
+    use crate::test_util::mount_image;
+
+    /// Write a directory record: inode number, rec_len, file type and name
+    fn write_record(buffer: &mut [u8], offset: usize, record: (u32, u16, u8, &[u8])) {
+        let (inode_num, rec_len, file_type, name) = record;
+        write_u32(buffer, offset, inode_num);
+        write_u16(buffer, offset + 4, rec_len);
+        buffer[offset + 6] = name.len() as u8;
+        buffer[offset + 7] = file_type;
+        buffer[offset + 8..offset + 8 + name.len()].copy_from_slice(name);
+    }
+
+    #[test]
+    fn test_dir_rec_len() {
+        assert_eq!(dir_rec_len(1), 12);
+        assert_eq!(dir_rec_len(4), 12);
+        assert_eq!(dir_rec_len(5), 16);
+        assert_eq!(dir_rec_len(255), 264);
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut buffer = vec![0; 32];
+        write_record(&mut buffer, 0, (12, 16, 1, b"hello"));
+        write_record(&mut buffer, 16, (0, 16, 0, b""));
+        let record = Ext2DirRecord::parse(&buffer, 0).unwrap();
+        assert_eq!(record.inode_num, 12);
+        assert_eq!(record.rec_len, 16);
+        assert_eq!(record.file_type, 1);
+        assert_eq!(record.get_name(), "hello");
+        let record = Ext2DirRecord::parse(&buffer, 16).unwrap();
+        assert_eq!(record.inode_num, 0);
+        assert!(Ext2DirRecord::parse(&buffer, 28).is_err());
+    }
+
+    #[test]
+    fn test_parse_deleted() {
+        let mut buffer = vec![0; 64];
+        // First entry removed: the inode number is cleared
+        write_record(&mut buffer, 0, (0, 16, 1, b"first"));
+        // "a" was extended over "old.txt" and "x", still in its slack space
+        write_record(&mut buffer, 16, (12, 48, 1, b"a"));
+        write_record(&mut buffer, 28, (13, 16, 1, b"old.txt"));
+        write_record(&mut buffer, 44, (14, 20, 2, b"x"));
+        let deleted = Ext2DirRecord::parse_deleted(&buffer, 100);
+        let names: Vec<(String, u64)> = deleted
+            .iter()
+            .map(|x| (x.get_name(), x.inode_num))
+            .collect();
+        let expected = [("first", 0), ("old.txt", 13), ("x", 14)];
+        assert_eq!(names, expected.map(|(name, num)| (name.to_string(), num)));
+        assert_eq!(deleted[1].offset, 28);
+        // Inode numbers out of range are not deleted entries
+        let deleted = Ext2DirRecord::parse_deleted(&buffer, 13);
+        assert_eq!(deleted.len(), 2);
+        // Slack space with garbage
+        let mut buffer = vec![0xff; 64];
+        write_record(&mut buffer, 0, (12, 64, 1, b"a"));
+        assert!(Ext2DirRecord::parse_deleted(&buffer, 100).is_empty());
+    }
+
+    #[test]
+    fn test_read_deleted_dir() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let entries = fs.read_deleted_dir("/").unwrap();
+        let names: Vec<(String, u64)> = entries.iter().map(|x| (x.path(), x.inode_num())).collect();
+        assert_eq!(names, [("/deleted.txt".to_string(), 13)]);
+        assert!(fs.read_deleted_dir("/dir1").unwrap().is_empty());
+        assert!(fs.read_deleted_dir("/hello.txt").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::disk::Offset;
+use crate::ext2::inode::Ext2InodeStruct;
+use crate::ext2::Ext2Filesystem;
+use std::io::{Error, ErrorKind};
+
+impl Ext2Filesystem {
+    /// Read raw filesystem blocks
+    pub fn read_blocks(&self, block_num: u64, count: u64) -> Result<Vec<u8>, Error> {
+        if block_num + count > self.super_block.get_blocks_count() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Block out of range"));
+        }
+        let block_size = self.super_block.get_block_size();
+        let offset = Offset::Block {
+            block_size,
+            block_num,
+        };
+        self.disk.read(count * block_size, offset)
+    }
+
+    /// Read an inode struct as stored in the inode table.
+    /// The checksum is not verified, to allow the inspection of corrupted inodes.
+    pub fn read_inode_struct(&self, inode_num: u64) -> Result<Ext2InodeStruct, Error> {
+        if inode_num == 0 || inode_num > self.super_block.s_inodes_count as u64 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid inode number"));
+        }
+        let inode_size = self.super_block.s_inode_size as u64;
+        let group = self.block_groups.get_inode_group(inode_num);
+        let offset = Offset::BlockDelta {
+            block_size: self.super_block.get_block_size(),
+            base_block_num: group.ext2_group_desc.get_inode_table(),
+            delta: (inode_num - group.first_inode_num) * inode_size,
+        };
+        let buffer = self.disk.read(inode_size, offset)?;
+        Ok(Ext2InodeStruct::from_bytes(&buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext2::checksum::ChecksumMode;
+    use crate::ext2::read_u16;
+    use crate::fs::MountOptions;
+    use crate::test_util::{block_pattern, inode_offset, mount_image, patch_image};
+
+    #[test]
+    fn test_read_blocks() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        // Superblock
+        let buffer = fs.read_blocks(1, 1).unwrap();
+        assert_eq!(buffer.len(), 1024);
+        assert_eq!(read_u16(&buffer, 0x38), 0xef53);
+        // First blocks of /big.txt
+        let buffer = fs.read_blocks(342, 2).unwrap();
+        assert_eq!(buffer, block_pattern(2).as_bytes());
+        let blocks_count = fs.get_super_block().get_blocks_count();
+        assert!(fs.read_blocks(blocks_count - 1, 1).is_ok());
+        let err = fs.read_blocks(blocks_count - 1, 2).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_read_inode_struct() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let inode = fs.read_inode_struct(16).unwrap();
+        assert_eq!(inode.i_mode & 0xf000, 0x8000);
+        assert_eq!(inode.i_size, 14);
+        assert_eq!(inode.i_links_count, 1);
+        assert_eq!(inode.i_mtime, 1700000000);
+        assert!(inode.get_flag_names().is_empty());
+        // Deleted inode
+        assert_ne!(fs.read_inode_struct(13).unwrap().i_dtime, 0);
+        for inode_num in [0, fs.get_super_block().s_inodes_count as u64 + 1] {
+            let err = fs.read_inode_struct(inode_num).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        assert_eq!(
+            fs.read_inode_struct(53).unwrap().get_flag_names(),
+            ["EXTENTS"]
+        );
+        assert_eq!(
+            fs.read_inode_struct(59).unwrap().get_flag_names(),
+            ["INLINE_DATA"]
+        );
+    }
+
+    #[test]
+    fn test_read_corrupted_inode_struct() {
+        // The checksum is not verified
+        let offset = inode_offset("ext4.img.gz", 59) + 0x10;
+        let image = patch_image("ext4.img.gz", &[(offset, &[0xff])]);
+        let options = MountOptions {
+            checksum_mode: ChecksumMode::Strict,
+            ..Default::default()
+        };
+        let fs = Ext2Filesystem::mount(&image.path, &options).unwrap();
+        assert!(fs.read_inode(59).is_err());
+        assert_eq!(fs.read_inode_struct(59).unwrap().i_size, 18);
+    }
+}
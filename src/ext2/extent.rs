@@ -0,0 +1,346 @@
+use crate::disk::{Disk, Offset};
+use crate::ext2::checksum::Ext2Checksum;
+use crate::ext2::inode::I_BLOCKS_SIZE;
+use crate::ext2::{read_u16, read_u32};
+use std::collections::HashSet;
+use std::io::Error;
+use std::io::ErrorKind;
+
+const EXT4_EXT_MAGIC: u16 = 0xf30a;
+pub const EXT4_EXT_HEADER_SIZE: usize = 12;
+pub const EXT4_EXT_ENTRY_SIZE: usize = 12;
+const EXT4_EXT_INIT_MAX_LEN: u16 = 1 << 15; // Maximum length of an initialized extent
+const EXT4_EXT_MAX_DEPTH: u16 = 5; // Maximum depth of the extent tree
+
+/// Each node of the extent tree starts with a header
+#[derive(Debug)]
+pub struct Ext4ExtentHeader {
+    pub eh_magic: u16,      // Magic number, 0xf30a
+    pub eh_entries: u16,    // Number of valid entries following the header
+    pub eh_max: u16,        // Maximum number of entries that could follow the header
+    pub eh_depth: u16,      // Depth of this extent node in the extent tree (0 = leaf)
+    pub eh_generation: u32, // Generation of the tree
+}
+
+impl Ext4ExtentHeader {
+    pub fn new(buffer: &[u8]) -> Result<Ext4ExtentHeader, Error> {
+        if buffer.len() < EXT4_EXT_HEADER_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid extent header"));
+        }
+        let header = Ext4ExtentHeader {
+            eh_magic: read_u16(buffer, 0),
+            eh_entries: read_u16(buffer, 2),
+            eh_max: read_u16(buffer, 4),
+            eh_depth: read_u16(buffer, 6),
+            eh_generation: read_u32(buffer, 8),
+        };
+        let size = EXT4_EXT_HEADER_SIZE + header.eh_entries as usize * EXT4_EXT_ENTRY_SIZE;
+        if header.eh_magic != EXT4_EXT_MAGIC
+            || header.eh_entries > header.eh_max
+            || size > buffer.len()
+        {
+            Err(Error::new(ErrorKind::InvalidData, "Invalid extent header"))
+        } else {
+            Ok(header)
+        }
+    }
+
+    /// Offset of the checksum tail in an extent block
+    pub fn tail_offset(&self) -> usize {
+        EXT4_EXT_HEADER_SIZE + self.eh_max as usize * EXT4_EXT_ENTRY_SIZE
+    }
+}
+
+/// Leaf node of the extent tree, a range of contiguous blocks
+#[derive(Debug, Clone, Copy)]
+pub struct Ext4Extent {
+    pub ee_block: u64,       // First file block number that this extent covers
+    pub ee_len: u64,         // Number of blocks covered by extent
+    pub ee_start: u64,       // Block number to which this extent points
+    pub uninitialized: bool, // Blocks allocated but not initialized (read as zeros)
+    pub parent: u64,         // Block containing the extent (0 if in the inode)
+    pub index: usize,        // Position of the extent in the parent
+}
+
+impl Ext4Extent {
+    fn new(buffer: &[u8], parent: u64, index: usize) -> Ext4Extent {
+        let len = read_u16(buffer, 4);
+        let (len, uninitialized) = if len > EXT4_EXT_INIT_MAX_LEN {
+            (len - EXT4_EXT_INIT_MAX_LEN, true)
+        } else {
+            (len, false)
+        };
+        Ext4Extent {
+            ee_block: read_u32(buffer, 0) as u64,
+            ee_len: len as u64,
+            ee_start: read_u32(buffer, 8) as u64 | (read_u16(buffer, 6) as u64) << 32,
+            uninitialized,
+            parent,
+            index,
+        }
+    }
+
+    /// Returns the physical block of a file block, if the extent covers it
+    pub fn get_block(&self, file_block_num: u64) -> Option<u64> {
+        if file_block_num >= self.ee_block && file_block_num < self.ee_block + self.ee_len {
+            Some(self.ee_start + file_block_num - self.ee_block)
+        } else {
+            None
+        }
+    }
+}
+
+/// Index or leaf block of the extent tree (the root node is stored in the inode)
+#[derive(Debug, Clone, Copy)]
+pub struct Ext4ExtentNode {
+    pub block_num: u64, // Block number of the node
+    pub ee_block: u64,  // First file block number covered by the node
+    pub parent: u64,    // Block containing the index (0 if in the inode)
+    pub index: usize,   // Position of the index in the parent
+}
+
+/// Extent tree of an inode
+#[derive(Debug, Default)]
+pub struct Ext4ExtentTree {
+    pub extents: Vec<Ext4Extent>,   // Leaf extents
+    pub nodes: Vec<Ext4ExtentNode>, // Index and leaf blocks
+}
+
+/// Read the extent tree of an inode, following the index nodes
+pub fn read_extents(
+    disk: &dyn Disk,
+    i_block: &[u8; I_BLOCKS_SIZE],
+    block_size: u64,
+    checksum: &Ext2Checksum,
+    inode_num: u64,
+    inode_seed: u32,
+) -> Result<Ext4ExtentTree, Error> {
+    let reader = ExtentReader {
+        disk,
+        block_size,
+        checksum,
+        inode_num,
+        inode_seed,
+    };
+    let mut tree = Ext4ExtentTree::default();
+    let depth = Ext4ExtentHeader::new(i_block)?.eh_depth;
+    reader.read_node(i_block, depth, 0, &mut HashSet::new(), &mut tree)?;
+    Ok(tree)
+}
+
+struct ExtentReader<'a> {
+    disk: &'a dyn Disk,
+    block_size: u64,
+    checksum: &'a Ext2Checksum,
+    inode_num: u64,
+    inode_seed: u32,
+}
+
+impl ExtentReader<'_> {
+    /// Read a node of the given depth, stored in the parent block (0 for the root).
+    /// Visited contains the blocks of the nodes already read: a block can only be
+    /// referenced once in the tree.
+    fn read_node(
+        &self,
+        buffer: &[u8],
+        depth: u16,
+        parent: u64,
+        visited: &mut HashSet<u64>,
+        tree: &mut Ext4ExtentTree,
+    ) -> Result<(), Error> {
+        let header = Ext4ExtentHeader::new(buffer)?;
+        if header.eh_depth != depth || depth > EXT4_EXT_MAX_DEPTH {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid extent tree depth {}", header.eh_depth),
+            ));
+        }
+        for i in 0..header.eh_entries as usize {
+            let entry = &buffer[EXT4_EXT_HEADER_SIZE + i * EXT4_EXT_ENTRY_SIZE..];
+            if header.eh_depth == 0 {
+                // Leaf node
+                tree.extents.push(Ext4Extent::new(entry, parent, i));
+            } else {
+                // Index node, points to the next level of the tree
+                let block_num = read_u32(entry, 4) as u64 | (read_u16(entry, 8) as u64) << 32;
+                if !visited.insert(block_num) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Extent tree block {} referenced twice", block_num),
+                    ));
+                }
+                tree.nodes.push(Ext4ExtentNode {
+                    block_num,
+                    ee_block: read_u32(entry, 0) as u64,
+                    parent,
+                    index: i,
+                });
+                let offset = Offset::Block {
+                    block_size: self.block_size,
+                    block_num,
+                };
+                let block = self.disk.read(self.block_size, offset)?;
+                let child = Ext4ExtentHeader::new(&block)?;
+                self.checksum.report(self.checksum.verify_extent_block(
+                    self.inode_num,
+                    self.inode_seed,
+                    block_num,
+                    &block,
+                    child.tail_offset(),
+                ))?;
+                self.read_node(&block, depth - 1, block_num, visited, tree)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MountOptions;
+    use crate::test_util::{block_pattern, mount_image, read_file, MemoryDisk};
+
+    const BLOCK_SIZE: u64 = 1024;
+
+    /// Extent tree node: header followed by the entries (file block, block number, length)
+    fn node(depth: u16, entries: &[(u32, u64, u16)], size: usize) -> Vec<u8> {
+        let max = (size - EXT4_EXT_HEADER_SIZE) / EXT4_EXT_ENTRY_SIZE;
+        let mut buffer = Vec::with_capacity(size);
+        buffer.extend(EXT4_EXT_MAGIC.to_le_bytes());
+        buffer.extend((entries.len() as u16).to_le_bytes());
+        buffer.extend((max as u16).to_le_bytes());
+        buffer.extend(depth.to_le_bytes());
+        buffer.extend(0u32.to_le_bytes());
+        for (file_block, block_num, len) in entries {
+            buffer.extend(file_block.to_le_bytes());
+            if depth == 0 {
+                buffer.extend(len.to_le_bytes());
+                buffer.extend(((block_num >> 32) as u16).to_le_bytes());
+                buffer.extend((*block_num as u32).to_le_bytes());
+            } else {
+                buffer.extend((*block_num as u32).to_le_bytes());
+                buffer.extend(((block_num >> 32) as u16).to_le_bytes());
+                buffer.extend(0u16.to_le_bytes());
+            }
+        }
+        buffer.resize(size, 0);
+        buffer
+    }
+
+    /// Read the tree rooted in i_block, the other nodes are stored in a 16 blocks disk
+    fn read_tree(root: Vec<u8>, nodes: &[(u64, Vec<u8>)]) -> Result<Ext4ExtentTree, Error> {
+        let mut data = vec![0; 16 * BLOCK_SIZE as usize];
+        for (block_num, buffer) in nodes {
+            let start = (block_num * BLOCK_SIZE) as usize;
+            data[start..start + buffer.len()].copy_from_slice(buffer);
+        }
+        let disk = MemoryDisk::new(data);
+        let i_block: [u8; I_BLOCKS_SIZE] = root.try_into().unwrap();
+        read_extents(&disk, &i_block, BLOCK_SIZE, &Ext2Checksum::default(), 12, 0)
+    }
+
+    #[test]
+    fn test_extents_in_inode() {
+        let root = node(0, &[(0, 100, 4), (4, 200, 0x8000 + 2)], I_BLOCKS_SIZE);
+        let tree = read_tree(root, &[]).unwrap();
+        assert!(tree.nodes.is_empty());
+        assert_eq!(tree.extents.len(), 2);
+        let (first, second) = (tree.extents[0], tree.extents[1]);
+        assert_eq!((first.ee_len, first.uninitialized), (4, false));
+        assert_eq!((second.ee_len, second.uninitialized), (2, true));
+        assert_eq!(first.get_block(3), Some(103));
+        assert_eq!(first.get_block(4), None);
+        assert_eq!(second.get_block(5), Some(201));
+    }
+
+    #[test]
+    fn test_index_nodes() {
+        let size = BLOCK_SIZE as usize;
+        let root = node(2, &[(0, 3, 0)], I_BLOCKS_SIZE);
+        let nodes = [
+            (3, node(1, &[(0, 4, 0), (10, 5, 0)], size)),
+            (4, node(0, &[(0, 0x1_0000_0000, 10)], size)),
+            (5, node(0, &[(10, 300, 5)], size)),
+        ];
+        let tree = read_tree(root, &nodes).unwrap();
+        let blocks: Vec<u64> = tree.nodes.iter().map(|x| x.block_num).collect();
+        assert_eq!(blocks, [3, 4, 5]);
+        let parents: Vec<u64> = tree.nodes.iter().map(|x| x.parent).collect();
+        assert_eq!(parents, [0, 3, 3]);
+        let starts: Vec<u64> = tree.extents.iter().map(|x| x.ee_start).collect();
+        assert_eq!(starts, [0x1_0000_0000, 300]);
+        assert_eq!((tree.extents[1].parent, tree.extents[1].index), (5, 0));
+    }
+
+    #[test]
+    fn test_depth_mismatch() {
+        let size = BLOCK_SIZE as usize;
+        // The child of a depth 2 node must have the depth 1
+        let root = node(2, &[(0, 3, 0)], I_BLOCKS_SIZE);
+        let err = read_tree(root, &[(3, node(0, &[(0, 100, 1)], size))]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // Index pointing to a node of the same depth, here itself
+        let root = node(1, &[(0, 3, 0)], I_BLOCKS_SIZE);
+        let err = read_tree(root, &[(3, node(1, &[(0, 3, 0)], size))]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_loop() {
+        let size = BLOCK_SIZE as usize;
+        let root = node(3, &[(0, 3, 0)], I_BLOCKS_SIZE);
+        let nodes = [(3, node(2, &[(0, 3, 0)], size))];
+        let err = read_tree(root, &nodes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("referenced twice"), "{}", err);
+    }
+
+    #[test]
+    fn test_shared_node() {
+        let size = BLOCK_SIZE as usize;
+        // Two index entries pointing to the same subtree
+        let root = node(2, &[(0, 3, 0), (10, 3, 0)], I_BLOCKS_SIZE);
+        let nodes = [
+            (3, node(1, &[(0, 4, 0)], size)),
+            (4, node(0, &[(0, 100, 10)], size)),
+        ];
+        let err = read_tree(root, &nodes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // Two index nodes pointing to the same leaf
+        let root = node(2, &[(0, 3, 0), (10, 5, 0)], I_BLOCKS_SIZE);
+        let nodes = [
+            (3, node(1, &[(0, 4, 0)], size)),
+            (4, node(0, &[(0, 100, 10)], size)),
+            (5, node(1, &[(10, 4, 0)], size)),
+        ];
+        let err = read_tree(root, &nodes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_too_deep() {
+        let root = node(EXT4_EXT_MAX_DEPTH + 1, &[(0, 3, 0)], I_BLOCKS_SIZE);
+        let err = read_tree(root, &[]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut root = node(0, &[(0, 100, 1)], I_BLOCKS_SIZE);
+        root[0] = 0;
+        assert!(read_tree(root, &[]).is_err());
+        // More entries than the maximum
+        let mut root = node(0, &[(0, 100, 1)], I_BLOCKS_SIZE);
+        root[2] = 5;
+        assert!(read_tree(root, &[]).is_err());
+    }
+
+    #[test]
+    fn test_read_file() {
+        // 3 extents in the inode
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        let data = read_file(&fs, "/extents.txt").unwrap();
+        assert_eq!(data, block_pattern(40).as_bytes());
+    }
+}
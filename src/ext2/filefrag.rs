@@ -0,0 +1,117 @@
+use crate::ext2::inode::{Ext2BlockKind, Ext2BlockRef};
+use crate::ext2::Ext2Filesystem;
+use std::io::Error;
+
+/// Contiguous run of data blocks, both in the file and on the disk
+#[derive(Debug, Clone, Copy)]
+pub struct Ext2FileExtent {
+    pub logical: u64,  // First file block number
+    pub physical: u64, // First physical block number
+    pub len: u64,      // Number of blocks
+}
+
+impl Ext2FileExtent {
+    /// Physical block following the extent
+    pub fn get_physical_end(&self) -> u64 {
+        self.physical + self.len
+    }
+
+    /// File block following the extent
+    pub fn get_logical_end(&self) -> u64 {
+        self.logical + self.len
+    }
+}
+
+impl Ext2Filesystem {
+    /// Block map of a file: data blocks and metadata blocks (indirect blocks, extent tree blocks),
+    /// sorted by file block number. The metadata blocks precede the data blocks they map.
+    pub fn get_file_block_map(&self, path: &str) -> Result<Vec<Ext2BlockRef>, Error> {
+        let inode = self.resolve(path)?;
+        let mut blocks =
+            inode.get_block_map(self.disk.as_ref(), self.super_block.get_blocks_count())?;
+        blocks.sort_by_key(|x| x.file_block_num);
+        Ok(blocks)
+    }
+
+    /// Extents of a file (contiguous runs of data blocks), sorted by file block number
+    pub fn get_file_extents(&self, path: &str) -> Result<Vec<Ext2FileExtent>, Error> {
+        let mut extents: Vec<Ext2FileExtent> = Vec::new();
+        for block in self.get_file_block_map(path)? {
+            if block.kind != Ext2BlockKind::Data {
+                continue;
+            }
+            match extents.last_mut() {
+                Some(extent)
+                    if extent.get_logical_end() == block.file_block_num
+                        && extent.get_physical_end() == block.block_num =>
+                {
+                    extent.len += 1
+                }
+                _ => extents.push(Ext2FileExtent {
+                    logical: block.file_block_num,
+                    physical: block.block_num,
+                    len: 1,
+                }),
+            }
+        }
+        Ok(extents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MountOptions;
+    use crate::test_util::mount_image;
+
+    /// (logical, physical, len) of each extent
+    fn get_extents(fs: &Ext2Filesystem, path: &str) -> Vec<(u64, u64, u64)> {
+        fs.get_file_extents(path)
+            .unwrap()
+            .iter()
+            .map(|x| (x.logical, x.physical, x.len))
+            .collect()
+    }
+
+    #[test]
+    fn test_block_map() {
+        // Same layout as debugfs: (0-11):342-353, (IND):354, (12-267):355-610,
+        // (DIND):611, (IND):612, (268-299):613-644
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let blocks = fs.get_file_block_map("/big.txt").unwrap();
+        assert_eq!(blocks.len(), 303);
+        let metadata: Vec<(u64, Ext2BlockKind, u64)> = blocks
+            .iter()
+            .filter(|x| x.kind != Ext2BlockKind::Data)
+            .map(|x| (x.block_num, x.kind, x.file_block_num))
+            .collect();
+        let expected = [
+            (354, Ext2BlockKind::Indirect, 12),
+            (611, Ext2BlockKind::DoublyIndirect, 268),
+            (612, Ext2BlockKind::Indirect, 268),
+        ];
+        assert_eq!(metadata, expected);
+        // The metadata blocks precede the data blocks they map
+        let position = blocks.iter().position(|x| x.block_num == 354).unwrap();
+        assert_eq!(blocks[position + 1].block_num, 355);
+        assert!(blocks
+            .windows(2)
+            .all(|x| x[0].file_block_num <= x[1].file_block_num));
+    }
+
+    #[test]
+    fn test_extents() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let expected = [(0, 342, 12), (12, 355, 256), (268, 613, 32)];
+        assert_eq!(get_extents(&fs, "/big.txt"), expected);
+        assert_eq!(get_extents(&fs, "/sparse"), [(100, 650, 1), (300, 653, 1)]);
+        let extent = fs.get_file_extents("/big.txt").unwrap()[1];
+        assert_eq!(extent.get_logical_end(), 268);
+        assert_eq!(extent.get_physical_end(), 611);
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        let expected = [(0, 18, 1), (1, 20, 15), (16, 99, 24)];
+        assert_eq!(get_extents(&fs, "/extents.txt"), expected);
+        // Inline data
+        assert!(get_extents(&fs, "/small.txt").is_empty());
+    }
+}
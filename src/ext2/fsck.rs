@@ -0,0 +1,2124 @@
+use crate::disk::Offset;
+use crate::ext2::bitmap::Ext2Bitmap;
+use crate::ext2::checksum::{ChecksumError, ChecksumKind, DIR_TAIL_SIZE};
+use crate::ext2::dir::{dir_rec_len, Ext2DirRecord, EXT2_DIR_ENTRY_HEADER_SIZE};
+use crate::ext2::extent::{Ext4ExtentHeader, EXT4_EXT_ENTRY_SIZE, EXT4_EXT_HEADER_SIZE};
+use crate::ext2::group::{EXT2_BG_BLOCK_UNINIT, EXT2_BG_INODE_UNINIT};
+use crate::ext2::inode::{
+    Ext2BlockKind, Ext2BlockRef, Ext2Inode, EXT2_DOUBLY_IND_BLOCK, I_BLOCKS_SIZE,
+};
+use crate::ext2::superblock::EXT4_FEATURE_INCOMPAT_64BIT;
+use crate::ext2::{read_u16, read_u32, write_u16, write_u32, Ext2Filesystem, EXT2_ROOT_INO};
+use crate::inode::Inode;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::Error;
+
+const EXT2_RESIZE_INO: u64 = 7; // Reserved group descriptors inode
+const EXT2_GOOD_OLD_FIRST_INO: u64 = 11; // First non-reserved inode for old ext2 filesystems
+const EXT2_FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+const EXT4_FEATURE_RO_COMPAT_HUGE_FILE: u32 = 0x0008;
+const EXT4_FEATURE_RO_COMPAT_DIR_NLINK: u32 = 0x0020;
+const EXT4_HUGE_FILE_FL: u32 = 0x00040000; // i_blocks in filesystem blocks (huge_file)
+const UNKNOWN_OWNER: u64 = u64::MAX; // Owner of a multiply-claimed block before pass 1b
+
+// Offsets of the inode fields modified by the repair
+const INODE_LINKS_COUNT_OFFSET: usize = 0x1a;
+const INODE_BLOCKS_OFFSET: usize = 0x1c;
+const INODE_FLAGS_OFFSET: usize = 0x20;
+const INODE_BLOCK_OFFSET: usize = 0x28;
+const INODE_FILE_ACL_OFFSET: usize = 0x68;
+const INODE_BLOCKS_HI_OFFSET: usize = 0x74;
+
+/// Severity of a problem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Problem found by the checker
+#[derive(Debug, Clone)]
+pub enum Ext2Problem {
+    Checksum(ChecksumError),
+    UnreadableInode {
+        inode_num: u64,
+        error: String,
+    },
+    InvalidMode {
+        inode_num: u64,
+        mode: u16,
+    },
+    UnreadableBlockMap {
+        inode_num: u64,
+        error: String,
+    },
+    BlockOutOfRange {
+        inode_num: u64,
+        block: Ext2BlockRef,
+    },
+    MultiplyClaimedBlock {
+        inode_num: u64,
+        block: Ext2BlockRef,
+        owner: u64, // 0 = filesystem metadata
+    },
+    UnreadableDirectory {
+        dir_inode: u64,
+        error: String,
+    },
+    InvalidDirEntry {
+        dir_inode: u64,
+        block_num: u64,
+        offset: usize,
+        reason: &'static str,
+    },
+    MissingDot {
+        dir_inode: u64,
+    },
+    MissingDotDot {
+        dir_inode: u64,
+    },
+    DirEntryInvalidInode {
+        dir_inode: u64,
+        name: String,
+        inode_num: u64,
+    },
+    DirEntryUnusedInode {
+        dir_inode: u64,
+        name: String,
+        inode_num: u64,
+    },
+    RootNotDirectory,
+    UnconnectedDir {
+        inode_num: u64,
+        dotdot: u64,
+    },
+    WrongDotDot {
+        dir_inode: u64,
+        dotdot: u64,
+        parent: u64,
+    },
+    UnattachedInode {
+        inode_num: u64,
+    },
+    WrongLinkCount {
+        inode_num: u64,
+        links_count: u64,
+        refs: u64,
+    },
+    BlockBitmapDifference {
+        first: u64,
+        last: u64,
+        used: bool, // true if the blocks are in use but marked as free
+    },
+    InodeBitmapDifference {
+        first: u64,
+        last: u64,
+        used: bool, // true if the inodes are in use but marked as free
+    },
+    GroupFreeBlocksCount {
+        group_num: usize,
+        found: u64,
+        expected: u64,
+    },
+    GroupFreeInodesCount {
+        group_num: usize,
+        found: u64,
+        expected: u64,
+    },
+    GroupUsedDirsCount {
+        group_num: usize,
+        found: u64,
+        expected: u64,
+    },
+    FreeBlocksCount {
+        found: u64,
+        expected: u64,
+    },
+    FreeInodesCount {
+        found: u64,
+        expected: u64,
+    },
+}
+
+fn format_range(first: u64, last: u64) -> String {
+    if first == last {
+        format!("{}", first)
+    } else {
+        format!("{}-{}", first, last)
+    }
+}
+
+impl fmt::Display for Ext2Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ext2Problem::Checksum(err) => write!(f, "{}", err),
+            Ext2Problem::UnreadableInode { inode_num, error } => {
+                write!(f, "Inode {} cannot be read: {}", inode_num, error)
+            }
+            Ext2Problem::InvalidMode { inode_num, mode } => {
+                write!(f, "Inode {} has invalid mode {:#o}", inode_num, mode)
+            }
+            Ext2Problem::UnreadableBlockMap { inode_num, error } => {
+                write!(f, "Inode {} block map cannot be read: {}", inode_num, error)
+            }
+            Ext2Problem::BlockOutOfRange { inode_num, block } => write!(
+                f,
+                "Inode {} {:?} block {} is out of range",
+                inode_num, block.kind, block.block_num
+            ),
+            Ext2Problem::MultiplyClaimedBlock {
+                inode_num,
+                block,
+                owner,
+            } => {
+                if *owner == 0 {
+                    write!(
+                        f,
+                        "Inode {} {:?} block {} is filesystem metadata",
+                        inode_num, block.kind, block.block_num
+                    )
+                } else {
+                    write!(
+                        f,
+                        "Inode {} {:?} block {} is also claimed by inode {}",
+                        inode_num, block.kind, block.block_num, owner
+                    )
+                }
+            }
+            Ext2Problem::UnreadableDirectory { dir_inode, error } => {
+                write!(f, "Directory inode {} cannot be read: {}", dir_inode, error)
+            }
+            Ext2Problem::InvalidDirEntry {
+                dir_inode,
+                block_num,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "Directory inode {}, block {}, offset {}: {}",
+                dir_inode, block_num, offset, reason
+            ),
+            Ext2Problem::MissingDot { dir_inode } => {
+                write!(f, "Missing '.' in directory inode {}", dir_inode)
+            }
+            Ext2Problem::MissingDotDot { dir_inode } => {
+                write!(f, "Missing '..' in directory inode {}", dir_inode)
+            }
+            Ext2Problem::DirEntryInvalidInode {
+                dir_inode,
+                name,
+                inode_num,
+            } => write!(
+                f,
+                "Entry '{}' in directory inode {} has invalid inode {}",
+                name, dir_inode, inode_num
+            ),
+            Ext2Problem::DirEntryUnusedInode {
+                dir_inode,
+                name,
+                inode_num,
+            } => write!(
+                f,
+                "Entry '{}' in directory inode {} has deleted/unused inode {}",
+                name, dir_inode, inode_num
+            ),
+            Ext2Problem::RootNotDirectory => write!(f, "Root inode is not a directory"),
+            Ext2Problem::UnconnectedDir { inode_num, dotdot } => write!(
+                f,
+                "Unconnected directory inode {} ('..' is {})",
+                inode_num, dotdot
+            ),
+            Ext2Problem::WrongDotDot {
+                dir_inode,
+                dotdot,
+                parent,
+            } => write!(
+                f,
+                "'..' in directory inode {} is {}, should be {}",
+                dir_inode, dotdot, parent
+            ),
+            Ext2Problem::UnattachedInode { inode_num } => {
+                write!(f, "Unattached inode {}", inode_num)
+            }
+            Ext2Problem::WrongLinkCount {
+                inode_num,
+                links_count,
+                refs,
+            } => write!(
+                f,
+                "Inode {} ref count is {}, should be {}",
+                inode_num, links_count, refs
+            ),
+            Ext2Problem::BlockBitmapDifference { first, last, used } => write!(
+                f,
+                "Block bitmap differences: {}{}",
+                if *used { "+" } else { "-" },
+                format_range(*first, *last)
+            ),
+            Ext2Problem::InodeBitmapDifference { first, last, used } => write!(
+                f,
+                "Inode bitmap differences: {}{}",
+                if *used { "+" } else { "-" },
+                format_range(*first, *last)
+            ),
+            Ext2Problem::GroupFreeBlocksCount {
+                group_num,
+                found,
+                expected,
+            } => write!(
+                f,
+                "Free blocks count wrong for group {} ({}, counted={})",
+                group_num, found, expected
+            ),
+            Ext2Problem::GroupFreeInodesCount {
+                group_num,
+                found,
+                expected,
+            } => write!(
+                f,
+                "Free inodes count wrong for group {} ({}, counted={})",
+                group_num, found, expected
+            ),
+            Ext2Problem::GroupUsedDirsCount {
+                group_num,
+                found,
+                expected,
+            } => write!(
+                f,
+                "Directories count wrong for group {} ({}, counted={})",
+                group_num, found, expected
+            ),
+            Ext2Problem::FreeBlocksCount { found, expected } => write!(
+                f,
+                "Free blocks count wrong ({}, counted={})",
+                found, expected
+            ),
+            Ext2Problem::FreeInodesCount { found, expected } => write!(
+                f,
+                "Free inodes count wrong ({}, counted={})",
+                found, expected
+            ),
+        }
+    }
+}
+
+/// Problem found by the checker, with the pass that found it
+#[derive(Debug, Clone)]
+pub struct Ext2Diagnostic {
+    pub pass: u8,             // Checker pass (0 = superblock and group descriptors)
+    pub problem: Ext2Problem, // Problem
+    pub fixed: bool,          // The problem has been repaired
+}
+
+impl Ext2Diagnostic {
+    pub fn severity(&self) -> Severity {
+        match self.problem {
+            // The kernel does not keep the superblock counters up to date
+            Ext2Problem::FreeBlocksCount { .. } | Ext2Problem::FreeInodesCount { .. } => {
+                Severity::Warning
+            }
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Ext2Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity(), self.problem)?;
+        if self.fixed {
+            write!(f, " (fixed)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Change written to the disk by the repair
+#[derive(Debug, Clone)]
+pub struct Ext2Change {
+    pub pass: u8,            // Checker pass
+    pub description: String, // What has been changed
+}
+
+impl fmt::Display for Ext2Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "repair: {}", self.description)
+    }
+}
+
+/// State of an inode collected during the check
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InodeState {
+    pub in_use: bool,     // Inode is in use
+    pub is_dir: bool,     // Inode is a directory
+    pub mode: u16,        // File mode
+    pub links_count: u64, // Links count stored in the inode
+    pub refs: u64,        // Number of directory entries referencing the inode
+    pub dotdot: u64,      // ".." entry (directories only)
+    pub parent: u64,      // First directory found containing the inode (directories only)
+}
+
+/// Filesystem consistency checker
+pub struct Ext2Checker<'a> {
+    fs: &'a Ext2Filesystem,
+    blocks_count: u64,
+    inodes_count: u64,
+    first_ino: u64,
+    pub repair: bool, // Fix the problems (the disk must be writable)
+    used_blocks: Vec<Option<Ext2Bitmap>>, // Blocks in use, per group (allocated on first use)
+    metadata_blocks: Vec<(u64, u64)>, // Filesystem metadata (first block, count), sorted
+    pub inodes: Vec<InodeState>, // State of each inode (indexed by inode number)
+    xattr_blocks: HashSet<u64>, // Extended attributes blocks (can be shared)
+    pub diagnostics: Vec<Ext2Diagnostic>,
+    pub changes: Vec<Ext2Change>, // Changes written by the repair
+    pass: u8,
+}
+
+impl Ext2Checker<'_> {
+    pub fn new(fs: &Ext2Filesystem) -> Ext2Checker<'_> {
+        let super_block = &fs.super_block;
+        let blocks_count = super_block.get_blocks_count();
+        let inodes_count = super_block.s_inodes_count as u64;
+        Ext2Checker {
+            fs,
+            blocks_count,
+            inodes_count,
+            first_ino: if super_block.s_rev_level == 0 {
+                EXT2_GOOD_OLD_FIRST_INO
+            } else {
+                super_block.s_first_ino as u64
+            },
+            repair: false,
+            used_blocks: vec![None; fs.block_groups.len()],
+            metadata_blocks: Vec::new(),
+            inodes: vec![InodeState::default(); inodes_count as usize + 1],
+            xattr_blocks: HashSet::new(),
+            diagnostics: Vec::new(),
+            changes: Vec::new(),
+            pass: 0,
+        }
+    }
+
+    fn report(&mut self, problem: Ext2Problem) {
+        self.diagnostics.push(Ext2Diagnostic {
+            pass: self.pass,
+            problem,
+            fixed: false,
+        });
+    }
+
+    /// Report a checksum mismatch, returns true if the checksum is wrong
+    fn report_checksum(&mut self, result: Result<(), ChecksumError>) -> bool {
+        if let Err(err) = result {
+            self.report(Ext2Problem::Checksum(err));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Mark the last reported problem as repaired
+    fn fixed(&mut self) {
+        if let Some(diagnostic) = self.diagnostics.last_mut() {
+            diagnostic.fixed = true;
+        }
+    }
+
+    /// Log a change written to the disk
+    fn log(&mut self, description: String) {
+        self.changes.push(Ext2Change {
+            pass: self.pass,
+            description,
+        });
+    }
+
+    /// Returns true if errors were found
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.severity() == Severity::Error)
+    }
+
+    fn is_csum_enabled(&self) -> bool {
+        self.fs.super_block.has_metadata_csum() || self.fs.super_block.has_gdt_csum()
+    }
+
+    fn read_block(&self, block_num: u64) -> Result<Vec<u8>, Error> {
+        let block_size = self.fs.super_block.get_block_size();
+        let offset = Offset::Block {
+            block_size,
+            block_num,
+        };
+        self.fs.disk.read(block_size, offset)
+    }
+
+    fn write_block(&self, block_num: u64, buffer: &[u8]) -> Result<(), Error> {
+        let offset = Offset::Block {
+            block_size: self.fs.super_block.get_block_size(),
+            block_num,
+        };
+        self.fs.disk.write(buffer, offset)?;
+        self.fs.clear_inode_caches();
+        Ok(())
+    }
+
+    /// Offset of an inode in the inode table
+    fn inode_offset(&self, inode_num: u64) -> Offset {
+        let group = self.fs.block_groups.get_inode_group(inode_num);
+        Offset::BlockDelta {
+            block_size: self.fs.super_block.get_block_size(),
+            base_block_num: group.ext2_group_desc.get_inode_table(),
+            delta: (inode_num - group.first_inode_num) * self.fs.super_block.s_inode_size as u64,
+        }
+    }
+
+    fn read_raw_inode(&self, inode_num: u64) -> Result<Vec<u8>, Error> {
+        let inode_size = self.fs.super_block.s_inode_size as u64;
+        self.fs.disk.read(inode_size, self.inode_offset(inode_num))
+    }
+
+    /// Write an inode, updating the checksum
+    fn write_raw_inode(&self, inode_num: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        self.fs.checksum.set_inode(inode_num, buffer);
+        self.fs.disk.write(buffer, self.inode_offset(inode_num))?;
+        self.fs.clear_inode_caches();
+        Ok(())
+    }
+
+    /// Run a checker pass (0 to 5)
+    pub fn check_pass(&mut self, pass: u8) -> Result<(), Error> {
+        match pass {
+            0 => self.check_super_block(),
+            1 => self.check_inodes(),
+            2 => self.check_directories(),
+            3 => self.check_connectivity(),
+            4 => self.check_reference_counts(),
+            _ => self.check_group_summary(),
+        }
+    }
+
+    /// Pass 0: superblock and group descriptors checksums
+    /// (repaired in pass 5, when the counters are rewritten)
+    pub fn check_super_block(&mut self) -> Result<(), Error> {
+        self.pass = 0;
+        let checksum = self.fs.checksum;
+        self.report_checksum(checksum.verify_super_block(self.fs.super_block.as_bytes()));
+        for group in self.fs.block_groups.iter() {
+            let result =
+                checksum.verify_group_desc(group.group_num, group.ext2_group_desc.as_bytes());
+            self.report_checksum(result);
+        }
+        Ok(())
+    }
+
+    /// Mark the blocks used by the filesystem metadata
+    fn mark_metadata_blocks(&mut self) {
+        let mut blocks = self.fs.block_groups.get_metadata_blocks();
+        blocks.sort();
+        for (first, count) in blocks.iter() {
+            for block_num in *first..(first + count).min(self.blocks_count) {
+                self.set_block_used(block_num);
+            }
+        }
+        // Merge the overlapping ranges, for the lookups
+        for (first, count) in blocks {
+            match self.metadata_blocks.last_mut() {
+                Some((last_first, last_count)) if first <= *last_first + *last_count => {
+                    *last_count = (*last_count).max(first + count - *last_first);
+                }
+                _ => self.metadata_blocks.push((first, count)),
+            }
+        }
+    }
+
+    /// Returns true if the block belongs to the filesystem metadata
+    fn is_metadata_block(&self, block_num: u64) -> bool {
+        let i = self
+            .metadata_blocks
+            .partition_point(|(first, _)| *first <= block_num);
+        i > 0 && {
+            let (first, count) = self.metadata_blocks[i - 1];
+            block_num < first + count
+        }
+    }
+
+    /// Bitmap of the blocks in use of the group containing a block
+    fn get_used_bitmap(&self, block_num: u64) -> Option<&Ext2Bitmap> {
+        let super_block = &self.fs.super_block;
+        let first_data_block = super_block.s_first_data_block as u64;
+        if block_num < first_data_block {
+            return None;
+        }
+        let group_num = (block_num - first_data_block) / super_block.s_blocks_per_group as u64;
+        self.used_blocks.get(group_num as usize)?.as_ref()
+    }
+
+    /// Returns true if the block is claimed by an inode or by the metadata
+    fn is_block_used(&self, block_num: u64) -> bool {
+        self.get_used_bitmap(block_num)
+            .is_some_and(|bitmap| bitmap.is_used(block_num))
+    }
+
+    fn set_block_used(&mut self, block_num: u64) {
+        let super_block = &self.fs.super_block;
+        let first_data_block = super_block.s_first_data_block as u64;
+        if block_num < first_data_block || block_num >= self.blocks_count {
+            return;
+        }
+        let group_num =
+            ((block_num - first_data_block) / super_block.s_blocks_per_group as u64) as usize;
+        if let Some(bitmap) = self.used_blocks.get_mut(group_num) {
+            bitmap
+                .get_or_insert_with(|| {
+                    let len = super_block.get_group_blocks_count(group_num);
+                    let first = super_block.get_group_first_block(group_num);
+                    Ext2Bitmap::new(vec![0; len.div_ceil(8) as usize], first, len)
+                })
+                .set_used(block_num);
+        }
+    }
+
+    fn set_block_free(&mut self, block_num: u64) {
+        let super_block = &self.fs.super_block;
+        let first_data_block = super_block.s_first_data_block as u64;
+        if block_num < first_data_block {
+            return;
+        }
+        let group_num = (block_num - first_data_block) / super_block.s_blocks_per_group as u64;
+        if let Some(Some(bitmap)) = self.used_blocks.get_mut(group_num as usize) {
+            bitmap.set_free(block_num);
+        }
+    }
+
+    /// Claim a block for an inode, returns false if the block is invalid
+    fn claim_block(&mut self, inode_num: u64, block: Ext2BlockRef) -> bool {
+        if block.block_num < self.fs.super_block.s_first_data_block as u64
+            || block.block_num >= self.blocks_count
+        {
+            self.report(Ext2Problem::BlockOutOfRange { inode_num, block });
+            return false;
+        }
+        if !self.is_block_used(block.block_num) {
+            self.set_block_used(block.block_num);
+            return true;
+        }
+        // The owner inode is found in pass 1b
+        let owner = if self.is_metadata_block(block.block_num) {
+            0
+        } else {
+            UNKNOWN_OWNER
+        };
+        self.report(Ext2Problem::MultiplyClaimedBlock {
+            inode_num,
+            block,
+            owner,
+        });
+        false
+    }
+
+    /// Pass 1: scan the inode tables, check the modes and the blocks ownership
+    pub fn check_inodes(&mut self) -> Result<(), Error> {
+        self.pass = 1;
+        self.mark_metadata_blocks();
+        let inode_size = self.fs.super_block.s_inode_size as usize;
+        let block_size = self.fs.super_block.get_block_size() as usize;
+        let inodes_per_group = self.fs.super_block.s_inodes_per_group as u64;
+        for group_num in 0..self.fs.block_groups.len() {
+            let group = self.fs.block_groups.get_group(group_num);
+            let desc = &group.ext2_group_desc;
+            let mut count = inodes_per_group;
+            if self.is_csum_enabled() {
+                if desc.bg_flags & EXT2_BG_INODE_UNINIT != 0 {
+                    continue;
+                }
+                count -= desc.get_itable_unused().min(count);
+            }
+            let first_inode_num = group.first_inode_num;
+            let inode_table = desc.get_inode_table();
+            let inodes_per_block = (block_size / inode_size) as u64;
+            let mut i: u64 = 0;
+            while i < count {
+                let buffer = match self.read_block(inode_table + i / inodes_per_block) {
+                    Ok(buffer) => buffer,
+                    Err(err) => {
+                        self.report(Ext2Problem::UnreadableInode {
+                            inode_num: first_inode_num + i,
+                            error: err.to_string(),
+                        });
+                        i += inodes_per_block;
+                        continue;
+                    }
+                };
+                for chunk in buffer.chunks(inode_size) {
+                    if i < count {
+                        self.check_inode(first_inode_num + i, chunk)?;
+                    }
+                    i += 1;
+                }
+            }
+        }
+        self.find_duplicate_owners();
+        Ok(())
+    }
+
+    /// Pass 1b: find the inodes owning the multiply-claimed blocks (only the blocks
+    /// in use are known after pass 1), the first inode claiming a block owns it
+    fn find_duplicate_owners(&mut self) {
+        let mut owners: HashMap<u64, u64> = HashMap::new();
+        for diagnostic in self.diagnostics.iter() {
+            if let Ext2Problem::MultiplyClaimedBlock { block, owner, .. } = diagnostic.problem {
+                if owner == UNKNOWN_OWNER {
+                    owners.insert(block.block_num, UNKNOWN_OWNER);
+                }
+            }
+        }
+        let mut missing = owners.len();
+        let mut inode_num = 1;
+        while missing > 0 && inode_num <= self.inodes_count {
+            if self.inodes[inode_num as usize].in_use {
+                for block_num in self.get_claimed_blocks(inode_num) {
+                    match owners.get_mut(&block_num) {
+                        Some(owner) if *owner == UNKNOWN_OWNER => {
+                            *owner = inode_num;
+                            missing -= 1;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            inode_num += 1;
+        }
+        for diagnostic in self.diagnostics.iter_mut() {
+            if let Ext2Problem::MultiplyClaimedBlock { block, owner, .. } = &mut diagnostic.problem
+            {
+                if *owner == UNKNOWN_OWNER {
+                    *owner = owners[&block.block_num];
+                }
+            }
+        }
+    }
+
+    /// Blocks claimed by an inode in pass 1
+    fn get_claimed_blocks(&self, inode_num: u64) -> Vec<u64> {
+        let Ok(inode) = self.fs.read_inode(inode_num) else {
+            return Vec::new();
+        };
+        let ext2_inode = inode.get_ext2_inode();
+        let mut blocks = Vec::new();
+        if ext2_inode.i_links_count == 0 {
+            return blocks;
+        }
+        let metadata = inode.metadata();
+        let reserved = inode_num < self.first_ino && inode_num != EXT2_ROOT_INO;
+        if !reserved && !metadata.is_file() && !metadata.is_dir() && !metadata.is_symlink() {
+            // No blocks
+        } else if inode_num == EXT2_RESIZE_INO {
+            blocks.push(ext2_inode.i_block[EXT2_DOUBLY_IND_BLOCK] as u64);
+        } else if let Ok(refs) = inode.get_block_map(self.fs.disk.as_ref(), self.blocks_count) {
+            blocks.extend(refs.iter().map(|block| block.block_num));
+        }
+        blocks.push(ext2_inode.i_file_acl as u64);
+        blocks.retain(|block_num| *block_num != 0);
+        blocks
+    }
+
+    fn check_inode(&mut self, inode_num: u64, buffer: &[u8]) -> Result<(), Error> {
+        if buffer.iter().all(|x| *x == 0) {
+            // Never used inode
+            if inode_num < self.first_ino {
+                self.inodes[inode_num as usize].in_use = true;
+            }
+            return Ok(());
+        }
+        // Raw inode, written back if repaired
+        let mut raw = buffer.to_vec();
+        let mut dirty = false;
+        let result = self.fs.checksum.verify_inode(inode_num, buffer);
+        if self.report_checksum(result) && self.repair {
+            dirty = true;
+            self.fixed();
+        }
+        let block_size = self.fs.super_block.get_block_size();
+        let inode = match Ext2Inode::from_buffer(
+            self.fs.disk.as_ref(),
+            buffer,
+            block_size,
+            &self.fs.checksum,
+            inode_num,
+        ) {
+            Ok(inode) => inode,
+            Err(err) => {
+                self.report(Ext2Problem::UnreadableInode {
+                    inode_num,
+                    error: err.to_string(),
+                });
+                return Ok(());
+            }
+        };
+        let ext2_inode = inode.get_ext2_inode();
+        let reserved = inode_num < self.first_ino && inode_num != EXT2_ROOT_INO;
+        let state = &mut self.inodes[inode_num as usize];
+        // Reserved inodes are always marked as in use
+        state.in_use = ext2_inode.i_links_count > 0 || reserved;
+        state.links_count = ext2_inode.i_links_count as u64;
+        state.mode = ext2_inode.i_mode;
+        if ext2_inode.i_links_count == 0 {
+            // Deleted inode
+            return Ok(());
+        }
+        let metadata = inode.metadata();
+        state.is_dir = metadata.is_dir();
+        if !reserved {
+            let file_type = metadata.file_type();
+            let valid = file_type.is_file()
+                || file_type.is_dir()
+                || file_type.is_symlink()
+                || file_type.is_char_device()
+                || file_type.is_block_device()
+                || file_type.is_fifo()
+                || file_type.is_socket();
+            if !valid {
+                self.report(Ext2Problem::InvalidMode {
+                    inode_num,
+                    mode: ext2_inode.i_mode,
+                });
+            }
+        }
+        if !reserved && !metadata.is_file() && !metadata.is_dir() && !metadata.is_symlink() {
+            // Devices, fifos and sockets have no blocks
+        } else if inode_num == EXT2_RESIZE_INO {
+            // The resize inode points to the reserved GDT blocks (already marked as metadata)
+            let block_num = ext2_inode.i_block[EXT2_DOUBLY_IND_BLOCK] as u64;
+            if block_num != 0 {
+                self.claim_block(
+                    inode_num,
+                    Ext2BlockRef {
+                        block_num,
+                        kind: Ext2BlockKind::DoublyIndirect,
+                        file_block_num: 0,
+                        parent: 0,
+                        index: EXT2_DOUBLY_IND_BLOCK,
+                    },
+                );
+            }
+        } else {
+            match inode.get_block_map(self.fs.disk.as_ref(), self.blocks_count) {
+                Ok(refs) => {
+                    if self.check_block_map(&inode, &refs, &mut raw)? {
+                        dirty = true;
+                    }
+                }
+                Err(err) => self.report(Ext2Problem::UnreadableBlockMap {
+                    inode_num,
+                    error: err.to_string(),
+                }),
+            }
+        }
+        // Extended attributes block, can be shared between inodes
+        let xattr_block = ext2_inode.i_file_acl as u64;
+        if xattr_block != 0 && !self.xattr_blocks.contains(&xattr_block) {
+            self.xattr_blocks.insert(xattr_block);
+            if xattr_block < self.blocks_count {
+                if let Ok(buffer) = self.read_block(xattr_block) {
+                    let result = self.fs.checksum.verify_xattr_block(xattr_block, &buffer);
+                    self.report_checksum(result);
+                }
+            }
+            let block = Ext2BlockRef {
+                block_num: xattr_block,
+                kind: Ext2BlockKind::Data,
+                file_block_num: 0,
+                parent: 0,
+                index: 0,
+            };
+            if !self.claim_block(inode_num, block) && self.repair {
+                write_u32(&mut raw, INODE_FILE_ACL_OFFSET, 0);
+                let i_blocks = self.read_i_blocks(&raw);
+                let unit = self.i_blocks_per_block(&raw);
+                self.write_i_blocks(&mut raw, i_blocks.saturating_sub(unit));
+                dirty = true;
+                self.fixed();
+                self.log(format!(
+                    "Inode {}: cleared extended attributes block {}",
+                    inode_num, xattr_block
+                ));
+            }
+        }
+        if dirty {
+            self.write_raw_inode(inode_num, &mut raw)?;
+            self.log(format!("Inode {}: written", inode_num));
+        }
+        Ok(())
+    }
+
+    /// Claim the blocks of an inode. In repair mode the pointers to invalid blocks
+    /// are cleared (with the blocks below them); returns true if the inode changed
+    fn check_block_map(
+        &mut self,
+        inode: &Ext2Inode,
+        refs: &[Ext2BlockRef],
+        raw: &mut [u8],
+    ) -> Result<bool, Error> {
+        let inode_num = inode.get_inode_num();
+        let mut cleared: Vec<Ext2BlockRef> = Vec::new();
+        let mut cleared_keys: HashSet<(u64, usize)> = HashSet::new(); // Pointers to be cleared
+        let mut dropped: HashSet<u64> = HashSet::new(); // Metadata blocks no longer referenced
+        let mut claimed: HashSet<u64> = HashSet::new(); // Blocks claimed by this inode
+        for block in refs {
+            let key = (block.parent, block.index);
+            if self.repair
+                && (cleared_keys.contains(&key)
+                    || (block.parent != 0 && dropped.contains(&block.parent)))
+            {
+                if block.kind != Ext2BlockKind::Data {
+                    dropped.insert(block.block_num);
+                }
+                continue;
+            }
+            if block.kind == Ext2BlockKind::ExtentTree {
+                self.check_extent_block(inode, block.block_num)?;
+            }
+            if self.claim_block(inode_num, *block) {
+                claimed.insert(block.block_num);
+            } else if self.repair {
+                self.fixed();
+                cleared.push(*block);
+                cleared_keys.insert(key);
+                if block.kind != Ext2BlockKind::Data {
+                    dropped.insert(block.block_num);
+                }
+            }
+        }
+        if cleared.is_empty() {
+            return Ok(false);
+        }
+        // Release the blocks below the cleared pointers
+        // (including the blocks of a cleared extent claimed before the invalid one)
+        let mut released: u32 = 0;
+        for block in refs {
+            let key = (block.parent, block.index);
+            if cleared_keys.contains(&key) || (block.parent != 0 && dropped.contains(&block.parent))
+            {
+                released += 1;
+                if claimed.contains(&block.block_num) {
+                    self.set_block_free(block.block_num);
+                }
+            }
+        }
+        // Clear the pointers, grouped by the block containing them
+        let mut pointers: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+        for block in cleared.iter() {
+            if block.parent == 0 || !dropped.contains(&block.parent) {
+                pointers.entry(block.parent).or_default().push(block.index);
+            }
+            self.log(format!(
+                "Inode {}: cleared pointer to {:?} block {}",
+                inode_num, block.kind, block.block_num
+            ));
+        }
+        for (parent, mut indexes) in pointers {
+            // Remove the entries from the last one, the following entries are shifted
+            indexes.sort_unstable();
+            indexes.dedup();
+            indexes.reverse();
+            if parent == 0 {
+                let node = &mut raw[INODE_BLOCK_OFFSET..INODE_BLOCK_OFFSET + I_BLOCKS_SIZE];
+                clear_pointers(node, &indexes, inode.has_extents());
+            } else {
+                let mut buffer = self.read_block(parent)?;
+                clear_pointers(&mut buffer, &indexes, inode.has_extents());
+                if inode.has_extents() {
+                    let tail_offset = Ext4ExtentHeader::new(&buffer)?.tail_offset();
+                    let seed = self
+                        .fs
+                        .checksum
+                        .inode_seed(inode_num, inode.get_ext2_inode().i_generation);
+                    self.fs
+                        .checksum
+                        .set_extent_block(seed, &mut buffer, tail_offset);
+                }
+                self.write_block(parent, &buffer)?;
+                self.log(format!("Inode {}: written block {}", inode_num, parent));
+            }
+        }
+        // Recompute i_blocks from the remaining blocks
+        let mut kept = refs.len() as u64 - released as u64;
+        if inode.get_ext2_inode().i_file_acl != 0 {
+            kept += 1;
+        }
+        let unit = self.i_blocks_per_block(raw);
+        self.write_i_blocks(raw, kept * unit);
+        Ok(true)
+    }
+
+    /// Value of i_blocks per filesystem block: 512 bytes sectors, unless the inode has
+    /// the huge file flag
+    fn i_blocks_per_block(&self, raw: &[u8]) -> u64 {
+        if self.has_huge_file() && read_u32(raw, INODE_FLAGS_OFFSET) & EXT4_HUGE_FILE_FL != 0 {
+            1
+        } else {
+            self.fs.super_block.get_block_size() / 512
+        }
+    }
+
+    fn has_huge_file(&self) -> bool {
+        self.fs.super_block.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_HUGE_FILE != 0
+    }
+
+    /// i_blocks of a raw inode (48 bits with huge_file)
+    fn read_i_blocks(&self, raw: &[u8]) -> u64 {
+        let mut i_blocks = read_u32(raw, INODE_BLOCKS_OFFSET) as u64;
+        if self.has_huge_file() {
+            i_blocks |= (read_u16(raw, INODE_BLOCKS_HI_OFFSET) as u64) << 32;
+        }
+        i_blocks
+    }
+
+    fn write_i_blocks(&self, raw: &mut [u8], i_blocks: u64) {
+        write_u32(raw, INODE_BLOCKS_OFFSET, i_blocks as u32);
+        if self.has_huge_file() {
+            write_u16(raw, INODE_BLOCKS_HI_OFFSET, (i_blocks >> 32) as u16);
+        }
+    }
+
+    fn check_extent_block(&mut self, inode: &Ext2Inode, block_num: u64) -> Result<(), Error> {
+        if block_num >= self.blocks_count {
+            return Ok(());
+        }
+        if let Ok(mut buffer) = self.read_block(block_num) {
+            if let Ok(header) = Ext4ExtentHeader::new(&buffer) {
+                let seed = self
+                    .fs
+                    .checksum
+                    .inode_seed(inode.get_inode_num(), inode.get_ext2_inode().i_generation);
+                let result = self.fs.checksum.verify_extent_block(
+                    inode.get_inode_num(),
+                    seed,
+                    block_num,
+                    &buffer,
+                    header.tail_offset(),
+                );
+                if self.report_checksum(result) && self.repair {
+                    self.fs
+                        .checksum
+                        .set_extent_block(seed, &mut buffer, header.tail_offset());
+                    self.write_block(block_num, &buffer)?;
+                    self.fixed();
+                    self.log(format!(
+                        "Inode {}: updated extent block {} checksum",
+                        inode.get_inode_num(),
+                        block_num
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass 2: check the directory entries
+    pub fn check_directories(&mut self) -> Result<(), Error> {
+        self.pass = 2;
+        let dirs: Vec<u64> = (1..=self.inodes_count)
+            .filter(|i| self.inodes[*i as usize].in_use && self.inodes[*i as usize].is_dir)
+            .collect();
+        for dir_inode in dirs {
+            if let Err(err) = self.check_directory(dir_inode) {
+                self.report(Ext2Problem::UnreadableDirectory {
+                    dir_inode,
+                    error: err.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_directory(&mut self, dir_inode: u64) -> Result<(), Error> {
+        let inode = self.fs.read_inode(dir_inode)?;
+        if let Some(data) = inode.get_inline_data() {
+            // Inline directory: "." and ".." are implicit (not repaired)
+            let mut data = data.to_vec();
+            let dotdot = read_u32(&data, 0) as u64;
+            self.add_dir_entry(dir_inode, ".", dir_inode);
+            self.add_dir_entry(dir_inode, "..", dotdot);
+            self.inodes[dir_inode as usize].dotdot = dotdot;
+            let end = data.len().min(60);
+            self.check_dir_records(dir_inode, 0, &mut data[4..end], 4, false);
+            if data.len() > 60 {
+                self.check_dir_records(dir_inode, 0, &mut data[60..], 0, false);
+            }
+            return Ok(());
+        }
+        let seed = self
+            .fs
+            .checksum
+            .inode_seed(dir_inode, inode.get_ext2_inode().i_generation);
+        let blocks: Vec<u64> = inode.get_blocks(&self.fs.disk)?;
+        for (i, block_num) in blocks.into_iter().enumerate() {
+            if block_num == 0 || block_num >= self.blocks_count {
+                continue;
+            }
+            let mut buffer = self.read_block(block_num)?;
+            let result = self
+                .fs
+                .checksum
+                .verify_dir_block(dir_inode, seed, block_num, &buffer);
+            let mut dirty = self.report_checksum(result) && self.repair;
+            if dirty {
+                self.fixed();
+            }
+            let (records, changed) =
+                self.check_dir_records(dir_inode, block_num, &mut buffer, 0, true);
+            if i == 0 {
+                // The first block starts with "." and ".."
+                let mut names = records.iter().map(|r| (r.name.as_slice(), r.inode_num));
+                match names.next() {
+                    Some((b".", _)) => {}
+                    _ => self.report(Ext2Problem::MissingDot { dir_inode }),
+                }
+                match names.next() {
+                    Some((b"..", dotdot)) => self.inodes[dir_inode as usize].dotdot = dotdot,
+                    _ => self.report(Ext2Problem::MissingDotDot { dir_inode }),
+                }
+            }
+            dirty |= changed;
+            if dirty {
+                self.fs.checksum.set_dir_block(seed, &mut buffer);
+                self.write_block(block_num, &buffer)?;
+                self.log(format!(
+                    "Directory inode {}: written block {}",
+                    dir_inode, block_num
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the records of a directory block, returning the valid records.
+    /// If writable, in repair mode the invalid entries are removed and the broken
+    /// records are truncated; returns true if the buffer changed
+    fn check_dir_records(
+        &mut self,
+        dir_inode: u64,
+        block_num: u64,
+        buffer: &mut [u8],
+        base_offset: usize,
+        writable: bool,
+    ) -> (Vec<Ext2DirRecord>, bool) {
+        let repair = self.repair && writable;
+        let mut records: Vec<Ext2DirRecord> = Vec::new();
+        let mut changed = false;
+        let mut prev: Option<usize> = None; // Offset of the previous record
+        let mut offset: usize = 0;
+        while offset < buffer.len() {
+            match Ext2DirRecord::parse(buffer, offset) {
+                Ok(record) => {
+                    let valid = record.inode_num == 0
+                        || self.add_dir_entry(dir_inode, &record.get_name(), record.inode_num);
+                    if valid {
+                        if record.inode_num != 0 {
+                            records.push(record.clone());
+                        }
+                        prev = Some(offset);
+                    } else if repair {
+                        // Remove the entry, merging it with the previous one
+                        match prev {
+                            Some(prev) => {
+                                let rec_len = read_u16(buffer, prev + 4) as usize + record.rec_len;
+                                write_u16(buffer, prev + 4, rec_len as u16);
+                            }
+                            None => {
+                                write_u32(buffer, offset, 0);
+                                prev = Some(offset);
+                            }
+                        }
+                        changed = true;
+                        self.fixed();
+                        self.log(format!(
+                            "Directory inode {}, block {}: removed entry '{}'",
+                            dir_inode,
+                            block_num,
+                            record.get_name()
+                        ));
+                    }
+                    offset += record.rec_len;
+                }
+                Err(reason) => {
+                    self.report(Ext2Problem::InvalidDirEntry {
+                        dir_inode,
+                        block_num,
+                        offset: base_offset + offset,
+                        reason,
+                    });
+                    if repair {
+                        // The previous record (or an empty one) covers the rest of the block
+                        let end = if self.fs.checksum.has_dir_tail(buffer) {
+                            buffer.len() - DIR_TAIL_SIZE
+                        } else {
+                            buffer.len()
+                        };
+                        match prev {
+                            Some(prev) if prev < end => {
+                                write_u16(buffer, prev + 4, (end - prev) as u16);
+                            }
+                            _ => {
+                                let empty = [0, 0, 0, 0, 0, 0, 0, 0];
+                                buffer[offset..offset + 8].copy_from_slice(&empty);
+                                write_u16(buffer, offset + 4, (end - offset) as u16);
+                            }
+                        }
+                        changed = true;
+                        self.fixed();
+                        self.log(format!(
+                            "Directory inode {}, block {}: truncated at offset {}",
+                            dir_inode, block_num, offset
+                        ));
+                    }
+                    break;
+                }
+            }
+        }
+        (records, changed)
+    }
+
+    /// Count a directory entry, returns false if the entry is invalid
+    fn add_dir_entry(&mut self, dir_inode: u64, name: &str, inode_num: u64) -> bool {
+        if inode_num < 1 || inode_num > self.inodes_count {
+            self.report(Ext2Problem::DirEntryInvalidInode {
+                dir_inode,
+                name: String::from(name),
+                inode_num,
+            });
+            return false;
+        }
+        let state = &mut self.inodes[inode_num as usize];
+        if !state.in_use || state.links_count == 0 {
+            self.report(Ext2Problem::DirEntryUnusedInode {
+                dir_inode,
+                name: String::from(name),
+                inode_num,
+            });
+            return false;
+        }
+        state.refs += 1;
+        if state.is_dir && name != "." && name != ".." && state.parent == 0 {
+            state.parent = dir_inode;
+        }
+        true
+    }
+
+    /// Pass 3: check that every directory is connected to the root
+    pub fn check_connectivity(&mut self) -> Result<(), Error> {
+        self.pass = 3;
+        let root = self.inodes[EXT2_ROOT_INO as usize];
+        if !root.in_use || !root.is_dir {
+            self.report(Ext2Problem::RootNotDirectory);
+            return Ok(());
+        }
+        self.inodes[EXT2_ROOT_INO as usize].parent = EXT2_ROOT_INO;
+        // Directories tree
+        let mut children: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for (inode_num, state) in self.inodes.iter().enumerate() {
+            if state.in_use && state.is_dir && state.parent != 0 {
+                children
+                    .entry(state.parent)
+                    .or_default()
+                    .push(inode_num as u64);
+            }
+        }
+        let mut connected: HashSet<u64> = HashSet::new();
+        let mut queue: VecDeque<u64> = VecDeque::from([EXT2_ROOT_INO]);
+        while let Some(dir_inode) = queue.pop_front() {
+            if connected.insert(dir_inode) {
+                for child in children.get(&dir_inode).into_iter().flatten() {
+                    queue.push_back(*child);
+                }
+            }
+        }
+        for inode_num in 1..=self.inodes_count {
+            let state = self.inodes[inode_num as usize];
+            if !state.in_use || !state.is_dir || state.links_count == 0 {
+                continue;
+            }
+            if !connected.contains(&inode_num) {
+                // Report only the top of the unconnected subtree
+                if self.get_unconnected_top(inode_num) != inode_num {
+                    continue;
+                }
+                self.report(Ext2Problem::UnconnectedDir {
+                    inode_num,
+                    dotdot: state.dotdot,
+                });
+                if self.repair && self.reconnect(inode_num)? {
+                    self.fixed();
+                }
+            } else if state.dotdot != 0 && state.dotdot != state.parent {
+                self.report(Ext2Problem::WrongDotDot {
+                    dir_inode: inode_num,
+                    dotdot: state.dotdot,
+                    parent: state.parent,
+                });
+                if self.repair && self.set_dotdot(inode_num, state.parent)? {
+                    self.fixed();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Follow the parents of an unconnected directory up to the top (or the smallest
+    /// inode number, if the parents form a loop)
+    fn get_unconnected_top(&self, inode_num: u64) -> u64 {
+        let mut path: Vec<u64> = vec![inode_num];
+        loop {
+            let parent = self.inodes[*path.last().unwrap() as usize].parent;
+            if parent == 0 {
+                return *path.last().unwrap();
+            }
+            if let Some(i) = path.iter().position(|x| *x == parent) {
+                return *path[i..].iter().min().unwrap();
+            }
+            path.push(parent);
+        }
+    }
+
+    /// Change the ".." entry of a directory, returns false if the entry is not found
+    fn set_dotdot(&mut self, dir_inode: u64, parent: u64) -> Result<bool, Error> {
+        let inode = self.fs.read_inode(dir_inode)?;
+        if inode.has_inline_data() {
+            // The parent is stored in the first 4 bytes of i_block
+            let mut raw = self.read_raw_inode(dir_inode)?;
+            write_u32(&mut raw, INODE_BLOCK_OFFSET, parent as u32);
+            self.write_raw_inode(dir_inode, &mut raw)?;
+        } else {
+            let block_num = match inode.get_blocks(&self.fs.disk)?.first() {
+                Some(block_num) if *block_num != 0 && *block_num < self.blocks_count => *block_num,
+                _ => return Ok(false),
+            };
+            let mut buffer = self.read_block(block_num)?;
+            let dotdot = Ext2DirRecord::parse(&buffer, 0)
+                .and_then(|dot| Ext2DirRecord::parse(&buffer, dot.rec_len));
+            match dotdot {
+                Ok(dotdot) if dotdot.name == b".." => {
+                    write_u32(&mut buffer, dotdot.offset, parent as u32);
+                }
+                _ => return Ok(false),
+            }
+            let seed = self
+                .fs
+                .checksum
+                .inode_seed(dir_inode, inode.get_ext2_inode().i_generation);
+            self.fs.checksum.set_dir_block(seed, &mut buffer);
+            self.write_block(block_num, &buffer)?;
+        }
+        // Update the reference counts
+        let old = self.inodes[dir_inode as usize].dotdot;
+        if old >= 1 && old <= self.inodes_count && self.inodes[old as usize].refs > 0 {
+            self.inodes[old as usize].refs -= 1;
+        }
+        self.inodes[parent as usize].refs += 1;
+        self.inodes[dir_inode as usize].dotdot = parent;
+        self.inodes[dir_inode as usize].parent = parent;
+        self.log(format!(
+            "Directory inode {}: '..' changed from {} to {}",
+            dir_inode, old, parent
+        ));
+        Ok(true)
+    }
+
+    /// Find the lost+found directory
+    fn get_lost_found(&self) -> Option<u64> {
+        let inode = self.fs.resolve("/lost+found").ok()?;
+        let state = self.inodes[inode.get_inode_num() as usize];
+        if state.in_use && state.is_dir && state.parent == EXT2_ROOT_INO {
+            Some(inode.get_inode_num())
+        } else {
+            None
+        }
+    }
+
+    /// Link an inode into lost+found, returns false if it cannot be linked
+    fn reconnect(&mut self, inode_num: u64) -> Result<bool, Error> {
+        let lost_found = match self.get_lost_found() {
+            Some(lost_found) if lost_found != inode_num => lost_found,
+            _ => return Ok(false),
+        };
+        let name = format!("#{}", inode_num);
+        let state = self.inodes[inode_num as usize];
+        if !self.insert_dir_entry(lost_found, &name, inode_num, state.mode)? {
+            return Ok(false);
+        }
+        self.inodes[inode_num as usize].refs += 1;
+        self.log(format!(
+            "Inode {}: linked into /lost+found as '{}'",
+            inode_num, name
+        ));
+        if state.is_dir {
+            self.inodes[inode_num as usize].parent = lost_found;
+            self.set_dotdot(inode_num, lost_found)?;
+        }
+        Ok(true)
+    }
+
+    /// Add an entry in the free space of a directory block,
+    /// returns false if the directory is full
+    fn insert_dir_entry(
+        &mut self,
+        dir_inode: u64,
+        name: &str,
+        inode_num: u64,
+        mode: u16,
+    ) -> Result<bool, Error> {
+        let inode = self.fs.read_inode(dir_inode)?;
+        if inode.has_inline_data() {
+            return Ok(false);
+        }
+        let seed = self
+            .fs
+            .checksum
+            .inode_seed(dir_inode, inode.get_ext2_inode().i_generation);
+        let file_type =
+            if self.fs.super_block.s_feature_incompat & EXT2_FEATURE_INCOMPAT_FILETYPE != 0 {
+                get_dir_file_type(mode)
+            } else {
+                0
+            };
+        let needed = dir_rec_len(name.len());
+        for block_num in inode.get_blocks(&self.fs.disk)? {
+            if block_num == 0 || block_num >= self.blocks_count {
+                continue;
+            }
+            let mut buffer = self.read_block(block_num)?;
+            let end = if self.fs.checksum.has_dir_tail(&buffer) {
+                buffer.len() - DIR_TAIL_SIZE
+            } else {
+                buffer.len()
+            };
+            let mut offset: usize = 0;
+            while offset < end {
+                let record = match Ext2DirRecord::parse(&buffer[..end], offset) {
+                    Ok(record) => record,
+                    Err(_) => break,
+                };
+                let used = if record.inode_num == 0 {
+                    0
+                } else {
+                    dir_rec_len(record.name_len)
+                };
+                if record.rec_len - used >= needed {
+                    let new_offset = offset + used;
+                    if used != 0 {
+                        write_u16(&mut buffer, offset + 4, used as u16);
+                    }
+                    write_u32(&mut buffer, new_offset, inode_num as u32);
+                    write_u16(&mut buffer, new_offset + 4, (record.rec_len - used) as u16);
+                    buffer[new_offset + 6] = name.len() as u8;
+                    buffer[new_offset + 7] = file_type;
+                    let name_start = new_offset + EXT2_DIR_ENTRY_HEADER_SIZE;
+                    buffer[name_start..name_start + name.len()].copy_from_slice(name.as_bytes());
+                    self.fs.checksum.set_dir_block(seed, &mut buffer);
+                    self.write_block(block_num, &buffer)?;
+                    return Ok(true);
+                }
+                offset += record.rec_len;
+            }
+        }
+        Ok(false)
+    }
+
+    /// Change the links count of an inode
+    fn set_links_count(&mut self, inode_num: u64, links_count: u64) -> Result<(), Error> {
+        let mut raw = self.read_raw_inode(inode_num)?;
+        write_u16(&mut raw, INODE_LINKS_COUNT_OFFSET, links_count as u16);
+        self.write_raw_inode(inode_num, &mut raw)?;
+        let state = &mut self.inodes[inode_num as usize];
+        let old = state.links_count;
+        state.links_count = links_count;
+        self.log(format!(
+            "Inode {}: links count changed from {} to {}",
+            inode_num, old, links_count
+        ));
+        Ok(())
+    }
+
+    /// Pass 4: check the reference counts
+    pub fn check_reference_counts(&mut self) -> Result<(), Error> {
+        self.pass = 4;
+        let dir_nlink =
+            self.fs.super_block.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_DIR_NLINK != 0;
+        for inode_num in 1..=self.inodes_count {
+            let state = self.inodes[inode_num as usize];
+            if !state.in_use
+                || state.links_count == 0
+                || (inode_num < self.first_ino && inode_num != EXT2_ROOT_INO)
+            {
+                continue;
+            }
+            if state.refs == 0 {
+                self.report(Ext2Problem::UnattachedInode { inode_num });
+                if !self.repair || !self.reconnect(inode_num)? {
+                    continue;
+                }
+                self.fixed();
+            }
+            let state = self.inodes[inode_num as usize];
+            if state.links_count != state.refs
+                && !(dir_nlink && state.is_dir && state.links_count == 1)
+            {
+                self.report(Ext2Problem::WrongLinkCount {
+                    inode_num,
+                    links_count: state.links_count,
+                    refs: state.refs,
+                });
+                if self.repair {
+                    self.set_links_count(inode_num, state.refs)?;
+                    self.fixed();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the block is in use in the on-disk bitmap
+    fn is_block_marked(&self, group_num: usize, bitmap: &Option<Vec<u8>>, block_num: u64) -> bool {
+        match bitmap {
+            Some(bitmap) => {
+                let i = block_num - self.fs.super_block.get_group_first_block(group_num);
+                bitmap[(i / 8) as usize] & (1 << (i % 8)) != 0
+            }
+            // Uninitialized bitmap, only the metadata blocks are in use
+            None => self.is_metadata_block(block_num),
+        }
+    }
+
+    /// Pass 5: compare the bitmaps and the summary counters
+    pub fn check_group_summary(&mut self) -> Result<(), Error> {
+        self.pass = 5;
+        let super_block = &self.fs.super_block;
+        let inodes_per_group = super_block.s_inodes_per_group as u64;
+        let mut free_blocks: u64 = 0;
+        let mut free_inodes: u64 = 0;
+        let mut block_diffs: Vec<(u64, bool)> = Vec::new();
+        let mut inode_diffs: Vec<(u64, bool)> = Vec::new();
+        for group_num in 0..self.fs.block_groups.len() {
+            let desc = &self.fs.block_groups.get_group(group_num).ext2_group_desc;
+            let csum = self.is_csum_enabled();
+            // Block bitmap
+            let bitmap = if csum && desc.bg_flags & EXT2_BG_BLOCK_UNINIT != 0 {
+                None
+            } else {
+                let bitmap = self
+                    .fs
+                    .block_groups
+                    .read_block_bitmap(self.fs.disk.as_ref(), group_num)?;
+                let result = self.fs.checksum.verify_block_bitmap(
+                    group_num,
+                    &bitmap,
+                    desc.bg_block_bitmap_csum_lo,
+                    desc.bg_block_bitmap_csum_hi,
+                );
+                self.report_checksum(result);
+                Some(bitmap)
+            };
+            let first = super_block.get_group_first_block(group_num);
+            let mut group_free_blocks: u64 = 0;
+            for block_num in first..first + super_block.get_group_blocks_count(group_num) {
+                let used = self.is_block_used(block_num);
+                if !used {
+                    group_free_blocks += 1;
+                }
+                if used != self.is_block_marked(group_num, &bitmap, block_num) {
+                    block_diffs.push((block_num, used));
+                }
+            }
+            if group_free_blocks != desc.get_free_blocks_count() {
+                self.report(Ext2Problem::GroupFreeBlocksCount {
+                    group_num,
+                    found: desc.get_free_blocks_count(),
+                    expected: group_free_blocks,
+                });
+            }
+            free_blocks += group_free_blocks;
+            // Inode bitmap
+            let bitmap = if csum && desc.bg_flags & EXT2_BG_INODE_UNINIT != 0 {
+                vec![0; (inodes_per_group / 8) as usize]
+            } else {
+                let bitmap = self
+                    .fs
+                    .block_groups
+                    .read_inode_bitmap(self.fs.disk.as_ref(), group_num)?;
+                let result = self.fs.checksum.verify_inode_bitmap(
+                    group_num,
+                    &bitmap,
+                    desc.bg_inode_bitmap_csum_lo,
+                    desc.bg_inode_bitmap_csum_hi,
+                );
+                self.report_checksum(result);
+                bitmap
+            };
+            let mut group_free_inodes: u64 = 0;
+            let mut group_dirs: u64 = 0;
+            for i in 0..inodes_per_group {
+                let inode_num = group_num as u64 * inodes_per_group + i + 1;
+                let state = self.inodes[inode_num as usize];
+                if !state.in_use {
+                    group_free_inodes += 1;
+                } else if state.is_dir && state.links_count > 0 {
+                    group_dirs += 1;
+                }
+                let marked = bitmap[(i / 8) as usize] & (1 << (i % 8)) != 0;
+                if state.in_use != marked {
+                    inode_diffs.push((inode_num, state.in_use));
+                }
+            }
+            if group_free_inodes != desc.get_free_inodes_count() {
+                self.report(Ext2Problem::GroupFreeInodesCount {
+                    group_num,
+                    found: desc.get_free_inodes_count(),
+                    expected: group_free_inodes,
+                });
+            }
+            if group_dirs != desc.get_used_dirs_count() {
+                self.report(Ext2Problem::GroupUsedDirsCount {
+                    group_num,
+                    found: desc.get_used_dirs_count(),
+                    expected: group_dirs,
+                });
+            }
+            free_inodes += group_free_inodes;
+            if self.repair {
+                self.write_group(group_num, group_free_blocks, group_free_inodes, group_dirs)?;
+            }
+        }
+        for (first, last, used) in collapse_ranges(&block_diffs) {
+            self.report(Ext2Problem::BlockBitmapDifference { first, last, used });
+        }
+        for (first, last, used) in collapse_ranges(&inode_diffs) {
+            self.report(Ext2Problem::InodeBitmapDifference { first, last, used });
+        }
+        let super_block = &self.fs.super_block;
+        if free_blocks != super_block.get_free_blocks_count() {
+            self.report(Ext2Problem::FreeBlocksCount {
+                found: super_block.get_free_blocks_count(),
+                expected: free_blocks,
+            });
+        }
+        let super_block = &self.fs.super_block;
+        if free_inodes != super_block.s_free_inodes_count as u64 {
+            self.report(Ext2Problem::FreeInodesCount {
+                found: super_block.s_free_inodes_count as u64,
+                expected: free_inodes,
+            });
+        }
+        if self.repair {
+            self.write_super_block(free_blocks, free_inodes)?;
+            // The bitmaps, the group descriptors and the superblock have been rewritten
+            for diagnostic in self.diagnostics.iter_mut() {
+                let rewritten = match &diagnostic.problem {
+                    Ext2Problem::Checksum(err) => matches!(
+                        err.kind,
+                        ChecksumKind::SuperBlock
+                            | ChecksumKind::GroupDesc { .. }
+                            | ChecksumKind::BlockBitmap { .. }
+                            | ChecksumKind::InodeBitmap { .. }
+                    ),
+                    _ => diagnostic.pass == 5,
+                };
+                if rewritten {
+                    diagnostic.fixed = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the bitmaps of a group and update the group descriptor
+    fn write_group(
+        &mut self,
+        group_num: usize,
+        free_blocks: u64,
+        free_inodes: u64,
+        used_dirs: u64,
+    ) -> Result<(), Error> {
+        let super_block = &self.fs.super_block;
+        let block_size = super_block.get_block_size() as usize;
+        let old_desc = &self.fs.block_groups.get_group(group_num).ext2_group_desc;
+        let mut desc = old_desc.clone();
+        let csum = self.is_csum_enabled();
+        // Block bitmap
+        let first = super_block.get_group_first_block(group_num);
+        let blocks = super_block.get_group_blocks_count(group_num);
+        let uninit = csum && desc.bg_flags & EXT2_BG_BLOCK_UNINIT != 0;
+        let only_metadata =
+            (first..first + blocks).all(|b| !self.is_block_used(b) || self.is_metadata_block(b));
+        if !(uninit && only_metadata) {
+            let mut bitmap = if uninit {
+                // The padding after the last block is marked as in use
+                let mut bitmap = vec![0; block_size];
+                for i in blocks as usize..block_size * 8 {
+                    bitmap[i / 8] |= 1 << (i % 8);
+                }
+                bitmap
+            } else {
+                self.read_block(desc.get_block_bitmap())?
+            };
+            for i in 0..blocks as usize {
+                if self.is_block_used(first + i as u64) {
+                    bitmap[i / 8] |= 1 << (i % 8);
+                } else {
+                    bitmap[i / 8] &= !(1 << (i % 8));
+                }
+            }
+            let size = (super_block.s_blocks_per_group / 8) as usize;
+            let (lo, hi) = self.fs.checksum.bitmap_checksum(&bitmap[..size]);
+            desc.bg_flags &= !EXT2_BG_BLOCK_UNINIT;
+            desc.bg_block_bitmap_csum_lo = lo;
+            desc.bg_block_bitmap_csum_hi = hi;
+            if uninit || bitmap != self.read_block(desc.get_block_bitmap())? {
+                self.write_block(desc.get_block_bitmap(), &bitmap)?;
+                self.log(format!(
+                    "Group {}: written block bitmap (block {})",
+                    group_num,
+                    desc.get_block_bitmap()
+                ));
+            }
+        }
+        // Inode bitmap (the inodes of an uninitialized group are never in use)
+        if !(csum && desc.bg_flags & EXT2_BG_INODE_UNINIT != 0) {
+            let inodes_per_group = super_block.s_inodes_per_group as usize;
+            let mut bitmap = self.read_block(desc.get_inode_bitmap())?;
+            let old_bitmap = bitmap.clone();
+            for i in 0..inodes_per_group {
+                if self.inodes[group_num * inodes_per_group + i + 1].in_use {
+                    bitmap[i / 8] |= 1 << (i % 8);
+                } else {
+                    bitmap[i / 8] &= !(1 << (i % 8));
+                }
+            }
+            let (lo, hi) = self
+                .fs
+                .checksum
+                .bitmap_checksum(&bitmap[..inodes_per_group / 8]);
+            desc.bg_inode_bitmap_csum_lo = lo;
+            desc.bg_inode_bitmap_csum_hi = hi;
+            if bitmap != old_bitmap {
+                self.write_block(desc.get_inode_bitmap(), &bitmap)?;
+                self.log(format!(
+                    "Group {}: written inode bitmap (block {})",
+                    group_num,
+                    desc.get_inode_bitmap()
+                ));
+            }
+        }
+        // Group descriptor
+        desc.bg_free_blocks_count = free_blocks as u16;
+        desc.bg_free_blocks_count_hi = (free_blocks >> 16) as u16;
+        desc.bg_free_inodes_count = free_inodes as u16;
+        desc.bg_free_inodes_count_hi = (free_inodes >> 16) as u16;
+        desc.bg_used_dirs_count = used_dirs as u16;
+        desc.bg_used_dirs_count_hi = (used_dirs >> 16) as u16;
+        let desc_size = super_block.get_desc_size();
+        let mut buffer = desc.as_bytes()[..desc_size].to_vec();
+        self.fs.checksum.set_group_desc(group_num, &mut buffer);
+        // Primary and backup group descriptor tables, following the superblocks
+        // (when mounted from a backup, the primary table is rewritten from the backup)
+        for table_group in 0..super_block.get_groups_count() {
+            if !super_block.group_has_super(table_group) {
+                continue;
+            }
+            let offset = || Offset::BlockDelta {
+                block_size: block_size as u64,
+                base_block_num: super_block.get_group_first_block(table_group) + 1,
+                delta: (group_num * desc_size) as u64,
+            };
+            if self.fs.disk.read(desc_size as u64, offset())? != buffer {
+                self.fs.disk.write(&buffer, offset())?;
+                self.log(if table_group == 0 {
+                    format!("Group {}: written group descriptor", group_num)
+                } else {
+                    format!(
+                        "Group {}: written group descriptor backup in group {}",
+                        group_num, table_group
+                    )
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Update the free counters of the superblock
+    fn write_super_block(&mut self, free_blocks: u64, free_inodes: u64) -> Result<(), Error> {
+        let mut super_block = self.fs.super_block.clone();
+        super_block.s_free_blocks_count = free_blocks as u32;
+        if super_block.s_feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+            super_block.s_free_blocks_count_hi = (free_blocks >> 32) as u32;
+        }
+        super_block.s_free_inodes_count = free_inodes as u32;
+        // Primary superblock (restored when mounted from a backup) and backups
+        let block_size = super_block.get_block_size();
+        for group_num in 0..super_block.get_groups_count() {
+            if !super_block.group_has_super(group_num) {
+                continue;
+            }
+            super_block.s_block_group_nr = group_num as u16;
+            let mut buffer = super_block.as_bytes().to_vec();
+            self.fs.checksum.set_super_block(&mut buffer);
+            let offset = || {
+                if group_num == 0 {
+                    Offset::Block {
+                        block_size: buffer.len() as u64,
+                        block_num: 1,
+                    }
+                } else {
+                    Offset::Block {
+                        block_size,
+                        block_num: super_block.get_group_first_block(group_num),
+                    }
+                }
+            };
+            if self.fs.disk.read(buffer.len() as u64, offset())? != buffer {
+                self.fs.disk.write(&buffer, offset())?;
+                self.log(if group_num == 0 {
+                    String::from("Written superblock")
+                } else {
+                    format!("Written superblock backup in group {}", group_num)
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of inodes in the filesystem
+    pub fn get_inodes_count(&self) -> u64 {
+        self.inodes_count
+    }
+
+    /// Number of blocks in the filesystem
+    pub fn get_blocks_count(&self) -> u64 {
+        self.blocks_count
+    }
+
+    /// Number of inodes in use
+    pub fn get_used_inodes_count(&self) -> u64 {
+        self.inodes.iter().filter(|s| s.in_use).count() as u64
+    }
+
+    /// Number of blocks in use
+    pub fn get_used_blocks_count(&self) -> u64 {
+        // Blocks before the first data block are counted as used
+        let free: u64 = (0..self.used_blocks.len())
+            .map(|group_num| match &self.used_blocks[group_num] {
+                Some(bitmap) => bitmap.get_free_count(),
+                None => self.fs.super_block.get_group_blocks_count(group_num),
+            })
+            .sum();
+        self.blocks_count - free
+    }
+}
+
+/// Clear block pointers in an indirect block or extent node (indexes in descending order)
+fn clear_pointers(node: &mut [u8], indexes: &[usize], extents: bool) {
+    for index in indexes {
+        if extents {
+            // Remove the entry, shifting the following ones
+            let entries = read_u16(node, 2) as usize;
+            let start = EXT4_EXT_HEADER_SIZE + index * EXT4_EXT_ENTRY_SIZE;
+            let end = EXT4_EXT_HEADER_SIZE + entries * EXT4_EXT_ENTRY_SIZE;
+            node.copy_within(start + EXT4_EXT_ENTRY_SIZE..end, start);
+            node[end - EXT4_EXT_ENTRY_SIZE..end].fill(0);
+            write_u16(node, 2, (entries - 1) as u16);
+        } else {
+            write_u32(node, index * 4, 0);
+        }
+    }
+}
+
+/// Directory entry file type of an inode mode
+fn get_dir_file_type(mode: u16) -> u8 {
+    match mode & 0xf000 {
+        0x8000 => 1, // Regular file
+        0x4000 => 2, // Directory
+        0x2000 => 3, // Character device
+        0x6000 => 4, // Block device
+        0x1000 => 5, // Fifo
+        0xc000 => 6, // Socket
+        0xa000 => 7, // Symbolic link
+        _ => 0,
+    }
+}
+
+/// Collapse a sorted list of (number, used) into ranges
+fn collapse_ranges(diffs: &[(u64, bool)]) -> Vec<(u64, u64, bool)> {
+    let mut ranges: Vec<(u64, u64, bool)> = Vec::new();
+    for (num, used) in diffs {
+        match ranges.last_mut() {
+            Some((_, last, last_used)) if *last + 1 == *num && *last_used == *used => *last = *num,
+            _ => ranges.push((*num, *num, *used)),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MountOptions;
+    use crate::test_util::{inode_offset, mount_image, patch_image, read_image, PatchedImage};
+
+    const INODE_LINKS_COUNT_OFFSET: u64 = 0x1a;
+
+    /// Run all the passes
+    fn check(fs: &Ext2Filesystem, repair: bool) -> Ext2Checker<'_> {
+        let mut checker = Ext2Checker::new(fs);
+        checker.repair = repair;
+        for pass in 0..=5 {
+            checker.check_pass(pass).unwrap();
+        }
+        checker
+    }
+
+    #[test]
+    fn test_clean_images() {
+        for name in ["ext2.img.gz", "ext4.img.gz"] {
+            let fs = mount_image(name, &MountOptions::default());
+            let checker = check(&fs, false);
+            assert!(
+                checker.diagnostics.is_empty(),
+                "{}: {:?}",
+                name,
+                checker.diagnostics
+            );
+            assert!(!checker.has_errors());
+        }
+    }
+
+    #[test]
+    fn test_used_counts() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let checker = check(&fs, false);
+        let super_block = &fs.super_block;
+        assert_eq!(
+            checker.get_inodes_count(),
+            super_block.s_inodes_count as u64
+        );
+        assert_eq!(
+            checker.get_used_inodes_count(),
+            (super_block.s_inodes_count - super_block.s_free_inodes_count) as u64
+        );
+        assert_eq!(
+            checker.get_used_blocks_count(),
+            super_block.get_blocks_count() - super_block.get_free_blocks_count()
+        );
+        // Inodes found in the directories
+        assert!(checker.inodes[EXT2_ROOT_INO as usize].is_dir);
+        assert!(checker.inodes[14].is_dir);
+        assert_eq!(checker.inodes[14].parent, EXT2_ROOT_INO);
+        assert_eq!(checker.inodes[16].refs, 1);
+        assert!(!checker.inodes[13].in_use); // deleted.txt
+    }
+
+    fn read_write() -> MountOptions {
+        MountOptions {
+            read_write: true,
+            ..Default::default()
+        }
+    }
+
+    /// ext2.img with the links count of /hello.txt (inode 16) set to 3
+    fn wrong_link_count() -> PatchedImage {
+        let offset = inode_offset("ext2.img.gz", 16) + INODE_LINKS_COUNT_OFFSET;
+        patch_image("ext2.img.gz", &[(offset, &3u16.to_le_bytes())])
+    }
+
+    #[test]
+    fn test_wrong_link_count() {
+        let image = wrong_link_count();
+        let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+        let checker = check(&fs, false);
+        assert_eq!(checker.diagnostics.len(), 1, "{:?}", checker.diagnostics);
+        let diagnostic = &checker.diagnostics[0];
+        assert_eq!(diagnostic.pass, 4);
+        assert!(!diagnostic.fixed);
+        assert_eq!(diagnostic.severity(), Severity::Error);
+        assert!(matches!(
+            diagnostic.problem,
+            Ext2Problem::WrongLinkCount {
+                inode_num: 16,
+                links_count: 3,
+                refs: 1
+            }
+        ));
+        assert!(checker.has_errors());
+        assert!(checker.changes.is_empty());
+    }
+
+    #[test]
+    fn test_repair_link_count() {
+        let image = wrong_link_count();
+        let fs = Ext2Filesystem::mount(&image.path, &read_write()).unwrap();
+        let checker = check(&fs, true);
+        assert_eq!(checker.diagnostics.len(), 1);
+        assert!(checker.diagnostics[0].fixed);
+        assert!(checker
+            .changes
+            .iter()
+            .any(|change| change.pass == 4 && change.description.contains("16")));
+        // The changes are written to the image, check again from scratch
+        let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+        let checker = check(&fs, false);
+        assert!(checker.diagnostics.is_empty(), "{:?}", checker.diagnostics);
+        assert_eq!(checker.inodes[16].links_count, 1);
+    }
+
+    #[test]
+    fn test_repair_inode_bitmap() {
+        // Mark /hello.txt (inode 16) as free in the bitmap
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let bitmap = fs
+            .block_groups
+            .get_group(0)
+            .ext2_group_desc
+            .get_inode_bitmap();
+        let offset = bitmap * fs.super_block.get_block_size() + 1;
+        let byte = read_image("ext2.img.gz")[offset as usize];
+        let image = patch_image("ext2.img.gz", &[(offset, &[byte & !0x80])]);
+        let fs = Ext2Filesystem::mount(&image.path, &read_write()).unwrap();
+        let checker = check(&fs, false);
+        assert_eq!(checker.diagnostics.len(), 1, "{:?}", checker.diagnostics);
+        assert_eq!(checker.diagnostics[0].pass, 5);
+        assert!(matches!(
+            checker.diagnostics[0].problem,
+            Ext2Problem::InodeBitmapDifference {
+                first: 16,
+                last: 16,
+                used: true
+            }
+        ));
+        let checker = check(&fs, true);
+        assert!(checker.diagnostics.iter().all(|d| d.fixed));
+        let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+        assert!(check(&fs, false).diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_repair_free_blocks_count() {
+        // Superblock free blocks count (s_free_blocks_count) off by one
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let free_blocks = fs.super_block.get_free_blocks_count() as u32 + 1;
+        let image = patch_image("ext2.img.gz", &[(1024 + 12, &free_blocks.to_le_bytes())]);
+        let fs = Ext2Filesystem::mount(&image.path, &read_write()).unwrap();
+        let checker = check(&fs, false);
+        assert_eq!(checker.diagnostics.len(), 1, "{:?}", checker.diagnostics);
+        assert_eq!(checker.diagnostics[0].severity(), Severity::Warning);
+        assert!(!checker.has_errors());
+        let checker = check(&fs, true);
+        assert!(checker
+            .changes
+            .iter()
+            .any(|change| change.description == "Written superblock backup in group 1"));
+        let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+        assert!(check(&fs, false).diagnostics.is_empty());
+        // The backup superblock and group descriptors have been updated too
+        let options = MountOptions {
+            superblock: 8193,
+            ..Default::default()
+        };
+        let backup = Ext2Filesystem::mount(&image.path, &options).unwrap();
+        assert_eq!(
+            backup.super_block.get_free_blocks_count(),
+            fs.super_block.get_free_blocks_count()
+        );
+        assert!(check(&backup, false).diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_multiply_claimed_blocks() {
+        // First block of /hello.txt (inode 16) pointing to the block of /dir1/file.txt
+        // (inode 15), then to the inode bitmap
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let shared = fs.read_inode(15).unwrap().get_blocks(&fs.disk).unwrap()[0];
+        let bitmap = fs
+            .block_groups
+            .get_group(0)
+            .ext2_group_desc
+            .get_inode_bitmap();
+        let offset = inode_offset("ext2.img.gz", 16) + INODE_BLOCK_OFFSET as u64;
+        for (block_num, expected_owner) in [(shared, 15), (bitmap, 0)] {
+            let block_bytes = (block_num as u32).to_le_bytes();
+            let image = patch_image("ext2.img.gz", &[(offset, &block_bytes)]);
+            let fs = Ext2Filesystem::mount(&image.path, &read_write()).unwrap();
+            let checker = check(&fs, false);
+            let claims: Vec<(u64, u64, u64)> = checker
+                .diagnostics
+                .iter()
+                .filter_map(|d| match d.problem {
+                    Ext2Problem::MultiplyClaimedBlock {
+                        inode_num,
+                        block,
+                        owner,
+                    } => Some((inode_num, block.block_num, owner)),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(claims, [(16, block_num, expected_owner)]);
+            // The block of /hello.txt is left free
+            let used = checker.get_used_blocks_count();
+            let super_block = &fs.super_block;
+            assert_eq!(
+                used,
+                super_block.get_blocks_count() - super_block.get_free_blocks_count() - 1
+            );
+            // The pointer is cleared
+            let checker = check(&fs, true);
+            assert!(checker.diagnostics[0].fixed);
+            let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+            assert_eq!(fs.read_inode(16).unwrap().get_ext2_inode().i_block[0], 0);
+            assert_eq!(fs.read_inode(16).unwrap().get_ext2_inode().i_blocks, 0);
+        }
+    }
+
+    #[test]
+    fn test_i_blocks_units() {
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        let checker = Ext2Checker::new(&fs);
+        let mut raw = vec![0; 256];
+        checker.write_i_blocks(&mut raw, 0x1_0000_0002);
+        assert_eq!(read_u32(&raw, INODE_BLOCKS_OFFSET), 2);
+        assert_eq!(read_u16(&raw, INODE_BLOCKS_HI_OFFSET), 1);
+        assert_eq!(checker.read_i_blocks(&raw), 0x1_0000_0002);
+        assert_eq!(checker.i_blocks_per_block(&raw), 2); // 1 KiB blocks
+        write_u32(&mut raw, INODE_FLAGS_OFFSET, EXT4_HUGE_FILE_FL);
+        assert_eq!(checker.i_blocks_per_block(&raw), 1);
+        // Without huge_file, i_blocks has 32 bits and the flag is ignored
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let checker = Ext2Checker::new(&fs);
+        assert_eq!(checker.read_i_blocks(&raw), 2);
+        assert_eq!(checker.i_blocks_per_block(&raw), 2);
+    }
+
+    #[test]
+    fn test_collapse_ranges() {
+        let diffs = [(1, true), (2, true), (3, false), (5, false), (6, false)];
+        assert_eq!(
+            collapse_ranges(&diffs),
+            vec![(1, 2, true), (3, 3, false), (5, 6, false)]
+        );
+        assert!(collapse_ranges(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_dir_file_type() {
+        assert_eq!(get_dir_file_type(0o100644), 1);
+        assert_eq!(get_dir_file_type(0o040755), 2);
+        assert_eq!(get_dir_file_type(0o120777), 7);
+        assert_eq!(get_dir_file_type(0), 0);
+    }
+}
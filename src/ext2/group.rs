@@ -1,5 +1,7 @@
 use crate::disk::Disk;
 use crate::disk::Offset;
+use crate::ext2::bitmap::Ext2Bitmap;
+use crate::ext2::checksum::Ext2Checksum;
 use crate::ext2::superblock::Ext2SuperBlock;
 use std::io::Error;
 use std::io::Read;
@@ -8,10 +10,15 @@ use std::slice;
 
 const EXT2_GROUP_DESC_SIZE: usize = mem::size_of::<Ext2GroupDesc>();
 
+// Block group flags
+pub const EXT2_BG_INODE_UNINIT: u16 = 0x0001; // Inode table/bitmap not in use
+pub const EXT2_BG_BLOCK_UNINIT: u16 = 0x0002; // Block bitmap not in use
+pub const EXT2_BG_INODE_ZEROED: u16 = 0x0004; // On-disk itable initialized to zero
+
 /// Blocks are divided up into block groups.
 /// A block group is a contiguous groups of blocks
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ext2GroupDesc {
     pub bg_block_bitmap: u32, // The block which contains the block bitmap for the group.
     pub bg_inode_bitmap: u32, // The block contains the inode bitmap for the group.
@@ -25,23 +32,69 @@ pub struct Ext2GroupDesc {
     pub bg_inode_bitmap_csum_lo: u16, // crc32c(s_uuid+grp_num+bitmap) LSB
     pub bg_itable_unused: u16,        // Unused inodes count
     pub bg_checksum: u16,             // crc16(s_uuid+group_num+group_desc)
+    // -- EXT4_FEATURE_INCOMPAT_64BIT flag is on ---
+    pub bg_block_bitmap_hi: u32,      // Blocks bitmap block MSB
+    pub bg_inode_bitmap_hi: u32,      // Inodes bitmap block MSB
+    pub bg_inode_table_hi: u32,       // Inodes table block MSB
+    pub bg_free_blocks_count_hi: u16, // Free blocks count MSB
+    pub bg_free_inodes_count_hi: u16, // Free inodes count MSB
+    pub bg_used_dirs_count_hi: u16,   // Directories count MSB
+    pub bg_itable_unused_hi: u16,     // Unused inodes count MSB
+    pub bg_exclude_bitmap_hi: u32,    // Exclude bitmap block MSB
+    pub bg_block_bitmap_csum_hi: u16, // crc32c(s_uuid+grp_num+bitmap) MSB
+    pub bg_inode_bitmap_csum_hi: u16, // crc32c(s_uuid+grp_num+bitmap) MSB
+    bg_reserved: u32,
 }
 impl Ext2GroupDesc {
     pub fn default() -> Ext2GroupDesc {
         let group: Ext2GroupDesc = unsafe { mem::zeroed() };
         group
     }
-    pub fn new(group_num: usize, buffer: &Vec<u8>) -> Ext2GroupDesc {
+    pub fn new(group_num: usize, buffer: &[u8], desc_size: usize) -> Ext2GroupDesc {
         let mut group: Ext2GroupDesc = unsafe { mem::zeroed() };
-        let mut buf =
-            &buffer[EXT2_GROUP_DESC_SIZE * group_num..EXT2_GROUP_DESC_SIZE * (group_num + 1)];
+        // Only the first 32 bytes are used if the 64bit feature is off
+        let size = desc_size.min(EXT2_GROUP_DESC_SIZE);
+        let mut buf = &buffer[desc_size * group_num..desc_size * group_num + size];
         let p = &mut group as *mut _ as *mut u8;
         unsafe {
-            let group_slice = slice::from_raw_parts_mut(p, EXT2_GROUP_DESC_SIZE);
+            let group_slice = slice::from_raw_parts_mut(p, size);
             buf.read_exact(group_slice).unwrap();
         }
         group
     }
+    /// Raw bytes of the descriptor
+    pub fn as_bytes(&self) -> &[u8] {
+        let p = self as *const _ as *const u8;
+        unsafe { slice::from_raw_parts(p, EXT2_GROUP_DESC_SIZE) }
+    }
+    /// Number of free blocks
+    pub fn get_free_blocks_count(&self) -> u64 {
+        self.bg_free_blocks_count as u64 | (self.bg_free_blocks_count_hi as u64) << 16
+    }
+    /// Number of free inodes
+    pub fn get_free_inodes_count(&self) -> u64 {
+        self.bg_free_inodes_count as u64 | (self.bg_free_inodes_count_hi as u64) << 16
+    }
+    /// Number of directories
+    pub fn get_used_dirs_count(&self) -> u64 {
+        self.bg_used_dirs_count as u64 | (self.bg_used_dirs_count_hi as u64) << 16
+    }
+    /// Number of unused inodes at the end of the inode table
+    pub fn get_itable_unused(&self) -> u64 {
+        self.bg_itable_unused as u64 | (self.bg_itable_unused_hi as u64) << 16
+    }
+    /// Block bitmap block number
+    pub fn get_block_bitmap(&self) -> u64 {
+        self.bg_block_bitmap as u64 | (self.bg_block_bitmap_hi as u64) << 32
+    }
+    /// Inode bitmap block number
+    pub fn get_inode_bitmap(&self) -> u64 {
+        self.bg_inode_bitmap as u64 | (self.bg_inode_bitmap_hi as u64) << 32
+    }
+    /// Inode table first block number
+    pub fn get_inode_table(&self) -> u64 {
+        self.bg_inode_table as u64 | (self.bg_inode_table_hi as u64) << 32
+    }
 }
 
 #[derive(Debug)]
@@ -51,10 +104,15 @@ pub struct GroupDesc {
     pub first_inode_num: u64,           // Fist inode in the group
 }
 impl GroupDesc {
-    pub fn new(group_num: usize, buffer: &Vec<u8>, inodes_per_group: u32) -> GroupDesc {
+    pub fn new(
+        group_num: usize,
+        buffer: &[u8],
+        desc_size: usize,
+        inodes_per_group: u32,
+    ) -> GroupDesc {
         GroupDesc {
             group_num: group_num,
-            ext2_group_desc: Ext2GroupDesc::new(group_num, &buffer),
+            ext2_group_desc: Ext2GroupDesc::new(group_num, buffer, desc_size),
             first_inode_num: group_num as u64 * inodes_per_group as u64 + 1,
         }
     }
@@ -64,34 +122,179 @@ impl GroupDesc {
 pub struct Ext2BlockGroups {
     block_groups: Vec<GroupDesc>,
     inodes_per_group: u64, // Number of inodes in each block group
+    blocks_per_group: u64, // Number of blocks in each block group
+    block_size: u64,       // Block size
+    checksum: Ext2Checksum,
+    super_block: Ext2SuperBlock,
 }
 impl Ext2BlockGroups {
-    /// Read the Block Groups
-    pub fn new(disk: &dyn Disk, super_block: &Ext2SuperBlock) -> Result<Ext2BlockGroups, Error> {
-        let size = (EXT2_GROUP_DESC_SIZE * super_block.get_groups_count()) as u64;
+    /// Read the Block Groups from the descriptor table following the superblock
+    /// of the given group (0 for the primary, or the group of a backup superblock)
+    pub fn new(
+        disk: &dyn Disk,
+        super_block: &Ext2SuperBlock,
+        checksum: &Ext2Checksum,
+        super_block_group: usize,
+    ) -> Result<Ext2BlockGroups, Error> {
+        let desc_size = super_block.get_desc_size();
+        let size = (desc_size * super_block.get_groups_count()) as u64;
         let block_size = super_block.get_block_size();
         // Read from disk
         let offset = Offset::Block {
             block_size: block_size,
-            // If block size is 1024 the Block Group Descriptor Table will begin at block 2,
-            // for any other block size, it will begin at block 1
-            block_num: if block_size == 1024 { 2 } else { 1 },
+            // The Block Group Descriptor Table begins at the block following the superblock
+            // (block 2 if block size is 1024, block 1 for any other block size)
+            block_num: super_block.get_group_first_block(super_block_group) + 1,
         };
         let buffer = disk.read(size, offset)?;
+        // Verify the checksums
+        for i in 0..super_block.get_groups_count() {
+            let desc = &buffer[desc_size * i..desc_size * (i + 1)];
+            checksum.report(checksum.verify_group_desc(i, desc))?;
+        }
         // Prepare the Ext2GroupDesc instances
         let block_groups: Vec<GroupDesc> = (0..super_block.get_groups_count())
-            .into_iter()
-            .map(|i| GroupDesc::new(i, &buffer, super_block.s_inodes_per_group))
+            .map(|i| GroupDesc::new(i, &buffer, desc_size, super_block.s_inodes_per_group))
             .collect();
         let result = Ext2BlockGroups {
             block_groups: block_groups,
             inodes_per_group: super_block.s_inodes_per_group as u64,
+            blocks_per_group: super_block.s_blocks_per_group as u64,
+            block_size,
+            checksum: *checksum,
+            super_block: super_block.clone(),
         };
         Ok(result)
     }
 
+    /// Number of groups
+    pub fn len(&self) -> usize {
+        self.block_groups.len()
+    }
+
+    /// Returns true if there are no groups
+    pub fn is_empty(&self) -> bool {
+        self.block_groups.is_empty()
+    }
+
+    /// Get a group by number
+    pub fn get_group(&self, group_num: usize) -> &GroupDesc {
+        &self.block_groups[group_num]
+    }
+
+    /// Iterate over the groups
+    pub fn iter(&self) -> std::slice::Iter<'_, GroupDesc> {
+        self.block_groups.iter()
+    }
+
     /// Determine which block group the inode belongs to and return the group
     pub fn get_inode_group(&self, inode_num: u64) -> &GroupDesc {
         &self.block_groups[((inode_num - 1) / self.inodes_per_group) as usize]
     }
+
+    /// Blocks used by the filesystem metadata (first block, number of blocks):
+    /// superblocks, group descriptor tables, bitmaps and inode tables
+    pub fn get_metadata_blocks(&self) -> Vec<(u64, u64)> {
+        let super_block = &self.super_block;
+        let gdt_blocks =
+            super_block.get_gdt_blocks_count() + super_block.s_reserved_gdt_blocks as u64;
+        let itable_blocks =
+            (self.inodes_per_group * super_block.s_inode_size as u64).div_ceil(self.block_size);
+        let mut blocks: Vec<(u64, u64)> = Vec::new();
+        for group in self.block_groups.iter() {
+            if super_block.group_has_super(group.group_num) {
+                // Superblock and group descriptor table
+                let first = super_block.get_group_first_block(group.group_num);
+                blocks.push((first, 1 + gdt_blocks));
+            }
+            let desc = &group.ext2_group_desc;
+            blocks.push((desc.get_block_bitmap(), 1));
+            blocks.push((desc.get_inode_bitmap(), 1));
+            blocks.push((desc.get_inode_table(), itable_blocks));
+        }
+        blocks
+    }
+
+    /// Returns true if the block/inode uninit flags are enabled
+    fn is_uninit_enabled(&self) -> bool {
+        self.super_block.has_metadata_csum() || self.super_block.has_gdt_csum()
+    }
+
+    /// Get the block bitmap of a group.
+    /// For uninitialized bitmaps, only the metadata blocks are in use.
+    pub fn get_block_bitmap(&self, disk: &dyn Disk, group_num: usize) -> Result<Ext2Bitmap, Error> {
+        let desc = &self.block_groups[group_num].ext2_group_desc;
+        let first = self.super_block.get_group_first_block(group_num);
+        let len = self.super_block.get_group_blocks_count(group_num);
+        if self.is_uninit_enabled() && desc.bg_flags & EXT2_BG_BLOCK_UNINIT != 0 {
+            let mut bitmap = Ext2Bitmap::new(vec![0; len.div_ceil(8) as usize], first, len);
+            for (block_num, count) in self.get_metadata_blocks() {
+                // With flex_bg the metadata of a group can be stored in another group
+                for block_num in block_num.max(first)..(block_num + count).min(first + len) {
+                    bitmap.set_used(block_num);
+                }
+            }
+            Ok(bitmap)
+        } else {
+            let bitmap = self.read_block_bitmap(disk, group_num)?;
+            Ok(Ext2Bitmap::new(bitmap, first, len))
+        }
+    }
+
+    /// Get the inode bitmap of a group (uninitialized bitmaps are empty)
+    pub fn get_inode_bitmap(&self, disk: &dyn Disk, group_num: usize) -> Result<Ext2Bitmap, Error> {
+        let desc = &self.block_groups[group_num].ext2_group_desc;
+        let first = self.block_groups[group_num].first_inode_num;
+        let len = self.inodes_per_group;
+        if self.is_uninit_enabled() && desc.bg_flags & EXT2_BG_INODE_UNINIT != 0 {
+            Ok(Ext2Bitmap::new(
+                vec![0; len.div_ceil(8) as usize],
+                first,
+                len,
+            ))
+        } else {
+            let bitmap = self.read_inode_bitmap(disk, group_num)?;
+            Ok(Ext2Bitmap::new(bitmap, first, len))
+        }
+    }
+
+    /// Read the block bitmap of a group, verifying the checksum
+    pub fn read_block_bitmap(&self, disk: &dyn Disk, group_num: usize) -> Result<Vec<u8>, Error> {
+        let desc = &self.block_groups[group_num].ext2_group_desc;
+        let offset = Offset::Block {
+            block_size: self.block_size,
+            block_num: desc.get_block_bitmap(),
+        };
+        let mut bitmap = disk.read(self.block_size, offset)?;
+        bitmap.truncate((self.blocks_per_group / 8) as usize);
+        if desc.bg_flags & EXT2_BG_BLOCK_UNINIT == 0 {
+            self.checksum.report(self.checksum.verify_block_bitmap(
+                group_num,
+                &bitmap,
+                desc.bg_block_bitmap_csum_lo,
+                desc.bg_block_bitmap_csum_hi,
+            ))?;
+        }
+        Ok(bitmap)
+    }
+
+    /// Read the inode bitmap of a group, verifying the checksum
+    pub fn read_inode_bitmap(&self, disk: &dyn Disk, group_num: usize) -> Result<Vec<u8>, Error> {
+        let desc = &self.block_groups[group_num].ext2_group_desc;
+        let offset = Offset::Block {
+            block_size: self.block_size,
+            block_num: desc.get_inode_bitmap(),
+        };
+        let mut bitmap = disk.read(self.block_size, offset)?;
+        bitmap.truncate((self.inodes_per_group / 8) as usize);
+        if desc.bg_flags & EXT2_BG_INODE_UNINIT == 0 {
+            self.checksum.report(self.checksum.verify_inode_bitmap(
+                group_num,
+                &bitmap,
+                desc.bg_inode_bitmap_csum_lo,
+                desc.bg_inode_bitmap_csum_hi,
+            ))?;
+        }
+        Ok(bitmap)
+    }
 }
@@ -0,0 +1,168 @@
+use crate::disk::Offset;
+use crate::ext2::group::EXT2_BG_INODE_UNINIT;
+use crate::ext2::inode::Ext2Inode;
+use crate::ext2::{Ext2Filesystem, EXT2_ROOT_INO};
+use crate::inode::Inode;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::io::Error;
+
+impl Ext2Filesystem {
+    /// Find the inode owning each block (data, indirect, extent tree or extended attributes block).
+    /// Returns None for the blocks not owned by any inode (free blocks or filesystem metadata).
+    pub fn find_block_owners(&self, blocks: &[u64]) -> Result<Vec<Option<u64>>, Error> {
+        let mut owners: BTreeMap<u64, Option<u64>> = blocks.iter().map(|x| (*x, None)).collect();
+        let mut remaining = owners.len();
+        let inode_size = self.super_block.s_inode_size as u64;
+        let block_size = self.super_block.get_block_size();
+        let blocks_count = self.super_block.get_blocks_count();
+        let csum = self.super_block.has_metadata_csum() || self.super_block.has_gdt_csum();
+        for (group_num, group) in self.block_groups.iter().enumerate() {
+            if remaining == 0 {
+                break;
+            }
+            let desc = &group.ext2_group_desc;
+            if csum && desc.bg_flags & EXT2_BG_INODE_UNINIT != 0 {
+                continue;
+            }
+            let bitmap = self.get_inode_bitmap(group_num)?;
+            let count = bitmap
+                .iter_used()
+                .last()
+                .map_or(0, |x| x - group.first_inode_num + 1);
+            let offset = Offset::Block {
+                block_size,
+                block_num: desc.get_inode_table(),
+            };
+            let table = self.disk.read(count * inode_size, offset)?;
+            for inode_num in bitmap.iter_used() {
+                let start = ((inode_num - group.first_inode_num) * inode_size) as usize;
+                let buffer = &table[start..start + inode_size as usize];
+                let inode = match Ext2Inode::from_buffer(
+                    self.disk.as_ref(),
+                    buffer,
+                    block_size,
+                    &self.checksum,
+                    inode_num,
+                ) {
+                    Ok(inode) => inode,
+                    Err(_) => continue,
+                };
+                let refs = inode
+                    .get_block_map(self.disk.as_ref(), blocks_count)
+                    .unwrap_or_default();
+                let mut inode_blocks: Vec<u64> = refs.iter().map(|x| x.block_num).collect();
+                let xattr_block = inode.get_ext2_inode().i_file_acl as u64;
+                if xattr_block != 0 {
+                    inode_blocks.push(xattr_block);
+                }
+                for block_num in inode_blocks {
+                    if let Some(owner) = owners.get_mut(&block_num) {
+                        if owner.is_none() {
+                            *owner = Some(inode_num);
+                            remaining -= 1;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(blocks.iter().map(|x| owners[x]).collect())
+    }
+
+    /// Find the paths naming each inode by scanning the directories from the root.
+    /// Returns (inode number, path) pairs, an inode can have multiple paths (hard links).
+    pub fn find_inode_paths(&self, inodes: &[u64]) -> Result<Vec<(u64, String)>, Error> {
+        let wanted: HashSet<u64> = inodes.iter().copied().collect();
+        let mut result: Vec<(u64, String)> = Vec::new();
+        let mut visited: HashSet<u64> = HashSet::new();
+        let mut queue: VecDeque<(Ext2Inode, String)> = VecDeque::new();
+        if wanted.contains(&EXT2_ROOT_INO) {
+            result.push((EXT2_ROOT_INO, String::from("/")));
+        }
+        visited.insert(EXT2_ROOT_INO);
+        queue.push_back((self.read_inode(EXT2_ROOT_INO)?, String::from("/")));
+        while let Some((dir, path)) = queue.pop_front() {
+            let entries = match dir.read_dir(&self.disk, &path) {
+                Ok(entries) => entries,
+                Err(_) => continue, // Unreadable directory
+            };
+            for entry in entries.values() {
+                let name = entry.file_name();
+                if name == "." || name == ".." {
+                    continue;
+                }
+                let inode_num = entry.inode_num();
+                if wanted.contains(&inode_num) {
+                    result.push((inode_num, entry.path()));
+                }
+                if visited.contains(&inode_num) {
+                    continue;
+                }
+                if let Ok(inode) = self.read_inode(inode_num) {
+                    if inode.metadata().is_dir() {
+                        visited.insert(inode_num);
+                        queue.push_back((inode, entry.path()));
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext2::inode::Ext2BlockKind;
+    use crate::fs::MountOptions;
+    use crate::test_util::mount_image;
+
+    /// Blocks of an inode: data and metadata
+    fn get_blocks(fs: &Ext2Filesystem, inode_num: u64, kind: Ext2BlockKind) -> Vec<u64> {
+        let inode = fs.read_inode(inode_num).unwrap();
+        let blocks_count = fs.super_block.get_blocks_count();
+        let refs = inode.get_block_map(fs.disk.as_ref(), blocks_count).unwrap();
+        refs.iter()
+            .filter(|x| x.kind == kind)
+            .map(|x| x.block_num)
+            .collect()
+    }
+
+    #[test]
+    fn test_find_block_owners() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let data = get_blocks(&fs, 16, Ext2BlockKind::Data)[0];
+        let indirect = get_blocks(&fs, 12, Ext2BlockKind::Indirect)[0];
+        let last = *get_blocks(&fs, 12, Ext2BlockKind::Data).last().unwrap();
+        let (free, _) = fs.get_free_extents().unwrap()[0];
+        // Superblock and free blocks have no owner
+        let blocks = [data, 1, indirect, free, last, data];
+        let owners = fs.find_block_owners(&blocks).unwrap();
+        assert_eq!(owners, [Some(16), None, Some(12), None, Some(12), Some(16)]);
+    }
+
+    #[test]
+    fn test_find_block_owners_extents() {
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        let blocks = get_blocks(&fs, 53, Ext2BlockKind::Data);
+        assert_eq!(blocks.len(), 40);
+        let owners = fs.find_block_owners(&blocks).unwrap();
+        assert!(owners.iter().all(|x| *x == Some(53)));
+    }
+
+    #[test]
+    fn test_find_inode_paths() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let mut paths = fs.find_inode_paths(&[16, 15, 2, 13, 14]).unwrap();
+        paths.sort();
+        let expected = [
+            (2, "/"),
+            (14, "/dir1"),
+            (15, "/dir1/file.txt"),
+            (16, "/hello.txt"),
+        ];
+        assert_eq!(paths, expected.map(|(num, path)| (num, path.to_string())));
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        let paths = fs.find_inode_paths(&[56]).unwrap();
+        assert_eq!(paths, [(56, "/inline_dir/b.txt".to_string())]);
+    }
+}
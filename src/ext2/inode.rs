@@ -1,7 +1,12 @@
 use crate::dir::DirEntry;
-use crate::disk::{BlockCache, Disk, Offset};
-use crate::ext2::dir::Ext2DirEntry;
+use crate::disk::{Disk, Offset};
+use crate::ext2::checksum::Ext2Checksum;
+use crate::ext2::dir::{Ext2DirEntry, Ext2DirRecord, EXT2_DIR_ENTRY_HEADER_SIZE};
+use crate::ext2::extent::{read_extents, Ext4Extent, Ext4ExtentTree};
 use crate::ext2::group::Ext2BlockGroups;
+use crate::ext2::read_u32;
+use crate::ext2::superblock::get_bit_names;
+use crate::ext2::xattr::{read_block_xattrs, read_inode_xattrs, EXT2_XATTR_INDEX_SYSTEM};
 use crate::inode::Inode;
 use crate::metadata::Metadata;
 use std::collections::BTreeMap;
@@ -20,8 +25,41 @@ pub const EXT2_TRIPLY_IND_BLOCK: usize = EXT2_DOUBLY_IND_BLOCK + 1;
 pub const EXT2_N_BLOCKS: usize = EXT2_TRIPLY_IND_BLOCK + 1;
 pub const I_BLOCKS_SIZE: usize = EXT2_N_BLOCKS * 4;
 
+// Inode flags
+pub const EXT4_EXTENTS_FL: u32 = 0x00080000; // Inode uses extents
+pub const EXT4_INLINE_DATA_FL: u32 = 0x10000000; // Inode has inline data
+
+const INODE_FLAG_NAMES: [(u32, &str); 26] = [
+    (0x00000001, "SECRM"),
+    (0x00000002, "UNRM"),
+    (0x00000004, "COMPR"),
+    (0x00000008, "SYNC"),
+    (0x00000010, "IMMUTABLE"),
+    (0x00000020, "APPEND"),
+    (0x00000040, "NODUMP"),
+    (0x00000080, "NOATIME"),
+    (0x00000100, "DIRTY"),
+    (0x00000200, "COMPRBLK"),
+    (0x00000400, "NOCOMPR"),
+    (0x00000800, "ENCRYPT"),
+    (0x00001000, "INDEX"),
+    (0x00002000, "IMAGIC"),
+    (0x00004000, "JOURNAL_DATA"),
+    (0x00008000, "NOTAIL"),
+    (0x00010000, "DIRSYNC"),
+    (0x00020000, "TOPDIR"),
+    (0x00040000, "HUGE_FILE"),
+    (EXT4_EXTENTS_FL, "EXTENTS"),
+    (0x00100000, "VERITY"),
+    (0x00200000, "EA_INODE"),
+    (0x02000000, "DAX"),
+    (EXT4_INLINE_DATA_FL, "INLINE_DATA"),
+    (0x20000000, "PROJINHERIT"),
+    (0x40000000, "CASEFOLD"),
+];
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ext2InodeStruct {
     pub i_mode: u16,        /* File mode */
     pub i_uid: u16,         /* Low 16 bits of Owner Uid */
@@ -46,9 +84,20 @@ pub struct Ext2InodeStruct {
     pub l_i_frag: u8,  /* Fragment number */
     pub l_i_fsize: u8, /* Fragment size */
     pub i_pad1: u16,
-    pub l_i_uid_high: u16, /* these 2 fields    */
-    pub l_i_gid_high: u16, /* were reserved2[0] */
-    pub l_i_reserved2: u32,
+    pub l_i_uid_high: u16,    /* these 2 fields    */
+    pub l_i_gid_high: u16,    /* were reserved2[0] */
+    pub l_i_checksum_lo: u16, /* crc32c(uuid+inum+inode) LE */
+    pub l_i_reserved: u16,
+    // -- Large inodes only (inode size > 128) ---
+    pub i_extra_isize: u16,  /* Size of this inode - 128 */
+    pub i_checksum_hi: u16,  /* crc32c(uuid+inum+inode) BE */
+    pub i_ctime_extra: u32,  /* extra Change time (nsec << 2 | epoch) */
+    pub i_mtime_extra: u32,  /* extra Modification time(nsec << 2 | epoch) */
+    pub i_atime_extra: u32,  /* extra Access time (nsec << 2 | epoch) */
+    pub i_crtime: u32,       /* File Creation time */
+    pub i_crtime_extra: u32, /* extra FileCreationtime (nsec << 2 | epoch) */
+    pub i_version_hi: u32,   /* high 32 bits for 64-bit version */
+    pub i_projid: u32,       /* Project ID */
 }
 
 impl Ext2InodeStruct {
@@ -56,6 +105,33 @@ impl Ext2InodeStruct {
         let ionode: Ext2InodeStruct = unsafe { mem::zeroed() };
         ionode
     }
+    /// Parse an inode struct from the inode table
+    pub fn from_bytes(buffer: &[u8]) -> Ext2InodeStruct {
+        let mut inode = Ext2InodeStruct::default();
+        // The inode size can be smaller or bigger than the struct
+        let size = buffer.len().min(mem::size_of::<Ext2InodeStruct>());
+        let mut buf = &buffer[..size];
+        let p = &mut inode as *mut _ as *mut u8;
+        unsafe {
+            let inode_slice = slice::from_raw_parts_mut(p, size);
+            buf.read_exact(inode_slice).unwrap();
+        }
+        inode
+    }
+
+    /// Names of the inode flags
+    pub fn get_flag_names(&self) -> Vec<String> {
+        get_bit_names(self.i_flags, &INODE_FLAG_NAMES, "FLAG_")
+    }
+
+    pub fn i_block_bytes(&self) -> [u8; I_BLOCKS_SIZE] {
+        let mut buffer = [0u8; I_BLOCKS_SIZE];
+        for (i, block) in self.i_block.iter().enumerate() {
+            let addr = i * mem::size_of::<u32>();
+            buffer[addr..addr + 4].copy_from_slice(&block.to_le_bytes());
+        }
+        buffer
+    }
     pub fn size(&self) -> u64 {
         // Calculate the size in bytes
         if unix_mode::is_file(self.i_mode as u32) {
@@ -66,14 +142,35 @@ impl Ext2InodeStruct {
     }
 }
 
-#[derive(Debug)]
+/// Role of a block in the block map of an inode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ext2BlockKind {
+    Data,           // File content
+    Indirect,       // Singly indirect block
+    DoublyIndirect, // Doubly indirect block
+    TriplyIndirect, // Triply indirect block
+    ExtentTree,     // Extent tree index or leaf block
+}
+
+/// Block referenced by an inode
+#[derive(Debug, Clone, Copy)]
+pub struct Ext2BlockRef {
+    pub block_num: u64,      // Physical block number
+    pub kind: Ext2BlockKind, // Role of the block
+    pub file_block_num: u64, // First file block mapped by the block
+    pub parent: u64,         // Block containing the pointer (0 if the pointer is in the inode)
+    pub index: usize,        // Position of the pointer in the parent
+}
+
+#[derive(Debug, Clone)]
 pub struct Ext2Inode {
-    inode_num: u64,              // Inode number
-    ext2_inode: Ext2InodeStruct, // Ext2 inode struct
-    inode_size: u64,             // Inode size
-    block_size: u64,             // Block size
-    size: u64,                   // Size in bytes
-    data_blocks_count: u64,      // Number of data blocks
+    inode_num: u64,               // Inode number
+    ext2_inode: Ext2InodeStruct,  // Ext2 inode struct
+    block_size: u64,              // Block size
+    size: u64,                    // Size in bytes
+    data_blocks_count: u64,       // Number of data blocks
+    checksum: Ext2Checksum,       // Metadata checksums
+    inline_data: Option<Vec<u8>>, // Content stored inside the inode
 }
 
 impl Ext2Inode {
@@ -82,6 +179,7 @@ impl Ext2Inode {
         inode_size: u64,
         block_size: u64,
         block_groups: &Ext2BlockGroups,
+        checksum: &Ext2Checksum,
         inode_num: u64,
     ) -> Result<Ext2Inode, Error> {
         // Determinate the block group
@@ -89,43 +187,143 @@ impl Ext2Inode {
         // Calculate the offset
         let offset = Offset::BlockDelta {
             block_size: block_size,
-            base_block_num: group.ext2_group_desc.bg_inode_table as u64,
+            base_block_num: group.ext2_group_desc.get_inode_table(),
             delta: (inode_num - group.first_inode_num) as u64 * inode_size,
         };
         // Read the inode from the disk
         let buffer = disk.read(inode_size, offset)?;
-        let mut inode = Ext2InodeStruct::default();
-        let mut buf = buffer.as_slice();
-        let p = &mut inode as *mut _ as *mut u8;
-        unsafe {
-            let inode_slice = slice::from_raw_parts_mut(p, inode_size as usize);
-            buf.read_exact(inode_slice).unwrap();
+        Ext2Inode::from_buffer(disk.as_ref(), &buffer, block_size, checksum, inode_num)
+    }
+
+    /// Parse an inode read from the inode table
+    pub fn from_buffer(
+        disk: &dyn Disk,
+        buffer: &[u8],
+        block_size: u64,
+        checksum: &Ext2Checksum,
+        inode_num: u64,
+    ) -> Result<Ext2Inode, Error> {
+        // Verify the checksum (unused inodes are all zeros)
+        if buffer.iter().any(|x| *x != 0) {
+            checksum.report(checksum.verify_inode(inode_num, buffer))?;
         }
+        let inode = Ext2InodeStruct::from_bytes(buffer);
         // Calculate the size
         let size = inode.size();
+        // Read the inline data
+        let inline_data = if inode.i_flags & EXT4_INLINE_DATA_FL != 0 {
+            Some(Ext2Inode::read_inline_data(
+                disk, &inode, buffer, block_size, checksum,
+            )?)
+        } else {
+            None
+        };
         // Calculate the number of data blocks
-        let data_blocks_count: u64 = (size as f64 / block_size as f64).ceil() as u64;
+        let data_blocks_count: u64 = if inline_data.is_some() {
+            0
+        } else {
+            (size as f64 / block_size as f64).ceil() as u64
+        };
         Ok(Ext2Inode {
             inode_num: inode_num,
             ext2_inode: inode,
-            inode_size: inode_size,
             block_size: block_size,
             size: size,
             data_blocks_count: data_blocks_count,
+            checksum: *checksum,
+            inline_data,
         })
     }
 
+    /// Read the inline data: the first 60 bytes are stored in i_block,
+    /// the rest in the "system.data" extended attribute
+    fn read_inline_data(
+        disk: &dyn Disk,
+        inode: &Ext2InodeStruct,
+        buffer: &[u8],
+        block_size: u64,
+        checksum: &Ext2Checksum,
+    ) -> Result<Vec<u8>, Error> {
+        let mut data = inode.i_block_bytes().to_vec();
+        let mut xattrs = read_inode_xattrs(buffer)?;
+        if inode.i_file_acl != 0 {
+            xattrs.extend(read_block_xattrs(
+                disk,
+                block_size,
+                inode.i_file_acl as u64,
+                checksum,
+            )?);
+        }
+        if let Some(xattr) = xattrs
+            .iter()
+            .find(|x| x.is(EXT2_XATTR_INDEX_SYSTEM, "data"))
+        {
+            data.extend(&xattr.value);
+        }
+        Ok(data)
+    }
+
+    /// Returns true if the content is stored inside the inode
+    pub fn has_inline_data(&self) -> bool {
+        self.inline_data.is_some()
+    }
+
+    /// Inode number
+    pub fn get_inode_num(&self) -> u64 {
+        self.inode_num
+    }
+
+    /// On-disk inode struct
+    pub fn get_ext2_inode(&self) -> &Ext2InodeStruct {
+        &self.ext2_inode
+    }
+
+    /// Checksum seed of the inode metadata blocks
+    fn get_checksum_seed(&self) -> u32 {
+        self.checksum
+            .inode_seed(self.inode_num, self.ext2_inode.i_generation)
+    }
+
+    /// Returns true if the inode uses extents
+    pub fn has_extents(&self) -> bool {
+        self.ext2_inode.i_flags & EXT4_EXTENTS_FL != 0
+    }
+
+    /// Read the extent tree of the inode
+    pub fn read_extents(&self, disk: &dyn Disk) -> Result<Ext4ExtentTree, Error> {
+        read_extents(
+            disk,
+            &self.ext2_inode.i_block_bytes(),
+            self.block_size,
+            &self.checksum,
+            self.inode_num,
+            self.get_checksum_seed(),
+        )
+    }
+
+    /// Read a block from the disk
+    fn read_block(&self, disk: &dyn Disk, block_num: u64) -> Result<Vec<u8>, Error> {
+        let offset = Offset::Block {
+            block_size: self.block_size,
+            block_num,
+        };
+        disk.read(self.block_size, offset)
+    }
+
     /// Read blocks iterator
     pub fn read_blocks_iter<'a>(&'a self, disk: &'a Box<dyn Disk>) -> Result<ReadBlock<'a>, Error> {
         Ok(ReadBlock {
             disk: disk,
             block_size: self.block_size,
-            blocks: self.get_blocks_iter(disk)?,
+            blocks: self.get_blocks_iter(disk.as_ref())?,
         })
     }
 
     /// Read file content
     pub fn read(&self, disk: &Box<dyn Disk>) -> Result<Vec<u8>, Error> {
+        if let Some(data) = &self.inline_data {
+            return Ok(data[..(self.size as usize).min(data.len())].to_vec());
+        }
         let mut buffer: Vec<u8> = Vec::new();
         for block in self.read_blocks_iter(disk)? {
             buffer.extend(&block?);
@@ -134,41 +332,131 @@ impl Ext2Inode {
     }
 
     /// Block numbers iterator
-    pub fn get_blocks_iter<'a>(
-        &'a self,
-        disk: &'a Box<dyn Disk>,
-    ) -> Result<ReadBlockNum<'a>, Error> {
+    pub fn get_blocks_iter<'a>(&'a self, disk: &'a dyn Disk) -> Result<ReadBlockNum<'a>, Error> {
+        let extents = if self.has_extents() {
+            Some(self.read_extents(disk)?.extents)
+        } else {
+            None
+        };
         Ok(ReadBlockNum::new(
             disk,
             &self.ext2_inode.i_block,
             self.block_size,
             self.data_blocks_count,
+            extents,
         ))
     }
 
-    /// Resolve a child by name - return the child's inode
-    pub fn get_child(
+    /// Block map: data blocks and metadata blocks (indirect blocks, extent tree blocks).
+    /// Pointers to blocks beyond blocks_count are returned but not followed.
+    pub fn get_block_map(
         &self,
-        disk: &Box<dyn Disk>,
-        block_groups: &Ext2BlockGroups,
-        name: &str,
-    ) -> Option<Ext2Inode> {
-        match self.read_dir(disk, "") {
-            Ok(entries) => match entries.get(name) {
-                Some(dir_entry) => Some(
-                    Ext2Inode::new(
-                        disk,
-                        self.inode_size,
-                        self.block_size,
-                        block_groups,
-                        dir_entry.inode_num(),
-                    )
-                    .ok()?,
-                ),
-                None => None,
-            },
-            Err(_) => None,
+        disk: &dyn Disk,
+        blocks_count: u64,
+    ) -> Result<Vec<Ext2BlockRef>, Error> {
+        let mut refs: Vec<Ext2BlockRef> = Vec::new();
+        if self.has_inline_data() || (self.metadata().is_symlink() && self.is_fast_symlink()) {
+            // No blocks
+        } else if self.has_extents() {
+            let tree = self.read_extents(disk)?;
+            for node in tree.nodes.iter() {
+                refs.push(Ext2BlockRef {
+                    block_num: node.block_num,
+                    kind: Ext2BlockKind::ExtentTree,
+                    file_block_num: node.ee_block,
+                    parent: node.parent,
+                    index: node.index,
+                });
+            }
+            for extent in tree.extents.iter() {
+                for i in 0..extent.ee_len {
+                    refs.push(Ext2BlockRef {
+                        block_num: extent.ee_start + i,
+                        kind: Ext2BlockKind::Data,
+                        file_block_num: extent.ee_block + i,
+                        parent: extent.parent,
+                        index: extent.index,
+                    });
+                }
+            }
+        } else {
+            let blocks_per_block = self.block_size / mem::size_of::<u32>() as u64;
+            let mut walker = IndirectWalker {
+                disk,
+                block_size: self.block_size,
+                blocks_count,
+                refs: &mut refs,
+            };
+            for (i, block_num) in self.ext2_inode.i_block.iter().enumerate() {
+                let (kind, file_block_num) = match i {
+                    EXT2_IND_BLOCK => (Ext2BlockKind::Indirect, EXT2_NDIR_BLOCKS as u64),
+                    EXT2_DOUBLY_IND_BLOCK => (
+                        Ext2BlockKind::DoublyIndirect,
+                        EXT2_NDIR_BLOCKS as u64 + blocks_per_block,
+                    ),
+                    EXT2_TRIPLY_IND_BLOCK => (
+                        Ext2BlockKind::TriplyIndirect,
+                        EXT2_NDIR_BLOCKS as u64
+                            + blocks_per_block
+                            + blocks_per_block * blocks_per_block,
+                    ),
+                    _ => (Ext2BlockKind::Data, i as u64),
+                };
+                if *block_num != 0 {
+                    walker.walk(*block_num as u64, kind, file_block_num, 0, i)?;
+                }
+            }
         }
+        Ok(refs)
+    }
+
+    /// Read the deleted entries still present in the directory blocks
+    pub fn read_deleted_dir(
+        &self,
+        disk: &dyn Disk,
+        path: &str,
+        inodes_count: u64,
+    ) -> Result<Vec<Ext2DirEntry>, Error> {
+        if !self.metadata().is_dir() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Not a directory"));
+        }
+        let mut entries: Vec<Ext2DirEntry> = Vec::new();
+        if self.inline_data.is_some() {
+            // Inline directories have no slack space worth scanning
+            return Ok(entries);
+        }
+        let seed = self.get_checksum_seed();
+        for block_num in self.get_blocks_iter(disk)? {
+            let block_num = block_num?;
+            if block_num == 0 {
+                break;
+            }
+            let buffer = self.read_block(disk, block_num)?;
+            self.checksum.report(self.checksum.verify_dir_block(
+                self.inode_num,
+                seed,
+                block_num,
+                &buffer,
+            ))?;
+            for record in Ext2DirRecord::parse_deleted(&buffer, inodes_count) {
+                entries.push(Ext2DirEntry::with_name(
+                    &record.get_name(),
+                    record.inode_num,
+                    path,
+                ));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns true if the symbolic link target is stored in the inode
+    pub fn is_fast_symlink(&self) -> bool {
+        let xattr_sectors = if self.ext2_inode.i_file_acl != 0 {
+            self.block_size / 512
+        } else {
+            0
+        };
+        self.ext2_inode.i_blocks as u64 == xattr_sectors
     }
 
     /// Read value of a symbolic link
@@ -206,16 +494,33 @@ impl Inode for Ext2Inode {
             // Err(Error::new(ErrorKind::NotADirectory, "Not a directory"))
         } else {
             let mut entries: BTreeMap<String, Box<dyn DirEntry>> = BTreeMap::new();
-            // Iterate over blocks
-            for buffer in self.read_blocks_iter(disk)? {
-                let buffer = buffer?;
-                let mut offset: usize = 0;
-                // Iterate over block directory entries
-                while offset < self.block_size as usize {
-                    let (dir_entry, rec_len) = Ext2DirEntry::new(&buffer, offset, path);
-                    offset += rec_len;
+            if let Some(data) = &self.inline_data {
+                // Inline directories start with the parent inode number,
+                // followed by the entries (without "." and "..")
+                let parent = read_u32(data, 0) as u64;
+                for (name, inode_num) in [(".", self.inode_num), ("..", parent)] {
+                    let dir_entry = Ext2DirEntry::with_name(name, inode_num, path);
                     entries.insert(dir_entry.file_name(), Box::new(dir_entry));
                 }
+                read_dir_entries(&data[4..I_BLOCKS_SIZE], path, &mut entries)?;
+                read_dir_entries(&data[I_BLOCKS_SIZE..], path, &mut entries)?;
+                return Ok(entries);
+            }
+            let seed = self.get_checksum_seed();
+            // Iterate over blocks
+            for block_num in self.get_blocks_iter(disk.as_ref())? {
+                let block_num = block_num?;
+                if block_num == 0 {
+                    break;
+                }
+                let buffer = self.read_block(disk.as_ref(), block_num)?;
+                self.checksum.report(self.checksum.verify_dir_block(
+                    self.inode_num,
+                    seed,
+                    block_num,
+                    &buffer,
+                ))?;
+                read_dir_entries(&buffer, path, &mut entries)?;
             }
             Ok(entries)
         }
@@ -223,7 +528,7 @@ impl Inode for Ext2Inode {
 
     /// Block numbers
     fn get_blocks(&self, disk: &Box<dyn Disk>) -> Result<Vec<u64>, Error> {
-        match self.get_blocks_iter(disk) {
+        match self.get_blocks_iter(disk.as_ref()) {
             Ok(iterator) => iterator.collect::<Result<Vec<_>, _>>(),
             Err(x) => Err(x),
         }
@@ -239,6 +544,11 @@ impl Inode for Ext2Inode {
         self.size
     }
 
+    /// Content stored inside the inode (if any)
+    fn get_inline_data(&self) -> Option<&[u8]> {
+        self.inline_data.as_deref()
+    }
+
     /// Given a path, query the file system to get information about a file, directory, etc.
     fn metadata(&self) -> Metadata {
         Metadata {
@@ -262,11 +572,90 @@ impl Inode for Ext2Inode {
     }
 }
 
+/// Iterate over the directory entries of a buffer, failing on the first invalid entry
+fn read_dir_entries(
+    buffer: &[u8],
+    path: &str,
+    entries: &mut BTreeMap<String, Box<dyn DirEntry>>,
+) -> Result<(), Error> {
+    let mut offset: usize = 0;
+    while offset + EXT2_DIR_ENTRY_HEADER_SIZE <= buffer.len() {
+        let record = Ext2DirRecord::parse(buffer, offset).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid directory entry at offset {}: {}", offset, err),
+            )
+        })?;
+        offset += record.rec_len;
+        // Skip unused entries and the checksum tail
+        if record.inode_num != 0 {
+            let dir_entry = Ext2DirEntry::with_name(&record.get_name(), record.inode_num, path);
+            entries.insert(dir_entry.file_name(), Box::new(dir_entry));
+        }
+    }
+    Ok(())
+}
+
+/// Walk the indirect blocks collecting the block map
+struct IndirectWalker<'a> {
+    disk: &'a dyn Disk,
+    block_size: u64,
+    blocks_count: u64,
+    refs: &'a mut Vec<Ext2BlockRef>,
+}
+
+impl IndirectWalker<'_> {
+    fn walk(
+        &mut self,
+        block_num: u64,
+        kind: Ext2BlockKind,
+        file_block_num: u64,
+        parent: u64,
+        index: usize,
+    ) -> Result<(), Error> {
+        self.refs.push(Ext2BlockRef {
+            block_num,
+            kind,
+            file_block_num,
+            parent,
+            index,
+        });
+        let (child_kind, child_blocks) = match kind {
+            Ext2BlockKind::Indirect => (Ext2BlockKind::Data, 1),
+            Ext2BlockKind::DoublyIndirect => (Ext2BlockKind::Indirect, self.block_size / 4),
+            Ext2BlockKind::TriplyIndirect => (
+                Ext2BlockKind::DoublyIndirect,
+                (self.block_size / 4) * (self.block_size / 4),
+            ),
+            _ => return Ok(()),
+        };
+        if block_num >= self.blocks_count {
+            return Ok(());
+        }
+        let offset = Offset::Block {
+            block_size: self.block_size,
+            block_num,
+        };
+        let buffer = self.disk.read(self.block_size, offset)?;
+        for i in 0..(self.block_size / 4) as usize {
+            let child = read_u32(&buffer, i * 4) as u64;
+            if child != 0 {
+                let child_file_block_num = file_block_num + i as u64 * child_blocks;
+                self.walk(child, child_kind, child_file_block_num, block_num, i)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct ReadBlockNum<'a> {
     blocks_per_block: u64, // number of block number (each block number is sizeof u32) in a block
     i_block: &'a [u32; EXT2_N_BLOCKS],
     data_blocks_count: u64,
-    cache: BlockCache<'a>,
+    extents: Option<Vec<Ext4Extent>>, // Extents (if the inode uses extents)
+    curr_extent: usize,
+    disk: &'a dyn Disk, // Filesystem disk, caching the indirect blocks
+    block_size: u64,
     first_indirect_block: u64,
     first_doubly_indirect_block: u64,
     first_triply_indirect_block: u64,
@@ -275,17 +664,21 @@ pub struct ReadBlockNum<'a> {
 
 impl ReadBlockNum<'_> {
     pub fn new<'a>(
-        disk: &'a Box<dyn Disk>,
+        disk: &'a dyn Disk,
         i_block: &'a [u32; EXT2_N_BLOCKS],
         block_size: u64,
         data_blocks_count: u64,
+        extents: Option<Vec<Ext4Extent>>,
     ) -> ReadBlockNum<'a> {
         let blocks_per_block = block_size / mem::size_of::<u32>() as u64;
         ReadBlockNum {
             blocks_per_block: blocks_per_block,
             i_block: i_block,
             data_blocks_count: data_blocks_count,
-            cache: BlockCache::new(disk, block_size),
+            extents,
+            curr_extent: 0,
+            disk,
+            block_size,
             first_indirect_block: EXT2_NDIR_BLOCKS as u64,
             first_doubly_indirect_block: EXT2_NDIR_BLOCKS as u64 + blocks_per_block,
             first_triply_indirect_block: EXT2_NDIR_BLOCKS as u64
@@ -295,6 +688,26 @@ impl ReadBlockNum<'_> {
         }
     }
 
+    /// Get block from the extents (0 if the block is not mapped)
+    fn get_extent_block(&mut self, i: u64) -> Result<u64, Error> {
+        let extents = self.extents.as_ref().unwrap();
+        // Extents are sorted, start from the current one
+        if extents.get(self.curr_extent).is_none_or(|e| i < e.ee_block) {
+            self.curr_extent = 0;
+        }
+        while let Some(extent) = extents.get(self.curr_extent) {
+            if i < extent.ee_block {
+                break;
+            }
+            if let Some(block_num) = extent.get_block(i) {
+                // Uninitialized extents are read as holes
+                return Ok(if extent.uninitialized { 0 } else { block_num });
+            }
+            self.curr_extent += 1;
+        }
+        Ok(0)
+    }
+
     /// Get direct block
     fn get_direct_block(&self, i: u64) -> Result<u64, Error> {
         Ok(self.i_block[i as usize] as u64)
@@ -302,12 +715,18 @@ impl ReadBlockNum<'_> {
 
     /// Get singly indirect block
     fn get_indirect_block(&mut self, i: u64, indirect_block_num: u64) -> Result<u64, Error> {
-        let indirect_blocks = self.cache.get_block(indirect_block_num)?;
-        let addr: usize = i as usize * mem::size_of::<u32>();
-        let bytes: [u8; 4] = indirect_blocks[addr..addr + 4]
-            .try_into()
-            .expect("incorrect length");
-        Ok(u32::from_le_bytes(bytes) as u64)
+        if indirect_block_num == 0 {
+            // Sparse file, the whole range is a hole
+            return Ok(0);
+        }
+        let size = mem::size_of::<u32>() as u64;
+        let offset = Offset::BlockDelta {
+            block_size: self.block_size,
+            base_block_num: indirect_block_num,
+            delta: i * size,
+        };
+        let bytes = self.disk.read(size, offset)?;
+        Ok(read_u32(&bytes, 0) as u64)
     }
 
     /// Get doubly indirect block
@@ -347,7 +766,9 @@ impl Iterator for ReadBlockNum<'_> {
             None
         } else {
             self.curr = self.curr + 1;
-            if i < self.first_indirect_block {
+            if self.extents.is_some() {
+                Some(self.get_extent_block(i))
+            } else if i < self.first_indirect_block {
                 Some(self.get_direct_block(i))
             } else if i < self.first_doubly_indirect_block {
                 let i = i - self.first_indirect_block;
@@ -401,3 +822,119 @@ impl Iterator for ReadBlock<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext2::checksum::ChecksumMode;
+    use crate::ext2::{write_u16, write_u32, Ext2Filesystem};
+    use crate::fs::{Filesystem, MountOptions};
+    use crate::test_util::{inode_offset, mount_image, patch_image, read_file};
+
+    const INLINE_DIR_INODE: u64 = 54; // /inline_dir in ext4.img.gz
+    const I_BLOCK_OFFSET: u64 = 0x28;
+
+    /// Directory entries: inode number, rec_len and name (rec_len may be invalid)
+    fn dir_block(entries: &[(u32, u16, &[u8])]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for (inode_num, rec_len, name) in entries {
+            let start = buffer.len();
+            let len = (*rec_len as usize).max(EXT2_DIR_ENTRY_HEADER_SIZE + name.len());
+            buffer.resize(start + len, 0);
+            write_u32(&mut buffer, start, *inode_num);
+            write_u16(&mut buffer, start + 4, *rec_len);
+            buffer[start + 6] = name.len() as u8;
+            buffer[start + 8..start + 8 + name.len()].copy_from_slice(name);
+        }
+        buffer
+    }
+
+    fn names(entries: &BTreeMap<String, Box<dyn DirEntry>>) -> Vec<(String, u64)> {
+        entries
+            .values()
+            .map(|x| (x.file_name(), x.inode_num()))
+            .collect()
+    }
+
+    #[test]
+    fn test_read_dir_entries() {
+        let buffer = dir_block(&[(12, 12, b"a"), (0, 12, b"gone"), (13, 40, b"b\xffc")]);
+        let mut entries = BTreeMap::new();
+        read_dir_entries(&buffer, "/dir", &mut entries).unwrap();
+        // Unused entries are skipped, invalid UTF-8 sequences are replaced
+        let expected = [("a".to_string(), 12), ("b\u{fffd}c".to_string(), 13)];
+        assert_eq!(names(&entries), expected);
+        assert_eq!(entries["a"].path(), "/dir/a");
+    }
+
+    #[test]
+    fn test_read_invalid_dir_entries() {
+        let invalid = [
+            dir_block(&[(12, 12, b"a"), (13, 4, b"")]), // rec_len too small
+            dir_block(&[(12, 14, b"a")]),               // rec_len not a multiple of 4
+            dir_block(&[(12, 12, b"abcdefgh")]),        // name_len larger than rec_len
+            dir_block(&[(12, 12, b"a/b")]),             // invalid name
+        ];
+        for buffer in invalid {
+            let mut entries = BTreeMap::new();
+            let err = read_dir_entries(&buffer, "/", &mut entries).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+        // rec_len past the end of the buffer
+        let mut buffer = dir_block(&[(12, 12, b"a")]);
+        write_u16(&mut buffer, 4, 16);
+        assert!(read_dir_entries(&buffer, "/", &mut BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_inline_file() {
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        assert_eq!(
+            read_file(&fs, "/small.txt").unwrap(),
+            b"Small inline file\n"
+        );
+        // The end of the file is stored in the system.data extended attribute
+        let medium = read_file(&fs, "/medium.txt").unwrap();
+        assert_eq!(medium, "0123456789".repeat(10).as_bytes());
+        let inode = fs.read_inode(59).unwrap();
+        assert!(inode.has_inline_data());
+        assert_eq!(inode.get_blocks(&fs.disk).unwrap(), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_inline_dir() {
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        let entries = fs.read_dir("/inline_dir").unwrap();
+        let expected = [
+            (".", INLINE_DIR_INODE),
+            ("..", 2),
+            ("a.txt", 55),
+            ("b.txt", 56),
+            ("c.txt", 57),
+        ];
+        let expected: Vec<(String, u64)> = expected
+            .iter()
+            .map(|(name, inode_num)| (name.to_string(), *inode_num))
+            .collect();
+        assert_eq!(names(&entries), expected);
+        assert_eq!(read_file(&fs, "/inline_dir/b.txt").unwrap(), b"b\n");
+        assert_eq!(
+            read_file(&fs, "/inline_dir/../small.txt").unwrap().len(),
+            18
+        );
+    }
+
+    #[test]
+    fn test_corrupted_inline_dir() {
+        // rec_len of the first entry, after the parent inode number
+        let offset = inode_offset("ext4.img.gz", INLINE_DIR_INODE) + I_BLOCK_OFFSET + 4 + 4;
+        let image = patch_image("ext4.img.gz", &[(offset, &200u16.to_le_bytes())]);
+        let options = MountOptions {
+            checksum_mode: ChecksumMode::Ignore,
+            ..Default::default()
+        };
+        let fs = Ext2Filesystem::mount(&image.path, &options).unwrap();
+        let err = fs.read_dir("/inline_dir").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
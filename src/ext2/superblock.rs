@@ -1,4 +1,5 @@
 use crate::disk::Offset;
+use crate::ext2::checksum::crc32c_le;
 use crate::Disk;
 use std::io::Error;
 use std::io::ErrorKind;
@@ -8,9 +9,108 @@ use std::slice;
 
 const SUPER_BLOCK_SIZE: u64 = 1024;
 const SUPER_BLOCK: u64 = 1;
+const EXT2_MIN_DESC_SIZE: usize = 32;
+const MAX_LOG_BLOCK_SIZE: u32 = 6; // Block size up to 64K
+const MAX_BACKUP_GROUP: u64 = 1 << 16; // Last group probed for backup superblocks
+
+// Features
+pub const EXT4_FEATURE_COMPAT_SPARSE_SUPER2: u32 = 0x0200;
+pub const EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+pub const EXT4_FEATURE_RO_COMPAT_GDT_CSUM: u32 = 0x0010;
+pub const EXT4_FEATURE_RO_COMPAT_METADATA_CSUM: u32 = 0x0400;
+pub const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x0080;
+pub const EXT4_FEATURE_INCOMPAT_CSUM_SEED: u32 = 0x2000;
+
+// Feature names, by bit
+const COMPAT_FEATURE_NAMES: [(u32, &str); 12] = [
+    (0x0001, "dir_prealloc"),
+    (0x0002, "imagic_inodes"),
+    (0x0004, "has_journal"),
+    (0x0008, "ext_attr"),
+    (0x0010, "resize_inode"),
+    (0x0020, "dir_index"),
+    (0x0040, "lazy_bg"),
+    (0x0100, "snapshot_bitmap"),
+    (0x0200, "sparse_super2"),
+    (0x0400, "fast_commit"),
+    (0x0800, "stable_inodes"),
+    (0x1000, "orphan_file"),
+];
+const INCOMPAT_FEATURE_NAMES: [(u32, &str); 16] = [
+    (0x0001, "compression"),
+    (0x0002, "filetype"),
+    (0x0004, "needs_recovery"),
+    (0x0008, "journal_dev"),
+    (0x0010, "meta_bg"),
+    (0x0040, "extent"),
+    (0x0080, "64bit"),
+    (0x0100, "mmp"),
+    (0x0200, "flex_bg"),
+    (0x0400, "ea_inode"),
+    (0x1000, "dirdata"),
+    (0x2000, "metadata_csum_seed"),
+    (0x4000, "large_dir"),
+    (0x8000, "inline_data"),
+    (0x10000, "encrypt"),
+    (0x20000, "casefold"),
+];
+const RO_COMPAT_FEATURE_NAMES: [(u32, &str); 17] = [
+    (0x0001, "sparse_super"),
+    (0x0002, "large_file"),
+    (0x0004, "btree_dir"),
+    (0x0008, "huge_file"),
+    (0x0010, "uninit_bg"),
+    (0x0020, "dir_nlink"),
+    (0x0040, "extra_isize"),
+    (0x0080, "snapshot"),
+    (0x0100, "quota"),
+    (0x0200, "bigalloc"),
+    (0x0400, "metadata_csum"),
+    (0x0800, "replica"),
+    (0x1000, "read-only"),
+    (0x2000, "project"),
+    (0x4000, "shared_blocks"),
+    (0x8000, "verity"),
+    (0x10000, "orphan_present"),
+];
+
+// Miscellaneous flags names
+const FLAG_NAMES: [(u32, &str); 3] = [
+    (0x0001, "signed_directory_hash"),
+    (0x0002, "unsigned_directory_hash"),
+    (0x0004, "test_filesystem"),
+];
+
+// Default mount options names (the journal mode is stored in bits 0x60)
+const DEFAULT_MOUNT_OPTS_NAMES: [(u32, &str); 8] = [
+    (0x0001, "debug"),
+    (0x0002, "bsdgroups"),
+    (0x0004, "user_xattr"),
+    (0x0008, "acl"),
+    (0x0010, "uid16"),
+    (0x0100, "nobarrier"),
+    (0x0200, "block_validity"),
+    (0x0400, "discard"),
+];
+const EXT4_DEFM_JMODE: u32 = 0x0060;
+
+/// Names of the bits set in a bitmask, unknown bits are named with the prefix and the bit number
+pub fn get_bit_names(mask: u32, names: &[(u32, &'static str)], prefix: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    for bit in 0..32 {
+        let flag = 1 << bit;
+        if mask & flag != 0 {
+            match names.iter().find(|(x, _)| *x == flag) {
+                Some((_, name)) => result.push(name.to_string()),
+                None => result.push(format!("{}{}", prefix, bit)),
+            }
+        }
+    }
+    result
+}
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ext2SuperBlock {
     pub s_inodes_count: u32,      // Total number of inodes in file system
     pub s_blocks_count: u32,      // Total number of blocks in file system
@@ -52,7 +152,72 @@ pub struct Ext2SuperBlock {
     pub s_prealloc_blocks: u8,      // Nr of blocks to try to preallocate
     pub s_prealloc_dir_blocks: u8,  // Nr to preallocate for dirs
     pub s_reserved_gdt_blocks: u16, // Per group table for online growth
-    s_reserved: [u32; 204],
+    // -- EXT3_FEATURE_COMPAT_HAS_JOURNAL flag is on ---
+    pub s_journal_uuid: [u8; 16], // uuid of journal superblock
+    pub s_journal_inum: u32,      // inode number of journal file
+    pub s_journal_dev: u32,       // device number of journal file
+    pub s_last_orphan: u32,       // start of list of inodes to delete
+    pub s_hash_seed: [u32; 4],    // HTREE hash seed
+    pub s_def_hash_version: u8,   // Default hash version to use
+    pub s_jnl_backup_type: u8,
+    pub s_desc_size: u16, // size of group descriptor
+    pub s_default_mount_opts: u32,
+    pub s_first_meta_bg: u32,    // First metablock block group
+    pub s_mkfs_time: u32,        // When the filesystem was created
+    pub s_jnl_blocks: [u32; 17], // Backup of the journal inode
+    // -- EXT4_FEATURE_INCOMPAT_64BIT flag is on ---
+    pub s_blocks_count_hi: u32,      // Blocks count
+    pub s_r_blocks_count_hi: u32,    // Reserved blocks count
+    pub s_free_blocks_count_hi: u32, // Free blocks count
+    pub s_min_extra_isize: u16,      // All inodes have at least # bytes
+    pub s_want_extra_isize: u16,     // New inodes should reserve # bytes
+    pub s_flags: u32,                // Miscellaneous flags
+    pub s_raid_stride: u16,          // RAID stride
+    pub s_mmp_interval: u16,         // # seconds to wait in MMP checking
+    pub s_mmp_block: u64,            // Block for multi-mount protection
+    pub s_raid_stripe_width: u32,    // blocks on all data disks (N*stride)
+    pub s_log_groups_per_flex: u8,   // FLEX_BG group size
+    pub s_checksum_type: u8,         // metadata checksum algorithm used
+    s_reserved_pad: u16,
+    pub s_kbytes_written: u64,          // nr of lifetime kilobytes written
+    pub s_snapshot_inum: u32,           // Inode number of active snapshot
+    pub s_snapshot_id: u32,             // sequential ID of active snapshot
+    pub s_snapshot_r_blocks_count: u64, // reserved blocks for active snapshot's future use
+    pub s_snapshot_list: u32,           // inode number of the head of the on-disk snapshot list
+    pub s_error_count: u32,             // number of fs errors
+    pub s_first_error_time: u32,        // first time an error happened
+    pub s_first_error_ino: u32,         // inode involved in first error
+    pub s_first_error_block: u64,       // block involved of first error
+    pub s_first_error_func: [u8; 32],   // function where the error happened
+    pub s_first_error_line: u32,        // line number where error happened
+    pub s_last_error_time: u32,         // most recent time of an error
+    pub s_last_error_ino: u32,          // inode involved in last error
+    pub s_last_error_line: u32,         // line number where error happened
+    pub s_last_error_block: u64,        // block involved of last error
+    pub s_last_error_func: [u8; 32],    // function where the error happened
+    pub s_mount_opts: [u8; 64],
+    pub s_usr_quota_inum: u32,       // inode for tracking user quota
+    pub s_grp_quota_inum: u32,       // inode for tracking group quota
+    pub s_overhead_blocks: u32,      // overhead blocks/clusters in fs
+    pub s_backup_bgs: [u32; 2],      // groups with sparse_super2 SBs
+    pub s_encrypt_algos: [u8; 4],    // Encryption algorithms in use
+    pub s_encrypt_pw_salt: [u8; 16], // Salt used for string2key algorithm
+    pub s_lpf_ino: u32,              // Location of the lost+found inode
+    pub s_prj_quota_inum: u32,       // inode for tracking project quota
+    pub s_checksum_seed: u32,        // crc32c(orig_uuid) if csum_seed set
+    pub s_wtime_hi: u8,
+    pub s_mtime_hi: u8,
+    pub s_mkfs_time_hi: u8,
+    pub s_lastcheck_hi: u8,
+    pub s_first_error_time_hi: u8,
+    pub s_last_error_time_hi: u8,
+    pub s_first_error_errcode: u8,
+    pub s_last_error_errcode: u8,
+    pub s_encoding: u16,         // Filename charset encoding
+    pub s_encoding_flags: u16,   // Filename charset encoding flags
+    pub s_orphan_file_inum: u32, // Inode for tracking orphan inodes
+    s_reserved: [u32; 94],
+    pub s_checksum: u32, // crc32c(superblock)
 }
 
 impl Ext2SuperBlock {
@@ -62,20 +227,229 @@ impl Ext2SuperBlock {
     }
     // Number of groups in the fs
     pub fn get_groups_count(&self) -> usize {
-        (self.s_blocks_count as f64 / self.s_blocks_per_group as f64).ceil() as usize
+        let blocks = self.get_blocks_count() - self.s_first_data_block as u64;
+        (blocks as f64 / self.s_blocks_per_group as f64).ceil() as usize
+    }
+    // Total number of blocks
+    pub fn get_blocks_count(&self) -> u64 {
+        if self.s_feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+            self.s_blocks_count as u64 | (self.s_blocks_count_hi as u64) << 32
+        } else {
+            self.s_blocks_count as u64
+        }
+    }
+    // Number of unallocated blocks
+    pub fn get_free_blocks_count(&self) -> u64 {
+        if self.s_feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+            self.s_free_blocks_count as u64 | (self.s_free_blocks_count_hi as u64) << 32
+        } else {
+            self.s_free_blocks_count as u64
+        }
+    }
+    // Number of blocks reserved for the superuser
+    pub fn get_r_blocks_count(&self) -> u64 {
+        if self.s_feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+            self.s_r_blocks_count as u64 | (self.s_r_blocks_count_hi as u64) << 32
+        } else {
+            self.s_r_blocks_count as u64
+        }
+    }
+    // First block of a group
+    pub fn get_group_first_block(&self, group_num: usize) -> u64 {
+        self.s_first_data_block as u64 + group_num as u64 * self.s_blocks_per_group as u64
+    }
+    // Number of blocks in a group (the last group can be smaller)
+    pub fn get_group_blocks_count(&self, group_num: usize) -> u64 {
+        let first = self.get_group_first_block(group_num);
+        (self.get_blocks_count() - first).min(self.s_blocks_per_group as u64)
+    }
+    // Number of blocks of the group descriptor table
+    pub fn get_gdt_blocks_count(&self) -> u64 {
+        let size = (self.get_groups_count() * self.get_desc_size()) as f64;
+        (size / self.get_block_size() as f64).ceil() as u64
+    }
+    // Returns true if the group contains a superblock backup
+    pub fn group_has_super(&self, group_num: usize) -> bool {
+        if group_num == 0 {
+            true
+        } else if self.s_feature_compat & EXT4_FEATURE_COMPAT_SPARSE_SUPER2 != 0 {
+            // Only two backups, in the groups listed in s_backup_bgs
+            self.s_backup_bgs.contains(&(group_num as u32))
+        } else if group_num == 1
+            || self.s_feature_ro_compat & EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER == 0
+        {
+            true
+        } else {
+            // With sparse_super, backups are in the groups powers of 3, 5 and 7
+            [3, 5, 7].iter().any(|base| {
+                let mut n = *base;
+                while n < group_num {
+                    n *= base;
+                }
+                n == group_num
+            })
+        }
     }
     // Get block size
     pub fn get_block_size(&self) -> u64 {
         1024 << self.s_log_block_size as u64
     }
+    // Get group descriptor size
+    pub fn get_desc_size(&self) -> usize {
+        if self.s_feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+            self.s_desc_size as usize
+        } else {
+            EXT2_MIN_DESC_SIZE
+        }
+    }
+    // Metadata checksums are enabled
+    pub fn has_metadata_csum(&self) -> bool {
+        self.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0
+    }
+    // Group descriptors checksums are enabled
+    pub fn has_gdt_csum(&self) -> bool {
+        self.s_feature_ro_compat & EXT4_FEATURE_RO_COMPAT_GDT_CSUM != 0
+    }
+    // Get the seed used by the metadata checksums
+    pub fn get_checksum_seed(&self) -> u32 {
+        if self.s_feature_incompat & EXT4_FEATURE_INCOMPAT_CSUM_SEED != 0 {
+            self.s_checksum_seed
+        } else {
+            crc32c_le(!0, &self.s_uuid)
+        }
+    }
+    // Names of the enabled features
+    pub fn get_feature_names(&self) -> Vec<String> {
+        let mut result = get_bit_names(self.s_feature_compat, &COMPAT_FEATURE_NAMES, "FEATURE_C");
+        result.extend(get_bit_names(
+            self.s_feature_incompat,
+            &INCOMPAT_FEATURE_NAMES,
+            "FEATURE_I",
+        ));
+        result.extend(get_bit_names(
+            self.s_feature_ro_compat,
+            &RO_COMPAT_FEATURE_NAMES,
+            "FEATURE_R",
+        ));
+        result
+    }
+    // Names of the miscellaneous flags
+    pub fn get_flag_names(&self) -> Vec<String> {
+        get_bit_names(self.s_flags, &FLAG_NAMES, "FLAG_")
+    }
+    // Names of the default mount options
+    pub fn get_default_mount_opts_names(&self) -> Vec<String> {
+        let mut result = get_bit_names(
+            self.s_default_mount_opts & !EXT4_DEFM_JMODE,
+            &DEFAULT_MOUNT_OPTS_NAMES,
+            "MNTOPT_",
+        );
+        match self.s_default_mount_opts & EXT4_DEFM_JMODE {
+            0x0020 => result.push(String::from("journal_data")),
+            0x0040 => result.push(String::from("journal_data_ordered")),
+            0x0060 => result.push(String::from("journal_data_writeback")),
+            _ => {}
+        }
+        result
+    }
+    // File system state
+    pub fn get_state_name(&self) -> String {
+        let mut result = String::from(if self.s_state & 0x0001 != 0 {
+            "clean"
+        } else {
+            "not clean"
+        });
+        if self.s_state & 0x0002 != 0 {
+            result.push_str(" with errors");
+        }
+        if self.s_state & 0x0004 != 0 {
+            result.push_str(", orphans being recovered");
+        }
+        result
+    }
+    // What to do when an error is detected
+    pub fn get_errors_name(&self) -> String {
+        match self.s_pad {
+            1 => String::from("Continue"),
+            2 => String::from("Remount read-only"),
+            3 => String::from("Panic"),
+            x => format!("Unknown (continue) {}", x),
+        }
+    }
+    // Operating system that created the file system
+    pub fn get_creator_os_name(&self) -> String {
+        match self.s_creator_os {
+            0 => String::from("Linux"),
+            1 => String::from("Hurd"),
+            2 => String::from("Masix"),
+            3 => String::from("FreeBSD"),
+            4 => String::from("Lites"),
+            x => format!("(unknown os) {}", x),
+        }
+    }
+    // Revision level
+    pub fn get_revision_name(&self) -> String {
+        match self.s_rev_level {
+            0 => String::from("0 (original)"),
+            1 => String::from("1 (dynamic)"),
+            x => format!("{} (unknown)", x),
+        }
+    }
+    // Default directory hash algorithm
+    pub fn get_hash_version_name(&self) -> String {
+        match self.s_def_hash_version {
+            0 => String::from("legacy"),
+            1 => String::from("half_md4"),
+            2 => String::from("tea"),
+            3 => String::from("legacy_unsigned"),
+            4 => String::from("half_md4_unsigned"),
+            5 => String::from("tea_unsigned"),
+            6 => String::from("siphash"),
+            x => format!("unknown ({})", x),
+        }
+    }
+    // Get the superblock raw bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        let p = self as *const _ as *const u8;
+        unsafe { slice::from_raw_parts(p, SUPER_BLOCK_SIZE as usize) }
+    }
     // Read the Superblock
     pub fn new(disk: &dyn Disk) -> Result<Ext2SuperBlock, Error> {
-        let mut super_block: Ext2SuperBlock = unsafe { mem::zeroed() };
-        assert_eq!(mem::size_of::<Ext2SuperBlock>(), SUPER_BLOCK_SIZE as usize);
         let offset = Offset::Block {
             block_size: SUPER_BLOCK_SIZE,
             block_num: SUPER_BLOCK,
         };
+        let super_block = Ext2SuperBlock::read(disk, offset)?;
+        super_block.check_geometry()?;
+        Ok(super_block)
+    }
+    // Check the values the layout of the filesystem is computed from
+    // The group descriptors of a 64-bit filesystem fill a block exactly
+    fn is_valid_desc_size(&self) -> bool {
+        let desc_size = self.get_desc_size();
+        desc_size >= EXT2_MIN_DESC_SIZE
+            && desc_size as u64 <= self.get_block_size()
+            && desc_size.is_power_of_two()
+    }
+    fn check_geometry(&self) -> Result<(), Error> {
+        if self.s_log_block_size > MAX_LOG_BLOCK_SIZE
+            || self.s_blocks_per_group == 0
+            || self.s_inodes_per_group == 0
+            || self.s_first_data_block as u64 >= self.get_blocks_count()
+            || !self.is_valid_desc_size()
+        {
+            Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid filesystem geometry",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+    // Read a Superblock at the given offset
+    fn read(disk: &dyn Disk, offset: Offset) -> Result<Ext2SuperBlock, Error> {
+        let mut super_block: Ext2SuperBlock = unsafe { mem::zeroed() };
+        assert_eq!(mem::size_of::<Ext2SuperBlock>(), SUPER_BLOCK_SIZE as usize);
         let buffer = disk.read(SUPER_BLOCK_SIZE, offset)?;
         let p = &mut super_block as *mut _ as *mut u8;
         unsafe {
@@ -89,4 +463,204 @@ impl Ext2SuperBlock {
             Err(Error::new(ErrorKind::InvalidData, "Invalid filesystem"))
         }
     }
+    // Read a backup Superblock at the given block number (the block size is guessed),
+    // returns the superblock and its group number
+    pub fn read_backup(disk: &dyn Disk, block_num: u64) -> Result<(Ext2SuperBlock, usize), Error> {
+        for log_block_size in 0..=MAX_LOG_BLOCK_SIZE {
+            let block_size = 1024 << log_block_size;
+            let offset = Offset::Block {
+                block_size,
+                block_num,
+            };
+            if let Ok(super_block) = Ext2SuperBlock::read(disk, offset) {
+                if super_block.s_log_block_size != log_block_size
+                    || super_block.s_blocks_per_group == 0
+                    || block_num < super_block.s_first_data_block as u64
+                {
+                    continue;
+                }
+                let first_data_block = super_block.s_first_data_block as u64;
+                let blocks_per_group = super_block.s_blocks_per_group as u64;
+                let group_num = ((block_num - first_data_block) / blocks_per_group) as usize;
+                // The superblock is stored at the beginning of the group
+                if super_block.get_group_first_block(group_num) != block_num
+                    || (super_block.s_rev_level > 0
+                        && super_block.s_block_group_nr as usize != group_num)
+                {
+                    continue;
+                }
+                return Ok((super_block, group_num));
+            }
+        }
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("No superblock found at block {}", block_num),
+        ))
+    }
+    // Look for a backup Superblock: in the group 1 and, with sparse_super,
+    // in the groups powers of 3, 5 and 7. Returns the superblock and its group number.
+    pub fn find_backup(disk: &dyn Disk) -> Result<(Ext2SuperBlock, usize), Error> {
+        let mut groups: Vec<u64> = vec![1];
+        for base in [3, 5, 7] {
+            let mut n = base;
+            while n < MAX_BACKUP_GROUP {
+                groups.push(n);
+                n *= base;
+            }
+        }
+        groups.sort_unstable();
+        for group_num in groups {
+            for log_block_size in 0..=MAX_LOG_BLOCK_SIZE {
+                // Default layout: 8 * block_size blocks per group
+                let block_size: u64 = 1024 << log_block_size;
+                let first_data_block = if block_size == 1024 { 1 } else { 0 };
+                let block_num = first_data_block + group_num * block_size * 8;
+                if let Ok(result) = Ext2SuperBlock::read_backup(disk, block_num) {
+                    return Ok(result);
+                }
+            }
+        }
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "Invalid filesystem (no backup superblock found)",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::FileDisk;
+    use crate::ext2::checksum::ChecksumMode;
+    use crate::ext2::Ext2Filesystem;
+    use crate::fs::MountOptions;
+    use crate::test_util::{mount_image, patch_image, read_file, unpack_image};
+
+    const BACKUP_BLOCK: u64 = 8193; // Backup superblock of ext2.img.gz (group 1)
+
+    #[test]
+    fn test_bit_names() {
+        let names = [(0x1, "one"), (0x4, "four")];
+        assert_eq!(
+            get_bit_names(0x15, &names, "BIT_"),
+            ["one", "four", "BIT_4"]
+        );
+        assert!(get_bit_names(0, &names, "BIT_").is_empty());
+    }
+
+    #[test]
+    fn test_names() {
+        // Same names as dumpe2fs
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let sb = fs.get_super_block();
+        assert_eq!(
+            sb.get_feature_names().join(" "),
+            "ext_attr resize_inode dir_index filetype sparse_super large_file"
+        );
+        assert_eq!(sb.get_flag_names(), ["signed_directory_hash"]);
+        assert_eq!(sb.get_default_mount_opts_names(), ["user_xattr", "acl"]);
+        assert_eq!(sb.get_state_name(), "clean");
+        assert_eq!(sb.get_errors_name(), "Continue");
+        assert_eq!(sb.get_creator_os_name(), "Linux");
+        assert_eq!(sb.get_revision_name(), "1 (dynamic)");
+        assert_eq!(sb.get_hash_version_name(), "half_md4");
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        assert_eq!(
+            fs.get_super_block().get_feature_names().join(" "),
+            "ext_attr dir_index filetype extent 64bit flex_bg inline_data sparse_super \
+             large_file huge_file dir_nlink extra_isize metadata_csum"
+        );
+    }
+
+    #[test]
+    fn test_state_name() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let mut sb = fs.get_super_block().clone();
+        sb.s_state = 0x0006;
+        assert_eq!(
+            sb.get_state_name(),
+            "not clean with errors, orphans being recovered"
+        );
+        sb.s_default_mount_opts = 0x0060;
+        assert_eq!(
+            sb.get_default_mount_opts_names(),
+            ["journal_data_writeback"]
+        );
+        sb.s_rev_level = 7;
+        assert_eq!(sb.get_revision_name(), "7 (unknown)");
+    }
+
+    #[test]
+    fn test_read_backup() {
+        let disk = FileDisk::open(&unpack_image("ext2.img.gz")).unwrap();
+        let (super_block, group_num) = Ext2SuperBlock::read_backup(&disk, BACKUP_BLOCK).unwrap();
+        assert_eq!(group_num, 1);
+        assert_eq!(super_block.s_block_group_nr, 1);
+        assert_eq!(
+            super_block.s_uuid,
+            Ext2SuperBlock::new(&disk).unwrap().s_uuid
+        );
+        let (_, group_num) = Ext2SuperBlock::find_backup(&disk).unwrap();
+        assert_eq!(group_num, 1);
+        assert!(Ext2SuperBlock::read_backup(&disk, BACKUP_BLOCK + 1).is_err());
+        // No backup in a filesystem with a single group
+        let disk = FileDisk::open(&unpack_image("ext4.img.gz")).unwrap();
+        assert!(Ext2SuperBlock::find_backup(&disk).is_err());
+    }
+
+    #[test]
+    fn test_mount_backup() {
+        let options = MountOptions {
+            superblock: BACKUP_BLOCK,
+            ..Default::default()
+        };
+        let fs = mount_image("ext2.img.gz", &options);
+        assert_eq!(fs.get_super_block_group(), 1);
+        assert_eq!(read_file(&fs, "/hello.txt").unwrap(), b"Hello, world!\n");
+        // Primary superblock with a bad magic (s_magic) or a bad geometry (s_blocks_per_group)
+        for (offset, bytes) in [(1024 + 0x38, [0; 2]), (1024 + 0x20, [0; 2])] {
+            let image = patch_image("ext2.img.gz", &[(offset, &bytes)]);
+            let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+            assert_eq!(fs.get_super_block_group(), 1);
+            assert_eq!(read_file(&fs, "/hello.txt").unwrap(), b"Hello, world!\n");
+        }
+    }
+
+    #[test]
+    fn test_invalid_desc_size() {
+        // s_desc_size of a 64-bit filesystem (ext4.img.gz has no backup superblock)
+        for desc_size in [0u16, 8, 48, 2048] {
+            let image = patch_image("ext4.img.gz", &[(1024 + 0xfe, &desc_size.to_le_bytes())]);
+            let options = MountOptions {
+                checksum_mode: ChecksumMode::Ignore,
+                ..Default::default()
+            };
+            let Err(err) = Ext2Filesystem::mount(&image.path, &options) else {
+                panic!("mounted with a descriptor size of {}", desc_size);
+            };
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_checksum_no_fallback() {
+        // Enable metadata_csum in the primary superblock only: the checksum is wrong
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let ro_compat = fs.get_super_block().s_feature_ro_compat | 0x400;
+        let image = patch_image("ext2.img.gz", &[(1024 + 0x64, &ro_compat.to_le_bytes())]);
+        let mut options = MountOptions {
+            checksum_mode: ChecksumMode::Strict,
+            ..Default::default()
+        };
+        let Err(err) = Ext2Filesystem::mount(&image.path, &options) else {
+            panic!("mounted with a wrong superblock checksum");
+        };
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("superblock checksum"), "{}", err);
+        // Not repaired in memory from the backup either
+        options.checksum_mode = ChecksumMode::Ignore;
+        let fs = Ext2Filesystem::mount(&image.path, &options).unwrap();
+        assert_eq!(fs.get_super_block_group(), 0);
+        assert!(fs.get_super_block().has_metadata_csum());
+    }
 }
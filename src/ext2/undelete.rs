@@ -0,0 +1,259 @@
+use crate::disk::Offset;
+use crate::ext2::bitmap::Ext2Bitmap;
+use crate::ext2::group::EXT2_BG_INODE_UNINIT;
+use crate::ext2::inode::{Ext2BlockKind, Ext2BlockRef, Ext2Inode};
+use crate::ext2::Ext2Filesystem;
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+/// Inode with a deletion time and its block pointers still in place
+#[derive(Debug)]
+pub struct Ext2DeletedInode {
+    pub inode_num: u64,         // Inode number
+    pub mode: u16,              // File mode
+    pub uid: u32,               // Owner Uid
+    pub gid: u32,               // Group Id
+    pub size: u64,              // Size in bytes
+    pub dtime: i64,             // Deletion time
+    pub blocks_count: u64,      // Number of data blocks
+    pub free_blocks_count: u64, // Number of data blocks not reallocated (recoverable)
+}
+
+/// Block bitmaps, read on demand
+struct BlockBitmaps<'a> {
+    fs: &'a Ext2Filesystem,
+    bitmaps: HashMap<usize, Ext2Bitmap>,
+}
+
+impl BlockBitmaps<'_> {
+    fn new(fs: &Ext2Filesystem) -> BlockBitmaps<'_> {
+        BlockBitmaps {
+            fs,
+            bitmaps: HashMap::new(),
+        }
+    }
+
+    /// Returns true if the block is not allocated
+    fn is_free(&mut self, block_num: u64) -> Result<bool, Error> {
+        let super_block = &self.fs.super_block;
+        let first_data_block = super_block.s_first_data_block as u64;
+        if block_num < first_data_block || block_num >= super_block.get_blocks_count() {
+            return Ok(false);
+        }
+        let group_num =
+            ((block_num - first_data_block) / super_block.s_blocks_per_group as u64) as usize;
+        if !self.bitmaps.contains_key(&group_num) {
+            let bitmap = self
+                .fs
+                .block_groups
+                .get_block_bitmap(self.fs.disk.as_ref(), group_num)?;
+            self.bitmaps.insert(group_num, bitmap);
+        }
+        Ok(self.bitmaps[&group_num].is_free(block_num))
+    }
+}
+
+impl Ext2Filesystem {
+    /// Scan the inode tables for deleted inodes with intact block pointers
+    pub fn get_deleted_inodes(&self) -> Result<Vec<Ext2DeletedInode>, Error> {
+        let mut result: Vec<Ext2DeletedInode> = Vec::new();
+        let mut bitmaps = BlockBitmaps::new(self);
+        let inode_size = self.super_block.s_inode_size as u64;
+        let block_size = self.super_block.get_block_size();
+        let inodes_per_group = self.super_block.s_inodes_per_group as u64;
+        let csum = self.super_block.has_metadata_csum() || self.super_block.has_gdt_csum();
+        for group in self.block_groups.iter() {
+            let desc = &group.ext2_group_desc;
+            let mut count = inodes_per_group;
+            if csum {
+                if desc.bg_flags & EXT2_BG_INODE_UNINIT != 0 {
+                    continue;
+                }
+                count -= desc.get_itable_unused().min(count);
+            }
+            let offset = Offset::Block {
+                block_size,
+                block_num: desc.get_inode_table(),
+            };
+            let table = self.disk.read(count * inode_size, offset)?;
+            for (i, buffer) in table.chunks(inode_size as usize).enumerate() {
+                let inode_num = group.first_inode_num + i as u64;
+                if let Some(deleted) = self.check_deleted_inode(inode_num, buffer, &mut bitmaps) {
+                    result.push(deleted);
+                }
+            }
+        }
+        result.sort_by_key(|x| (x.dtime, x.inode_num));
+        Ok(result)
+    }
+
+    /// Returns the deleted inode if it can be recovered
+    fn check_deleted_inode(
+        &self,
+        inode_num: u64,
+        buffer: &[u8],
+        bitmaps: &mut BlockBitmaps<'_>,
+    ) -> Option<Ext2DeletedInode> {
+        let inode = Ext2Inode::from_buffer(
+            self.disk.as_ref(),
+            buffer,
+            self.super_block.get_block_size(),
+            &self.checksum,
+            inode_num,
+        )
+        .ok()?;
+        let ext2_inode = inode.get_ext2_inode();
+        if ext2_inode.i_dtime == 0 || ext2_inode.i_links_count != 0 {
+            return None;
+        }
+        let blocks = self.get_data_blocks(&inode).ok()?;
+        if blocks.is_empty() && !inode.has_inline_data() {
+            // The block pointers were cleared
+            return None;
+        }
+        let mut free_blocks_count = 0;
+        for block in blocks.iter() {
+            if bitmaps.is_free(block.block_num).ok()? {
+                free_blocks_count += 1;
+            }
+        }
+        Some(Ext2DeletedInode {
+            inode_num,
+            mode: ext2_inode.i_mode,
+            uid: ext2_inode.i_uid as u32 | (ext2_inode.l_i_uid_high as u32) << 16,
+            gid: ext2_inode.i_gid as u32 | (ext2_inode.l_i_gid_high as u32) << 16,
+            size: inode.get_ext2_inode().size(),
+            dtime: ext2_inode.i_dtime as i64,
+            blocks_count: blocks.len() as u64,
+            free_blocks_count,
+        })
+    }
+
+    /// Data blocks of an inode, sorted by file block number.
+    /// Fails if a pointer is out of range (the block pointers are not intact).
+    fn get_data_blocks(&self, inode: &Ext2Inode) -> Result<Vec<Ext2BlockRef>, Error> {
+        let blocks_count = self.super_block.get_blocks_count();
+        let mut blocks = Vec::new();
+        for block in inode.get_block_map(self.disk.as_ref(), blocks_count)? {
+            if block.block_num < self.super_block.s_first_data_block as u64
+                || block.block_num >= blocks_count
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("block {} is out of range", block.block_num),
+                ));
+            }
+            if block.kind == Ext2BlockKind::Data {
+                blocks.push(block);
+            }
+        }
+        blocks.sort_by_key(|x| x.file_block_num);
+        Ok(blocks)
+    }
+
+    /// Write the content of a deleted inode, returns the number of data blocks
+    /// reallocated since the deletion (their content is probably lost)
+    pub fn undelete(&self, inode_num: u64, writer: &mut dyn Write) -> Result<u64, Error> {
+        if inode_num == 0 || inode_num > self.super_block.s_inodes_count as u64 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid inode number"));
+        }
+        let inode = self.read_inode(inode_num)?;
+        if inode.get_ext2_inode().i_links_count != 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Inode is in use"));
+        }
+        let size = inode.get_ext2_inode().size();
+        if inode.has_inline_data() {
+            writer.write_all(&inode.read(&self.disk)?)?;
+            return Ok(0);
+        }
+        let block_size = self.super_block.get_block_size();
+        let mut bitmaps = BlockBitmaps::new(self);
+        let mut reallocated = 0;
+        let mut position: u64 = 0; // Bytes written
+        for block in self.get_data_blocks(&inode)? {
+            let start = block.file_block_num * block_size;
+            if start >= size {
+                break;
+            }
+            if !bitmaps.is_free(block.block_num)? {
+                reallocated += 1;
+            }
+            // Fill the holes with zeros
+            if start > position {
+                io::copy(&mut io::repeat(0).take(start - position), writer)?;
+            }
+            let offset = Offset::Block {
+                block_size,
+                block_num: block.block_num,
+            };
+            let buffer = self.disk.read(block_size, offset)?;
+            let len = block_size.min(size - start) as usize;
+            writer.write_all(&buffer[..len])?;
+            position = start + len as u64;
+        }
+        if size > position {
+            io::copy(&mut io::repeat(0).take(size - position), writer)?;
+        }
+        Ok(reallocated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MountOptions;
+    use crate::test_util::{mount_image, patch_image, read_image};
+
+    const DELETED: &[u8] = b"This file will be deleted\n";
+
+    #[test]
+    fn test_deleted_inodes() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let deleted = fs.get_deleted_inodes().unwrap();
+        assert_eq!(deleted.len(), 1, "{:?}", deleted);
+        let inode = &deleted[0];
+        assert_eq!(inode.inode_num, 13);
+        assert_eq!(inode.mode & 0xf000, 0x8000);
+        assert_eq!(inode.size, DELETED.len() as u64);
+        assert_eq!(inode.dtime, 1700000000);
+        assert_eq!(inode.blocks_count, 1);
+        assert_eq!(inode.free_blocks_count, 1);
+        let fs = mount_image("ext4.img.gz", &MountOptions::default());
+        assert!(fs.get_deleted_inodes().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_undelete() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let mut content = Vec::new();
+        assert_eq!(fs.undelete(13, &mut content).unwrap(), 0);
+        assert_eq!(content, DELETED);
+        for inode_num in [0, 16, 1_000_000] {
+            let err = fs.undelete(inode_num, &mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_undelete_reallocated() {
+        // Mark the block of the deleted file as used again
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let inode = fs.read_inode(13).unwrap();
+        let block_num = fs.get_data_blocks(&inode).unwrap()[0].block_num;
+        let super_block = &fs.super_block;
+        let i = block_num - super_block.s_first_data_block as u64;
+        let bitmap = fs
+            .block_groups
+            .get_group(0)
+            .ext2_group_desc
+            .get_block_bitmap();
+        let offset = bitmap * super_block.get_block_size() + i / 8;
+        let byte = read_image("ext2.img.gz")[offset as usize];
+        let image = patch_image("ext2.img.gz", &[(offset, &[byte | 1 << (i % 8)])]);
+        let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+        assert_eq!(fs.get_deleted_inodes().unwrap()[0].free_blocks_count, 0);
+        let mut content = Vec::new();
+        assert_eq!(fs.undelete(13, &mut content).unwrap(), 1);
+        assert_eq!(content, DELETED);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::disk::{Disk, Offset};
+use crate::ext2::checksum::Ext2Checksum;
+use crate::ext2::{read_u16, read_u32};
+use std::io::Error;
+use std::io::ErrorKind;
+
+const EXT2_XATTR_MAGIC: u32 = 0xea020000;
+const EXT2_XATTR_BLOCK_HEADER_SIZE: usize = 32;
+const EXT2_XATTR_ENTRY_SIZE: usize = 16;
+const EXT2_GOOD_OLD_INODE_SIZE: usize = 128;
+
+// Attribute name indexes
+pub const EXT2_XATTR_INDEX_USER: u8 = 1;
+pub const EXT2_XATTR_INDEX_POSIX_ACL_ACCESS: u8 = 2;
+pub const EXT2_XATTR_INDEX_POSIX_ACL_DEFAULT: u8 = 3;
+pub const EXT2_XATTR_INDEX_TRUSTED: u8 = 4;
+pub const EXT2_XATTR_INDEX_SECURITY: u8 = 6;
+pub const EXT2_XATTR_INDEX_SYSTEM: u8 = 7;
+pub const EXT2_XATTR_INDEX_RICHACL: u8 = 8;
+
+/// Extended attribute
+#[derive(Debug)]
+pub struct Ext2XattrEntry {
+    pub name_index: u8, // Attribute name index
+    pub name: String,   // Attribute name (without prefix)
+    pub value: Vec<u8>, // Attribute value
+}
+
+impl Ext2XattrEntry {
+    /// Returns the attribute name, including the namespace prefix
+    pub fn full_name(&self) -> String {
+        let prefix = match self.name_index {
+            EXT2_XATTR_INDEX_USER => "user.",
+            EXT2_XATTR_INDEX_POSIX_ACL_ACCESS => "system.posix_acl_access",
+            EXT2_XATTR_INDEX_POSIX_ACL_DEFAULT => "system.posix_acl_default",
+            EXT2_XATTR_INDEX_TRUSTED => "trusted.",
+            EXT2_XATTR_INDEX_SECURITY => "security.",
+            EXT2_XATTR_INDEX_SYSTEM => "system.",
+            EXT2_XATTR_INDEX_RICHACL => "system.richacl",
+            _ => "",
+        };
+        format!("{}{}", prefix, self.name)
+    }
+
+    /// Returns true if the index and the name match
+    pub fn is(&self, name_index: u8, name: &str) -> bool {
+        self.name_index == name_index && self.name == name
+    }
+}
+
+/// Parse a list of entries; the value offsets are relative to values_base
+fn parse_entries(
+    buffer: &[u8],
+    entries_offset: usize,
+    values_base: usize,
+) -> Result<Vec<Ext2XattrEntry>, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "Invalid extended attribute");
+    let mut entries: Vec<Ext2XattrEntry> = Vec::new();
+    let mut offset = entries_offset;
+    // The list is terminated by four zero bytes
+    while offset + 4 <= buffer.len() && read_u32(buffer, offset) != 0 {
+        if offset + EXT2_XATTR_ENTRY_SIZE > buffer.len() {
+            return Err(invalid());
+        }
+        let name_len = buffer[offset] as usize;
+        let name_index = buffer[offset + 1];
+        let value_offs = read_u16(buffer, offset + 2) as usize;
+        let value_inum = read_u32(buffer, offset + 4);
+        let value_size = read_u32(buffer, offset + 8) as usize;
+        let name_start = offset + EXT2_XATTR_ENTRY_SIZE;
+        let name = buffer
+            .get(name_start..name_start + name_len)
+            .ok_or_else(invalid)?;
+        // Values stored in a separate inode (ea_inode feature) are not loaded
+        let value = if value_inum != 0 {
+            Vec::new()
+        } else {
+            let value_start = values_base + value_offs;
+            buffer
+                .get(value_start..value_start + value_size)
+                .ok_or_else(invalid)?
+                .to_vec()
+        };
+        entries.push(Ext2XattrEntry {
+            name_index,
+            name: String::from_utf8_lossy(name).into_owned(),
+            value,
+        });
+        // Entries are aligned to 4 bytes
+        offset = (name_start + name_len + 3) & !3;
+    }
+    Ok(entries)
+}
+
+/// Read the extended attributes stored in the inode, after the extra fields
+pub fn read_inode_xattrs(inode_buffer: &[u8]) -> Result<Vec<Ext2XattrEntry>, Error> {
+    if inode_buffer.len() <= EXT2_GOOD_OLD_INODE_SIZE + 4 {
+        return Ok(Vec::new());
+    }
+    let extra_isize = read_u16(inode_buffer, EXT2_GOOD_OLD_INODE_SIZE) as usize;
+    let header = EXT2_GOOD_OLD_INODE_SIZE + extra_isize;
+    if header + 4 > inode_buffer.len() || read_u32(inode_buffer, header) != EXT2_XATTR_MAGIC {
+        return Ok(Vec::new());
+    }
+    // The value offsets are relative to the first entry
+    parse_entries(inode_buffer, header + 4, header + 4)
+}
+
+/// Read the extended attributes stored in a block, verifying the checksum
+pub fn read_block_xattrs(
+    disk: &dyn Disk,
+    block_size: u64,
+    block_num: u64,
+    checksum: &Ext2Checksum,
+) -> Result<Vec<Ext2XattrEntry>, Error> {
+    let offset = Offset::Block {
+        block_size,
+        block_num,
+    };
+    let buffer = disk.read(block_size, offset)?;
+    if read_u32(&buffer, 0) != EXT2_XATTR_MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Invalid extended attribute block",
+        ));
+    }
+    checksum.report(checksum.verify_xattr_block(block_num, &buffer))?;
+    // The value offsets are relative to the block start
+    parse_entries(&buffer, EXT2_XATTR_BLOCK_HEADER_SIZE, 0)
+}
@@ -1,52 +1,80 @@
 use crate::disk::{Disk, Offset};
 use crate::fs::Filesystem;
 use crate::inode::Inode;
-use std::io::{/*BufRead,*/ Error, Read};
+use std::borrow::Cow;
+use std::io::{BufRead, Error, Read};
 
 pub struct FsFile<'a> {
-    disk: &'a Box<dyn Disk>,
+    disk: &'a dyn Disk,
     inode: Box<dyn Inode>,
     blocks: Vec<u64>,
     pos: u64,
+    buffer: Cow<'a, [u8]>, // Data of the current blocks
+    buffer_pos: usize,     // Start of the data not consumed yet in buffer (at pos)
+    read_ahead: u64,       // Maximum size of a read, in bytes
 }
 
-impl FsFile<'_> {
-    pub fn new<'a>(disk: &'a Box<dyn Disk>, inode: Box<dyn Inode>, blocks: Vec<u64>) -> FsFile<'a> {
+impl<'a> FsFile<'a> {
+    pub fn new(
+        disk: &'a dyn Disk,
+        inode: Box<dyn Inode>,
+        blocks: Vec<u64>,
+        read_ahead: u64,
+    ) -> FsFile<'a> {
         FsFile {
             disk: disk,
             inode: inode,
             blocks: blocks,
             pos: 0,
+            buffer: Cow::Borrowed(&[]),
+            buffer_pos: 0,
+            read_ahead,
         }
     }
 
     /// Attempts to open a file
-    pub fn open<'a>(fs: &'a dyn Filesystem, path: &str) -> Result<FsFile<'a>, Error> {
+    pub fn open(fs: &'a dyn Filesystem, path: &str) -> Result<FsFile<'a>, Error> {
         fs.open(path)
     }
 
-    fn read_file_block(&mut self, file_block_num: u64) -> Result<Vec<u8>, Error> {
-        let offset = Offset::Block {
-            block_size: self.inode.get_block_size(),
-            block_num: self.blocks[file_block_num as usize],
-        };
-        self.disk.read(self.inode.get_block_size(), offset)
-    }
-
-    fn how_many_bytes(&self, buffer_len: usize) -> usize {
-        if self.pos + buffer_len as u64 > self.inode.get_size() {
-            (self.inode.get_size() - self.pos) as usize
-        } else {
-            buffer_len
+    /// Data of a file block and of the physically contiguous blocks (or holes) following it,
+    /// up to the read-ahead size, borrowed from the disk when possible
+    fn read_file_blocks(&self, file_block_num: u64) -> Result<Cow<'a, [u8]>, Error> {
+        let block_size = self.inode.get_block_size();
+        if let Some(data) = self.inode.get_inline_data() {
+            // Small files can be stored inside the inode
+            let mut buffer = data.to_vec();
+            buffer.resize(block_size as usize, 0);
+            return Ok(Cow::Owned(buffer));
         }
-    }
-
-    /// If len < buffer length, fill the buffer with 0
-    fn zero_padding(&self, read_bytes: usize, buffer_len: usize, buffer: &mut Vec<u8>) {
-        if read_bytes < buffer_len {
-            let zero: Vec<u8> = vec![0; buffer_len - read_bytes];
-            buffer.extend_from_slice(&zero);
+        // Blocks missing from the block map are holes
+        let get_block = |i: u64| self.blocks.get(i as usize).copied().unwrap_or(0);
+        let first = get_block(file_block_num);
+        let max_count = (self.read_ahead / block_size).max(1);
+        let end = (file_block_num + max_count).min(self.inode.get_size().div_ceil(block_size));
+        let mut count = 1;
+        while file_block_num + count < end {
+            let block_num = get_block(file_block_num + count);
+            let contiguous = if first == 0 {
+                block_num == 0
+            } else {
+                block_num == first + count
+            };
+            if !contiguous {
+                break;
+            }
+            count += 1;
+        }
+        if first == 0 {
+            // Hole in a sparse file
+            return Ok(Cow::Owned(vec![0; (count * block_size) as usize]));
         }
+        let offset = Offset::Block {
+            block_size,
+            block_num: first,
+        };
+        let disk: &'a dyn Disk = self.disk;
+        disk.read_slice(count * block_size, offset)
     }
 
     /// Is EOL ?
@@ -59,32 +87,167 @@ impl Read for FsFile<'_> {
     /// Pull some bytes from this file into the specified buffer,
     /// returning how many bytes were read
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.is_eol() {
-            // End of file
-            let zero: Vec<u8> = vec![0; buf.len()];
-            buf.copy_from_slice(&zero[..]);
-            Ok(0)
-        } else {
-            let buffer_len = buf.len();
-            let read_bytes = self.how_many_bytes(buffer_len);
-            let block_num = (self.pos / self.inode.get_block_size()) as u64;
-            let mut buffer = self.read_file_block(block_num)?;
-            self.zero_padding(read_bytes, buffer_len, &mut buffer);
-            let block_pos: usize =
-                (self.pos - block_num as u64 * self.inode.get_block_size()) as usize;
-            // println!("block num: {} read_bytes: {} pos: {}", block_num, read_bytes, pos);
-            buf.copy_from_slice(&buffer[block_pos..block_pos + buffer_len]);
-            self.pos += read_bytes as u64;
-            Ok(read_bytes)
+        let data = self.fill_buf()?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<'a> BufRead for FsFile<'a> {
+    /// Rest of the current run of contiguous blocks, without copy if the disk is memory-mapped
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.buffer_pos == self.buffer.len() && !self.is_eol() {
+            let block_size = self.inode.get_block_size();
+            let block_num = self.pos / block_size;
+            let start = (self.pos % block_size) as usize;
+            let data = self.read_file_blocks(block_num)?;
+            let remaining = self.inode.get_size() - block_num * block_size;
+            let end = (data.len() as u64).min(remaining) as usize;
+            self.buffer = match data {
+                Cow::Borrowed(data) => Cow::Borrowed(&data[..end]),
+                Cow::Owned(mut data) => {
+                    data.truncate(end);
+                    Cow::Owned(data)
+                }
+            };
+            self.buffer_pos = start;
         }
+        Ok(&self.buffer[self.buffer_pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = amt.min(self.buffer.len() - self.buffer_pos);
+        self.buffer_pos += amt;
+        self.pos += amt as u64;
     }
 }
 
-// impl BufRead for FsFile<'_> {
-//     fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
-//         Err(Error::new(ErrorKind::NotFound, "TODO"))
-//     }
-//
-//     fn consume(&mut self, amt: usize) {
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext2::Ext2Filesystem;
+    use crate::fs::MountOptions;
+    use crate::test_util::{block_pattern, mount_image, patch_image, unpack_image};
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_read() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let mut file = FsFile::open(&fs, "/big.txt").unwrap();
+        let mut data = Vec::new();
+        let mut buffer = [0; 1000];
+        loop {
+            let len = file.read(&mut buffer).unwrap();
+            if len == 0 {
+                break;
+            }
+            data.extend_from_slice(&buffer[..len]);
+        }
+        assert_eq!(data, block_pattern(300).as_bytes());
+        let mut sparse = Vec::new();
+        FsFile::open(&fs, "/sparse")
+            .unwrap()
+            .read_to_end(&mut sparse)
+            .unwrap();
+        assert_eq!(sparse.len(), 300 * 1024 + 4);
+        assert_eq!(&sparse[100 * 1024..][..18], b"data after a hole\n");
+        assert!(sparse[..100 * 1024].iter().all(|x| *x == 0));
+    }
+
+    #[test]
+    fn test_lines() {
+        let fs = mount_image("ext2.img.gz", &MountOptions::default());
+        let file = FsFile::open(&fs, "/big.txt").unwrap();
+        let lines: Vec<String> = file.lines().map(|x| x.unwrap()).collect();
+        assert_eq!(lines.len(), 300);
+        assert!(lines[299].starts_with("block 000299 ..."));
+    }
+
+    #[test]
+    fn test_zero_copy() {
+        // The first 12 blocks of big.txt are contiguous
+        let image = patch_image("ext2.img.gz", &[]);
+        let fs = Ext2Filesystem::mount(&image.path, &MountOptions::default()).unwrap();
+        drop(image);
+        let expected = block_pattern(13);
+        let mut file = FsFile::open(&fs, "/big.txt").unwrap();
+        assert_eq!(file.fill_buf().unwrap(), &expected.as_bytes()[..12 * 1024]);
+        assert!(matches!(file.buffer, Cow::Borrowed(_)));
+        file.consume(1000);
+        assert_eq!(
+            file.fill_buf().unwrap(),
+            &expected.as_bytes()[1000..12 * 1024]
+        );
+        file.consume(usize::MAX);
+        assert_eq!(file.pos, 12 * 1024);
+        assert_eq!(
+            file.fill_buf().unwrap()[..1024],
+            expected.as_bytes()[12 * 1024..]
+        );
+    }
+
+    /// Sizes of the successive reads of a file
+    fn read_sizes(fs: &Ext2Filesystem, path: &str) -> Vec<usize> {
+        let mut file = FsFile::open(fs, path).unwrap();
+        let mut sizes = Vec::new();
+        loop {
+            let len = file.fill_buf().unwrap().len();
+            if len == 0 {
+                return sizes;
+            }
+            sizes.push(len);
+            file.consume(len);
+        }
+    }
+
+    fn mount_read_ahead(read_ahead: Option<u64>) -> Ext2Filesystem {
+        let options = MountOptions {
+            read_ahead,
+            ..Default::default()
+        };
+        mount_image("ext2.img.gz", &options)
+    }
+
+    #[test]
+    fn test_read_ahead() {
+        // big.txt: 12 contiguous blocks, then 256 and 32 (after the indirect blocks)
+        let fs = mount_read_ahead(None);
+        let sizes = read_sizes(&fs, "/big.txt");
+        assert_eq!(sizes, [12 * 1024, 128 * 1024, 128 * 1024, 32 * 1024]);
+        let fs = mount_read_ahead(Some(4));
+        let sizes = read_sizes(&fs, "/big.txt");
+        assert_eq!(sizes.len(), 3 + 64 + 8);
+        assert!(sizes.iter().all(|x| *x == 4 * 1024));
+        // At least one block at a time
+        let fs = mount_read_ahead(Some(0));
+        assert_eq!(read_sizes(&fs, "/big.txt"), [1024; 300]);
+    }
+
+    #[test]
+    fn test_read_ahead_holes() {
+        // sparse: data in the blocks 100 and 300, the holes are read at once
+        let fs = mount_read_ahead(None);
+        let sizes = read_sizes(&fs, "/sparse");
+        assert_eq!(sizes, [100 * 1024, 1024, 128 * 1024, 71 * 1024, 4]);
+        let fs = mount_read_ahead(Some(1024));
+        let sizes = read_sizes(&fs, "/sparse");
+        assert_eq!(sizes, [100 * 1024, 1024, 199 * 1024, 4]);
+    }
+
+    #[test]
+    fn test_read_ahead_overflow() {
+        let options = MountOptions {
+            read_ahead: Some(u64::MAX),
+            ..Default::default()
+        };
+        let Err(err) = Ext2Filesystem::mount(&unpack_image("ext2.img.gz"), &options) else {
+            panic!("read-ahead overflow accepted");
+        };
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        // The largest size is accepted
+        let fs = mount_read_ahead(Some(u64::MAX >> 10));
+        assert_eq!(read_sizes(&fs, "/sparse").len(), 4);
+    }
+}
@@ -1,23 +1,50 @@
 use crate::dir::DirEntry;
+use crate::disk::compressed::{CompressedDisk, CompressionFormat};
+use crate::disk::overlay::{Overlay, OverlayDisk};
+use crate::disk::partition::{PartitionDisk, PartitionTable};
+use crate::disk::qcow2::{Qcow2Disk, QCOW2_MAGIC};
+use crate::disk::split::open_segments;
+use crate::disk::vhd::{open_vhd, read_vhd_footer};
+use crate::disk::vhdx::{VhdxDisk, VHDX_SIGNATURE};
+use crate::disk::vmdk::{VmdkDisk, VMDK_MAGIC};
+use crate::disk::{Disk, Offset};
+use crate::ext2::checksum::ChecksumMode;
 use crate::ext2::Ext2Filesystem;
 use crate::file::FsFile;
 use crate::metadata::Metadata;
 use std::collections::BTreeMap;
-use std::io::{Error, Read};
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
+use std::sync::Arc;
 
-pub trait Filesystem {
+pub trait Filesystem: Send + Sync {
     /// Open a file
-    fn open(&self, path: &str) -> Result<FsFile, Error>;
+    fn open(&self, path: &str) -> Result<FsFile<'_>, Error>;
+    /// Open a file by inode number
+    fn open_inode(&self, inode_num: u64) -> Result<FsFile<'_>, Error>;
     /// Get block size
     fn get_block_size(&self) -> u64;
     /// Get the number of blocks in file system
     fn get_blocks_count(&self) -> u64;
     /// Get the number of unallocated blocks
     fn get_free_blocks_count(&self) -> u64;
+    /// Get the number of inodes in file system
+    fn get_inodes_count(&self) -> u64;
+    /// Get the number of unallocated inodes (counted in the inode bitmaps)
+    fn get_free_inodes_count(&self) -> Result<u64, Error>;
     /// Read the contents of a given directory
     fn read_dir(&self, path: &str) -> Result<BTreeMap<String, Box<dyn DirEntry>>, Error>;
+    /// Read the contents of a directory by inode number
+    fn read_dir_by_inode(
+        &self,
+        inode_num: u64,
+    ) -> Result<BTreeMap<String, Box<dyn DirEntry>>, Error>;
+    /// Read the deleted entries still present in a given directory
+    fn read_deleted_dir(&self, path: &str) -> Result<Vec<Box<dyn DirEntry>>, Error>;
     /// Given a path, query the file system to get information about a file, directory, etc.
     fn metadata(&self, path: &str) -> Result<Metadata, Error>;
+    /// Query the metadata of an inode (symbolic links are not followed)
+    fn metadata_by_inode(&self, inode_num: u64) -> Result<Metadata, Error>;
     /// Like stat, except that if path is a symbolic link, then the link itself is stat-ed,
     /// not the file that it refers to.
     fn symlink_metadata(&self, path: &str) -> Result<Metadata, Error>;
@@ -49,6 +76,127 @@ impl dyn Filesystem {
     }
 }
 
-pub fn mount(filename: &str) -> Result<Box<dyn Filesystem>, Error> {
-    Ok(Box::new(Ext2Filesystem::mount(filename)?))
+#[derive(Debug, Default, Clone)]
+pub struct MountOptions {
+    pub checksum_mode: ChecksumMode, // What to do when a metadata checksum does not match
+    pub read_write: bool,            // Open the device for writing
+    pub superblock: u64,             // Block number of the superblock to use (0 = primary)
+    pub partition: u32,              // Number of the partition to open (0 = whole disk)
+    pub offset: u64,                 // Offset of the filesystem in the disk, in bytes
+    pub cache_size: Option<u64>,     // Size of the block cache in MiB (None = default)
+    pub read_ahead: Option<u64>,     // Maximum size of a file read in KiB (None = default)
+    pub overlay: Option<Arc<Overlay>>, // Changes kept aside, the image is opened read-only
+}
+
+/// Split a DEVICE@N filename into the device and the partition number
+pub fn split_partition(filename: &str) -> (&str, Option<u32>) {
+    if let Some((device, number)) = filename.rsplit_once('@') {
+        if let Ok(number) = number.parse::<u32>() {
+            if !Path::new(filename).exists() {
+                return (device, Some(number));
+            }
+        }
+    }
+    (filename, None)
+}
+
+/// Read the first bytes of a disk, to detect the image format
+fn read_magic(disk: &dyn Disk, size: u64) -> Result<Vec<u8>, Error> {
+    if disk.get_size()? < size {
+        return Ok(Vec::new());
+    }
+    let offset = Offset::Block {
+        block_size: size,
+        block_num: 0,
+    };
+    disk.read(size, offset)
+}
+
+/// Maximum length of a chain of qcow2 backing files (also catches loops)
+const MAX_BACKING_DEPTH: u32 = 16;
+
+/// Open a whole disk image, possibly split in segments: a raw image, a qcow2, VMDK, VHD
+/// or VHDX image, or a compressed raw image (detected by its magic number)
+pub fn open_image(filename: &str, read_write: bool) -> Result<Box<dyn Disk>, Error> {
+    open_image_depth(filename, read_write, 0)
+}
+
+/// Open an image used as backing file at the given depth of the chain
+fn open_image_depth(filename: &str, read_write: bool, depth: u32) -> Result<Box<dyn Disk>, Error> {
+    if depth > MAX_BACKING_DEPTH {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Backing chain longer than {} images", MAX_BACKING_DEPTH),
+        ));
+    }
+    let disk = open_segments(filename, read_write)?;
+    let magic = read_magic(disk.as_ref(), 8)?;
+    let footer = read_vhd_footer(disk.as_ref())?;
+    let compression = CompressionFormat::detect(&magic);
+    let format = if magic.starts_with(QCOW2_MAGIC) {
+        "qcow2"
+    } else if magic.starts_with(VMDK_MAGIC) {
+        "vmdk"
+    } else if magic.starts_with(VHDX_SIGNATURE) {
+        "vhdx"
+    } else if let Some(compression) = compression {
+        compression.get_name()
+    } else if footer.is_some() {
+        "vhd"
+    } else {
+        return Ok(disk);
+    };
+    if read_write {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} images are read-only", format),
+        ));
+    }
+    match format {
+        "qcow2" => {
+            let open_backing = move |path: &str| open_image_depth(path, false, depth + 1);
+            Ok(Box::new(Qcow2Disk::open(disk, filename, &open_backing)?))
+        }
+        "vmdk" => Ok(Box::new(VmdkDisk::open(disk)?)),
+        "vhdx" => Ok(Box::new(VhdxDisk::open(disk, filename)?)),
+        _ if compression.is_some() => {
+            Ok(Box::new(CompressedDisk::open(disk, compression.unwrap())?))
+        }
+        _ => open_vhd(disk, &footer.unwrap()),
+    }
+}
+
+/// Open the disk containing the filesystem: the partition given by DEVICE@N or --partition,
+/// the data following --offset, or the whole image. With an overlay, the image is never
+/// written: the writes go to the overlay.
+pub fn open_disk(filename: &str, options: &MountOptions) -> Result<Box<dyn Disk>, Error> {
+    let (device, number) = split_partition(filename);
+    let number = number.unwrap_or(options.partition);
+    let disk = match &options.overlay {
+        Some(overlay) => Box::new(OverlayDisk::new(
+            open_image(device, false)?,
+            overlay.clone(),
+        )?),
+        None => open_image(device, options.read_write)?,
+    };
+    if number != 0 {
+        if options.offset != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "A partition and an offset cannot be both given",
+            ));
+        }
+        let table = PartitionTable::read(disk.as_ref())?;
+        let partition = table.get_partition(number)?;
+        let (start, size) = (partition.start, partition.size);
+        Ok(Box::new(PartitionDisk::new(disk, start, Some(size))))
+    } else if options.offset != 0 {
+        Ok(Box::new(PartitionDisk::new(disk, options.offset, None)))
+    } else {
+        Ok(disk)
+    }
+}
+
+pub fn mount(filename: &str, options: &MountOptions) -> Result<Box<dyn Filesystem>, Error> {
+    Ok(Box::new(Ext2Filesystem::mount(filename, options)?))
 }
@@ -17,6 +17,8 @@ pub trait Inode {
     fn get_block_size(&self) -> u64;
     /// Size in bytes
     fn get_size(&self) -> u64;
+    /// Content stored inside the inode (if any)
+    fn get_inline_data(&self) -> Option<&[u8]>;
     /// Given a path, query the file system to get information about a file, directory, etc.
     fn metadata(&self) -> Metadata;
 }
@@ -1,3 +1,4 @@
+pub mod cache;
 pub mod cmds;
 pub mod dir;
 pub mod disk;
@@ -6,13 +7,18 @@ pub mod file;
 pub mod fs;
 pub mod inode;
 pub mod metadata;
+#[cfg(test)]
+pub mod test_util;
 
-use crate::cmds::{Command, Options};
+use crate::cmds::{get_exit_status, Command, Options};
+use crate::disk::overlay::Overlay;
 use crate::disk::Disk;
-use argparse::{ArgumentParser, List, Store};
+use crate::fs::MountOptions;
+use argparse::{ArgumentParser, List, Store, StoreOption, StoreTrue};
 use std::env;
 use std::io;
 use std::str;
+use std::sync::Arc;
 
 const FILENAME: &str = "root";
 
@@ -21,7 +27,13 @@ fn get_cmd() -> String {
     args[0].clone()
 }
 
-fn parse_args(subcommand: &mut Command, args: &mut Vec<String>, options: &mut Options) {
+fn parse_args(
+    subcommand: &mut Command,
+    args: &mut Vec<String>,
+    options: &mut Options,
+    overlay: &mut Option<String>,
+    overlay_memory: &mut bool,
+) {
     // Parse command argument
     let mut parser = ArgumentParser::new();
     parser
@@ -35,16 +47,101 @@ fn parse_args(subcommand: &mut Command, args: &mut Vec<String>, options: &mut Op
     parser
         .refer(args)
         .add_argument("arguments", List, "Arguments for command");
+    parser
+        .refer(&mut options.mount_options.checksum_mode)
+        .add_option(
+            &["--checksum"],
+            Store,
+            "Checksum mode (lenient, strict, none)",
+        );
+    parser
+        .refer(&mut options.mount_options.superblock)
+        .add_option(
+            &["--superblock"],
+            Store,
+            "Use the backup superblock at the given block",
+        );
+    parser
+        .refer(&mut options.mount_options.partition)
+        .add_option(&["--partition"], Store, "Open the partition N of the disk");
+    parser.refer(&mut options.mount_options.offset).add_option(
+        &["--offset"],
+        Store,
+        "Open the filesystem starting at the given byte offset",
+    );
+    parser
+        .refer(&mut options.mount_options.cache_size)
+        .add_option(
+            &["--cache-size"],
+            StoreOption,
+            "Size of the block cache in MiB",
+        );
+    parser
+        .refer(&mut options.mount_options.read_ahead)
+        .add_option(
+            &["--read-ahead"],
+            StoreOption,
+            "Maximum size of a file read in KiB",
+        );
+    parser.refer(overlay).add_option(
+        &["--overlay"],
+        StoreOption,
+        "Write the changes to a sidecar file",
+    );
+    parser.refer(overlay_memory).add_option(
+        &["--overlay-memory"],
+        StoreTrue,
+        "Keep the changes in memory",
+    );
     parser.stop_on_first_argument(true);
     if let Err(x) = parser.parse(env::args().collect(), &mut io::stdout(), &mut io::sink()) {
         eprintln!("Usage:");
-        eprintln!("  {} DEVICE COMMAND [ARGUMENTS ...]", get_cmd());
+        eprintln!("  {} [OPTIONS] DEVICE COMMAND [ARGUMENTS ...]", get_cmd());
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --checksum MODE  On metadata checksum mismatch warn (lenient, default)");
+        eprintln!(
+            "                   refuse the corrupted structure (strict) or ignore it (none)."
+        );
+        eprintln!(
+            "  --superblock N   Use the backup superblock at block N (by default the backups"
+        );
+        eprintln!("                   are probed when the primary superblock is damaged).");
+        eprintln!("  --partition N    Open the partition N of the disk (or use DEVICE@N).");
+        eprintln!("  --offset BYTES   Open the filesystem starting at the given byte offset.");
+        eprintln!("  --cache-size MIB Size of the block cache (default 64, 0 to disable).");
+        eprintln!(
+            "  --read-ahead KIB Read the contiguous blocks of a file by up to KIB (default 128)."
+        );
+        eprintln!(
+            "  --overlay FILE   Write the changes to FILE (created if needed), never to DEVICE."
+        );
+        eprintln!("  --overlay-memory Keep the changes in memory, never write to DEVICE.");
         eprintln!();
         eprintln!("Commands:");
+        eprintln!("  blocks           List the blocks used by the FILE(s).");
         eprintln!("  cat              Concatenate FILE(s) to standard output.");
         eprintln!("  df               Show information about the file system.");
+        eprintln!("  dumpblock        Display filesystem blocks in hexadecimal.");
+        eprintln!("  dumpe2fs         Dump the superblock and the block group descriptors.");
+        eprintln!("  dumpinode        Display all the fields of an inode.");
+        eprintln!("  filefrag         Report the fragmentation of the FILE(s).");
+        eprintln!("  freefrag         Report the free space fragmentation.");
+        eprintln!("  fsck             Check (and --repair) the file system consistency.");
         eprintln!("  hd               Display file contents in hexadecimal.");
+        eprintln!("  icheck           Find the inodes owning the given blocks.");
         eprintln!("  ls               List information about the FILEs.");
+        eprintln!("  lsdel            List the deleted inodes.");
+        eprintln!("  ncheck           Find the pathnames of the given inodes.");
+        eprintln!("  overlay          Show, discard or commit the changes kept in the overlay.");
+        eprintln!("  partitions       List the partitions (MBR or GPT) of the disk.");
+        eprintln!("  shell            Run commands on a single mount (interactive or -f SCRIPT).");
+        eprintln!("  undelete         Recover the content of a deleted inode.");
+        eprintln!();
+        eprintln!("The DEVICE is a raw, qcow2, VMDK, VHD or VHDX image, or a raw image compressed");
+        eprintln!("with gzip, xz or zstd (detected automatically).");
+        eprintln!("A split image is opened from its first segment (image.001 or image.E01).");
+        eprintln!("A path can begin with an inode number, e.g. <12> or <12>/file.");
         std::process::exit(x);
     }
 }
@@ -52,15 +149,42 @@ fn parse_args(subcommand: &mut Command, args: &mut Vec<String>, options: &mut Op
 fn main() {
     let mut options: Options = Options {
         filename: String::from(FILENAME),
+        mount_options: MountOptions::default(),
+        fs: None,
+        cwd: String::from("/"),
     };
     let mut subcommand = Command::ls;
     let mut args = vec![];
-    parse_args(&mut subcommand, &mut args, &mut options);
+    let mut overlay = None;
+    let mut overlay_memory = false;
+    parse_args(
+        &mut subcommand,
+        &mut args,
+        &mut options,
+        &mut overlay,
+        &mut overlay_memory,
+    );
+    // The overlay is shared by all the mounts of the image
+    if let Some(path) = overlay {
+        match Overlay::open(&path) {
+            Ok(overlay) => options.mount_options.overlay = Some(Arc::new(overlay)),
+            Err(x) => {
+                eprintln!("{}: {}: {}", get_cmd(), path, x);
+                std::process::exit(1);
+            }
+        }
+    } else if overlay_memory {
+        options.mount_options.overlay = Some(Arc::new(Overlay::new_memory()));
+    }
     args.insert(0, format!("{} {:?}", get_cmd(), subcommand));
     let result = subcommand.run_command(&options, args);
     match result {
         Ok(_) => std::process::exit(0),
         Err(x) => {
+            if let Some(status) = get_exit_status(&x) {
+                // The command already displayed the error
+                std::process::exit(status);
+            }
             eprintln!("{}: {}", get_cmd(), x);
             std::process::exit(1);
         }
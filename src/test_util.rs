@@ -0,0 +1,150 @@
+//! Helpers shared by the unit tests
+
+use crate::disk::{Disk, Offset};
+use crate::ext2::Ext2Filesystem;
+use crate::fs::{Filesystem, MountOptions};
+use flate2::read::GzDecoder;
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Path of a test image (see tests/data/make_images.sh)
+pub fn image_path(name: &str) -> String {
+    format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Path of a temporary file, unique to the test process
+pub fn temp_path(name: &str) -> String {
+    let name = format!("ext2-test-{}-{}", std::process::id(), name);
+    std::env::temp_dir()
+        .join(name)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Decompressed content of a test image
+pub fn read_image(name: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    let file = std::fs::File::open(image_path(name)).unwrap();
+    GzDecoder::new(file).read_to_end(&mut data).unwrap();
+    data
+}
+
+/// Decompress a test image to a temporary file (once per test process), returns its path
+pub fn unpack_image(name: &str) -> String {
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _guard = LOCK.lock().unwrap();
+    let path = temp_path(name.trim_end_matches(".gz"));
+    if !Path::new(&path).exists() {
+        std::fs::write(&path, read_image(name)).unwrap();
+    }
+    path
+}
+
+/// Mount a test image read-only
+pub fn mount_image(name: &str, options: &MountOptions) -> Ext2Filesystem {
+    Ext2Filesystem::mount(&unpack_image(name), options).unwrap()
+}
+
+/// Read a whole file, one block at a time (the test images have 1 KiB blocks)
+pub fn read_file(fs: &Ext2Filesystem, path: &str) -> Result<Vec<u8>, Error> {
+    let mut file = (fs as &dyn Filesystem).open(path)?;
+    let mut data = Vec::new();
+    let mut buffer = [0; 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(data),
+            count => data.extend_from_slice(&buffer[..count]),
+        }
+    }
+}
+
+/// Temporary copy of a test image, deleted when dropped
+pub struct PatchedImage {
+    pub path: String,
+}
+
+impl Drop for PatchedImage {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Copy a test image to a temporary file and apply the changes (offset, bytes) to the copy,
+/// the image itself is not modified
+pub fn patch_image(name: &str, changes: &[(u64, &[u8])]) -> PatchedImage {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut data = read_image(name);
+    for (offset, bytes) in changes {
+        let offset = *offset as usize;
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let name = format!("{}-{}", count, name.trim_end_matches(".gz"));
+    let image = PatchedImage {
+        path: temp_path(&name),
+    };
+    std::fs::write(&image.path, data).unwrap();
+    image
+}
+
+/// Offset of an inode in a test image
+pub fn inode_offset(name: &str, inode_num: u64) -> u64 {
+    let fs = mount_image(name, &MountOptions::default());
+    let super_block = fs.get_super_block();
+    let index = (inode_num - 1) % super_block.s_inodes_per_group as u64;
+    let table = fs
+        .get_block_groups()
+        .get_inode_group(inode_num)
+        .ext2_group_desc
+        .get_inode_table();
+    table * super_block.get_block_size() + index * super_block.s_inode_size as u64
+}
+
+/// Content of the files made of numbered 1 KiB blocks (big.txt, extents.txt)
+pub fn block_pattern(count: usize) -> String {
+    (0..count)
+        .map(|i| format!("{:.<1023}\n", format!("block {:06} ", i)))
+        .collect()
+}
+
+/// Disk kept in memory
+pub struct MemoryDisk {
+    pub data: Mutex<Vec<u8>>,
+}
+
+impl MemoryDisk {
+    pub fn new(data: Vec<u8>) -> MemoryDisk {
+        MemoryDisk {
+            data: Mutex::new(data),
+        }
+    }
+}
+
+impl Disk for MemoryDisk {
+    fn read(&self, size: u64, offset: Offset) -> Result<Vec<u8>, Error> {
+        let offset = offset.calc_offset() as usize;
+        let data = self.data.lock().unwrap();
+        match data.get(offset..offset + size as usize) {
+            Some(bytes) => Ok(bytes.to_vec()),
+            None => Err(Error::new(ErrorKind::UnexpectedEof, "Read beyond the end")),
+        }
+    }
+
+    fn write(&self, buffer: &[u8], offset: Offset) -> Result<(), Error> {
+        let offset = offset.calc_offset() as usize;
+        let mut data = self.data.lock().unwrap();
+        match data.get_mut(offset..offset + buffer.len()) {
+            Some(bytes) => {
+                bytes.copy_from_slice(buffer);
+                Ok(())
+            }
+            None => Err(Error::new(ErrorKind::UnexpectedEof, "Write beyond the end")),
+        }
+    }
+
+    fn get_size(&self) -> Result<u64, Error> {
+        Ok(self.data.lock().unwrap().len() as u64)
+    }
+}
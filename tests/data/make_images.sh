@@ -0,0 +1,47 @@
+#!/bin/sh
+# Build the test images (needs e2fsprogs and python3):
+# ext2.img.gz  ext2 with two groups, a fragmented file, a sparse file, a symbolic link
+#              and a deleted file
+# ext4.img.gz  ext4 with metadata_csum, extents and inline data
+set -e
+cd "$(dirname "$0")"
+TMP=$(mktemp -d)
+trap 'rm -rf "$TMP"' EXIT
+export E2FSPROGS_FAKE_TIME=1700000000
+
+# Files made of numbered 1 KiB blocks
+blocks() {
+    python3 -c "import sys; sys.stdout.write(''.join(('block %06d ' % i).ljust(1023, '.') + '\n' for i in range($1)))"
+}
+
+mkdir -p "$TMP/ext2/dir1"
+printf 'Hello, world!\n' > "$TMP/ext2/hello.txt"
+printf 'File in dir1\n' > "$TMP/ext2/dir1/file.txt"
+ln -s hello.txt "$TMP/ext2/link"
+blocks 300 > "$TMP/ext2/big.txt"
+python3 - "$TMP/ext2/sparse" <<'EOF'
+import sys
+f = open(sys.argv[1], 'wb')
+f.seek(100 * 1024)
+f.write(b'data after a hole\n')
+f.seek(300 * 1024)
+f.write(b'end\n')
+EOF
+printf 'This file will be deleted\n' > "$TMP/ext2/deleted.txt"
+find "$TMP/ext2" -exec touch -h -d @1700000000 {} +
+mke2fs -q -t ext2 -b 1024 -L ext2test -U 11111111-2222-3333-4444-555555555555 \
+    -E root_owner=0:0 -d "$TMP/ext2" "$TMP/ext2.img" 9M
+debugfs -w -R "rm deleted.txt" "$TMP/ext2.img"
+gzip -9 -n -c "$TMP/ext2.img" > ext2.img.gz
+
+mkdir -p "$TMP/ext4/inline_dir" "$TMP/ext4/big_dir"
+printf 'Small inline file\n' > "$TMP/ext4/small.txt"
+python3 -c "print('0123456789' * 10, end='')" > "$TMP/ext4/medium.txt"
+for x in a b c; do printf '%s\n' $x > "$TMP/ext4/inline_dir/$x.txt"; done
+blocks 40 > "$TMP/ext4/extents.txt"
+for i in $(seq -w 0 39); do echo $i > "$TMP/ext4/big_dir/file_with_a_long_name_0$i"; done
+find "$TMP/ext4" -exec touch -h -d @1700000000 {} +
+mke2fs -q -t ext4 -b 1024 -O metadata_csum,inline_data,^has_journal,^resize_inode \
+    -L ext4test -U 66666666-7777-8888-9999-aaaaaaaaaaaa -E root_owner=0:0 \
+    -d "$TMP/ext4" "$TMP/ext4.img" 2M
+gzip -9 -n -c "$TMP/ext4.img" > ext4.img.gz